@@ -1,5 +1,5 @@
 use axum::{
-    async_trait,
+    RequestPartsExt,
     extract::FromRequestParts,
     http::{StatusCode, request::Parts},
     response::{IntoResponse, Response},
@@ -19,9 +19,10 @@ pub struct AuthConfig {
 #[derive(Clone)] // Added Clone derive
 pub struct AuthenticatedUser {
     pub username: String,
+    /// The named database this user belongs to; `None` for server users.
+    pub database: Option<String>,
 }
 
-#[async_trait]
 impl<S> FromRequestParts<S> for AuthenticatedUser
 where
     S: Send + Sync,
@@ -29,10 +30,10 @@ where
     type Rejection = Response;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let auth_config = parts
-            .extensions
-            .get::<AuthConfig>()
-            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Missing auth config").into_response())?;
+        let auth_config =
+            parts.extensions.get::<AuthConfig>().cloned().ok_or(
+                (StatusCode::INTERNAL_SERVER_ERROR, "Missing auth config").into_response(),
+            )?;
 
         let auth = parts
             .extract::<TypedHeader<Authorization<Basic>>>()
@@ -45,6 +46,7 @@ where
             Some(hashed_password) if verify(basic.password(), hashed_password).unwrap_or(false) => {
                 Ok(AuthenticatedUser {
                     username: basic.username().to_string(),
+                    database: None,
                 })
             }
             _ => Err((StatusCode::UNAUTHORIZED, "Invalid credentials").into_response()),
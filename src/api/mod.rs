@@ -121,19 +121,23 @@
 // }
 
 use axum::{
-    Json, Router,
+    Extension, Json, Router,
     body::Body,
-    extract::{Path, State},
-    http::{Request, StatusCode},
+    extract::{FromRequestParts, Path, Query, State},
+    http::{HeaderMap, Request, StatusCode, header, request::Parts},
     middleware::{self, Next},
     response::{IntoResponse, Response},
-    routing::{delete, get, post, put},
+    routing::{delete, get, patch, post, put},
 };
+use base64::Engine;
+use bcrypt::verify;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
 use serde_json::Value;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
 use tracing::info;
 
-use crate::db::{Database, DbError, Document};
+use crate::db::{self, Catalog, Database, DbError, Document};
 
 mod auth;
 pub use auth::{AuthConfig, AuthenticatedUser};
@@ -146,6 +150,10 @@ pub enum ApiError {
     JsonError(#[from] serde_json::Error),
     #[error("Authentication error")]
     AuthError,
+    #[error("Precondition failed")]
+    PreconditionFailed,
+    #[error("Forbidden")]
+    Forbidden,
 }
 
 impl IntoResponse for ApiError {
@@ -153,7 +161,28 @@ impl IntoResponse for ApiError {
         let status = match self {
             ApiError::DbError(DbError::NotFound) => StatusCode::NOT_FOUND,
             ApiError::DbError(DbError::CollectionNotFound) => StatusCode::NOT_FOUND,
+            ApiError::DbError(DbError::CollectionAlreadyExists) => StatusCode::CONFLICT,
+            ApiError::DbError(DbError::DatabaseNotFound) => StatusCode::NOT_FOUND,
+            ApiError::DbError(DbError::DatabaseAlreadyExists) => StatusCode::CONFLICT,
+            ApiError::DbError(DbError::InvalidName(_)) => StatusCode::BAD_REQUEST,
+            ApiError::DbError(DbError::HistoryDisabled) => StatusCode::BAD_REQUEST,
+            ApiError::DbError(DbError::LimitExceeded(_)) => StatusCode::CONFLICT,
+            ApiError::DbError(DbError::DocumentTooLarge(_)) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::DbError(DbError::QuotaExceeded(_)) => StatusCode::INSUFFICIENT_STORAGE,
+            ApiError::DbError(DbError::InvalidQuery(_)) => StatusCode::BAD_REQUEST,
+            ApiError::DbError(DbError::InvalidUpdate(_)) => StatusCode::BAD_REQUEST,
+            ApiError::DbError(DbError::PatchTestFailed(_)) => StatusCode::CONFLICT,
+            ApiError::DbError(DbError::TransactionConflict(_)) => StatusCode::CONFLICT,
+            ApiError::DbError(DbError::VersionConflict { .. }) => StatusCode::PRECONDITION_FAILED,
+            ApiError::DbError(DbError::InvalidId(_)) => StatusCode::BAD_REQUEST,
+            ApiError::DbError(DbError::DuplicateId(_)) => StatusCode::CONFLICT,
+            ApiError::DbError(DbError::InvalidSchema(_)) => StatusCode::BAD_REQUEST,
+            ApiError::DbError(DbError::InvalidTtl(_)) => StatusCode::BAD_REQUEST,
+            ApiError::DbError(DbError::ValidationFailed(_)) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ApiError::JsonError(_) => StatusCode::BAD_REQUEST,
             ApiError::AuthError => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden => StatusCode::FORBIDDEN,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         };
         (status, self.to_string()).into_response()
@@ -162,48 +191,238 @@ impl IntoResponse for ApiError {
 
 #[derive(Clone)]
 pub struct ApiState {
-    pub db: Arc<Database>,
+    pub catalog: Catalog,
     pub auth_config: AuthConfig,
 }
 
+/// The database a request addresses: the one named by `/db/:db/...`, or the
+/// default database for routes outside `/db`.
+pub struct Db(pub Database);
+
+impl FromRequestParts<ApiState> for Db {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &ApiState) -> Result<Self, ApiError> {
+        match database_param(parts, state).await {
+            Some(name) => Ok(Db(state.catalog.database(&name)?)),
+            None => Ok(Db(state.catalog.default_database())),
+        }
+    }
+}
+
+async fn database_param(parts: &mut Parts, state: &ApiState) -> Option<String> {
+    let Path(mut params) = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+        .await
+        .ok()?;
+    params.remove("db")
+}
+
+// Route parameters are taken by name so the same handlers serve both the
+// default database and `/db/:db/...`.
+#[derive(Debug, Deserialize)]
+struct CollectionPath {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DocumentPath {
+    name: String,
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RevisionPath {
+    name: String,
+    id: String,
+    version: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct FieldPath {
+    name: String,
+    field: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TrashPath {
+    id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct DatabasePath {
+    db: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserPath {
+    db: String,
+    username: String,
+}
+
 pub async fn start_server(
-    db: Database,
+    catalog: Catalog,
     host: &str,
     port: u16,
     auth_config: AuthConfig,
 ) -> anyhow::Result<()> {
     let state = ApiState {
-        db: Arc::new(db),
+        catalog,
         auth_config,
     };
 
-    let app = Router::new()
-        .route("/collections/:name", post(create_collection))
-        .route("/collections/:name", delete(delete_collection))
-        .route("/collections/:name/documents", post(insert_document))
-        .route("/collections/:name/documents", get(list_documents))
-        .route("/collections/:name/documents/:id", get(get_document))
-        .route("/collections/:name/documents/:id", put(update_document))
-        .route("/collections/:name/documents/:id", delete(delete_document))
-        .layer(middleware::from_fn(auth_middleware))
-        .with_state(state);
-
     let listener = tokio::net::TcpListener::bind(format!("{}:{}", host, port)).await?;
     info!("Server listening on {}:{}", host, port);
-    axum::serve(listener, app).await?;
+    axum::serve(listener, router(state)).await?;
     Ok(())
 }
 
-async fn auth_middleware(request: Request<Body>, next: Next) -> Result<Response, Response> {
-    // Extract auth config from extensions
-    let auth_config = request
-        .extensions()
-        .get::<AuthConfig>()
-        .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Missing auth config").into_response())?;
+fn router(state: ApiState) -> Router {
+    Router::new()
+        .merge(database_routes())
+        .nest("/db/{db}", database_routes())
+        .route("/databases", get(list_databases))
+        .route("/databases/{db}", get(describe_database))
+        .route("/databases/{db}", post(create_database))
+        .route("/databases/{db}", delete(drop_database))
+        .route("/databases/{db}/quota", put(set_quota))
+        .route("/databases/{db}/users/{username}", put(set_user))
+        .route("/databases/{db}/users/{username}", delete(remove_user))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth_middleware,
+        ))
+        .with_state(state)
+}
+
+// Everything that works on one database, served at the root for the default
+// database and under `/db/:db` for named ones.
+fn database_routes() -> Router<ApiState> {
+    Router::new()
+        .route("/collections", get(list_collections))
+        .route("/collections/{name}", get(describe_collection))
+        .route("/collections/{name}", post(create_collection))
+        .route("/collections/{name}", delete(delete_collection))
+        .route("/collections/{name}/rename", post(rename_collection))
+        .route("/collections/{name}/clone", post(clone_collection))
+        .route("/collections/{name}/truncate", post(truncate_collection))
+        .route("/collections/{name}/id-strategy", get(get_id_strategy))
+        .route("/collections/{name}/id-strategy", put(set_id_strategy))
+        .route("/collections/{name}/schema", get(get_schema))
+        .route("/collections/{name}/schema", put(set_schema))
+        .route("/collections/{name}/schema", delete(delete_schema))
+        .route("/collections/{name}/schema/validate", post(validate_schema))
+        .route("/collections/{name}/documents", post(insert_document))
+        .route("/collections/{name}/documents", get(list_documents))
+        .route("/collections/{name}/documents/{id}", get(get_document))
+        .route("/collections/{name}/documents/{id}", put(update_document))
+        .route("/collections/{name}/documents/{id}", patch(patch_document))
+        .route(
+            "/collections/{name}/documents/{id}",
+            delete(delete_document),
+        )
+        .route(
+            "/collections/{name}/documents/{id}/revisions",
+            get(list_revisions),
+        )
+        .route(
+            "/collections/{name}/documents/{id}/revisions/{version}",
+            get(get_revision),
+        )
+        .route(
+            "/collections/{name}/documents/{id}/revisions/{version}/restore",
+            post(restore_revision),
+        )
+        .route(
+            "/collections/{name}/documents/{id}/diff",
+            get(diff_revisions),
+        )
+        .route("/collections/{name}/history", put(set_history))
+        .route("/collections/{name}/history", delete(disable_history))
+        .route("/collections/{name}/default-ttl", put(set_default_ttl))
+        .route("/collections/{name}/default-ttl", delete(clear_default_ttl))
+        .route("/collections/{name}/indexes", get(list_indexes))
+        .route("/collections/{name}/indexes/{field}", put(create_index))
+        .route("/collections/{name}/indexes/{field}", delete(drop_index))
+        .route(
+            "/collections/{name}/geo-indexes/{field}",
+            put(create_geo_index),
+        )
+        .route(
+            "/collections/{name}/geo-indexes/{field}",
+            delete(drop_geo_index),
+        )
+        .route(
+            "/collections/{name}/vector-indexes/{field}",
+            put(create_vector_index),
+        )
+        .route(
+            "/collections/{name}/vector-indexes/{field}",
+            delete(drop_vector_index),
+        )
+        .route(
+            "/collections/{name}/ttl-indexes/{field}",
+            put(create_ttl_index),
+        )
+        .route(
+            "/collections/{name}/ttl-indexes/{field}",
+            delete(drop_ttl_index),
+        )
+        .route("/collections/{name}/documents/{id}/ttl", put(refresh_ttl))
+        .route("/collections/{name}/documents/{id}/ttl", delete(clear_ttl))
+        .route(
+            "/collections/{name}/documents/{id}/ttl/extend",
+            post(extend_ttl),
+        )
+        .route("/collections/{name}/capped", put(set_capped))
+        .route("/collections/{name}/capped", delete(uncap))
+        .route("/collections/{name}/tail", get(tail_documents))
+        .route("/collections/{name}/soft-delete", put(set_soft_delete))
+        .route(
+            "/collections/{name}/soft-delete",
+            delete(disable_soft_delete),
+        )
+        .route("/collections/{name}/trash", get(list_trash))
+        .route("/collections/{name}/trash", delete(empty_trash))
+        .route("/collections/{name}/trash/{id}", delete(purge_document))
+        .route(
+            "/collections/{name}/trash/{id}/restore",
+            post(undelete_document),
+        )
+        .route("/trash/collections", get(list_trashed_collections))
+        .route("/trash/collections/{id}", delete(purge_collection))
+        .route("/trash/collections/{id}/restore", post(restore_collection))
+        .route("/collections/{name}/bulk", post(bulk_write))
+        .route("/collections/{name}/knn", post(knn_search))
+        .route("/collections/{name}/aggregate", post(aggregate))
+        .route("/sequences", get(list_sequences))
+        .route("/sequences/{name}", get(get_sequence))
+        .route("/sequences/{name}", put(set_sequence))
+        .route("/sequences/{name}", delete(drop_sequence))
+        .route("/sequences/{name}/next", post(next_sequence))
+        .route("/counters", get(list_counters))
+        .route("/counters/{name}", get(get_counter))
+        .route("/counters/{name}", put(set_counter))
+        .route("/counters/{name}", delete(delete_counter))
+        .route("/counters/{name}/increment", post(increment_counter))
+        .route("/counters/{name}/decrement", post(decrement_counter))
+        .route("/ttl", get(expiry_stats))
+        .route("/ttl/sweep", post(sweep_expired))
+        .route("/sql", post(sql))
+        .route("/transactions", post(transaction))
+}
+
+// Server users from `AuthConfig` may use every database; users of a named
+// database only that database.
+async fn auth_middleware(
+    State(state): State<ApiState>,
+    request: Request<Body>,
+    next: Next,
+) -> Result<Response, Response> {
+    let (mut parts, body) = request.into_parts();
 
     // Extract authorization header
-    let auth_header = request
-        .headers()
+    let auth_header = parts
+        .headers
         .get(axum::http::header::AUTHORIZATION)
         .ok_or((StatusCode::UNAUTHORIZED, "Missing authorization header").into_response())?;
 
@@ -216,98 +435,1139 @@ async fn auth_middleware(request: Request<Body>, next: Next) -> Result<Response,
     }
 
     let credentials = auth_str.trim_start_matches("Basic ");
-    let decoded = base64::decode(credentials)
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(credentials)
         .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid basic auth format").into_response())?;
 
     let decoded_str = String::from_utf8(decoded)
         .map_err(|_| (StatusCode::UNAUTHORIZED, "Invalid UTF-8 in credentials").into_response())?;
 
-    let parts: Vec<&str> = decoded_str.splitn(2, ':').collect();
-    if parts.len() != 2 {
-        return Err((StatusCode::UNAUTHORIZED, "Invalid basic auth format").into_response());
-    }
-
-    let username = parts[0];
-    let password = parts[1];
+    let (username, password) = decoded_str
+        .split_once(':')
+        .ok_or((StatusCode::UNAUTHORIZED, "Invalid basic auth format").into_response())?;
 
-    match auth_config.users.get(username) {
-        Some(hashed_password) if verify(password, hashed_password).unwrap_or(false) => {
-            let mut req = request;
-            req.extensions_mut().insert(AuthenticatedUser {
-                username: username.to_string(),
+    let database = match state.auth_config.users.get(username) {
+        Some(hashed_password) if verify(password, hashed_password).unwrap_or(false) => None,
+        _ => {
+            let name = database_param(&mut parts, &state).await;
+            let allowed = name.as_deref().is_some_and(|name| {
+                state
+                    .catalog
+                    .database(name)
+                    .is_ok_and(|db| db.verify_user(username, password))
             });
-            Ok(next.run(req).await)
+            if !allowed {
+                return Err((StatusCode::UNAUTHORIZED, "Invalid credentials").into_response());
+            }
+            name
         }
-        _ => Err((StatusCode::UNAUTHORIZED, "Invalid credentials").into_response()),
+    };
+    parts.extensions.insert(AuthenticatedUser {
+        username: username.to_string(),
+        database,
+    });
+    Ok(next.run(Request::from_parts(parts, body)).await)
+}
+
+// Managing databases is reserved for server users.
+fn require_server_user(user: &AuthenticatedUser) -> Result<(), ApiError> {
+    match user.database {
+        None => Ok(()),
+        Some(_) => Err(ApiError::Forbidden),
     }
 }
 
-async fn create_collection(
+#[derive(Debug, serde::Serialize)]
+struct DatabaseInfo {
+    name: String,
+    collections: Vec<String>,
+    quota: db::Quota,
+    usage: db::Usage,
+    users: Vec<String>,
+}
+
+async fn list_databases(
     State(state): State<ApiState>,
-    Path(name): Path<String>,
+    Extension(user): Extension<AuthenticatedUser>,
+) -> Result<Json<Vec<String>>, ApiError> {
+    require_server_user(&user)?;
+    Ok(Json(state.catalog.database_names()?))
+}
+
+async fn describe_database(
+    State(state): State<ApiState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(DatabasePath { db: name }): Path<DatabasePath>,
+) -> Result<Json<DatabaseInfo>, ApiError> {
+    if user.database.as_ref().is_some_and(|db| *db != name) {
+        return Err(ApiError::Forbidden);
+    }
+    let db = state.catalog.database(&name)?;
+    Ok(Json(DatabaseInfo {
+        collections: db.collection_names()?,
+        quota: db.quota(),
+        usage: db.usage(),
+        users: db.users()?,
+        name,
+    }))
+}
+
+// The body is an optional quota.
+async fn create_database(
+    State(state): State<ApiState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(DatabasePath { db: name }): Path<DatabasePath>,
+    body: String,
 ) -> Result<StatusCode, ApiError> {
-    state.db.create_collection(&name)?;
+    require_server_user(&user)?;
+    let quota = if body.trim().is_empty() {
+        db::Quota::default()
+    } else {
+        serde_json::from_str(&body)?
+    };
+    state.catalog.create_database(&name, quota)?;
     Ok(StatusCode::CREATED)
 }
 
-#[axum::debug_handler]
-async fn delete_collection(
+async fn drop_database(
     State(state): State<ApiState>,
-    Path(name): Path<String>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(DatabasePath { db: name }): Path<DatabasePath>,
 ) -> Result<StatusCode, ApiError> {
-    state.db.drop_collection(&name)?;
+    require_server_user(&user)?;
+    state.catalog.drop_database(&name)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
-#[axum::debug_handler]
-async fn insert_document(
+async fn set_quota(
     State(state): State<ApiState>,
-    Path(collection): Path<String>,
-    Json(payload): Json<Value>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(DatabasePath { db: name }): Path<DatabasePath>,
+    Json(quota): Json<db::Quota>,
+) -> Result<StatusCode, ApiError> {
+    require_server_user(&user)?;
+    state.catalog.database(&name)?.set_quota(quota)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct UserBody {
+    password: String,
+}
+
+async fn set_user(
+    State(state): State<ApiState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(UserPath { db: name, username }): Path<UserPath>,
+    Json(body): Json<UserBody>,
+) -> Result<StatusCode, ApiError> {
+    require_server_user(&user)?;
+    state
+        .catalog
+        .database(&name)?
+        .set_user(&username, &body.password)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn remove_user(
+    State(state): State<ApiState>,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(UserPath { db: name, username }): Path<UserPath>,
+) -> Result<StatusCode, ApiError> {
+    require_server_user(&user)?;
+    state.catalog.database(&name)?.remove_user(&username)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn list_collections(Db(db): Db) -> Result<Json<Vec<db::CollectionInfo>>, ApiError> {
+    let infos = db
+        .collection_names()?
+        .iter()
+        .map(|name| db.get_collection(name)?.info())
+        .collect::<Result<Vec<_>, DbError>>()?;
+    Ok(Json(infos))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn describe_collection(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<Json<db::CollectionInfo>, ApiError> {
+    Ok(Json(db.get_collection(&name)?.info()?))
+}
+
+// The body is optional; without one the collection gets default options.
+async fn create_collection(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    body: String,
+) -> Result<StatusCode, ApiError> {
+    let options = if body.trim().is_empty() {
+        db::CollectionOptions::default()
+    } else {
+        serde_json::from_str(&body)?
+    };
+    db.create_collection_with(&name, options)?;
+    Ok(StatusCode::CREATED)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn delete_collection(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<StatusCode, ApiError> {
+    db.drop_collection(&name)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct RenameBody {
+    to: String,
+    /// Replace `to` if it exists instead of failing with 409.
+    #[serde(default)]
+    replace: bool,
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn rename_collection(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    Json(body): Json<RenameBody>,
+) -> Result<Json<db::CollectionInfo>, ApiError> {
+    db.rename_collection(&name, &body.to, body.replace)?;
+    Ok(Json(db.get_collection(&body.to)?.info()?))
+}
+
+#[derive(Debug, Deserialize)]
+struct CloneBody {
+    to: String,
+    #[serde(default)]
+    filter: Option<Value>,
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn clone_collection(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    Json(body): Json<CloneBody>,
+) -> Result<(StatusCode, Json<Value>), ApiError> {
+    let filter = body.filter.as_ref().map(db::Filter::parse).transpose()?;
+    let copied = db.clone_collection(&name, &body.to, filter.as_ref())?;
+    Ok((
+        StatusCode::CREATED,
+        Json(serde_json::json!({ "copied": copied })),
+    ))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn truncate_collection(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<Json<Value>, ApiError> {
+    let removed = db.truncate_collection(&name)?;
+    Ok(Json(serde_json::json!({ "removed": removed })))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn get_id_strategy(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<Json<db::IdStrategy>, ApiError> {
+    let strategy = db.get_collection(&name)?.id_strategy()?;
+    Ok(Json(strategy))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn set_id_strategy(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    Json(strategy): Json<db::IdStrategy>,
+) -> Result<Json<db::IdStrategy>, ApiError> {
+    let col = db.collection(&name)?;
+    col.set_id_strategy(strategy)?;
+    Ok(Json(col.id_strategy()?))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn get_schema(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<Json<db::Schema>, ApiError> {
+    let schema = db.get_collection(&name)?.schema()?;
+    Ok(Json(schema.ok_or(DbError::NotFound)?))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn set_schema(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    Json(schema): Json<Value>,
+) -> Result<Json<db::Schema>, ApiError> {
+    let schema = db::Schema::new(schema)?;
+    db.collection(&name)?.set_schema(Some(schema.clone()))?;
+    Ok(Json(schema))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn delete_schema(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.set_schema(None)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Reports which stored documents would fail `schema`, without enabling it.
+#[axum::debug_handler(state = ApiState)]
+async fn validate_schema(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    Json(schema): Json<Value>,
+) -> Result<Json<Vec<db::SchemaViolation>>, ApiError> {
+    let schema = db::Schema::new(schema)?;
+    let violations = db.get_collection(&name)?.validate_documents(&schema)?;
+    Ok(Json(violations))
+}
+
+#[derive(Debug, Deserialize)]
+struct InsertParams {
+    id: Option<String>,
+}
+
+/// Seconds until an inserted document expires, overriding the collection's
+/// default.
+const TTL_HEADER: &str = "x-ttl";
+
+/// Inserts a document. A TTL comes from the `X-TTL` header or a `_ttl`
+/// field, which is removed from the stored data.
+#[axum::debug_handler(state = ApiState)]
+async fn insert_document(
+    Db(db): Db,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(CollectionPath { name: collection }): Path<CollectionPath>,
+    Query(params): Query<InsertParams>,
+    headers: HeaderMap,
+    Json(mut payload): Json<Value>,
 ) -> Result<Json<Document>, ApiError> {
-    let col = state.db.collection(&collection)?;
-    let doc = col.insert(payload, None)?;
+    let ttl = match payload.as_object_mut().and_then(|obj| obj.remove("_ttl")) {
+        Some(Value::Number(n)) if n.is_i64() => n.as_i64(),
+        Some(other) => {
+            return Err(
+                DbError::InvalidTtl(format!("_ttl must be whole seconds, got {}", other)).into(),
+            );
+        }
+        None => header_ttl(&headers)?,
+    };
+    let ttl = ttl.map(db::check_ttl).transpose()?;
+    let col = db.collection(&collection)?.with_author(&user.username);
+    let doc = match params.id {
+        Some(id) => col.insert_with_id(&id, payload, ttl)?,
+        None => col.insert(payload, ttl)?,
+    };
     Ok(Json(doc))
 }
 
-#[axum::debug_handler]
+fn header_ttl(headers: &HeaderMap) -> Result<Option<i64>, ApiError> {
+    let Some(value) = headers.get(TTL_HEADER) else {
+        return Ok(None);
+    };
+    value
+        .to_str()
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .map(Some)
+        .ok_or_else(|| DbError::InvalidTtl("X-TTL must be whole seconds".to_string()).into())
+}
+
+#[derive(Debug, Deserialize)]
+struct ListParams {
+    filter: Option<String>,
+    skip: Option<usize>,
+    limit: Option<usize>,
+    #[serde(default)]
+    explain: bool,
+    include: Option<String>,
+    /// Query the collection as it was at this time.
+    as_of: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GetParams {
+    include: Option<String>,
+    as_of: Option<DateTime<Utc>>,
+}
+
+#[axum::debug_handler(state = ApiState)]
 async fn list_documents(
-    State(state): State<ApiState>,
-    Path(collection): Path<String>,
+    Db(db): Db,
+    Path(CollectionPath { name: collection }): Path<CollectionPath>,
+    Query(params): Query<ListParams>,
+) -> Result<Response, ApiError> {
+    let col = db.get_collection(&collection)?;
+    let filter = match params.filter {
+        Some(raw) => serde_json::from_str(&raw)?,
+        None => Value::Null,
+    };
+    let query = db::Query {
+        filter,
+        skip: params.skip,
+        limit: params.limit,
+    };
+    if let Some(at) = params.as_of {
+        return Ok(Json(col.query_as_of(&query, at)?).into_response());
+    }
+    if params.explain {
+        return Ok(Json(col.explain(&query)?).into_response());
+    }
+    let docs = match params.include {
+        Some(include) => {
+            let lookups = db::Lookup::parse_include(&include)?;
+            col.query_with_lookup(&db, &query, &lookups)?
+        }
+        None => col.query(&query)?,
+    };
+    Ok(Json(docs).into_response())
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn list_revisions(
+    Db(db): Db,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+) -> Result<Json<Vec<db::Revision>>, ApiError> {
+    let revisions = db.get_collection(&collection)?.revisions(&id)?;
+    Ok(Json(revisions))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn get_revision(
+    Db(db): Db,
+    Path(RevisionPath {
+        name: collection,
+        id,
+        version,
+    }): Path<RevisionPath>,
+) -> Result<Json<db::Revision>, ApiError> {
+    let revision = db.get_collection(&collection)?.revision(&id, version)?;
+    Ok(Json(revision))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn restore_revision(
+    Db(db): Db,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(RevisionPath {
+        name: collection,
+        id,
+        version,
+    }): Path<RevisionPath>,
+) -> Result<Response, ApiError> {
+    let col = db.get_collection(&collection)?.with_author(&user.username);
+    let doc = col.restore(&id, version)?;
+    Ok(with_etag(StatusCode::OK, doc))
+}
+
+#[derive(Debug, Deserialize)]
+struct DiffParams {
+    from: u64,
+    to: u64,
+}
+
+/// The JSON Patch that turns revision `from` into revision `to`.
+#[axum::debug_handler(state = ApiState)]
+async fn diff_revisions(
+    Db(db): Db,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+    Query(params): Query<DiffParams>,
+) -> Result<Json<Value>, ApiError> {
+    let col = db.get_collection(&collection)?;
+    Ok(Json(col.diff(&id, params.from, params.to)?))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn set_history(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    Json(retention): Json<db::HistoryRetention>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.set_history(Some(retention))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn disable_history(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.set_history(None)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn expiry_stats(Db(db): Db) -> Result<Json<db::ExpiryStats>, ApiError> {
+    Ok(Json(db.expiry_stats()?))
+}
+
+/// Removes expired documents now instead of waiting for the cleaner.
+#[axum::debug_handler(state = ApiState)]
+async fn sweep_expired(Db(db): Db) -> Result<Json<Value>, ApiError> {
+    let removed = db.remove_expired()?;
+    Ok(Json(serde_json::json!({ "removed": removed })))
+}
+
+#[derive(Debug, Deserialize)]
+struct CounterPath {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ValueBody {
+    value: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct SequenceBody {
+    value: i64,
+    /// Allows moving the sequence back, which hands out values again.
+    #[serde(default)]
+    force: bool,
+}
+
+// Increments and decrements default to 1; the body is optional.
+#[derive(Debug, Deserialize)]
+struct StepBody {
+    #[serde(default = "one")]
+    by: i64,
+}
+
+fn one() -> i64 {
+    1
+}
+
+fn step(body: &str) -> Result<i64, ApiError> {
+    if body.trim().is_empty() {
+        return Ok(1);
+    }
+    Ok(serde_json::from_str::<StepBody>(body)?.by)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn list_sequences(Db(db): Db) -> Result<Json<BTreeMap<String, u64>>, ApiError> {
+    Ok(Json(db.sequences()?))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn get_sequence(
+    Db(db): Db,
+    Path(CounterPath { name }): Path<CounterPath>,
+) -> Result<Json<Value>, ApiError> {
+    let value = db.sequence(&name)?;
+    Ok(Json(serde_json::json!({ "name": name, "value": value })))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn next_sequence(
+    Db(db): Db,
+    Path(CounterPath { name }): Path<CounterPath>,
+) -> Result<Json<Value>, ApiError> {
+    let value = db.next_sequence(&name)?;
+    Ok(Json(serde_json::json!({ "name": name, "value": value })))
+}
+
+// `value` is the last value handed out; the next one is `value + 1`.
+#[axum::debug_handler(state = ApiState)]
+async fn set_sequence(
+    Db(db): Db,
+    Path(CounterPath { name }): Path<CounterPath>,
+    Json(body): Json<SequenceBody>,
+) -> Result<StatusCode, ApiError> {
+    let last = u64::try_from(body.value)
+        .map_err(|_| DbError::InvalidUpdate("sequence values can't be negative".to_string()))?;
+    db.set_sequence(&name, last, body.force)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn drop_sequence(
+    Db(db): Db,
+    Path(CounterPath { name }): Path<CounterPath>,
+) -> Result<StatusCode, ApiError> {
+    db.drop_sequence(&name)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn list_counters(Db(db): Db) -> Result<Json<BTreeMap<String, i64>>, ApiError> {
+    Ok(Json(db.counters()?))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn get_counter(
+    Db(db): Db,
+    Path(CounterPath { name }): Path<CounterPath>,
+) -> Result<Json<Value>, ApiError> {
+    let value = db.counter(&name)?;
+    Ok(Json(serde_json::json!({ "name": name, "value": value })))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn increment_counter(
+    Db(db): Db,
+    Path(CounterPath { name }): Path<CounterPath>,
+    body: String,
+) -> Result<Json<Value>, ApiError> {
+    let value = db.increment_counter(&name, step(&body)?)?;
+    Ok(Json(serde_json::json!({ "name": name, "value": value })))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn decrement_counter(
+    Db(db): Db,
+    Path(CounterPath { name }): Path<CounterPath>,
+    body: String,
+) -> Result<Json<Value>, ApiError> {
+    let value = db.decrement_counter(&name, step(&body)?)?;
+    Ok(Json(serde_json::json!({ "name": name, "value": value })))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn set_counter(
+    Db(db): Db,
+    Path(CounterPath { name }): Path<CounterPath>,
+    Json(body): Json<ValueBody>,
+) -> Result<StatusCode, ApiError> {
+    db.set_counter(&name, body.value)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn delete_counter(
+    Db(db): Db,
+    Path(CounterPath { name }): Path<CounterPath>,
+) -> Result<StatusCode, ApiError> {
+    db.delete_counter(&name)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct TtlBody {
+    ttl: Option<i64>,
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn set_default_ttl(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    Json(body): Json<TtlBody>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.set_default_ttl(body.ttl)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn clear_default_ttl(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.set_default_ttl(None)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct TtlIndexBody {
+    expire_after: i64,
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn create_ttl_index(
+    Db(db): Db,
+    Path(FieldPath { name, field }): Path<FieldPath>,
+    Json(body): Json<TtlIndexBody>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?
+        .create_ttl_index(&field, body.expire_after)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn drop_ttl_index(
+    Db(db): Db,
+    Path(FieldPath { name, field }): Path<FieldPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.drop_ttl_index(&field)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Restarts a document's TTL from now; without a `ttl` the collection's
+/// default is used.
+#[axum::debug_handler(state = ApiState)]
+async fn refresh_ttl(
+    Db(db): Db,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+    Json(body): Json<TtlBody>,
+) -> Result<Response, ApiError> {
+    let doc = db.get_collection(&collection)?.refresh_ttl(&id, body.ttl)?;
+    Ok(with_etag(StatusCode::OK, doc))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn clear_ttl(
+    Db(db): Db,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+) -> Result<Response, ApiError> {
+    let doc = db.get_collection(&collection)?.clear_ttl(&id)?;
+    Ok(with_etag(StatusCode::OK, doc))
+}
+
+#[derive(Debug, Deserialize)]
+struct ExtendBody {
+    seconds: i64,
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn extend_ttl(
+    Db(db): Db,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+    Json(body): Json<ExtendBody>,
+) -> Result<Response, ApiError> {
+    let doc = db
+        .get_collection(&collection)?
+        .extend_ttl(&id, body.seconds)?;
+    Ok(with_etag(StatusCode::OK, doc))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn set_capped(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    Json(capped): Json<db::Capped>,
+) -> Result<Json<Value>, ApiError> {
+    let evicted = db.get_collection(&name)?.set_capped(Some(capped))?;
+    Ok(Json(serde_json::json!({ "evicted": evicted })))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn uncap(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.set_capped(None)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+struct TailParams {
+    after: Option<DateTime<Utc>>,
+    limit: Option<usize>,
+    /// Seconds to wait for new documents when there are none yet.
+    wait: Option<u64>,
+}
+
+const MAX_TAIL_WAIT_SECS: u64 = 30;
+
+/// Documents created after `after`, oldest first. With `wait`, blocks until
+/// at least one arrives or the time runs out.
+#[axum::debug_handler(state = ApiState)]
+async fn tail_documents(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    Query(params): Query<TailParams>,
 ) -> Result<Json<Vec<Document>>, ApiError> {
-    let col = state.db.collection(&collection)?;
-    let docs = col.find_all()?;
-    Ok(Json(docs))
+    let col = db.get_collection(&name)?;
+    let wait = std::time::Duration::from_secs(params.wait.unwrap_or(0).min(MAX_TAIL_WAIT_SECS));
+    let deadline = tokio::time::Instant::now() + wait;
+    loop {
+        let docs = col.tail(params.after, params.limit)?;
+        if !docs.is_empty() || tokio::time::Instant::now() >= deadline {
+            return Ok(Json(docs));
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn set_soft_delete(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+    Json(soft_delete): Json<db::SoftDelete>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?
+        .set_soft_delete(Some(soft_delete))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn disable_soft_delete(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.set_soft_delete(None)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn list_trash(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<Json<Vec<db::TrashedDocument>>, ApiError> {
+    let trashed = db.get_collection(&name)?.trashed()?;
+    Ok(Json(trashed))
 }
 
-#[axum::debug_handler]
+#[axum::debug_handler(state = ApiState)]
+async fn empty_trash(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<Json<Value>, ApiError> {
+    let purged = db.get_collection(&name)?.empty_trash()?;
+    Ok(Json(serde_json::json!({ "purged": purged })))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn purge_document(
+    Db(db): Db,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&collection)?.purge(&id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn undelete_document(
+    Db(db): Db,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+) -> Result<Response, ApiError> {
+    let col = db.get_collection(&collection)?.with_author(&user.username);
+    let doc = col.undelete(&id)?;
+    Ok(with_etag(StatusCode::OK, doc))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn list_trashed_collections(
+    Db(db): Db,
+) -> Result<Json<Vec<db::TrashedCollection>>, ApiError> {
+    Ok(Json(db.trashed_collections()?))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn purge_collection(
+    Db(db): Db,
+    Path(TrashPath { id }): Path<TrashPath>,
+) -> Result<StatusCode, ApiError> {
+    db.purge_collection(&id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn restore_collection(
+    Db(db): Db,
+    Path(TrashPath { id }): Path<TrashPath>,
+) -> Result<Json<db::CollectionInfo>, ApiError> {
+    let name = db.restore_collection(&id)?;
+    Ok(Json(db.get_collection(&name)?.info()?))
+}
+
+#[derive(Debug, Deserialize)]
+struct BulkParams {
+    ordered: Option<bool>,
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn bulk_write(
+    Db(db): Db,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(CollectionPath { name: collection }): Path<CollectionPath>,
+    Query(params): Query<BulkParams>,
+    body: String,
+) -> Result<Json<db::BulkResult>, ApiError> {
+    let ops = db::BulkOp::parse_ndjson(&body)?;
+    let col = db.collection(&collection)?.with_author(&user.username);
+    let result = col.bulk_write(ops, params.ordered.unwrap_or(true))?;
+    Ok(Json(result))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn knn_search(
+    Db(db): Db,
+    Path(CollectionPath { name: collection }): Path<CollectionPath>,
+    Json(query): Json<db::KnnQuery>,
+) -> Result<Json<Vec<db::KnnHit>>, ApiError> {
+    let col = db.get_collection(&collection)?;
+    let hits = col.knn(&query)?;
+    Ok(Json(hits))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn aggregate(
+    Db(db): Db,
+    Path(CollectionPath { name: collection }): Path<CollectionPath>,
+    Json(pipeline): Json<Vec<Value>>,
+) -> Result<Json<Vec<Value>>, ApiError> {
+    let col = db.get_collection(&collection)?;
+    let results = col.aggregate(&pipeline)?;
+    Ok(Json(results))
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionRequest {
+    operations: Vec<db::TxOp>,
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn transaction(
+    Db(db): Db,
+    Extension(user): Extension<AuthenticatedUser>,
+    Json(request): Json<TransactionRequest>,
+) -> Result<Json<Vec<Option<Document>>>, ApiError> {
+    let results = db.apply_transaction(request.operations, Some(&user.username))?;
+    Ok(Json(results))
+}
+
+#[derive(Debug, Deserialize)]
+struct SqlRequest {
+    query: String,
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn sql(Db(db): Db, Json(request): Json<SqlRequest>) -> Result<Json<Vec<Value>>, ApiError> {
+    let rows = db.sql(&request.query)?;
+    Ok(Json(rows))
+}
+
+// An `If-Match` / `If-None-Match` header: `*` or a list of entity tags.
+// Tags are compared weakly, so `W/"3"` matches version 3.
+fn etag_matches(
+    headers: &HeaderMap,
+    name: header::HeaderName,
+    doc: Option<&Document>,
+) -> Option<bool> {
+    let value = headers.get(name)?.to_str().ok()?.trim();
+    let Some(doc) = doc else {
+        return Some(false);
+    };
+    if value == "*" {
+        return Some(true);
+    }
+    let etag = doc.etag();
+    Some(
+        value
+            .split(',')
+            .map(|tag| tag.trim().trim_start_matches("W/"))
+            .any(|tag| tag == etag),
+    )
+}
+
+// Evaluates the conditional headers of a write against the current document.
+// Returns the version the write must still see, so the check and the write
+// happen atomically.
+fn precondition(headers: &HeaderMap, current: Option<&Document>) -> Result<Option<u64>, ApiError> {
+    if etag_matches(headers, header::IF_MATCH, current) == Some(false) {
+        return Err(ApiError::PreconditionFailed);
+    }
+    if etag_matches(headers, header::IF_NONE_MATCH, current) == Some(true) {
+        return Err(ApiError::PreconditionFailed);
+    }
+    let conditional =
+        headers.contains_key(header::IF_MATCH) || headers.contains_key(header::IF_NONE_MATCH);
+    Ok(current.filter(|_| conditional).map(|doc| doc.version))
+}
+
+fn with_etag(status: StatusCode, doc: Document) -> Response {
+    (status, [(header::ETAG, doc.etag())], Json(doc)).into_response()
+}
+
+#[axum::debug_handler(state = ApiState)]
 async fn get_document(
-    State(state): State<ApiState>,
-    Path((collection, id)): Path<(String, String)>,
-) -> Result<Json<Document>, ApiError> {
-    let col = state.db.collection(&collection)?;
-    let doc = col.find(&id)?.ok_or(DbError::NotFound)?;
-    Ok(Json(doc))
+    Db(db): Db,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+    Query(params): Query<GetParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let col = db.get_collection(&collection)?;
+    let doc = match (params.as_of, params.include) {
+        (Some(at), _) => col.find_as_of(&id, at)?,
+        (None, Some(include)) => {
+            let lookups = db::Lookup::parse_include(&include)?;
+            col.find_with_lookup(&db, &id, &lookups)?
+        }
+        (None, None) => col.find(&id)?,
+    };
+    let doc = doc.ok_or(DbError::NotFound)?;
+    if etag_matches(&headers, header::IF_NONE_MATCH, Some(&doc)) == Some(true) {
+        return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, doc.etag())]).into_response());
+    }
+    Ok(with_etag(StatusCode::OK, doc))
 }
 
-#[axum::debug_handler]
+#[axum::debug_handler(state = ApiState)]
 async fn update_document(
-    State(state): State<ApiState>,
-    Path((collection, id)): Path<(String, String)>,
+    Db(db): Db,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+    headers: HeaderMap,
     Json(payload): Json<Value>,
-) -> Result<Json<Document>, ApiError> {
-    let col = state.db.collection(&collection)?;
-    let doc = col.update(&id, payload)?;
-    Ok(Json(doc))
+) -> Result<Response, ApiError> {
+    let col = db.collection(&collection)?.with_author(&user.username);
+    // `If-None-Match: *` creates only; checked where the insert happens
+    let create_only = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim() == "*");
+    if create_only && !headers.contains_key(header::IF_MATCH) {
+        let doc = col
+            .create_if_absent(&id, payload)?
+            .ok_or(ApiError::PreconditionFailed)?;
+        return Ok(with_etag(StatusCode::CREATED, doc));
+    }
+    let current = col.find(&id)?;
+    if let Some(expected) = precondition(&headers, current.as_ref())? {
+        let doc = col.update_if_version(&id, expected, payload)?;
+        return Ok(with_etag(StatusCode::OK, doc));
+    }
+    let (doc, created) = col.upsert(&db::Selector::Id(id), payload)?;
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    Ok(with_etag(status, doc))
 }
 
-#[axum::debug_handler]
+#[axum::debug_handler(state = ApiState)]
+async fn patch_document(
+    Db(db): Db,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+    headers: HeaderMap,
+    Json(body): Json<Value>,
+) -> Result<Response, ApiError> {
+    let col = db.collection(&collection)?.with_author(&user.username);
+    let expected = precondition(&headers, col.find(&id)?.as_ref())?;
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    // plain application/json carries update operators
+    let patch = match content_type.split(';').next().unwrap_or("").trim() {
+        "application/merge-patch+json" => db::Patch::Merge(body),
+        "application/json-patch+json" => db::Patch::Json(body),
+        _ => db::Patch::Operators(body),
+    };
+    let doc = col.patch(&id, &patch, expected)?;
+    Ok(with_etag(StatusCode::OK, doc))
+}
+
+#[axum::debug_handler(state = ApiState)]
 async fn delete_document(
-    State(state): State<ApiState>,
-    Path((collection, id)): Path<(String, String)>,
+    Db(db): Db,
+    Extension(user): Extension<AuthenticatedUser>,
+    Path(DocumentPath {
+        name: collection,
+        id,
+    }): Path<DocumentPath>,
+    headers: HeaderMap,
+) -> Result<StatusCode, ApiError> {
+    let col = db.collection(&collection)?.with_author(&user.username);
+    match precondition(&headers, col.find(&id)?.as_ref())? {
+        Some(expected) => col.delete_if_version(&id, expected)?,
+        None => col.delete(&id)?,
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn list_indexes(
+    Db(db): Db,
+    Path(CollectionPath { name }): Path<CollectionPath>,
+) -> Result<Json<Vec<db::IndexStats>>, ApiError> {
+    Ok(Json(db.get_collection(&name)?.stats()?.indexes))
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn create_index(
+    Db(db): Db,
+    Path(FieldPath { name, field }): Path<FieldPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.create_index(&field)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn drop_index(
+    Db(db): Db,
+    Path(FieldPath { name, field }): Path<FieldPath>,
 ) -> Result<StatusCode, ApiError> {
-    let col = state.db.collection(&collection)?;
-    col.delete(&id)?;
+    db.get_collection(&name)?.drop_index(&field)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn create_geo_index(
+    Db(db): Db,
+    Path(FieldPath { name, field }): Path<FieldPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.create_geo_index(&field)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn drop_geo_index(
+    Db(db): Db,
+    Path(FieldPath { name, field }): Path<FieldPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.drop_geo_index(&field)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// The body holds the index options, e.g. `{"dims": 384, "metric": "cosine"}`.
+#[axum::debug_handler(state = ApiState)]
+async fn create_vector_index(
+    Db(db): Db,
+    Path(FieldPath { name, field }): Path<FieldPath>,
+    Json(options): Json<db::vector::VectorIndexOptions>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?
+        .create_vector_index(&field, options)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[axum::debug_handler(state = ApiState)]
+async fn drop_vector_index(
+    Db(db): Db,
+    Path(FieldPath { name, field }): Path<FieldPath>,
+) -> Result<StatusCode, ApiError> {
+    db.get_collection(&name)?.drop_vector_index(&field)?;
     Ok(StatusCode::NO_CONTENT)
 }
 
@@ -497,3 +1757,830 @@ async fn delete_document(
 //     col.delete(&id)?;
 //     Ok(StatusCode::NO_CONTENT)
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::{Method, header};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use tower::ServiceExt;
+
+    // The server's router over a fresh database, with one user
+    // `admin:secret`.
+    fn test_router(dir: &std::path::Path) -> Router {
+        let users = HashMap::from([("admin".to_string(), bcrypt::hash("secret", 4).unwrap())]);
+        router(ApiState {
+            catalog: Catalog::open(dir).unwrap(),
+            auth_config: AuthConfig { users },
+        })
+    }
+
+    // A request as `admin`; tests add their own headers.
+    fn authorized(method: Method, uri: &str) -> axum::http::request::Builder {
+        let credentials = base64::engine::general_purpose::STANDARD.encode("admin:secret");
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Basic {}", credentials))
+    }
+
+    async fn call(
+        app: &Router,
+        request: axum::http::request::Builder,
+        body: Option<Value>,
+    ) -> (StatusCode, HeaderMap, Value) {
+        let request = request
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let (status, headers) = (response.status(), response.headers().clone());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+        (status, headers, body)
+    }
+
+    async fn send(
+        app: &Router,
+        method: Method,
+        uri: &str,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let request = authorized(method, uri).header(header::CONTENT_TYPE, "application/json");
+        let (status, _, body) = call(app, request, body).await;
+        (status, body)
+    }
+
+    // Percent-encodes `value` for a query string.
+    fn encode(value: &Value) -> String {
+        value
+            .to_string()
+            .bytes()
+            .map(|b| match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => (b as char).to_string(),
+                _ => format!("%{:02X}", b),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn geo_indexes_are_managed_and_queried_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        for (name, lng) in [("near", 0.001), ("far", 20.0)] {
+            let doc = json!({"name": name, "loc": [lng, 0.0]});
+            let (status, _) = send(
+                &app,
+                Method::POST,
+                "/collections/places/documents",
+                Some(doc),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let index = "/collections/places/geo-indexes/loc";
+        assert_eq!(
+            send(&app, Method::PUT, index, None).await.0,
+            StatusCode::NO_CONTENT
+        );
+
+        let filter = json!({"loc": {"$near": [0, 0], "$maxDistance": 1000}});
+        let uri = format!("/collections/places/documents?filter={}", encode(&filter));
+        let (status, found) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(found.as_array().unwrap().len(), 1);
+        assert_eq!(found[0]["data"]["name"], "near");
+
+        assert_eq!(
+            send(&app, Method::DELETE, index, None).await.0,
+            StatusCode::NO_CONTENT
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn vector_indexes_and_knn_are_served_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        for (name, x) in [("a", 1.0), ("b", 0.0)] {
+            let doc = json!({"name": name, "emb": [x, 1.0 - x]});
+            let (status, _) = send(
+                &app,
+                Method::POST,
+                "/collections/items/documents",
+                Some(doc),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let index = "/collections/items/vector-indexes/emb";
+        let options = json!({"dims": 2, "kind": "hnsw"});
+        assert_eq!(
+            send(&app, Method::PUT, index, Some(options)).await.0,
+            StatusCode::NO_CONTENT
+        );
+
+        let query = json!({"field": "emb", "vector": [0.9, 0.1], "k": 1});
+        let (status, hits) = send(&app, Method::POST, "/collections/items/knn", Some(query)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(hits.as_array().unwrap().len(), 1);
+        assert_eq!(hits[0]["document"]["data"]["name"], "a");
+
+        assert_eq!(
+            send(&app, Method::DELETE, index, None).await.0,
+            StatusCode::NO_CONTENT
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn value_indexes_are_listed_and_chosen_by_explain() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        for age in 0..20 {
+            let doc = json!({"age": age});
+            let (status, _) = send(
+                &app,
+                Method::POST,
+                "/collections/people/documents",
+                Some(doc),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let index = "/collections/people/indexes/age";
+        assert_eq!(
+            send(&app, Method::PUT, index, None).await.0,
+            StatusCode::NO_CONTENT
+        );
+        let (status, indexes) = send(&app, Method::GET, "/collections/people/indexes", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(indexes[0]["field"], "age");
+        assert_eq!(indexes[0]["entries"], 20);
+
+        let filter = json!({"age": 7});
+        let uri = format!(
+            "/collections/people/documents?explain=true&filter={}",
+            encode(&filter)
+        );
+        let (status, explain) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(explain["index"], "age");
+        assert_eq!(explain["documents_returned"], 1);
+
+        assert_eq!(
+            send(&app, Method::DELETE, index, None).await.0,
+            StatusCode::NO_CONTENT
+        );
+        let (_, indexes) = send(&app, Method::GET, "/collections/people/indexes", None).await;
+        assert_eq!(indexes, json!([]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn aggregation_pipelines_run_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        for (team, score) in [("red", 1), ("red", 2), ("blue", 5)] {
+            let doc = json!({"team": team, "score": score});
+            let (status, _) = send(
+                &app,
+                Method::POST,
+                "/collections/games/documents",
+                Some(doc),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let pipeline = json!([
+            {"$group": {"_id": "$team", "total": {"$sum": "$score"}}},
+            {"$sort": {"_id": 1}},
+        ]);
+        let uri = "/collections/games/aggregate";
+        let (status, groups) = send(&app, Method::POST, uri, Some(pipeline)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            groups,
+            json!([{"_id": "blue", "total": 5}, {"_id": "red", "total": 3}])
+        );
+
+        let (status, _) = send(&app, Method::POST, uri, Some(json!([{"$nope": {}}]))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn includes_embed_related_documents() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let customer = json!({"name": "ada"});
+        let (_, customer) = send(
+            &app,
+            Method::POST,
+            "/collections/customers/documents",
+            Some(customer),
+        )
+        .await;
+        let order = json!({"customer_id": customer["id"]});
+        let (_, order) = send(
+            &app,
+            Method::POST,
+            "/collections/orders/documents",
+            Some(order),
+        )
+        .await;
+
+        let uri = format!(
+            "/collections/orders/documents/{}?include=customer_id:customers",
+            order["id"].as_str().unwrap()
+        );
+        let (status, found) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(found["data"]["customers"][0]["data"]["name"], "ada");
+
+        let uri = "/collections/orders/documents?include=customer_id:customers";
+        let (status, found) = send(&app, Method::GET, uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(found[0]["data"]["customers"][0]["id"], customer["id"]);
+
+        let uri = "/collections/orders/documents?include=customers";
+        assert_eq!(
+            send(&app, Method::GET, uri, None).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sql_queries_run_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        for (name, age) in [("ada", 36), ("bob", 17)] {
+            let doc = json!({"name": name, "age": age});
+            let (status, _) = send(
+                &app,
+                Method::POST,
+                "/collections/people/documents",
+                Some(doc),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        let query = json!({"query": "SELECT name FROM people WHERE age >= 18"});
+        let (status, rows) = send(&app, Method::POST, "/sql", Some(query)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(rows, json!([{"name": "ada"}]));
+
+        let query = json!({"query": "SELECT FROM"});
+        assert_eq!(
+            send(&app, Method::POST, "/sql", Some(query)).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn patch_applies_update_operators() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let doc = json!({"n": 1, "tags": []});
+        let (_, doc) = send(&app, Method::POST, "/collections/c/documents", Some(doc)).await;
+        let uri = format!("/collections/c/documents/{}", doc["id"].as_str().unwrap());
+
+        let ops = json!({"$inc": {"n": 2}, "$push": {"tags": "x"}});
+        let (status, doc) = send(&app, Method::PATCH, &uri, Some(ops)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(doc["data"], json!({"n": 3, "tags": ["x"]}));
+
+        let ops = json!({"$inc": {"tags": 1}});
+        assert_eq!(
+            send(&app, Method::PATCH, &uri, Some(ops)).await.0,
+            StatusCode::BAD_REQUEST
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn patch_picks_the_patch_format_from_the_content_type() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let doc = json!({"a": {"b": 1, "c": 2}});
+        let (_, doc) = send(&app, Method::POST, "/collections/c/documents", Some(doc)).await;
+        let uri = format!("/collections/c/documents/{}", doc["id"].as_str().unwrap());
+        let patch = |content_type: &str| {
+            authorized(Method::PATCH, &uri).header(header::CONTENT_TYPE, content_type.to_string())
+        };
+
+        let merge = json!({"a": {"b": null, "d": 3}});
+        let (status, _, doc) = call(&app, patch("application/merge-patch+json"), Some(merge)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(doc["data"], json!({"a": {"c": 2, "d": 3}}));
+
+        let ops = json!([{"op": "move", "from": "/a/c", "path": "/c"}]);
+        let (status, _, doc) = call(&app, patch("application/json-patch+json"), Some(ops)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(doc["data"], json!({"a": {"d": 3}, "c": 2}));
+
+        let failing = json!([{"op": "test", "path": "/c", "value": 3}]);
+        let (status, _, _) = call(&app, patch("application/json-patch+json"), Some(failing)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn put_creates_missing_documents_and_replaces_existing_ones() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let uri = "/collections/c/documents/a";
+        let (status, doc) = send(&app, Method::PUT, uri, Some(json!({"n": 1}))).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(doc["id"], "a");
+        let (status, doc) = send(&app, Method::PUT, uri, Some(json!({"n": 2}))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(doc["data"], json!({"n": 2}));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn bulk_writes_take_ndjson() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let body =
+            "{\"op\": \"insert\", \"data\": {\"n\": 1}}\n{\"op\": \"delete\", \"id\": \"x\"}\n";
+        let request = authorized(Method::POST, "/collections/c/bulk?ordered=false");
+        let request = request.body(Body::from(body)).unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let result: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            (result["inserted"].clone(), result["failed"].clone()),
+            (json!(1), json!(1))
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn transactions_apply_every_operation_or_none() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let ops = json!({"operations": [
+            {"collection": "a", "op": "upsert", "id": "x", "data": {"n": 1}},
+            {"collection": "b", "op": "insert", "data": {"n": 2}},
+        ]});
+        let (status, results) = send(&app, Method::POST, "/transactions", Some(ops)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(results.as_array().unwrap().len(), 2);
+        let ops = json!({"operations": [
+            {"collection": "a", "op": "delete", "id": "x"},
+            {"collection": "a", "op": "delete", "id": "missing"},
+        ]});
+        let (status, _) = send(&app, Method::POST, "/transactions", Some(ops)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = send(&app, Method::GET, "/collections/a/documents/x", None).await;
+        assert_eq!(status, StatusCode::OK);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn conditional_writes_check_the_etag() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let uri = "/collections/c/documents/a";
+        let json = |method: Method, tag: &str, name| {
+            authorized(method, uri)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(name, tag.to_string())
+        };
+        send(&app, Method::PUT, uri, Some(json!({"n": 1}))).await;
+
+        let stale = json(Method::PUT, "\"2\"", header::IF_MATCH);
+        let (status, _, _) = call(&app, stale, Some(json!({"n": 2}))).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        let fresh = json(Method::PUT, "W/\"1\"", header::IF_MATCH);
+        let (status, headers, doc) = call(&app, fresh, Some(json!({"n": 2}))).await;
+        assert_eq!((status, doc["version"].clone()), (StatusCode::OK, json!(2)));
+        assert_eq!(headers[header::ETAG], "\"2\"");
+
+        let cached = authorized(Method::GET, uri).header(header::IF_NONE_MATCH, "\"2\"");
+        let (status, _, _) = call(&app, cached, None).await;
+        assert_eq!(status, StatusCode::NOT_MODIFIED);
+        let exists = json(Method::PUT, "*", header::IF_NONE_MATCH);
+        let (status, _, _) = call(&app, exists, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn id_strategies_pick_the_ids_of_inserts() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let strategy = json!({"type": "auto_increment"});
+        let uri = "/collections/c/id-strategy";
+        let (status, body) = send(&app, Method::PUT, uri, Some(strategy.clone())).await;
+        assert_eq!((status, body), (StatusCode::OK, strategy.clone()));
+        assert_eq!(send(&app, Method::GET, uri, None).await.1, strategy);
+
+        let uri = "/collections/c/documents";
+        let (_, doc) = send(&app, Method::POST, uri, Some(json!({}))).await;
+        assert_eq!(doc["id"], "00000000000000000001");
+        let uri = "/collections/c/documents?id=mine";
+        let (status, doc) = send(&app, Method::POST, uri, Some(json!({}))).await;
+        assert_eq!((status, doc["id"].clone()), (StatusCode::OK, json!("mine")));
+        let (status, _) = send(&app, Method::POST, uri, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn schemas_are_managed_and_enforced_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let docs = "/collections/c/documents";
+        let (status, _) = send(&app, Method::POST, docs, Some(json!({"n": "x"}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let schema = json!({"type": "object", "properties": {"n": {"type": "integer"}}});
+        let uri = "/collections/c/schema";
+        let (status, violations) = send(
+            &app,
+            Method::POST,
+            "/collections/c/schema/validate",
+            Some(schema.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(violations.as_array().unwrap().len(), 1);
+        let (status, _) = send(&app, Method::PUT, uri, Some(schema.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, stored) = send(&app, Method::GET, uri, None).await;
+        assert_eq!((status, stored), (StatusCode::OK, schema));
+        let (status, _) = send(&app, Method::POST, docs, Some(json!({"n": "y"}))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (status, _) = send(&app, Method::PUT, uri, Some(json!({"pattern": "^a"}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&app, Method::DELETE, uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, Method::GET, uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn collections_are_created_with_options_and_described() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let options =
+            json!({"id_strategy": {"type": "auto_increment"}, "limits": {"max_documents": 1}});
+        let (status, _) = send(&app, Method::POST, "/collections/c", Some(options)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send(&app, Method::POST, "/collections/c", None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let docs = "/collections/c/documents";
+        let (status, doc) = send(&app, Method::POST, docs, Some(json!({}))).await;
+        assert_eq!(
+            (status, &doc["id"]),
+            (StatusCode::OK, &json!("00000000000000000001"))
+        );
+        let (status, _) = send(&app, Method::POST, docs, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, info) = send(&app, Method::GET, "/collections/c", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            (&info["documents"], &info["limits"]["max_documents"]),
+            (&json!(1), &json!(1))
+        );
+        // reads of a missing collection do not create it
+        for uri in [
+            "/collections/missing/documents",
+            "/collections/missing/documents/x",
+        ] {
+            let (status, _) = send(&app, Method::GET, uri, None).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            "/collections/missing/aggregate",
+            Some(json!([])),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, infos) = send(&app, Method::GET, "/collections", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(infos.as_array().unwrap().len(), 1);
+        assert_eq!(infos[0]["name"], "c");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn revisions_are_listed_diffed_and_restored_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let (status, _) = send(&app, Method::POST, "/collections/c", None).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send(&app, Method::PUT, "/collections/c/history", Some(json!({}))).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let uri = "/collections/c/documents/a";
+        send(&app, Method::PUT, uri, Some(json!({"n": 1}))).await;
+        send(&app, Method::PUT, uri, Some(json!({"n": 2}))).await;
+
+        let (status, revisions) = send(&app, Method::GET, &format!("{uri}/revisions"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(revisions.as_array().unwrap().len(), 2);
+        assert_eq!(revisions[1]["author"], "admin");
+        // transactions attribute their revisions to the caller too
+        let ops = json!({"operations": [
+            {"collection": "c", "op": "update", "id": "a", "data": {"n": 3}},
+        ]});
+        let (status, _) = send(&app, Method::POST, "/transactions", Some(ops)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (_, revisions) = send(&app, Method::GET, &format!("{uri}/revisions"), None).await;
+        assert_eq!(revisions[2]["author"], "admin");
+        let (status, revision) = send(&app, Method::GET, &format!("{uri}/revisions/1"), None).await;
+        assert_eq!(
+            (status, &revision["document"]["data"]),
+            (StatusCode::OK, &json!({"n": 1}))
+        );
+        let (status, ops) = send(&app, Method::GET, &format!("{uri}/diff?from=1&to=2"), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(ops, json!([{"op": "replace", "path": "/n", "value": 2}]));
+        let (status, doc) = send(
+            &app,
+            Method::POST,
+            &format!("{uri}/revisions/1/restore"),
+            None,
+        )
+        .await;
+        assert_eq!((status, &doc["data"]), (StatusCode::OK, &json!({"n": 1})));
+
+        let (status, _) = send(&app, Method::DELETE, "/collections/c/history", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, Method::GET, &format!("{uri}/revisions"), None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn trashed_documents_and_collections_come_back_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        send(&app, Method::POST, "/collections/c", None).await;
+        let (status, _) = send(
+            &app,
+            Method::PUT,
+            "/collections/c/soft-delete",
+            Some(json!({})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        for id in ["a", "b", "c"] {
+            let uri = format!("/collections/c/documents/{id}");
+            send(&app, Method::PUT, &uri, Some(json!({}))).await;
+            send(&app, Method::DELETE, &uri, None).await;
+        }
+        let (status, trashed) = send(&app, Method::GET, "/collections/c/trash", None).await;
+        assert_eq!(
+            (status, trashed.as_array().unwrap().len()),
+            (StatusCode::OK, 3)
+        );
+        let (status, doc) = send(&app, Method::POST, "/collections/c/trash/a/restore", None).await;
+        assert_eq!((status, &doc["id"]), (StatusCode::OK, &json!("a")));
+        let (status, _) = send(&app, Method::DELETE, "/collections/c/trash/b", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, purged) = send(&app, Method::DELETE, "/collections/c/trash", None).await;
+        assert_eq!((status, purged), (StatusCode::OK, json!({"purged": 1})));
+
+        send(&app, Method::DELETE, "/collections/c", None).await;
+        let (status, dropped) = send(&app, Method::GET, "/trash/collections", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let id = dropped[0]["id"].as_str().unwrap().to_string();
+        let uri = format!("/trash/collections/{id}/restore");
+        let (status, _) = send(&app, Method::POST, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, Method::GET, "/collections/c/documents/a", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, Method::DELETE, "/collections/c/soft-delete", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let uri = format!("/trash/collections/{id}");
+        let (status, _) = send(&app, Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn capped_collections_are_tailed_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        send(&app, Method::POST, "/collections/log", None).await;
+        let capped = json!({"max_documents": 2});
+        let (status, _) = send(&app, Method::PUT, "/collections/log/capped", Some(capped)).await;
+        assert!(status.is_success(), "{status}");
+        for n in 0..3 {
+            let doc = json!({"n": n});
+            send(&app, Method::POST, "/collections/log/documents", Some(doc)).await;
+        }
+        let (status, docs) = send(&app, Method::GET, "/collections/log/tail", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let data: Vec<&Value> = docs
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| &d["data"])
+            .collect();
+        assert_eq!(data, [&json!({"n": 1}), &json!({"n": 2})]);
+        let (status, docs) = send(&app, Method::GET, "/collections/log/tail?limit=1", None).await;
+        assert_eq!(
+            (status, docs.as_array().unwrap().len()),
+            (StatusCode::OK, 1)
+        );
+        let (status, _) = send(&app, Method::DELETE, "/collections/log/capped", None).await;
+        assert!(status.is_success(), "{status}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn ttls_are_set_on_insert_and_updated_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        send(&app, Method::POST, "/collections/c", None).await;
+        let docs = "/collections/c/documents";
+        let (status, doc) = send(&app, Method::POST, docs, Some(json!({"_ttl": 60}))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(doc["data"], json!({}));
+        assert!(doc["expires_at"].is_string());
+        let request = authorized(Method::POST, docs)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(TTL_HEADER, "nope");
+        let (status, _, _) = call(&app, request, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let uri = format!("{docs}/{}/ttl", doc["id"].as_str().unwrap());
+        let (status, cleared) = send(&app, Method::DELETE, &uri, None).await;
+        assert_eq!(
+            (status, &cleared["expires_at"]),
+            (StatusCode::OK, &Value::Null)
+        );
+        let (status, _) = send(
+            &app,
+            Method::POST,
+            &format!("{uri}/extend"),
+            Some(json!({"seconds": 5})),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&app, Method::PUT, &uri, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let body = Some(json!({"ttl": 30}));
+        let (status, _) = send(&app, Method::PUT, "/collections/c/default-ttl", body).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, refreshed) = send(&app, Method::PUT, &uri, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(refreshed["expires_at"].is_string());
+        let (status, _) = send(&app, Method::DELETE, "/collections/c/default-ttl", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let uri = "/collections/c/ttl-indexes/at";
+        let (status, _) = send(&app, Method::PUT, uri, Some(json!({"expire_after": 60}))).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, Method::DELETE, uri, None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, Method::DELETE, uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn expired_documents_are_hidden_and_swept_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        send(&app, Method::POST, "/collections/c", None).await;
+        let old = (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+        let body = Some(json!({"at": old}));
+        let (_, doc) = send(&app, Method::POST, "/collections/c/documents", body).await;
+        let body = Some(json!({"expire_after": 60}));
+        send(&app, Method::PUT, "/collections/c/ttl-indexes/at", body).await;
+
+        let uri = format!("/collections/c/documents/{}", doc["id"].as_str().unwrap());
+        let (status, _) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, stats) = send(&app, Method::GET, "/ttl", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(stats["collections"][0]["overdue"], 1);
+        let (status, swept) = send(&app, Method::POST, "/ttl/sweep", None).await;
+        assert_eq!((status, swept), (StatusCode::OK, json!({"removed": 1})));
+        let (_, stats) = send(&app, Method::GET, "/ttl", None).await;
+        assert_eq!(
+            (&stats["sweeps"], &stats["expired"]),
+            (&json!(1), &json!(1))
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn named_databases_have_their_own_users_and_quotas() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let quota = Some(json!({"max_documents": 1}));
+        let (status, _) = send(&app, Method::POST, "/databases/shop", quota).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send(&app, Method::POST, "/databases/shop", None).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let body = Some(json!({"password": "pw"}));
+        let (status, _) = send(&app, Method::PUT, "/databases/shop/users/clerk", body).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let docs = "/db/shop/collections/c/documents";
+        let (status, _) = send(&app, Method::POST, docs, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&app, Method::POST, docs, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::INSUFFICIENT_STORAGE);
+        let (_, info) = send(&app, Method::GET, "/databases/shop", None).await;
+        assert_eq!(info["usage"]["documents"], 1);
+        assert_eq!(info["users"], json!(["clerk"]));
+
+        let clerk = base64::engine::general_purpose::STANDARD.encode("clerk:pw");
+        let as_clerk = |method: Method, uri: &str| {
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(header::AUTHORIZATION, format!("Basic {}", clerk))
+                .header(header::CONTENT_TYPE, "application/json")
+        };
+        let (status, _, found) = call(&app, as_clerk(Method::GET, docs), None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(found.as_array().unwrap().len(), 1);
+        let request = as_clerk(Method::PUT, "/databases/shop/quota");
+        let (status, _, _) = call(&app, request, Some(json!({}))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _, _) = call(&app, as_clerk(Method::GET, "/collections"), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, _) = send(&app, Method::DELETE, "/databases/shop", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, Method::GET, "/databases/shop", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn collections_are_renamed_cloned_and_truncated_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        for n in 0..3 {
+            let body = Some(json!({"n": n}));
+            send(&app, Method::POST, "/collections/a/documents", body).await;
+        }
+        let body = Some(json!({"to": "b", "filter": {"n": {"$lt": 2}}}));
+        let (status, copied) = send(&app, Method::POST, "/collections/a/clone", body).await;
+        assert_eq!(
+            (status, copied),
+            (StatusCode::CREATED, json!({"copied": 2}))
+        );
+
+        let body = Some(json!({"to": "b"}));
+        let (status, _) = send(&app, Method::POST, "/collections/a/rename", body).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let body = Some(json!({"to": "c"}));
+        let (status, info) = send(&app, Method::POST, "/collections/a/rename", body).await;
+        assert_eq!((status, &info["name"]), (StatusCode::OK, &json!("c")));
+
+        let (status, removed) = send(&app, Method::POST, "/collections/c/truncate", None).await;
+        assert_eq!((status, removed), (StatusCode::OK, json!({"removed": 3})));
+        let (status, _) = send(&app, Method::POST, "/collections/a/truncate", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn sequences_and_counters_are_served_over_rest() {
+        let dir = crate::db::test_dir("api");
+        let app = test_router(&dir);
+        let body = Some(json!({"value": 999}));
+        let (status, _) = send(&app, Method::PUT, "/sequences/invoice", body).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, next) = send(&app, Method::POST, "/sequences/invoice/next", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(next, json!({"name": "invoice", "value": 1000}));
+        let (_, all) = send(&app, Method::GET, "/sequences", None).await;
+        assert_eq!(all, json!({"invoice": 1000}));
+        let body = Some(json!({"value": -1}));
+        let (status, _) = send(&app, Method::PUT, "/sequences/invoice", body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let uri = "/counters/hits/increment";
+        let (_, hit) = send(&app, Method::POST, uri, None).await;
+        assert_eq!(hit["value"], 1);
+        let body = Some(json!({"by": 5}));
+        let (_, hit) = send(&app, Method::POST, "/counters/hits/decrement", body).await;
+        assert_eq!(hit["value"], -4);
+        let (status, _) = send(&app, Method::DELETE, "/counters/hits", None).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = send(&app, Method::DELETE, "/counters/hits", None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
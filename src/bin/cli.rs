@@ -1,14 +1,20 @@
+use chrono::{DateTime, Utc};
 use clap::{Parser, Subcommand};
-use darkdb::db::{Database, DbError};
+use darkdb::db::{
+    BulkOp, Capped, Catalog, CollectionOptions, Database, DbError, Filter, HistoryRetention,
+    IdStrategy, Limits, Query, Quota, Schema, SoftDelete,
+};
 // use serde_json::{Value, json};
 use serde_json::Value;
-use tracing_subscriber;
 
 #[derive(Parser)]
 #[command(name = "cli")]
 #[command(about = "CLI for DarkDB", long_about = None)]
 #[command(version)]
 struct Cli {
+    /// Named database to use instead of the default one
+    #[arg(long, global = true)]
+    db: Option<String>,
     #[command(subcommand)]
     command: Commands,
 }
@@ -16,28 +22,326 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Create a new collection
-    Create { name: String },
+    Create {
+        name: String,
+        #[arg(long, default_value = "uuid")]
+        id_strategy: IdStrategy,
+        /// JSON Schema that documents must match
+        #[arg(long)]
+        schema: Option<String>,
+        /// Seconds until documents inserted without a TTL expire
+        #[arg(long)]
+        default_ttl: Option<i64>,
+        #[arg(long)]
+        max_documents: Option<usize>,
+        #[arg(long)]
+        max_document_bytes: Option<usize>,
+        /// Keep only the newest N documents
+        #[arg(long)]
+        capped_documents: Option<usize>,
+        /// Keep only the newest documents totalling N bytes
+        #[arg(long)]
+        capped_bytes: Option<usize>,
+    },
+    /// Show a collection's metadata, or of every collection
+    Describe { name: Option<String> },
     /// Insert a document
     Insert {
         collection: String,
         json: String,
         #[arg(short, long)]
         ttl: Option<i64>,
+        /// Use this id instead of generating one
+        #[arg(long)]
+        id: Option<String>,
+    },
+    /// Set how a collection generates document ids:
+    /// uuid, uuidv7, ulid, auto-increment or field:<path>
+    IdStrategy {
+        collection: String,
+        strategy: IdStrategy,
+    },
+    /// Show, set or remove a collection's JSON Schema
+    Schema {
+        collection: String,
+        schema: Option<String>,
+        /// Only report the documents that don't match, without enabling the schema
+        #[arg(long)]
+        check: bool,
+        /// Enable the schema even if existing documents don't match it
+        #[arg(long)]
+        force: bool,
+        /// Remove the schema
+        #[arg(long, conflicts_with_all = ["schema", "check", "force"])]
+        clear: bool,
     },
     /// Find a document
-    Find { collection: String, id: String },
+    Find {
+        collection: String,
+        id: String,
+        /// Read the document as it was at this time (RFC 3339)
+        #[arg(long)]
+        as_of: Option<DateTime<Utc>>,
+    },
     /// List all documents in a collection
-    List { collection: String },
+    List {
+        collection: String,
+        #[arg(long)]
+        as_of: Option<DateTime<Utc>>,
+    },
+    /// Keep prior revisions of documents, or stop with --disable
+    History {
+        collection: String,
+        #[arg(long)]
+        max_revisions: Option<usize>,
+        /// Seconds a superseded revision is kept
+        #[arg(long)]
+        max_age: Option<i64>,
+        #[arg(long, conflicts_with_all = ["max_revisions", "max_age"])]
+        disable: bool,
+    },
+    /// List the revisions of a document
+    Revisions { collection: String, id: String },
+    /// Print the JSON Patch between two revisions of a document
+    Diff {
+        collection: String,
+        id: String,
+        from: u64,
+        to: u64,
+    },
+    /// Write an old revision of a document back
+    Restore {
+        collection: String,
+        id: String,
+        version: u64,
+    },
+    /// Find documents matching a filter
+    Query {
+        collection: String,
+        #[arg(default_value = "{}")]
+        filter: String,
+        #[arg(long)]
+        skip: Option<usize>,
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Print the query plan and execution statistics instead of documents
+        #[arg(long)]
+        explain: bool,
+    },
+    /// Apply NDJSON bulk operations from a file, or stdin when omitted
+    Bulk {
+        collection: String,
+        file: Option<std::path::PathBuf>,
+        /// Each line is a document to insert rather than an operation
+        #[arg(long)]
+        documents: bool,
+        /// Keep going after a failed operation
+        #[arg(long)]
+        unordered: bool,
+    },
+    /// Run a SQL SELECT statement
+    Sql { query: String },
     /// Update a document
     Update {
         collection: String,
         id: String,
         json: String,
     },
+    /// Apply update operators to a document, e.g. '{"$inc": {"visits": 1}}'
+    Patch {
+        collection: String,
+        id: String,
+        ops: String,
+        /// Treat the argument as an RFC 7386 JSON Merge Patch
+        #[arg(long, conflicts_with = "json_patch")]
+        merge: bool,
+        /// Treat the argument as an RFC 6902 JSON Patch
+        #[arg(long)]
+        json_patch: bool,
+    },
     /// Delete a document
     Delete { collection: String, id: String },
     /// Drop a collection
     Drop { name: String },
+    /// Index a field for equality and range queries, or drop the index with --drop
+    Index {
+        collection: String,
+        field: String,
+        #[arg(long)]
+        drop: bool,
+    },
+    /// Index a field of GeoJSON or [lng, lat] points, or drop the index with --drop
+    GeoIndex {
+        collection: String,
+        field: String,
+        #[arg(long)]
+        drop: bool,
+    },
+    /// Index a vector field for k-NN search, or drop the index with --drop
+    VectorIndex {
+        collection: String,
+        field: String,
+        /// Index options as JSON, e.g. '{"dims": 384, "kind": "hnsw"}'
+        #[arg(long, required_unless_present = "drop", conflicts_with = "drop")]
+        options: Option<String>,
+        #[arg(long)]
+        drop: bool,
+    },
+    /// Set the TTL of documents inserted without one, or remove it with --clear
+    DefaultTtl {
+        collection: String,
+        #[arg(required_unless_present = "clear")]
+        seconds: Option<i64>,
+        #[arg(long, conflicts_with = "seconds")]
+        clear: bool,
+    },
+    /// Expire documents some seconds after a date field, or stop with --drop
+    TtlIndex {
+        collection: String,
+        field: String,
+        #[arg(long, required_unless_present = "drop")]
+        expire_after: Option<i64>,
+        #[arg(long, conflicts_with = "expire_after")]
+        drop: bool,
+    },
+    /// Remove expired documents now and show what is left to expire
+    Expire,
+    /// Change when a document expires
+    Ttl {
+        collection: String,
+        id: String,
+        /// Push the expiry back by this many seconds
+        #[arg(long, conflicts_with_all = ["refresh", "clear"])]
+        extend: Option<i64>,
+        /// Expire this many seconds from now, or the collection's default TTL
+        #[arg(long, num_args = 0..=1, conflicts_with = "clear")]
+        refresh: Option<Option<i64>>,
+        /// Never expire
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Keep only the newest documents of a collection, or uncap it with --disable
+    Cap {
+        collection: String,
+        #[arg(long)]
+        max_documents: Option<usize>,
+        #[arg(long)]
+        max_bytes: Option<usize>,
+        #[arg(long, conflicts_with_all = ["max_documents", "max_bytes"])]
+        disable: bool,
+    },
+    /// Print documents created after a position, oldest first
+    Tail {
+        collection: String,
+        /// `created_at` of the last document already seen
+        #[arg(long)]
+        after: Option<DateTime<Utc>>,
+        #[arg(long)]
+        limit: Option<usize>,
+        /// Keep printing new documents as they are inserted
+        #[arg(short, long)]
+        follow: bool,
+    },
+    /// Send deletes and drops to the trash, or delete for good with --disable
+    SoftDelete {
+        collection: String,
+        /// Seconds trashed items are kept before being purged
+        #[arg(long)]
+        retention: Option<i64>,
+        #[arg(long, conflicts_with = "retention")]
+        disable: bool,
+    },
+    /// List deleted documents of a collection, or dropped collections when omitted
+    Trash { collection: Option<String> },
+    /// Move a deleted document back out of the trash
+    Undelete { collection: String, id: String },
+    /// Purge a deleted document for good, or the whole trash with --all
+    Purge {
+        collection: String,
+        #[arg(required_unless_present = "all")]
+        id: Option<String>,
+        #[arg(long, conflicts_with = "id")]
+        all: bool,
+    },
+    /// Bring back a dropped collection by its trash id
+    RestoreCollection { id: String },
+    /// Delete a dropped collection for good
+    PurgeCollection { id: String },
+    /// Print the next value of a sequence, or change it with --set / --drop
+    Seq {
+        name: String,
+        /// Continue the sequence after this value
+        #[arg(long, conflicts_with = "drop")]
+        set: Option<u64>,
+        /// Let --set move the sequence back, handing out values again
+        #[arg(long, requires = "set")]
+        force: bool,
+        #[arg(long)]
+        drop: bool,
+    },
+    /// Print a counter, or change it with --inc / --dec / --set / --delete
+    Counter {
+        name: String,
+        #[arg(long, allow_hyphen_values = true, conflicts_with_all = ["dec", "set", "delete"])]
+        inc: Option<i64>,
+        #[arg(long, allow_hyphen_values = true, conflicts_with_all = ["set", "delete"])]
+        dec: Option<i64>,
+        #[arg(long, allow_hyphen_values = true, conflicts_with = "delete")]
+        set: Option<i64>,
+        #[arg(long)]
+        delete: bool,
+    },
+    /// List sequences and counters
+    Counters,
+    /// List named databases
+    Databases,
+    /// Create a named database
+    CreateDatabase {
+        name: String,
+        #[arg(long)]
+        max_collections: Option<usize>,
+        #[arg(long)]
+        max_documents: Option<usize>,
+        #[arg(long)]
+        max_bytes: Option<u64>,
+    },
+    /// Rename a collection, replacing an existing target with --replace
+    Rename {
+        from: String,
+        to: String,
+        #[arg(long)]
+        replace: bool,
+    },
+    /// Copy a collection, or only the documents matching --filter
+    Clone {
+        from: String,
+        to: String,
+        #[arg(long)]
+        filter: Option<String>,
+    },
+    /// Remove every document of a collection, keeping its options and indexes
+    Truncate { collection: String },
+    /// Drop a named database and everything in it
+    DropDatabase { name: String },
+    /// Show or replace the quota of the database
+    Quota {
+        #[arg(long)]
+        max_collections: Option<usize>,
+        #[arg(long)]
+        max_documents: Option<usize>,
+        #[arg(long)]
+        max_bytes: Option<u64>,
+        #[arg(long, conflicts_with_all = ["max_collections", "max_documents", "max_bytes"])]
+        clear: bool,
+    },
+    /// Add a user of the database or change their password, or remove them with --remove
+    User {
+        username: String,
+        #[arg(required_unless_present = "remove")]
+        password: Option<String>,
+        #[arg(long, conflicts_with = "password")]
+        remove: bool,
+    },
 }
 
 fn init_logging() {
@@ -51,44 +355,229 @@ fn main() -> Result<(), DbError> {
     init_logging();
     let cli = Cli::parse();
     // let db = Database::new("data")?;
-    let db = Database::load("data")?;
+    let catalog = Catalog::open("data")?;
+    let db = match &cli.db {
+        Some(name) => catalog.database(name)?,
+        None => catalog.default_database(),
+    };
 
     // db.start_ttl_cleaner(10); // Clean every 60 seconds
 
     match cli.command {
-        Commands::Create { name } => {
-            db.collection(&name)?;
-            println!("Created collection: {}", name);
-            db.create_collection(&name)?;
+        Commands::Create {
+            name,
+            id_strategy,
+            schema,
+            default_ttl,
+            max_documents,
+            max_document_bytes,
+            capped_documents,
+            capped_bytes,
+        } => {
+            let options = CollectionOptions {
+                id_strategy,
+                schema: match schema {
+                    Some(raw) => Some(Schema::new(serde_json::from_str(&raw)?)?),
+                    None => None,
+                },
+                default_ttl,
+                limits: Limits {
+                    max_documents,
+                    max_document_bytes,
+                },
+                capped: (capped_documents.is_some() || capped_bytes.is_some()).then_some(Capped {
+                    max_documents: capped_documents,
+                    max_bytes: capped_bytes,
+                }),
+                ..Default::default()
+            };
+            db.create_collection_with(&name, options)?;
             println!("Created collection: {}", name);
         }
+        Commands::Describe { name } => {
+            let names = match name {
+                Some(name) => vec![name],
+                None => db.collection_names()?,
+            };
+            let infos = names
+                .iter()
+                .map(|name| db.get_collection(name)?.info())
+                .collect::<Result<Vec<_>, DbError>>()?;
+            println!("{}", serde_json::to_string_pretty(&infos)?);
+        }
         Commands::Insert {
             collection,
             json,
             ttl,
+            id,
         } => {
             // let mut docs = self.documents.write().map_err(|_| DbError::LockPoisoned)?;
             let col = db.collection(&collection)?;
             println!("Parsing JSON: {}", &json);
             let value: Value = serde_json::from_str(&json)?;
             println!("Inserting...");
-            let doc = col.insert(value, ttl)?;
+            let doc = match id {
+                Some(id) => col.insert_with_id(&id, value, ttl)?,
+                None => col.insert(value, ttl)?,
+            };
             println!("Inserted document with ID: {}", doc.id);
             // println!("Parsed JSON: {}",);
         }
-        Commands::Find { collection, id } => {
+        Commands::IdStrategy {
+            collection,
+            strategy,
+        } => {
             let col = db.collection(&collection)?;
-            if let Some(doc) = col.find(&id)? {
+            col.set_id_strategy(strategy)?;
+            println!("{}", serde_json::to_string_pretty(&col.id_strategy()?)?);
+        }
+        Commands::Schema {
+            collection,
+            schema,
+            check,
+            force,
+            clear,
+        } => {
+            let col = db.get_collection(&collection)?;
+            let schema = match schema {
+                Some(raw) => Some(Schema::new(serde_json::from_str(&raw)?)?),
+                None => col.schema()?,
+            };
+            if clear {
+                col.set_schema(None)?;
+                println!("Removed schema of {}", collection);
+            } else if let Some(schema) = schema {
+                let violations = col.validate_documents(&schema)?;
+                for violation in &violations {
+                    for error in &violation.errors {
+                        println!("{}: {}", violation.id, error);
+                    }
+                }
+                if check || (!violations.is_empty() && !force) {
+                    println!("{} documents don't match the schema", violations.len());
+                } else {
+                    col.set_schema(Some(schema))?;
+                    println!("Set schema of {}", collection);
+                }
+            } else {
+                println!("{} has no schema", collection);
+            }
+        }
+        Commands::Find {
+            collection,
+            id,
+            as_of,
+        } => {
+            let col = db.collection(&collection)?;
+            let doc = match as_of {
+                Some(at) => col.find_as_of(&id, at)?,
+                None => col.find(&id)?,
+            };
+            if let Some(doc) = doc {
                 println!("{}", serde_json::to_string_pretty(&doc.data)?);
             } else {
                 println!("Document not found");
             }
         }
-        Commands::List { collection } => {
+        Commands::List { collection, as_of } => {
             let col = db.collection(&collection)?;
-            let docs = col.find_all()?;
+            let docs = match as_of {
+                Some(at) => col.query_as_of(&Query::new(Value::Null), at)?,
+                None => col.find_all()?,
+            };
             println!("{}", serde_json::to_string_pretty(&docs)?);
         }
+        Commands::History {
+            collection,
+            max_revisions,
+            max_age,
+            disable,
+        } => {
+            let col = db.get_collection(&collection)?;
+            if disable {
+                col.set_history(None)?;
+                println!("Disabled history of {}", collection);
+            } else {
+                col.set_history(Some(HistoryRetention {
+                    max_revisions,
+                    max_age,
+                }))?;
+                println!("Enabled history of {}", collection);
+            }
+        }
+        Commands::Revisions { collection, id } => {
+            let revisions = db.get_collection(&collection)?.revisions(&id)?;
+            println!("{}", serde_json::to_string_pretty(&revisions)?);
+        }
+        Commands::Diff {
+            collection,
+            id,
+            from,
+            to,
+        } => {
+            let patch = db.get_collection(&collection)?.diff(&id, from, to)?;
+            println!("{}", serde_json::to_string_pretty(&patch)?);
+        }
+        Commands::Restore {
+            collection,
+            id,
+            version,
+        } => {
+            let doc = db.get_collection(&collection)?.restore(&id, version)?;
+            println!("Restored document {} as version {}", doc.id, doc.version);
+        }
+        Commands::Query {
+            collection,
+            filter,
+            skip,
+            limit,
+            explain,
+        } => {
+            let col = db.collection(&collection)?;
+            let query = Query {
+                filter: serde_json::from_str(&filter)?,
+                skip,
+                limit,
+            };
+            if explain {
+                println!("{}", serde_json::to_string_pretty(&col.explain(&query)?)?);
+            } else {
+                println!("{}", serde_json::to_string_pretty(&col.query(&query)?)?);
+            }
+        }
+        Commands::Bulk {
+            collection,
+            file,
+            documents,
+            unordered,
+        } => {
+            let input = match file {
+                Some(path) => std::fs::read_to_string(path)?,
+                None => std::io::read_to_string(std::io::stdin())?,
+            };
+            let ops = if documents {
+                input
+                    .lines()
+                    .filter(|line| !line.trim().is_empty())
+                    .map(|line| {
+                        Ok(BulkOp::Insert {
+                            id: None,
+                            data: serde_json::from_str(line)?,
+                            ttl: None,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, DbError>>()?
+            } else {
+                BulkOp::parse_ndjson(&input)?
+            };
+            let col = db.collection(&collection)?;
+            let result = col.bulk_write(ops, !unordered)?;
+            println!("{}", serde_json::to_string_pretty(&result)?);
+        }
+        Commands::Sql { query } => {
+            let rows = db.sql(&query)?;
+            println!("{}", serde_json::to_string_pretty(&rows)?);
+        }
         Commands::Update {
             collection,
             id,
@@ -99,6 +588,24 @@ fn main() -> Result<(), DbError> {
             let doc = col.update(&id, value)?;
             println!("Updated document with ID: {}", doc.id);
         }
+        Commands::Patch {
+            collection,
+            id,
+            ops,
+            merge,
+            json_patch,
+        } => {
+            let col = db.collection(&collection)?;
+            let ops: Value = serde_json::from_str(&ops)?;
+            let doc = if merge {
+                col.merge_patch(&id, &ops)?
+            } else if json_patch {
+                col.json_patch(&id, &ops)?
+            } else {
+                col.update_with(&id, &ops)?
+            };
+            println!("{}", serde_json::to_string_pretty(&doc.data)?);
+        }
         Commands::Delete { collection, id } => {
             let col = db.collection(&collection)?;
             col.delete(&id)?;
@@ -108,6 +615,321 @@ fn main() -> Result<(), DbError> {
             db.drop_collection(&name)?;
             println!("Dropped collection: {}", name);
         }
+        Commands::Index {
+            collection,
+            field,
+            drop,
+        } => {
+            let col = db.collection(&collection)?;
+            if drop {
+                col.drop_index(&field)?;
+                println!("Dropped index on {}.{}", collection, field);
+            } else {
+                col.create_index(&field)?;
+                println!("Created index on {}.{}", collection, field);
+            }
+        }
+        Commands::GeoIndex {
+            collection,
+            field,
+            drop,
+        } => {
+            let col = db.collection(&collection)?;
+            if drop {
+                col.drop_geo_index(&field)?;
+                println!("Dropped geo index on {}.{}", collection, field);
+            } else {
+                col.create_geo_index(&field)?;
+                println!("Created geo index on {}.{}", collection, field);
+            }
+        }
+        Commands::VectorIndex {
+            collection,
+            field,
+            options,
+            drop,
+        } => {
+            let col = db.collection(&collection)?;
+            match options {
+                Some(options) if !drop => {
+                    col.create_vector_index(&field, serde_json::from_str(&options)?)?;
+                    println!("Created vector index on {}.{}", collection, field);
+                }
+                _ => {
+                    col.drop_vector_index(&field)?;
+                    println!("Dropped vector index on {}.{}", collection, field);
+                }
+            }
+        }
+        Commands::DefaultTtl {
+            collection,
+            seconds,
+            clear,
+        } => {
+            let ttl = if clear { None } else { seconds };
+            db.get_collection(&collection)?.set_default_ttl(ttl)?;
+            match ttl {
+                Some(secs) => println!("Default TTL of {} is {}s", collection, secs),
+                None => println!("Cleared default TTL of {}", collection),
+            }
+        }
+        Commands::TtlIndex {
+            collection,
+            field,
+            expire_after,
+            drop,
+        } => {
+            let col = db.get_collection(&collection)?;
+            match expire_after {
+                Some(secs) if !drop => {
+                    col.create_ttl_index(&field, secs)?;
+                    println!("Created TTL index on {}.{}", collection, field);
+                }
+                _ => {
+                    col.drop_ttl_index(&field)?;
+                    println!("Dropped TTL index on {}.{}", collection, field);
+                }
+            }
+        }
+        Commands::Expire => {
+            let removed = db.remove_expired()?;
+            println!("Removed {} expired documents", removed);
+            println!("{}", serde_json::to_string_pretty(&db.expiry_stats()?)?);
+        }
+        Commands::Ttl {
+            collection,
+            id,
+            extend,
+            refresh,
+            clear,
+        } => {
+            let col = db.get_collection(&collection)?;
+            let doc = match (extend, refresh) {
+                (Some(secs), _) => col.extend_ttl(&id, secs)?,
+                (None, Some(secs)) => col.refresh_ttl(&id, secs)?,
+                (None, None) if clear => col.clear_ttl(&id)?,
+                (None, None) => col.find(&id)?.ok_or(DbError::NotFound)?,
+            };
+            match doc.expires_at {
+                Some(at) => println!("Document {} expires at {}", id, at),
+                None => println!("Document {} does not expire", id),
+            }
+        }
+        Commands::Cap {
+            collection,
+            max_documents,
+            max_bytes,
+            disable,
+        } => {
+            let col = db.get_collection(&collection)?;
+            if disable {
+                col.set_capped(None)?;
+                println!("Uncapped {}", collection);
+            } else {
+                let evicted = col.set_capped(Some(Capped {
+                    max_documents,
+                    max_bytes,
+                }))?;
+                println!("Capped {}, evicting {} documents", collection, evicted);
+            }
+        }
+        Commands::Tail {
+            collection,
+            mut after,
+            limit,
+            follow,
+        } => {
+            let mut col = db.get_collection(&collection)?;
+            loop {
+                let docs = col.tail(after, limit)?;
+                for doc in &docs {
+                    println!("{}", serde_json::to_string(doc)?);
+                }
+                if let Some(last) = docs.last() {
+                    after = Some(last.created_at);
+                }
+                if !follow {
+                    break;
+                }
+                std::thread::sleep(std::time::Duration::from_secs(1));
+                // other processes write to the file, so read it again
+                col = Database::new(db.path())?.get_collection(&collection)?;
+            }
+        }
+        Commands::SoftDelete {
+            collection,
+            retention,
+            disable,
+        } => {
+            let col = db.get_collection(&collection)?;
+            if disable {
+                col.set_soft_delete(None)?;
+                println!("Disabled soft delete of {}", collection);
+            } else {
+                col.set_soft_delete(Some(SoftDelete { retention }))?;
+                println!("Enabled soft delete of {}", collection);
+            }
+        }
+        Commands::Trash { collection } => match collection {
+            Some(collection) => {
+                let trashed = db.get_collection(&collection)?.trashed()?;
+                println!("{}", serde_json::to_string_pretty(&trashed)?);
+            }
+            None => {
+                let trashed = db.trashed_collections()?;
+                println!("{}", serde_json::to_string_pretty(&trashed)?);
+            }
+        },
+        Commands::Undelete { collection, id } => {
+            let doc = db.get_collection(&collection)?.undelete(&id)?;
+            println!("Undeleted document {} as version {}", doc.id, doc.version);
+        }
+        Commands::Purge {
+            collection,
+            id,
+            all,
+        } => {
+            let col = db.get_collection(&collection)?;
+            match id {
+                Some(id) if !all => {
+                    col.purge(&id)?;
+                    println!("Purged document with ID: {}", id);
+                }
+                _ => {
+                    let purged = col.empty_trash()?;
+                    println!("Purged {} documents from the trash", purged);
+                }
+            }
+        }
+        Commands::RestoreCollection { id } => {
+            let name = db.restore_collection(&id)?;
+            println!("Restored collection: {}", name);
+        }
+        Commands::PurgeCollection { id } => {
+            db.purge_collection(&id)?;
+            println!("Purged collection: {}", id);
+        }
+        Commands::Rename { from, to, replace } => {
+            db.rename_collection(&from, &to, replace)?;
+            println!("Renamed collection {} to {}", from, to);
+        }
+        Commands::Clone { from, to, filter } => {
+            let filter = match filter {
+                Some(raw) => Some(Filter::parse(&serde_json::from_str(&raw)?)?),
+                None => None,
+            };
+            let copied = db.clone_collection(&from, &to, filter.as_ref())?;
+            println!("Cloned {} documents from {} into {}", copied, from, to);
+        }
+        Commands::Truncate { collection } => {
+            let removed = db.truncate_collection(&collection)?;
+            println!("Removed {} documents from {}", removed, collection);
+        }
+        Commands::Seq {
+            name,
+            set,
+            force,
+            drop,
+        } => {
+            if drop {
+                db.drop_sequence(&name)?;
+                println!("Dropped sequence: {}", name);
+            } else if let Some(last) = set {
+                db.set_sequence(&name, last, force)?;
+                println!("Sequence {} continues after {}", name, last);
+            } else {
+                println!("{}", db.next_sequence(&name)?);
+            }
+        }
+        Commands::Counter {
+            name,
+            inc,
+            dec,
+            set,
+            delete,
+        } => {
+            if delete {
+                db.delete_counter(&name)?;
+                println!("Deleted counter: {}", name);
+            } else if let Some(value) = set {
+                db.set_counter(&name, value)?;
+                println!("{}", value);
+            } else if let Some(by) = inc {
+                println!("{}", db.increment_counter(&name, by)?);
+            } else if let Some(by) = dec {
+                println!("{}", db.decrement_counter(&name, by)?);
+            } else {
+                println!("{}", db.counter(&name)?);
+            }
+        }
+        Commands::Counters => {
+            for (name, value) in db.sequences()? {
+                println!("sequence {}: {}", name, value);
+            }
+            for (name, value) in db.counters()? {
+                println!("counter {}: {}", name, value);
+            }
+        }
+        Commands::Databases => {
+            for name in catalog.database_names()? {
+                let usage = catalog.database(&name)?.usage();
+                println!(
+                    "{} ({} collections, {} documents, {} bytes)",
+                    name, usage.collections, usage.documents, usage.bytes
+                );
+            }
+        }
+        Commands::CreateDatabase {
+            name,
+            max_collections,
+            max_documents,
+            max_bytes,
+        } => {
+            catalog.create_database(
+                &name,
+                Quota {
+                    max_collections,
+                    max_documents,
+                    max_bytes,
+                },
+            )?;
+            println!("Created database: {}", name);
+        }
+        Commands::DropDatabase { name } => {
+            catalog.drop_database(&name)?;
+            println!("Dropped database: {}", name);
+        }
+        Commands::Quota {
+            max_collections,
+            max_documents,
+            max_bytes,
+            clear,
+        } => {
+            let quota = Quota {
+                max_collections,
+                max_documents,
+                max_bytes,
+            };
+            if clear || quota != Quota::default() {
+                db.set_quota(quota)?;
+            }
+            println!("{}", serde_json::to_string_pretty(&db.quota())?);
+            println!("{}", serde_json::to_string_pretty(&db.usage())?);
+        }
+        Commands::User {
+            username,
+            password,
+            remove,
+        } => match password {
+            Some(password) if !remove => {
+                db.set_user(&username, &password)?;
+                println!("Set password of user: {}", username);
+            }
+            _ => {
+                db.remove_user(&username)?;
+                println!("Removed user: {}", username);
+            }
+        },
     }
 
     Ok(())
@@ -1,6 +1,6 @@
 // src/bin/server.rs
-use darkdb::{api, db::Database};
-use std::{collections::HashMap, net::SocketAddr, path::PathBuf};
+use darkdb::{api, db::Catalog};
+use std::{collections::HashMap, path::PathBuf};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -30,9 +30,9 @@ async fn main() -> anyhow::Result<()> {
 
     let opt = Opt::from_args();
 
-    // Initialize database
-    let db = Database::load(&opt.data_dir)?;
-    db.start_ttl_cleaner(60); // Clean every 60 seconds
+    // Initialize databases
+    let catalog = Catalog::open(&opt.data_dir)?;
+    let cleaner = catalog.start_ttl_cleaner(60); // Clean every 60 seconds
 
     // Set up authentication
     let mut users = HashMap::new();
@@ -42,7 +42,7 @@ async fn main() -> anyhow::Result<()> {
 
     // let auth_config = api::AuthConfig { users };
     // In your main.rs or server.rs:
-    let auth_config = AuthConfig {
+    let auth_config = api::AuthConfig {
         users: {
             let mut map = HashMap::new();
             map.insert(
@@ -53,7 +53,8 @@ async fn main() -> anyhow::Result<()> {
         },
     };
 
-    start_server(db, "0.0.0.0", 8080, auth_config).await?;
+    api::start_server(catalog, &opt.host, opt.port, auth_config).await?;
+    cleaner.shutdown().await;
 
     // Start server
     // api::start_server(db, &opt.host, opt.port, auth_config).await?;
@@ -0,0 +1,550 @@
+use serde_json::{Map, Number, Value};
+use std::{cmp::Ordering, collections::HashMap};
+
+use super::{
+    DbError, Document,
+    query::{Filter, path_value, remove_path, set_path, total_cmp},
+};
+
+#[derive(Debug, Clone)]
+pub enum Stage {
+    Match(Filter),
+    Group(Group),
+    Sort(Vec<(String, Ordering)>),
+    Project(Projection),
+    Unwind { path: String, preserve_empty: bool },
+    Skip(usize),
+    Limit(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct Group {
+    key: Value,
+    fields: Vec<(String, Accumulator)>,
+}
+
+#[derive(Debug, Clone)]
+enum Accumulator {
+    Sum(Value),
+    Avg(Value),
+    Min(Value),
+    Max(Value),
+    /// Counts every record, or only those where the expression is not null.
+    Count(Option<Value>),
+    Push(Value),
+}
+
+#[derive(Debug, Clone)]
+pub struct Projection {
+    include_id: bool,
+    inclusive: bool,
+    fields: Vec<(String, Option<Value>)>,
+}
+
+fn invalid(msg: impl Into<String>) -> DbError {
+    DbError::InvalidQuery(msg.into())
+}
+
+/// Parses a pipeline given as a JSON array of single-key stage objects.
+pub fn parse_pipeline(stages: &[Value]) -> Result<Vec<Stage>, DbError> {
+    stages.iter().map(Stage::parse).collect()
+}
+
+impl Stage {
+    fn parse(value: &Value) -> Result<Self, DbError> {
+        let (name, arg) = value
+            .as_object()
+            .filter(|m| m.len() == 1)
+            .and_then(|m| m.iter().next())
+            .ok_or_else(|| invalid("each stage must be an object with one operator"))?;
+        Ok(match name.as_str() {
+            "$match" => Stage::Match(Filter::parse(arg)?),
+            "$group" => Stage::Group(Group::parse(arg)?),
+            "$sort" => {
+                let spec = arg
+                    .as_object()
+                    .ok_or_else(|| invalid("$sort expects an object"))?;
+                let keys = spec
+                    .iter()
+                    .map(|(field, dir)| match dir.as_i64() {
+                        Some(1) => Ok((field.clone(), Ordering::Less)),
+                        Some(-1) => Ok((field.clone(), Ordering::Greater)),
+                        _ => Err(invalid("$sort directions must be 1 or -1")),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Stage::Sort(keys)
+            }
+            "$project" => Stage::Project(Projection::parse(arg)?),
+            "$unwind" => {
+                let (path, preserve_empty) = match arg {
+                    Value::String(path) => (path.as_str(), false),
+                    Value::Object(spec) => (
+                        spec.get("path").and_then(Value::as_str).unwrap_or_default(),
+                        spec.get("preserveNullAndEmptyArrays")
+                            .and_then(Value::as_bool)
+                            .unwrap_or(false),
+                    ),
+                    _ => ("", false),
+                };
+                let path = path
+                    .strip_prefix('$')
+                    .ok_or_else(|| invalid("$unwind expects a \"$field\" path"))?;
+                Stage::Unwind {
+                    path: path.to_string(),
+                    preserve_empty,
+                }
+            }
+            "$skip" => Stage::Skip(count(name, arg)?),
+            "$limit" => Stage::Limit(count(name, arg)?),
+            other => return Err(invalid(format!("unknown stage {}", other))),
+        })
+    }
+}
+
+fn count(stage: &str, arg: &Value) -> Result<usize, DbError> {
+    arg.as_u64()
+        .map(|n| n as usize)
+        .ok_or_else(|| invalid(format!("{} expects a non-negative integer", stage)))
+}
+
+impl Group {
+    fn parse(arg: &Value) -> Result<Self, DbError> {
+        let spec = arg
+            .as_object()
+            .ok_or_else(|| invalid("$group expects an object"))?;
+        let key = spec
+            .get("_id")
+            .cloned()
+            .ok_or_else(|| invalid("$group requires an _id"))?;
+        let mut fields = Vec::new();
+        for (name, acc) in spec.iter().filter(|(k, _)| *k != "_id") {
+            let (op, expr) = acc
+                .as_object()
+                .filter(|m| m.len() == 1)
+                .and_then(|m| m.iter().next())
+                .ok_or_else(|| invalid(format!("{} must be an accumulator object", name)))?;
+            let acc = match op.as_str() {
+                "$sum" => Accumulator::Sum(expr.clone()),
+                "$avg" => Accumulator::Avg(expr.clone()),
+                "$min" => Accumulator::Min(expr.clone()),
+                "$max" => Accumulator::Max(expr.clone()),
+                "$count" => match expr {
+                    Value::Object(spec) if spec.is_empty() => Accumulator::Count(None),
+                    expr => Accumulator::Count(Some(expr.clone())),
+                },
+                "$push" => Accumulator::Push(expr.clone()),
+                other => return Err(invalid(format!("unknown accumulator {}", other))),
+            };
+            fields.push((name.clone(), acc));
+        }
+        Ok(Self { key, fields })
+    }
+
+    fn apply(&self, records: Vec<Value>) -> Vec<Value> {
+        let mut order: Vec<Value> = Vec::new();
+        let mut groups: HashMap<String, (usize, Vec<Vec<Value>>)> = HashMap::new();
+        for record in &records {
+            let key = eval(&self.key, record);
+            let slot = key.to_string();
+            let entry = groups.entry(slot).or_insert_with(|| {
+                order.push(key);
+                (order.len() - 1, vec![Vec::new(); self.fields.len()])
+            });
+            for (i, (_, acc)) in self.fields.iter().enumerate() {
+                let value = match acc {
+                    Accumulator::Count(None) => Value::Null,
+                    Accumulator::Count(Some(e))
+                    | Accumulator::Sum(e)
+                    | Accumulator::Avg(e)
+                    | Accumulator::Min(e)
+                    | Accumulator::Max(e)
+                    | Accumulator::Push(e) => eval(e, record),
+                };
+                entry.1[i].push(value);
+            }
+        }
+
+        let mut grouped: Vec<(usize, Vec<Vec<Value>>)> = groups.into_values().collect();
+        grouped.sort_by_key(|(i, _)| *i);
+        grouped
+            .into_iter()
+            .map(|(i, values)| {
+                let mut out = Map::new();
+                out.insert("_id".to_string(), order[i].clone());
+                for ((name, acc), values) in self.fields.iter().zip(values) {
+                    out.insert(name.clone(), acc.finish(values));
+                }
+                Value::Object(out)
+            })
+            .collect()
+    }
+}
+
+impl Accumulator {
+    fn finish(&self, values: Vec<Value>) -> Value {
+        let present = values.iter().filter(|v| !v.is_null());
+        match self {
+            Accumulator::Count(None) => Value::from(values.len()),
+            Accumulator::Count(Some(_)) => Value::from(present.count()),
+            Accumulator::Sum(_) => sum(values.iter()),
+            Accumulator::Avg(_) => {
+                let nums: Vec<f64> = values.iter().filter_map(Value::as_f64).collect();
+                if nums.is_empty() {
+                    Value::Null
+                } else {
+                    number(nums.iter().sum::<f64>() / nums.len() as f64)
+                }
+            }
+            Accumulator::Min(_) => present
+                .min_by(|a, b| total_cmp(a, b))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Accumulator::Max(_) => present
+                .max_by(|a, b| total_cmp(a, b))
+                .cloned()
+                .unwrap_or(Value::Null),
+            Accumulator::Push(_) => Value::Array(present.cloned().collect()),
+        }
+    }
+}
+
+/// Sums numeric values, keeping integers exact when every input is an integer.
+fn sum<'a>(values: impl Iterator<Item = &'a Value>) -> Value {
+    let (mut int, mut float, mut all_int) = (0i64, 0f64, true);
+    for v in values {
+        match v.as_i64() {
+            Some(i) if all_int => match int.checked_add(i) {
+                Some(total) => int = total,
+                None => {
+                    all_int = false;
+                    float = int as f64 + i as f64;
+                }
+            },
+            _ => {
+                if let Some(f) = v.as_f64() {
+                    if all_int {
+                        all_int = false;
+                        float = int as f64;
+                    }
+                    float += f;
+                }
+            }
+        }
+    }
+    if all_int {
+        Value::from(int)
+    } else {
+        number(float)
+    }
+}
+
+fn number(f: f64) -> Value {
+    Number::from_f64(f).map_or(Value::Null, Value::Number)
+}
+
+impl Projection {
+    fn parse(arg: &Value) -> Result<Self, DbError> {
+        let spec = arg
+            .as_object()
+            .ok_or_else(|| invalid("$project expects an object"))?;
+        let mut include_id = true;
+        let mut inclusive = None;
+        let mut fields = Vec::new();
+        for (field, value) in spec {
+            let flag = match value {
+                Value::Bool(b) => Some(*b),
+                Value::Number(n) if n.as_f64() == Some(0.0) => Some(false),
+                Value::Number(n) if n.as_f64() == Some(1.0) => Some(true),
+                _ => None,
+            };
+            match flag {
+                Some(keep) if field == "_id" => include_id = keep,
+                Some(keep) => {
+                    if inclusive.is_some_and(|mode| mode != keep) {
+                        return Err(invalid("$project cannot mix inclusion and exclusion"));
+                    }
+                    inclusive = Some(keep);
+                    fields.push((field.clone(), None));
+                }
+                None => {
+                    if inclusive == Some(false) {
+                        return Err(invalid("$project cannot mix inclusion and exclusion"));
+                    }
+                    inclusive = Some(true);
+                    fields.push((field.clone(), Some(value.clone())));
+                }
+            }
+        }
+        Ok(Self {
+            include_id,
+            inclusive: inclusive.unwrap_or(false),
+            fields,
+        })
+    }
+
+    fn apply(&self, record: Value) -> Value {
+        if !self.inclusive {
+            let mut record = record;
+            for (field, _) in &self.fields {
+                remove_path(&mut record, field);
+            }
+            if !self.include_id {
+                remove_path(&mut record, "_id");
+            }
+            return record;
+        }
+
+        let mut out = Value::Object(Map::new());
+        if self.include_id
+            && let Some(id) = path_value(&record, "_id")
+        {
+            let _ = set_path(&mut out, "_id", id.clone());
+        }
+        for (field, expr) in &self.fields {
+            let value = match expr {
+                Some(expr) => Some(eval(expr, &record)),
+                None => path_value(&record, field).cloned(),
+            };
+            if let Some(value) = value {
+                let _ = set_path(&mut out, field, value);
+            }
+        }
+        out
+    }
+}
+
+/// Evaluates an expression: `"$path"` reads a field, objects and arrays are
+/// evaluated element-wise and anything else is a literal.
+fn eval(expr: &Value, record: &Value) -> Value {
+    match expr {
+        Value::String(s) if s.starts_with('$') => {
+            path_value(record, &s[1..]).cloned().unwrap_or(Value::Null)
+        }
+        Value::Object(map) => match map.get("$literal") {
+            Some(literal) if map.len() == 1 => literal.clone(),
+            _ => Value::Object(
+                map.iter()
+                    .map(|(k, v)| (k.clone(), eval(v, record)))
+                    .collect(),
+            ),
+        },
+        Value::Array(items) => Value::Array(items.iter().map(|v| eval(v, record)).collect()),
+        literal => literal.clone(),
+    }
+}
+
+/// The pipeline view of a document: its data object with `_id` added.
+pub fn record(doc: &Document) -> Value {
+    let mut map = match &doc.data {
+        Value::Object(map) => map.clone(),
+        other => {
+            let mut map = Map::new();
+            map.insert("value".to_string(), other.clone());
+            map
+        }
+    };
+    map.insert("_id".to_string(), Value::String(doc.id.clone()));
+    Value::Object(map)
+}
+
+pub fn run(stages: &[Stage], mut records: Vec<Value>) -> Vec<Value> {
+    for stage in stages {
+        records = match stage {
+            Stage::Match(filter) => records
+                .into_iter()
+                .filter(|r| filter.matches_value(r))
+                .collect(),
+            Stage::Group(group) => group.apply(records),
+            Stage::Sort(keys) => {
+                records.sort_by(|a, b| {
+                    keys.iter()
+                        .map(|(field, dir)| {
+                            let (x, y) = (
+                                path_value(a, field).unwrap_or(&Value::Null),
+                                path_value(b, field).unwrap_or(&Value::Null),
+                            );
+                            let ord = total_cmp(x, y);
+                            if *dir == Ordering::Greater {
+                                ord.reverse()
+                            } else {
+                                ord
+                            }
+                        })
+                        .find(|o| o.is_ne())
+                        .unwrap_or(Ordering::Equal)
+                });
+                records
+            }
+            Stage::Project(projection) => {
+                records.into_iter().map(|r| projection.apply(r)).collect()
+            }
+            Stage::Unwind {
+                path,
+                preserve_empty,
+            } => records
+                .into_iter()
+                .flat_map(|r| unwind(r, path, *preserve_empty))
+                .collect(),
+            Stage::Skip(n) => records.into_iter().skip(*n).collect(),
+            Stage::Limit(n) => records.into_iter().take(*n).collect(),
+        };
+    }
+    records
+}
+
+fn unwind(record: Value, path: &str, preserve_empty: bool) -> Vec<Value> {
+    match path_value(&record, path) {
+        Some(Value::Array(items)) if !items.is_empty() => items
+            .clone()
+            .into_iter()
+            .map(|item| {
+                let mut out = record.clone();
+                let _ = set_path(&mut out, path, item);
+                out
+            })
+            .collect(),
+        Some(Value::Array(_)) | Some(Value::Null) | None => {
+            if preserve_empty {
+                vec![record]
+            } else {
+                Vec::new()
+            }
+        }
+        Some(_) => vec![record],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn aggregate(pipeline: Value, records: Value) -> Result<Vec<Value>, DbError> {
+        let stages = parse_pipeline(pipeline.as_array().unwrap())?;
+        let Value::Array(records) = records else {
+            panic!("records must be an array");
+        };
+        Ok(run(&stages, records))
+    }
+
+    #[test]
+    fn sums_stay_exact_until_they_overflow() {
+        let records = json!([
+            {"g": 1, "v": i64::MAX}, {"g": 1, "v": 1},
+            {"g": 2, "v": 2}, {"g": 2, "v": 0.5}, {"g": 2, "v": "x"},
+            {"g": 3, "v": null},
+        ]);
+        let out = aggregate(
+            json!([{"$group": {"_id": "$g", "sum": {"$sum": "$v"}, "avg": {"$avg": "$v"}}}]),
+            records,
+        )
+        .unwrap();
+        assert_eq!(out[0]["sum"], json!(i64::MAX as f64 + 1.0));
+        assert_eq!(out[1]["sum"], json!(2.5));
+        assert_eq!(out[1]["avg"], json!(1.25));
+        assert_eq!(out[2]["sum"], json!(0));
+        assert_eq!(out[2]["avg"], Value::Null);
+    }
+
+    #[test]
+    fn groups_keep_first_seen_order_and_skip_nulls() {
+        let records = json!([
+            {"k": "b", "v": 3}, {"v": 1}, {"k": "a", "v": null},
+            {"k": "b", "v": 1}, {"k": null, "v": 2},
+        ]);
+        let out = aggregate(
+            json!([{"$group": {
+                "_id": "$k",
+                "n": {"$count": {}},
+                "with_v": {"$count": "$v"},
+                "min": {"$min": "$v"},
+                "max": {"$max": "$v"},
+                "all": {"$push": "$v"},
+            }}]),
+            records,
+        )
+        .unwrap();
+        let keys: Vec<&Value> = out.iter().map(|g| &g["_id"]).collect();
+        // a missing key and an explicit null land in the same group
+        assert_eq!(keys, [&json!("b"), &Value::Null, &json!("a")]);
+        assert_eq!(out[1]["n"], json!(2));
+        assert_eq!(out[2]["with_v"], json!(0));
+        assert_eq!(out[2]["min"], Value::Null);
+        assert_eq!((&out[0]["min"], &out[0]["max"]), (&json!(1), &json!(3)));
+        assert_eq!(out[0]["all"], json!([3, 1]));
+    }
+
+    #[test]
+    fn unwind_drops_empty_values_unless_asked() {
+        let records = json!([
+            {"_id": 1, "t": [1, 2]}, {"_id": 2, "t": []},
+            {"_id": 3, "t": null}, {"_id": 4}, {"_id": 5, "t": "x"},
+        ]);
+        let ids = |out: Vec<Value>| out.iter().map(|r| r["_id"].clone()).collect::<Vec<_>>();
+        let plain = aggregate(json!([{"$unwind": "$t"}]), records.clone()).unwrap();
+        assert_eq!(ids(plain.clone()), [json!(1), json!(1), json!(5)]);
+        assert_eq!(plain[1]["t"], json!(2));
+        let preserved = aggregate(
+            json!([{"$unwind": {"path": "$t", "preserveNullAndEmptyArrays": true}}]),
+            records,
+        )
+        .unwrap();
+        assert_eq!(preserved.len(), 6);
+    }
+
+    #[test]
+    fn projections_include_exclude_or_compute() {
+        let records = json!([{"_id": 1, "a": {"b": 1, "c": 2}, "d": 3}]);
+        let out = aggregate(
+            json!([{"$project": {"_id": 0, "a.b": 1, "sum": ["$d", {"$literal": "$d"}]}}]),
+            records.clone(),
+        )
+        .unwrap();
+        assert_eq!(out[0], json!({"a": {"b": 1}, "sum": [3, "$d"]}));
+        let out = aggregate(json!([{"$project": {"a.c": 0, "d": false}}]), records).unwrap();
+        assert_eq!(out[0], json!({"_id": 1, "a": {"b": 1}}));
+        for mixed in [json!({"a": 1, "d": 0}), json!({"d": 0, "x": "$a"})] {
+            assert!(aggregate(json!([{"$project": mixed}]), json!([])).is_err());
+        }
+    }
+
+    #[test]
+    fn sort_orders_missing_fields_first_and_keeps_ties_stable() {
+        let records = json!([
+            {"_id": 1, "a": 2, "b": 1}, {"_id": 2, "b": 5},
+            {"_id": 3, "a": 2, "b": 9}, {"_id": 4, "a": 1},
+        ]);
+        let out = aggregate(
+            json!([{"$sort": {"a": 1}}, {"$skip": 1}, {"$limit": 2}]),
+            records.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            out.iter().map(|r| &r["_id"]).collect::<Vec<_>>(),
+            [&json!(4), &json!(1)]
+        );
+        let out = aggregate(json!([{"$sort": {"a": -1, "b": -1}}]), records).unwrap();
+        let ids: Vec<&Value> = out.iter().map(|r| &r["_id"]).collect();
+        assert_eq!(ids, [&json!(3), &json!(1), &json!(4), &json!(2)]);
+    }
+
+    #[test]
+    fn malformed_stages_are_rejected() {
+        for pipeline in [
+            json!([{"$match": {}, "$limit": 1}]),
+            json!([{"$out": "x"}]),
+            json!([{"$sort": {"a": 2}}]),
+            json!([{"$limit": -1}]),
+            json!([{"$skip": 1.5}]),
+            json!([{"$unwind": "t"}]),
+            json!([{"$group": {"n": {"$sum": 1}}}]),
+            json!([{"$group": {"_id": null, "n": {"$first": "$a"}}}]),
+            json!(["$match"]),
+        ] {
+            assert!(
+                aggregate(pipeline.clone(), json!([])).is_err(),
+                "{pipeline}"
+            );
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{Collection, DbError, Store, update};
+
+/// One operation of a bulk write, tagged by `op`, e.g.
+/// `{"op": "patch", "id": "...", "ops": {"$inc": {"n": 1}}}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum BulkOp {
+    Insert {
+        /// Caller-chosen id; generated by the collection's strategy when absent.
+        #[serde(default)]
+        id: Option<String>,
+        data: Value,
+        #[serde(default)]
+        ttl: Option<i64>,
+    },
+    Update {
+        id: String,
+        data: Value,
+    },
+    Patch {
+        id: String,
+        ops: Value,
+    },
+    Upsert {
+        id: String,
+        data: Value,
+    },
+    Delete {
+        id: String,
+    },
+}
+
+impl BulkOp {
+    /// Parses newline-delimited JSON, one operation per non-blank line.
+    pub fn parse_ndjson(input: &str) -> Result<Vec<Self>, DbError> {
+        input
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(n, line)| {
+                serde_json::from_str(line)
+                    .map_err(|e| DbError::InvalidUpdate(format!("line {}: {}", n + 1, e)))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkItem {
+    pub index: usize,
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Per-operation outcome of a bulk write. In ordered mode the batch stops at
+/// the first failure, so `items` may be shorter than the request.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BulkResult {
+    pub inserted: usize,
+    pub updated: usize,
+    pub deleted: usize,
+    pub failed: usize,
+    pub items: Vec<BulkItem>,
+}
+
+impl BulkResult {
+    pub fn is_ok(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+enum Applied {
+    Inserted,
+    Updated,
+    Deleted,
+}
+
+fn apply_one(
+    col: &Collection,
+    store: &mut Store,
+    op: BulkOp,
+) -> Result<(String, Applied), DbError> {
+    match op {
+        BulkOp::Insert { id, data, ttl } => {
+            let mut doc = col.new_document(store, id.as_deref(), data, ttl)?;
+            col.admit(store, &mut doc)?;
+            let id = doc.id.clone();
+            col.put(store, doc.clone())?;
+            col.record_write(&doc)?;
+            col.evict(store)?;
+            Ok((id, Applied::Inserted))
+        }
+        BulkOp::Update { id, data } => {
+            let mut doc = store.get(&id, Utc::now()).ok_or(DbError::NotFound)?.clone();
+            col.validate(&data)?;
+            doc.data = data;
+            doc.touch();
+            col.put(store, doc.clone())?;
+            col.record_write(&doc)?;
+            col.evict(store)?;
+            Ok((id, Applied::Updated))
+        }
+        BulkOp::Patch { id, ops } => {
+            let ops = update::parse(&ops)?;
+            let mut doc = store.get(&id, Utc::now()).ok_or(DbError::NotFound)?.clone();
+            update::apply(&ops, &mut doc.data)?;
+            col.validate(&doc.data)?;
+            doc.touch();
+            col.put(store, doc.clone())?;
+            col.record_write(&doc)?;
+            col.evict(store)?;
+            Ok((id, Applied::Updated))
+        }
+        BulkOp::Upsert { id, data } => {
+            let (doc, applied) = match store.get(&id, Utc::now()) {
+                Some(doc) => {
+                    col.validate(&data)?;
+                    let mut doc = doc.clone();
+                    doc.data = data;
+                    doc.touch();
+                    (doc, Applied::Updated)
+                }
+                None => {
+                    let mut doc = col.new_document(store, Some(&id), data, None)?;
+                    col.admit(store, &mut doc)?;
+                    (doc, Applied::Inserted)
+                }
+            };
+            col.put(store, doc.clone())?;
+            col.record_write(&doc)?;
+            col.evict(store)?;
+            Ok((id, applied))
+        }
+        BulkOp::Delete { id } => {
+            store.get(&id, Utc::now()).ok_or(DbError::NotFound)?;
+            let doc = store.take(&id).ok_or(DbError::NotFound)?;
+            col.record_delete(&doc)?;
+            col.discard(doc)?;
+            Ok((id, Applied::Deleted))
+        }
+    }
+}
+
+/// Applies `ops` in order, recording each outcome. Failed operations leave
+/// no trace; `ordered` stops at the first one. `col` supplies the id
+/// strategy, schema and limits of the collection being written.
+pub(super) fn apply(
+    col: &Collection,
+    store: &mut Store,
+    ops: Vec<BulkOp>,
+    ordered: bool,
+) -> BulkResult {
+    let mut result = BulkResult::default();
+    for (index, op) in ops.into_iter().enumerate() {
+        let target = match &op {
+            BulkOp::Insert { id, .. } => id.clone(),
+            BulkOp::Update { id, .. }
+            | BulkOp::Patch { id, .. }
+            | BulkOp::Upsert { id, .. }
+            | BulkOp::Delete { id } => Some(id.clone()),
+        };
+        match apply_one(col, store, op) {
+            Ok((id, applied)) => {
+                match applied {
+                    Applied::Inserted => result.inserted += 1,
+                    Applied::Updated => result.updated += 1,
+                    Applied::Deleted => result.deleted += 1,
+                }
+                result.items.push(BulkItem {
+                    index,
+                    id: Some(id),
+                    error: None,
+                });
+            }
+            Err(e) => {
+                result.failed += 1;
+                result.items.push(BulkItem {
+                    index,
+                    id: target,
+                    error: Some(e.to_string()),
+                });
+                if ordered {
+                    break;
+                }
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, Limits, test_dir};
+    use serde_json::json;
+    use std::fs;
+
+    fn ops(ndjson: &str) -> Vec<BulkOp> {
+        BulkOp::parse_ndjson(ndjson).unwrap()
+    }
+
+    #[test]
+    fn ndjson_errors_name_the_source_line() {
+        let input = "\n{\"op\": \"delete\", \"id\": \"a\"}\n  \n{\"op\": \"drop\"}\n";
+        let err = BulkOp::parse_ndjson(input).unwrap_err().to_string();
+        assert!(err.contains("line 4"), "{err}");
+        assert_eq!(ops("{\"op\":\"delete\",\"id\":\"a\"}\r\n\r\n").len(), 1);
+        assert!(BulkOp::parse_ndjson("{\"op\": \"insert\"}").is_err());
+    }
+
+    #[test]
+    fn ordered_batches_stop_and_unordered_ones_go_on() {
+        let dir = test_dir("bulk-modes");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let batch = r#"
+            {"op": "upsert", "id": "a", "data": {"n": 1}}
+            {"op": "update", "id": "missing", "data": {"n": 2}}
+            {"op": "patch", "id": "a", "ops": {"$inc": {"n": 1}}}
+            {"op": "patch", "id": "a", "ops": {"$push": {"n": 1}}}
+            {"op": "upsert", "id": "b", "data": {"n": 5}}
+        "#;
+        let result = col.bulk_write(ops(batch), true).unwrap();
+        assert_eq!(
+            (result.inserted, result.failed, result.items.len()),
+            (1, 1, 2)
+        );
+        assert_eq!(result.items[1].id.as_deref(), Some("missing"));
+        assert!(result.items[1].error.is_some());
+        assert!(col.find("b").unwrap().is_none());
+
+        col.delete("a").unwrap();
+        let result = col.bulk_write(ops(batch), false).unwrap();
+        let counts = (result.inserted, result.updated, result.failed);
+        assert_eq!(counts, (2, 1, 2));
+        let failed: Vec<usize> = result
+            .items
+            .iter()
+            .filter(|item| item.error.is_some())
+            .map(|item| item.index)
+            .collect();
+        assert_eq!(failed, [1, 3]);
+        // later operations see the earlier ones of the same batch, and a
+        // failed one leaves no trace
+        assert_eq!(col.find("a").unwrap().unwrap().data, json!({"n": 2}));
+        assert!(!result.is_ok());
+
+        let reloaded = Database::load(&dir).unwrap().collection("c").unwrap();
+        assert_eq!(reloaded.find_all().unwrap().len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn operations_past_the_limit_fail_alone() {
+        let dir = test_dir("bulk-limit");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        col.set_limits(Limits {
+            max_documents: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+        let batch = r#"
+            {"op": "insert", "data": {}}
+            {"op": "insert", "data": {}}
+            {"op": "insert", "data": {}}
+            {"op": "upsert", "id": "x", "data": {}}
+        "#;
+        let result = col.bulk_write(ops(batch), false).unwrap();
+        assert_eq!((result.inserted, result.failed), (2, 2));
+        let deleted = format!(
+            r#"{{"op": "delete", "id": "{}"}}
+            {{"op": "upsert", "id": "x", "data": {{}}}}"#,
+            result.items[0].id.as_deref().unwrap()
+        );
+        let result = col.bulk_write(ops(&deleted), true).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(col.find_all().unwrap().len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
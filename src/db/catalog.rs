@@ -0,0 +1,257 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+use tracing::{error, info};
+
+use super::{Database, DbError, Quota, TtlCleaner, ttl};
+
+/// Named databases under one root. The root itself is the default database;
+/// every other database lives in `<root>/<name>/`.
+#[derive(Debug, Clone)]
+pub struct Catalog {
+    root: PathBuf,
+    default: Database,
+    databases: Arc<RwLock<HashMap<String, Database>>>,
+}
+
+impl Catalog {
+    pub fn open<P: AsRef<Path>>(root: P) -> Result<Self, DbError> {
+        let root = root.as_ref().to_path_buf();
+        let default = Database::load(&root)?;
+        let mut databases = HashMap::new();
+        if root.exists() {
+            for entry in fs::read_dir(&root)? {
+                let entry_path = entry?.path();
+                let Some(name) = entry_path.file_name().and_then(|s| s.to_str()) else {
+                    continue;
+                };
+                // `_`-prefixed directories belong to the default database
+                if entry_path.is_dir() && check_name(name).is_ok() {
+                    info!("Loading database: {}", name);
+                    databases.insert(name.to_string(), Database::load(&entry_path)?);
+                }
+            }
+        }
+        Ok(Self {
+            root,
+            default,
+            databases: Arc::new(RwLock::new(databases)),
+        })
+    }
+
+    /// The database rooted at the catalog root.
+    pub fn default_database(&self) -> Database {
+        self.default.clone()
+    }
+
+    pub fn database(&self, name: &str) -> Result<Database, DbError> {
+        self.databases
+            .read()
+            .map_err(|_| DbError::LockPoisoned)?
+            .get(name)
+            .cloned()
+            .ok_or(DbError::DatabaseNotFound)
+    }
+
+    pub fn create_database(&self, name: &str, quota: Quota) -> Result<Database, DbError> {
+        check_name(name)?;
+        let mut databases = self.databases.write().map_err(|_| DbError::LockPoisoned)?;
+        let path = self.root.join(name);
+        if databases.contains_key(name) || path.exists() {
+            return Err(DbError::DatabaseAlreadyExists);
+        }
+        fs::create_dir_all(&path)?;
+        let db = Database::load(&path)?;
+        db.set_quota(quota)?;
+        databases.insert(name.to_string(), db.clone());
+        info!("Created database: {}", name);
+        Ok(db)
+    }
+
+    /// Removes a database and everything in it.
+    pub fn drop_database(&self, name: &str) -> Result<(), DbError> {
+        let mut databases = self.databases.write().map_err(|_| DbError::LockPoisoned)?;
+        let db = databases.remove(name).ok_or(DbError::DatabaseNotFound)?;
+        // handles held elsewhere must not write files back into the directory
+        db.retire()?;
+        fs::remove_dir_all(self.root.join(name))?;
+        info!("Dropped database: {}", name);
+        Ok(())
+    }
+
+    /// Names of the named databases, sorted.
+    pub fn database_names(&self) -> Result<Vec<String>, DbError> {
+        let mut names: Vec<String> = self
+            .databases
+            .read()
+            .map_err(|_| DbError::LockPoisoned)?
+            .keys()
+            .cloned()
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+
+    /// Like [`Database::start_ttl_cleaner`], for the default database and
+    /// every named one, including those created later.
+    pub fn start_ttl_cleaner(&self, interval_secs: u64) -> TtlCleaner {
+        let catalog = self.clone();
+        ttl::spawn_cleaner(interval_secs, move || {
+            let mut databases = vec![catalog.default_database()];
+            databases.extend(
+                catalog
+                    .databases
+                    .read()
+                    .map_err(|_| DbError::LockPoisoned)?
+                    .values()
+                    .cloned(),
+            );
+            let mut purged = 0;
+            for db in databases {
+                match db.sweep() {
+                    Ok(n) => purged += n,
+                    Err(e) => error!("TTL cleanup failed for {}: {}", db.path().display(), e),
+                }
+            }
+            Ok(purged)
+        })
+    }
+}
+
+fn check_name(name: &str) -> Result<(), DbError> {
+    let valid = name.len() <= 64
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(DbError::InvalidName(format!(
+            "database names are 1-64 letters, digits, '_' or '-', starting with a letter or digit: {:?}",
+            name
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_dir;
+    use serde_json::json;
+
+    #[test]
+    fn databases_are_created_dropped_and_reopened() {
+        let dir = test_dir("catalog");
+        let catalog = Catalog::open(&dir).unwrap();
+        let quota = Quota {
+            max_documents: Some(10),
+            ..Quota::default()
+        };
+        let db = catalog.create_database("one", quota.clone()).unwrap();
+        assert!(matches!(
+            catalog.create_database("one", Quota::default()),
+            Err(DbError::DatabaseAlreadyExists)
+        ));
+        assert!(matches!(
+            catalog.create_database("_one", Quota::default()),
+            Err(DbError::InvalidName(_))
+        ));
+        let col = db.collection("c").unwrap();
+        col.insert_with_id("x", json!({}), None).unwrap();
+
+        let catalog = Catalog::open(&dir).unwrap();
+        assert_eq!(catalog.database_names().unwrap(), ["one"]);
+        let reopened = catalog.database("one").unwrap();
+        assert_eq!(reopened.quota(), quota);
+        assert!(
+            reopened
+                .collection("c")
+                .unwrap()
+                .find("x")
+                .unwrap()
+                .is_some()
+        );
+        // the default database doesn't see the named one's collections
+        assert!(
+            catalog
+                .default_database()
+                .collection_names()
+                .unwrap()
+                .is_empty()
+        );
+
+        catalog.drop_database("one").unwrap();
+        assert!(!dir.join("one").exists());
+        // a handle held across the drop can't bring the directory back
+        let err = reopened
+            .collection("c")
+            .unwrap()
+            .insert(json!({}), None)
+            .unwrap_err();
+        assert!(matches!(err, DbError::CollectionNotFound), "{err}");
+        assert!(!dir.join("one").exists());
+        assert!(matches!(
+            catalog.drop_database("one"),
+            Err(DbError::DatabaseNotFound)
+        ));
+        assert!(
+            Catalog::open(&dir)
+                .unwrap()
+                .database_names()
+                .unwrap()
+                .is_empty()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn each_database_is_held_to_its_own_quota() {
+        let dir = test_dir("catalog-quota");
+        let catalog = Catalog::open(&dir).unwrap();
+        let quota = Quota {
+            max_collections: Some(1),
+            max_documents: Some(1),
+            max_bytes: None,
+        };
+        let small = catalog.create_database("small", quota).unwrap();
+        let large = catalog.create_database("large", Quota::default()).unwrap();
+        small
+            .collection("a")
+            .unwrap()
+            .insert(json!({}), None)
+            .unwrap();
+        let full = |result: Result<(), DbError>| {
+            assert!(matches!(result, Err(DbError::QuotaExceeded(_))));
+        };
+        let a = small.collection("a").unwrap();
+        full(a.insert(json!({}), None).map(drop));
+        full(
+            small
+                .collection("b")
+                .unwrap()
+                .insert(json!({}), None)
+                .map(drop),
+        );
+        full(small.create_collection("c"));
+        for name in ["a", "b"] {
+            large
+                .collection(name)
+                .unwrap()
+                .insert(json!({}), None)
+                .unwrap();
+            large
+                .collection(name)
+                .unwrap()
+                .insert(json!({}), None)
+                .unwrap();
+        }
+        assert_eq!(large.usage().documents, 4);
+        let usage = catalog.database("small").unwrap().usage();
+        assert_eq!((usage.collections, usage.documents), (1, 1));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
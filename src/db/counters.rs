@@ -0,0 +1,165 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{DbError, file};
+
+/// Named sequences and counters of a database, stored in
+/// `_database/counters.json` and saved before every change is returned.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(super) struct Counters {
+    /// Last value handed out by each sequence.
+    #[serde(default)]
+    pub sequences: BTreeMap<String, u64>,
+    #[serde(default)]
+    pub counters: BTreeMap<String, i64>,
+}
+
+pub(super) fn path(db_path: &Path) -> PathBuf {
+    db_path.join("_database").join("counters.json")
+}
+
+pub(super) fn check_name(name: &str) -> Result<(), DbError> {
+    if name.is_empty() || name.len() > 64 || name.chars().any(char::is_control) {
+        return Err(DbError::InvalidName(format!(
+            "{:?}: counter and sequence names are 1-64 bytes without control characters",
+            name
+        )));
+    }
+    Ok(())
+}
+
+impl Counters {
+    pub fn load(path: &Path) -> Result<Self, DbError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    // Atomic, so a crash never leaves a torn file behind and a sequence
+    // value is never handed out twice.
+    pub fn save(&self, path: &Path) -> Result<(), DbError> {
+        file::write_atomic(path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    pub fn next_sequence(&mut self, name: &str) -> Result<u64, DbError> {
+        let last = self.sequences.get(name).copied().unwrap_or(0);
+        let next = last
+            .checked_add(1)
+            .ok_or_else(|| DbError::InvalidUpdate(format!("sequence {} is exhausted", name)))?;
+        self.sequences.insert(name.to_string(), next);
+        Ok(next)
+    }
+
+    pub fn add(&mut self, name: &str, by: i64) -> Result<i64, DbError> {
+        let value = self.counters.get(name).copied().unwrap_or(0);
+        let value = value
+            .checked_add(by)
+            .ok_or_else(|| DbError::InvalidUpdate(format!("counter {} would overflow", name)))?;
+        self.counters.insert(name.to_string(), value);
+        Ok(value)
+    }
+
+    /// Makes a sequence continue after `last`. Moving it back would hand
+    /// out values again, so that takes `force`.
+    pub fn set_sequence(&mut self, name: &str, last: u64, force: bool) -> Result<(), DbError> {
+        let current = self.sequences.get(name).copied().unwrap_or(0);
+        if last < current && !force {
+            return Err(DbError::InvalidUpdate(format!(
+                "sequence {} is already at {}; moving it back to {} needs force",
+                name, current, last
+            )));
+        }
+        self.sequences.insert(name.to_string(), last);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::Database;
+
+    #[test]
+    fn sequences_and_counters_refuse_to_overflow() {
+        let mut counters = Counters::default();
+        assert_eq!(counters.next_sequence("s").unwrap(), 1);
+        counters.sequences.insert("t".to_string(), u64::MAX);
+        assert!(counters.next_sequence("t").is_err());
+        assert!(counters.add("c", i64::MAX).is_ok());
+        assert!(counters.add("c", 1).is_err());
+        assert_eq!(counters.counters["c"], i64::MAX);
+    }
+
+    #[test]
+    fn concurrent_callers_get_distinct_values_that_survive_a_restart() {
+        let dir = crate::db::test_dir("sequences");
+        let db = Database::new(&dir).unwrap();
+        let mut values: Vec<u64> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        (0..50)
+                            .map(|_| {
+                                db.increment_counter("c", 1).unwrap();
+                                db.next_sequence("s").unwrap()
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        });
+        values.sort_unstable();
+        assert_eq!(values, (1..=200).collect::<Vec<_>>());
+
+        let db = Database::load(&dir).unwrap();
+        assert_eq!(db.next_sequence("s").unwrap(), 201);
+        assert_eq!(db.counter("c").unwrap(), 200);
+        for name in ["", "a\nb", &"x".repeat(65)] {
+            assert!(matches!(
+                db.next_sequence(name),
+                Err(DbError::InvalidName(_))
+            ));
+        }
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn sequences_only_move_back_when_forced() {
+        let mut counters = Counters::default();
+        counters.set_sequence("s", 10, false).unwrap();
+        assert_eq!(counters.next_sequence("s").unwrap(), 11);
+        assert!(counters.set_sequence("s", 5, false).is_err());
+        counters.set_sequence("s", 11, false).unwrap();
+        counters.set_sequence("s", 5, true).unwrap();
+        assert_eq!(counters.next_sequence("s").unwrap(), 6);
+    }
+
+    #[test]
+    fn a_failed_save_changes_nothing() {
+        let dir = crate::db::test_dir("counters");
+        let db = Database::new(&dir).unwrap();
+        assert_eq!(db.next_sequence("s").unwrap(), 1);
+        db.increment_counter("c", 2).unwrap();
+        // a directory in the file's place makes every save fail
+        let file = path(&dir);
+        fs::remove_file(&file).unwrap();
+        fs::create_dir(&file).unwrap();
+        assert!(db.next_sequence("s").is_err());
+        assert!(db.increment_counter("c", 1).is_err());
+        assert_eq!(db.sequence("s").unwrap(), 1);
+        assert_eq!(db.counter("c").unwrap(), 2);
+
+        fs::remove_dir(&file).unwrap();
+        assert_eq!(db.next_sequence("s").unwrap(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use super::DbError;
+
+/// Replaces the file at `path` with `contents`. The data goes to a temporary
+/// file next to it, is synced and then renamed over the original, so a crash
+/// leaves either the old file or the new one, never a torn mix of both.
+pub(super) fn write_atomic(path: &Path, contents: &[u8]) -> Result<(), DbError> {
+    let dir = parent(path);
+    fs::create_dir_all(dir)?;
+    let tmp = temp_path(path);
+    let mut file = fs::File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)?;
+    sync_dir(dir)?;
+    Ok(())
+}
+
+/// Appends `contents` to the file at `path`, creating it if needed, and
+/// syncs it. A crash can leave a torn tail, which readers must tolerate.
+pub(super) fn append(path: &Path, contents: &[u8]) -> Result<(), DbError> {
+    let dir = parent(path);
+    fs::create_dir_all(dir)?;
+    let created = !path.exists();
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(contents)?;
+    file.sync_data()?;
+    if created {
+        sync_dir(dir)?;
+    }
+    Ok(())
+}
+
+fn parent(path: &Path) -> &Path {
+    match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+// `<name>.tmp`, which no loader mistakes for a `.json` file.
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".tmp");
+    path.with_file_name(name)
+}
+
+// Makes the rename itself durable.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_the_file_and_leaves_no_temporary_behind() {
+        let dir = crate::db::test_dir("file");
+        let path = dir.join("nested").join("c.json");
+        write_atomic(&path, b"{\"a\":1}").unwrap();
+        write_atomic(&path, b"{}").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+        let names: Vec<_> = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(names, ["c.json"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn temporary_file_is_not_json() {
+        let tmp = temp_path(Path::new("/data/users.json"));
+        assert_eq!(tmp, Path::new("/data/users.json.tmp"));
+        assert_ne!(tmp.extension().unwrap(), "json");
+    }
+}
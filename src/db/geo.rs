@@ -0,0 +1,444 @@
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+const EARTH_RADIUS_M: f64 = 6_371_008.8;
+const METERS_PER_DEGREE: f64 = 111_320.0;
+const BASE32: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+// ~5m cells; queries scan prefixes of these keys
+const INDEX_PRECISION: usize = 9;
+const MAX_COVER_CELLS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    pub lng: f64,
+    pub lat: f64,
+}
+
+impl Point {
+    pub fn new(lng: f64, lat: f64) -> Self {
+        Self { lng, lat }
+    }
+
+    fn from_coords(v: &Value) -> Option<Self> {
+        let arr = v.as_array()?;
+        if arr.len() < 2 {
+            return None;
+        }
+        let lng = arr[0].as_f64()?;
+        let lat = arr[1].as_f64()?;
+        if !(-180.0..=180.0).contains(&lng) || !(-90.0..=90.0).contains(&lat) {
+            return None;
+        }
+        Some(Self { lng, lat })
+    }
+
+    /// Great-circle distance in meters.
+    pub fn distance_to(&self, other: &Point) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlng = (other.lng - self.lng).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlng / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+    pub exterior: Vec<Point>,
+    pub holes: Vec<Vec<Point>>,
+}
+
+impl Polygon {
+    fn from_coords(v: &Value) -> Option<Self> {
+        let mut rings = v
+            .as_array()?
+            .iter()
+            .map(|ring| {
+                ring.as_array()?
+                    .iter()
+                    .map(Point::from_coords)
+                    .collect::<Option<Vec<_>>>()
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if rings.is_empty() || rings.iter().any(|r| r.len() < 4) {
+            return None;
+        }
+        let exterior = rings.remove(0);
+        Some(Self {
+            exterior,
+            holes: rings,
+        })
+    }
+
+    pub fn contains(&self, p: &Point) -> bool {
+        ring_contains(&self.exterior, p) && !self.holes.iter().any(|h| ring_contains(h, p))
+    }
+
+    fn bbox(&self) -> BBox {
+        BBox::around(&self.exterior)
+    }
+}
+
+fn ring_contains(ring: &[Point], p: &Point) -> bool {
+    let mut inside = false;
+    let mut j = ring.len() - 1;
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[j]);
+        if (a.lat > p.lat) != (b.lat > p.lat)
+            && p.lng < (b.lng - a.lng) * (p.lat - a.lat) / (b.lat - a.lat) + a.lng
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
+}
+
+/// A GeoJSON geometry stored in a document field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Point(Point),
+    Polygon(Polygon),
+}
+
+impl Geometry {
+    /// Accepts GeoJSON `Point`/`Polygon` objects and legacy `[lng, lat]` pairs.
+    pub fn from_json(v: &Value) -> Option<Self> {
+        if v.is_array() {
+            return Point::from_coords(v).map(Geometry::Point);
+        }
+        let coords = v.get("coordinates")?;
+        match v.get("type")?.as_str()? {
+            "Point" => Point::from_coords(coords).map(Geometry::Point),
+            "Polygon" => Polygon::from_coords(coords).map(Geometry::Polygon),
+            _ => None,
+        }
+    }
+
+    /// Representative point used for indexing and distance ordering.
+    pub fn anchor(&self) -> Point {
+        match self {
+            Geometry::Point(p) => *p,
+            Geometry::Polygon(poly) => {
+                // the closing vertex repeats the first one
+                let ring = &poly.exterior[..poly.exterior.len() - 1];
+                let n = ring.len() as f64;
+                Point::new(
+                    ring.iter().map(|p| p.lng).sum::<f64>() / n,
+                    ring.iter().map(|p| p.lat).sum::<f64>() / n,
+                )
+            }
+        }
+    }
+
+    fn vertices(&self) -> &[Point] {
+        match self {
+            Geometry::Point(p) => std::slice::from_ref(p),
+            Geometry::Polygon(poly) => &poly.exterior,
+        }
+    }
+}
+
+/// Query region for `$geoWithin`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoShape {
+    /// Center and radius in meters.
+    Circle(Point, f64),
+    Polygon(Polygon),
+}
+
+impl GeoShape {
+    pub fn contains(&self, geometry: &Geometry) -> bool {
+        geometry.vertices().iter().all(|p| match self {
+            GeoShape::Circle(center, radius) => center.distance_to(p) <= *radius,
+            GeoShape::Polygon(poly) => poly.contains(p),
+        })
+    }
+
+    fn bbox(&self) -> BBox {
+        match self {
+            GeoShape::Circle(center, radius) => BBox::circle(center, *radius),
+            GeoShape::Polygon(poly) => poly.bbox(),
+        }
+    }
+}
+
+/// `$near` condition: matches within the distance bounds and orders by distance.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Near {
+    pub point: Point,
+    pub min_distance: Option<f64>,
+    pub max_distance: Option<f64>,
+}
+
+impl Near {
+    pub fn matches(&self, geometry: &Geometry) -> bool {
+        let d = self.point.distance_to(&geometry.anchor());
+        self.min_distance.is_none_or(|min| d >= min) && self.max_distance.is_none_or(|max| d <= max)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BBox {
+    min: Point,
+    max: Point,
+}
+
+impl BBox {
+    fn around(points: &[Point]) -> Self {
+        let mut bbox = BBox {
+            min: Point::new(f64::MAX, f64::MAX),
+            max: Point::new(f64::MIN, f64::MIN),
+        };
+        for p in points {
+            bbox.min.lng = bbox.min.lng.min(p.lng);
+            bbox.min.lat = bbox.min.lat.min(p.lat);
+            bbox.max.lng = bbox.max.lng.max(p.lng);
+            bbox.max.lat = bbox.max.lat.max(p.lat);
+        }
+        bbox
+    }
+
+    fn circle(center: &Point, radius: f64) -> Self {
+        let dlat = radius / METERS_PER_DEGREE;
+        let min_lat = (center.lat - dlat).max(-90.0);
+        let max_lat = (center.lat + dlat).min(90.0);
+        let cos = center
+            .lat
+            .abs()
+            .max(min_lat.abs())
+            .max(max_lat.abs())
+            .to_radians()
+            .cos();
+        let dlng = if cos > 1e-6 { dlat / cos } else { 360.0 };
+        let (min_lng, max_lng) = if center.lng - dlng < -180.0 || center.lng + dlng > 180.0 {
+            // wraps the antimeridian; fall back to the full longitude range
+            (-180.0, 180.0)
+        } else {
+            (center.lng - dlng, center.lng + dlng)
+        };
+        BBox {
+            min: Point::new(min_lng, min_lat),
+            max: Point::new(max_lng, max_lat),
+        }
+    }
+}
+
+fn geohash(p: &Point, precision: usize) -> String {
+    let (mut lng_range, mut lat_range) = ((-180.0, 180.0), (-90.0, 90.0));
+    let mut hash = String::with_capacity(precision);
+    let (mut bits, mut ch, mut even) = (0, 0usize, true);
+    while hash.len() < precision {
+        let (range, value) = if even {
+            (&mut lng_range, p.lng)
+        } else {
+            (&mut lat_range, p.lat)
+        };
+        let mid = (range.0 + range.1) / 2.0;
+        ch <<= 1;
+        if value >= mid {
+            ch |= 1;
+            range.0 = mid;
+        } else {
+            range.1 = mid;
+        }
+        even = !even;
+        bits += 1;
+        if bits == 5 {
+            hash.push(BASE32[ch] as char);
+            bits = 0;
+            ch = 0;
+        }
+    }
+    hash
+}
+
+fn cell_size(precision: usize) -> (f64, f64) {
+    let bits = 5 * precision as i32;
+    let lng_bits = (bits + 1) / 2;
+    let lat_bits = bits / 2;
+    (360.0 / 2f64.powi(lng_bits), 180.0 / 2f64.powi(lat_bits))
+}
+
+/// Geohash prefixes whose cells together cover the bounding box.
+fn cover(bbox: &BBox) -> Vec<String> {
+    for precision in (1..=INDEX_PRECISION).rev() {
+        let (w, h) = cell_size(precision);
+        let col = |lng: f64| ((lng + 180.0) / w).floor().min(360.0 / w - 1.0) as i64;
+        let row = |lat: f64| ((lat + 90.0) / h).floor().min(180.0 / h - 1.0) as i64;
+        let (c0, c1) = (col(bbox.min.lng), col(bbox.max.lng));
+        let (r0, r1) = (row(bbox.min.lat), row(bbox.max.lat));
+        if ((c1 - c0 + 1) * (r1 - r0 + 1)) as usize > MAX_COVER_CELLS && precision > 1 {
+            continue;
+        }
+        let mut cells = Vec::new();
+        for c in c0..=c1 {
+            for r in r0..=r1 {
+                let center =
+                    Point::new(-180.0 + (c as f64 + 0.5) * w, -90.0 + (r as f64 + 0.5) * h);
+                cells.push(geohash(&center, precision));
+            }
+        }
+        return cells;
+    }
+    unreachable!("precision 1 always returns")
+}
+
+/// Geohash index over one document field.
+#[derive(Debug, Clone, Default)]
+pub struct GeoIndex {
+    cells: BTreeMap<String, HashSet<String>>,
+    entries: HashMap<String, String>,
+}
+
+impl GeoIndex {
+    pub fn insert(&mut self, id: &str, geometry: &Geometry) {
+        self.remove(id);
+        let hash = geohash(&geometry.anchor(), INDEX_PRECISION);
+        self.cells
+            .entry(hash.clone())
+            .or_default()
+            .insert(id.to_string());
+        self.entries.insert(id.to_string(), hash);
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        if let Some(hash) = self.entries.remove(id)
+            && let Some(ids) = self.cells.get_mut(&hash)
+        {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.cells.remove(&hash);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn all(&self) -> HashSet<String> {
+        self.entries.keys().cloned().collect()
+    }
+
+    /// Ids whose anchor may fall inside `shape`; callers still check the filter.
+    pub fn within(&self, shape: &GeoShape) -> HashSet<String> {
+        self.scan(&shape.bbox())
+    }
+
+    pub fn near(&self, near: &Near) -> HashSet<String> {
+        match near.max_distance {
+            Some(max) => self.scan(&BBox::circle(&near.point, max)),
+            None => self.all(),
+        }
+    }
+
+    fn scan(&self, bbox: &BBox) -> HashSet<String> {
+        let mut ids = HashSet::new();
+        for prefix in cover(bbox) {
+            for (_, cell) in self
+                .cells
+                .range(prefix.clone()..)
+                .take_while(|(hash, _)| hash.starts_with(&prefix))
+            {
+                ids.extend(cell.iter().cloned());
+            }
+        }
+        ids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn ids(list: &[&str]) -> HashSet<String> {
+        list.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn only_valid_geojson_parses() {
+        assert!(Geometry::from_json(&json!([180, -90])).is_some());
+        for bad in [
+            json!([180.5, 0]),
+            json!([0, 91]),
+            json!([1]),
+            json!(["1", "2"]),
+            json!({"type": "Point", "coordinates": [0, -90.1]}),
+            json!({"type": "LineString", "coordinates": [[0, 0], [1, 1]]}),
+            // a ring needs four positions, the last closing it
+            json!({"type": "Polygon", "coordinates": [[[0, 0], [1, 0], [0, 0]]]}),
+            json!({"type": "Polygon", "coordinates": []}),
+        ] {
+            assert!(Geometry::from_json(&bad).is_none(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn polygons_exclude_their_holes() {
+        let square = json!({"type": "Polygon", "coordinates": [
+            [[0, 0], [10, 0], [10, 10], [0, 10], [0, 0]],
+            [[4, 4], [6, 4], [6, 6], [4, 6], [4, 4]],
+        ]});
+        let Some(Geometry::Polygon(poly)) = Geometry::from_json(&square) else {
+            panic!("not a polygon");
+        };
+        assert!(poly.contains(&Point::new(2.0, 2.0)));
+        assert!(!poly.contains(&Point::new(5.0, 5.0)));
+        assert!(!poly.contains(&Point::new(11.0, 5.0)));
+        // the closing vertex does not pull the anchor towards the first corner
+        assert_eq!(Geometry::Polygon(poly).anchor(), Point::new(5.0, 5.0));
+    }
+
+    #[test]
+    fn circles_reach_across_the_antimeridian() {
+        let mut index = GeoIndex::default();
+        index.insert("east", &Geometry::Point(Point::new(179.95, 0.0)));
+        index.insert("west", &Geometry::Point(Point::new(-179.95, 0.0)));
+        index.insert("far", &Geometry::Point(Point::new(170.0, 0.0)));
+        let center = Point::new(-179.99, 0.0);
+        let shape = GeoShape::Circle(center, 20_000.0);
+        let found = index.within(&shape);
+        assert!(found.is_superset(&ids(&["east", "west"])));
+        let east = Geometry::Point(Point::new(179.95, 0.0));
+        assert!(shape.contains(&east));
+
+        let near = Near {
+            point: center,
+            min_distance: Some(5_000.0),
+            max_distance: Some(20_000.0),
+        };
+        assert!(index.near(&near).contains("east"));
+        assert!(near.matches(&east));
+        assert!(!near.matches(&Geometry::Point(Point::new(-179.95, 0.0))));
+    }
+
+    #[test]
+    fn circles_around_a_pole_cover_every_longitude() {
+        let mut index = GeoIndex::default();
+        for (id, lng) in [("a", -170.0), ("b", 0.0), ("c", 95.0)] {
+            index.insert(id, &Geometry::Point(Point::new(lng, 89.99)));
+        }
+        let shape = GeoShape::Circle(Point::new(0.0, 90.0), 5_000.0);
+        assert_eq!(index.within(&shape), ids(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn moved_and_removed_documents_leave_their_old_cells() {
+        let mut index = GeoIndex::default();
+        let here = GeoShape::Circle(Point::new(0.0, 0.0), 1_000.0);
+        index.insert("a", &Geometry::Point(Point::new(0.0, 0.0)));
+        index.insert("a", &Geometry::Point(Point::new(50.0, 50.0)));
+        assert_eq!(index.entries.len(), 1);
+        assert!(index.within(&here).is_empty());
+        index.remove("a");
+        index.remove("a");
+        assert!(index.entries.is_empty());
+        assert!(index.cells.is_empty());
+    }
+}
@@ -0,0 +1,411 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+use super::{CollectionName, DbError, Document, file, ttl};
+
+/// How much history a collection keeps. Limits never drop the latest
+/// revision of a document that still exists.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryRetention {
+    /// Revisions kept per document, newest first.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_revisions: Option<usize>,
+    /// Seconds a revision is kept after it was superseded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<i64>,
+}
+
+impl HistoryRetention {
+    pub(super) fn check(&self) -> Result<(), DbError> {
+        self.max_age.map(ttl::check_ttl).transpose()?;
+        Ok(())
+    }
+}
+
+/// One state of a document in its history.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub version: u64,
+    pub at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// The document as written, or `None` when this revision deleted it.
+    pub document: Option<Document>,
+}
+
+/// Revisions of every document of a collection, oldest first, stored in
+/// `_history/<name>.json` as a log of one [`LogEntry`] per line. Saving
+/// appends what was recorded since; the log is only rewritten once pruned
+/// revisions make up most of it.
+#[derive(Debug, Default)]
+pub(super) struct History {
+    revisions: HashMap<String, Vec<Revision>>,
+    /// Revisions in `revisions`.
+    len: usize,
+    /// Recorded but not yet appended to the log.
+    pending: Vec<(String, Revision)>,
+    /// Lines in the log on disk.
+    logged: usize,
+    /// Set when the log must be rewritten from `revisions` on the next save.
+    rewrite: bool,
+}
+
+/// One line of the history log.
+#[derive(Serialize, Deserialize)]
+struct LogEntry<'a> {
+    id: Cow<'a, str>,
+    revision: Cow<'a, Revision>,
+}
+
+// Lines of pruned revisions tolerated in the log before it is rewritten.
+const LOG_SLACK: usize = 1024;
+
+pub(super) fn path(db_path: &Path, name: &CollectionName) -> PathBuf {
+    name.file_in(&db_path.join("_history"))
+}
+
+impl History {
+    /// Replays the log at `path`, pruning what `retention` no longer keeps.
+    pub fn load(path: &Path, retention: Option<&HistoryRetention>) -> Result<Self, DbError> {
+        let mut history = Self::default();
+        if !path.exists() {
+            return Ok(history);
+        }
+        let raw = fs::read_to_string(path)?;
+        // histories written before the log were a single JSON map
+        if let Ok(revisions) = serde_json::from_str::<HashMap<String, Vec<Revision>>>(&raw) {
+            history.revisions = revisions;
+            history.rewrite = true;
+        } else {
+            let lines: Vec<&str> = raw.lines().filter(|line| !line.trim().is_empty()).collect();
+            for (i, line) in lines.iter().enumerate() {
+                match serde_json::from_str::<LogEntry>(line) {
+                    Ok(entry) => history
+                        .revisions
+                        .entry(entry.id.into_owned())
+                        .or_default()
+                        .push(entry.revision.into_owned()),
+                    // a crash can tear the last append, which was never acknowledged
+                    Err(e) if i + 1 == lines.len() => {
+                        warn!("Dropping torn history entry in {}: {}", path.display(), e);
+                        history.rewrite = true;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            history.logged = lines.len();
+        }
+        if let Some(retention) = retention {
+            let now = Utc::now();
+            history.revisions.retain(|_, revisions| {
+                prune(revisions, retention, now);
+                !revisions.is_empty()
+            });
+        }
+        history.len = history.revisions.values().map(Vec::len).sum();
+        Ok(history)
+    }
+
+    pub fn save_if_dirty(&mut self, path: &Path) -> Result<(), DbError> {
+        if self.logged + self.pending.len() > 2 * self.len + LOG_SLACK {
+            self.rewrite = true;
+        }
+        if self.rewrite {
+            let mut log = String::new();
+            for (id, revisions) in &self.revisions {
+                for revision in revisions {
+                    log.push_str(&log_line(id, revision)?);
+                }
+            }
+            file::write_atomic(path, log.as_bytes())?;
+            self.logged = self.len;
+            self.rewrite = false;
+        } else if !self.pending.is_empty() {
+            let mut log = String::new();
+            for (id, revision) in &self.pending {
+                log.push_str(&log_line(id, revision)?);
+            }
+            file::append(path, log.as_bytes())?;
+            self.logged += self.pending.len();
+        }
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Starts the history of documents written before it was enabled.
+    pub fn seed<'a>(&mut self, docs: impl Iterator<Item = &'a Document>) {
+        for doc in docs {
+            self.revisions.entry(doc.id.clone()).or_insert_with(|| {
+                self.len += 1;
+                vec![Revision {
+                    version: doc.version,
+                    at: doc.updated_at,
+                    author: None,
+                    document: Some(doc.clone()),
+                }]
+            });
+        }
+        self.rewrite = true;
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn record(&mut self, id: &str, revision: Revision, retention: &HistoryRetention) {
+        self.pending.push((id.to_string(), revision.clone()));
+        let revisions = self.revisions.entry(id.to_string()).or_default();
+        let before = revisions.len();
+        revisions.push(revision);
+        prune(revisions, retention, Utc::now());
+        self.len = self.len + revisions.len() - before;
+        if revisions.is_empty() {
+            self.revisions.remove(id);
+        }
+    }
+
+    pub fn revisions(&self, id: &str) -> Option<&[Revision]> {
+        self.revisions.get(id).map(Vec::as_slice)
+    }
+
+    /// The document as it was at `at`, if it existed then.
+    pub fn as_of(&self, id: &str, at: DateTime<Utc>) -> Option<&Document> {
+        latest_before(self.revisions.get(id)?, at)
+    }
+
+    pub fn all_as_of(&self, at: DateTime<Utc>) -> impl Iterator<Item = &Document> {
+        self.revisions
+            .values()
+            .filter_map(move |revisions| latest_before(revisions, at))
+    }
+}
+
+fn log_line(id: &str, revision: &Revision) -> Result<String, DbError> {
+    let entry = LogEntry {
+        id: Cow::Borrowed(id),
+        revision: Cow::Borrowed(revision),
+    };
+    let mut line = serde_json::to_string(&entry)?;
+    line.push('\n');
+    Ok(line)
+}
+
+fn latest_before(revisions: &[Revision], at: DateTime<Utc>) -> Option<&Document> {
+    revisions
+        .iter()
+        .rev()
+        .find(|revision| revision.at <= at)?
+        .document
+        .as_ref()
+}
+
+// A revision ages from the moment the next one replaced it; a deletion is
+// the end of the document, so its tombstone ages from when it was written.
+fn prune(revisions: &mut Vec<Revision>, retention: &HistoryRetention, now: DateTime<Utc>) {
+    if let Some(max) = retention.max_revisions {
+        let excess = revisions.len().saturating_sub(max.max(1));
+        revisions.drain(..excess);
+    }
+    if let Some(cutoff) = retention
+        .max_age
+        .and_then(|max_age| ttl::offset(now, -max_age))
+    {
+        let superseded = (1..revisions.len())
+            .take_while(|&i| revisions[i].at <= cutoff)
+            .count();
+        revisions.drain(..superseded);
+        if revisions.len() == 1 && revisions[0].document.is_none() && revisions[0].at <= cutoff {
+            revisions.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, Query, SoftDelete, test_dir};
+    use serde_json::json;
+
+    fn versions(revisions: &[Revision]) -> Vec<u64> {
+        revisions.iter().map(|revision| revision.version).collect()
+    }
+
+    #[test]
+    fn revisions_follow_every_write_and_survive_a_reopen() {
+        let dir = test_dir("history");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let doc = col.insert(json!({"n": 0}), None).unwrap();
+        assert!(matches!(
+            col.revisions(&doc.id),
+            Err(DbError::HistoryDisabled)
+        ));
+        col.set_history(Some(HistoryRetention::default())).unwrap();
+        let before = Utc::now();
+        let col = col.with_author("ann");
+        col.update(&doc.id, json!({"n": 1, "s": "x"})).unwrap();
+        col.delete(&doc.id).unwrap();
+
+        let revisions = col.revisions(&doc.id).unwrap();
+        assert_eq!(versions(&revisions), [1, 2, 3]);
+        assert_eq!(revisions[0].author, None);
+        assert_eq!(revisions[1].author.as_deref(), Some("ann"));
+        assert!(revisions[2].document.is_none());
+        assert_eq!(
+            col.find_as_of(&doc.id, before).unwrap().unwrap().data,
+            json!({"n": 0})
+        );
+        assert_eq!(col.query_as_of(&Query::default(), before).unwrap().len(), 1);
+        assert!(col.find_as_of(&doc.id, Utc::now()).unwrap().is_none());
+        assert_eq!(
+            col.diff(&doc.id, 1, 2).unwrap(),
+            json!([
+                {"op": "replace", "path": "/n", "value": 1},
+                {"op": "add", "path": "/s", "value": "x"},
+            ])
+        );
+
+        let restored = col.restore(&doc.id, 1).unwrap();
+        assert_eq!((restored.version, restored.data), (4, json!({"n": 0})));
+        assert!(col.restore(&doc.id, 3).is_err());
+        let col = Database::new(&dir).unwrap().collection("c").unwrap();
+        assert_eq!(versions(&col.revisions(&doc.id).unwrap()), [1, 2, 3, 4]);
+        col.set_history(None).unwrap();
+        assert!(!path(&dir, &CollectionName::new("c").unwrap()).exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_revisions() {
+        let dir = test_dir("history-retention");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        col.set_history(Some(HistoryRetention {
+            max_revisions: Some(2),
+            ..Default::default()
+        }))
+        .unwrap();
+        let doc = col.insert(json!({"n": 0}), None).unwrap();
+        for n in 1..5 {
+            col.update(&doc.id, json!({"n": n})).unwrap();
+        }
+        assert_eq!(versions(&col.revisions(&doc.id).unwrap()), [4, 5]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn revisions_are_numbered_monotonically_per_id() {
+        let dir = test_dir("history");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        col.set_history(Some(HistoryRetention::default())).unwrap();
+        col.set_soft_delete(Some(SoftDelete::default())).unwrap();
+        col.insert_with_id("a", json!({"n": 1}), None).unwrap();
+        col.delete("a").unwrap();
+        col.insert_with_id("a", json!({"n": 2}), None).unwrap();
+        col.delete("a").unwrap();
+        col.undelete("a").unwrap();
+        col.delete("a").unwrap();
+        col.restore("a", 1).unwrap();
+
+        let numbers = versions(&col.revisions("a").unwrap());
+        assert_eq!(numbers.len(), 7);
+        assert!(
+            numbers.windows(2).all(|pair| pair[0] < pair[1]),
+            "{:?}",
+            numbers
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writes_append_to_the_log_and_reload_from_it() {
+        let dir = test_dir("history-log");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        col.set_history(Some(HistoryRetention::default())).unwrap();
+        let doc = col.insert(json!({"n": 0}), None).unwrap();
+        for n in 1..5 {
+            col.update(&doc.id, json!({"n": n})).unwrap();
+        }
+        let log_path = path(&dir, &CollectionName::new("c").unwrap());
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert_eq!(log.lines().count(), 5);
+
+        // a torn append is dropped, and the next save rewrites the log
+        fs::write(&log_path, format!("{}{{\"id\":", log)).unwrap();
+        let col = Database::load(&dir).unwrap().collection("c").unwrap();
+        assert_eq!(versions(&col.revisions(&doc.id).unwrap()), [1, 2, 3, 4, 5]);
+        col.update(&doc.id, json!({"n": 5})).unwrap();
+        let log = fs::read_to_string(&log_path).unwrap();
+        assert_eq!(log.lines().count(), 6);
+        assert!(
+            log.lines()
+                .all(|line| serde_json::from_str::<LogEntry>(line).is_ok())
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn loads_histories_written_as_one_map() {
+        let dir = test_dir("history-legacy");
+        let doc = Document::new("a".into(), json!({}), None).unwrap();
+        let legacy = HashMap::from([(
+            "a".to_string(),
+            vec![Revision {
+                version: 1,
+                at: doc.updated_at,
+                author: None,
+                document: Some(doc),
+            }],
+        )]);
+        let log_path = dir.join("c.json");
+        file::write_atomic(
+            &log_path,
+            serde_json::to_string(&legacy).unwrap().as_bytes(),
+        )
+        .unwrap();
+
+        let mut history = History::load(&log_path, None).unwrap();
+        assert_eq!(versions(history.revisions("a").unwrap()), [1]);
+        history.save_if_dirty(&log_path).unwrap();
+        let history = History::load(&log_path, None).unwrap();
+        assert_eq!(history.logged, 1);
+        assert_eq!(versions(history.revisions("a").unwrap()), [1]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn pruned_revisions_are_compacted_out_of_the_log() {
+        let dir = test_dir("history-prune");
+        let log_path = dir.join("c.json");
+        let retention = HistoryRetention {
+            max_revisions: Some(1),
+            ..Default::default()
+        };
+        let mut history = History::default();
+        let mut doc = Document::new("a".into(), json!({}), None).unwrap();
+        for _ in 0..LOG_SLACK + 10 {
+            doc.touch();
+            let revision = Revision {
+                version: doc.version,
+                at: doc.updated_at,
+                author: None,
+                document: Some(doc.clone()),
+            };
+            history.record("a", revision, &retention);
+            history.save_if_dirty(&log_path).unwrap();
+        }
+        assert!(fs::read_to_string(&log_path).unwrap().lines().count() <= LOG_SLACK + 2);
+        let history = History::load(&log_path, Some(&retention)).unwrap();
+        assert_eq!(versions(history.revisions("a").unwrap()), [doc.version]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
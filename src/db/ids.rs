@@ -0,0 +1,136 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{str::FromStr, sync::Mutex};
+use uuid::Uuid;
+
+use super::{DbError, query::path_value};
+
+/// How a collection picks ids for documents inserted without one.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IdStrategy {
+    /// Random UUIDv4.
+    #[default]
+    Uuid,
+    /// Time-ordered UUIDv7.
+    UuidV7,
+    /// Time-ordered ULID in Crockford base32.
+    Ulid,
+    /// Integers from a persistent per-collection counter, starting at 1.
+    /// Zero-padded to 20 digits, the width of any `u64`, so that they sort
+    /// as strings in the order they were handed out.
+    AutoIncrement,
+    /// The value of a field of the document, making it a natural key.
+    Field { field: String },
+}
+
+impl FromStr for IdStrategy {
+    type Err = DbError;
+
+    /// Parses `uuid`, `uuidv7`, `ulid`, `auto-increment` or `field:<path>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "uuid" | "uuidv4" => Ok(Self::Uuid),
+            "uuidv7" => Ok(Self::UuidV7),
+            "ulid" => Ok(Self::Ulid),
+            "auto-increment" | "autoincrement" => Ok(Self::AutoIncrement),
+            _ => match s.strip_prefix("field:") {
+                Some(field) if !field.is_empty() => Ok(Self::Field {
+                    field: field.to_string(),
+                }),
+                _ => Err(DbError::InvalidId(format!("unknown id strategy '{}'", s))),
+            },
+        }
+    }
+}
+
+impl IdStrategy {
+    /// Generates an id for `data`. `counter` is the collection's next
+    /// auto-increment value and is advanced when used.
+    pub(super) fn generate(&self, data: &Value, counter: &mut u64) -> Result<String, DbError> {
+        match self {
+            Self::Uuid => Ok(Uuid::new_v4().to_string()),
+            Self::UuidV7 => Ok(Uuid::now_v7().to_string()),
+            Self::Ulid => Ok(ulid()),
+            Self::AutoIncrement => {
+                let id = *counter;
+                *counter = id
+                    .checked_add(1)
+                    .ok_or_else(|| DbError::InvalidId("auto-increment ids are exhausted".into()))?;
+                Ok(format!("{:020}", id))
+            }
+            Self::Field { field } => match path_value(data, field) {
+                Some(Value::String(s)) => check(s),
+                Some(Value::Number(n)) => Ok(n.to_string()),
+                _ => Err(DbError::InvalidId(format!(
+                    "field '{}' must be a string or number",
+                    field
+                ))),
+            },
+        }
+    }
+}
+
+/// Validates a caller-supplied id.
+pub(super) fn check(id: &str) -> Result<String, DbError> {
+    if id.is_empty() || id.trim() != id || id.chars().any(char::is_control) {
+        return Err(DbError::InvalidId(format!("'{}' is not a valid id", id)));
+    }
+    Ok(id.to_string())
+}
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+// The last ULID handed out by this process.
+static LAST_ULID: Mutex<u128> = Mutex::new(0);
+
+// 48-bit millisecond timestamp followed by 80 random bits. Within one
+// millisecond, or if the clock steps back, the last ULID plus one is used
+// instead, so ids from this process always sort in creation order.
+fn ulid() -> String {
+    let millis = Utc::now().timestamp_millis() as u128 & ((1 << 48) - 1);
+    // skip the version and variant bits in the middle of the v4 uuid
+    let bits = Uuid::new_v4().as_u128();
+    let random = ((bits >> 80) << 32) | (bits & 0xffff_ffff);
+    let mut value = (millis << 80) | random;
+    let mut last = LAST_ULID
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if value >> 80 <= *last >> 80 {
+        value = *last + 1;
+    }
+    *last = value;
+    drop(last);
+    (0..26)
+        .rev()
+        .map(|i| CROCKFORD[((value >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_increment_ids_sort_in_order() {
+        let mut counter = 9;
+        let strategy = IdStrategy::AutoIncrement;
+        let ids: Vec<String> = (0..3)
+            .map(|_| strategy.generate(&Value::Null, &mut counter).unwrap())
+            .collect();
+        assert_eq!(ids[0], "00000000000000000009");
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(counter, 12);
+        let mut last = u64::MAX;
+        assert!(strategy.generate(&Value::Null, &mut last).is_err());
+        assert_eq!(last, u64::MAX);
+    }
+
+    #[test]
+    fn ulids_sort_in_order_within_a_millisecond() {
+        let ids: Vec<String> = (0..1000).map(|_| ulid()).collect();
+        assert!(ids.iter().all(|id| id.len() == 26));
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
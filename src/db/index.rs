@@ -0,0 +1,411 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet},
+    ops::Bound,
+};
+
+use super::{
+    Capped, Document,
+    geo::{GeoIndex, Geometry},
+    planner::Access,
+    query::{Condition, field_value, same_type, total_cmp},
+    ttl::{ExpiryIndex, TtlIndex},
+    vector::{VectorIndex, VectorIndexOptions, vector_from_json},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexKind {
+    Value,
+    Geo,
+    Vector,
+    /// Expires documents after a date field; see [`super::Collection::create_ttl_index`].
+    Ttl,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexStats {
+    pub field: String,
+    pub kind: IndexKind,
+    /// Indexed (document, key) pairs.
+    pub entries: usize,
+    /// Distinct keys; unknown for geo and vector indexes.
+    pub distinct_keys: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct IndexKey(Value);
+
+impl PartialEq for IndexKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for IndexKey {}
+
+impl PartialOrd for IndexKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for IndexKey {
+    fn cmp(&self, other: &Self) -> Ordering {
+        total_cmp(&self.0, &other.0)
+    }
+}
+
+/// Ordered index over the values of one field. Arrays are indexed per element.
+#[derive(Debug, Clone, Default)]
+pub struct ValueIndex {
+    keys: BTreeMap<IndexKey, HashSet<String>>,
+    entries: HashMap<String, Vec<IndexKey>>,
+    size: usize,
+}
+
+impl ValueIndex {
+    fn insert(&mut self, id: &str, value: &Value) {
+        self.remove(id);
+        let mut keys: Vec<IndexKey> = match value {
+            Value::Array(items) => items.iter().cloned().map(IndexKey).collect(),
+            v => vec![IndexKey(v.clone())],
+        };
+        keys.sort();
+        keys.dedup();
+        for key in &keys {
+            self.keys
+                .entry(key.clone())
+                .or_default()
+                .insert(id.to_string());
+        }
+        self.size += keys.len();
+        self.entries.insert(id.to_string(), keys);
+    }
+
+    fn remove(&mut self, id: &str) {
+        let Some(keys) = self.entries.remove(id) else {
+            return;
+        };
+        self.size -= keys.len();
+        for key in keys {
+            if let Some(ids) = self.keys.get_mut(&key) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.keys.remove(&key);
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    pub fn distinct_keys(&self) -> usize {
+        self.keys.len()
+    }
+
+    fn eq(&self, value: &Value) -> HashSet<String> {
+        self.keys
+            .get(&IndexKey(value.clone()))
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Ids with a key inside the bounds, restricted to the bounds' JSON type
+    /// to mirror the query comparison operators.
+    fn range(&self, lower: Bound<&Value>, upper: Bound<&Value>) -> HashSet<String> {
+        let (lo, hi) = (bound_value(lower), bound_value(upper));
+        let Some(typed) = lo.or(hi) else {
+            return HashSet::new();
+        };
+        if let (Some(lo), Some(hi)) = (lo, hi) {
+            match total_cmp(lo, hi) {
+                Ordering::Greater => return HashSet::new(),
+                Ordering::Equal
+                    if matches!(lower, Bound::Excluded(_))
+                        || matches!(upper, Bound::Excluded(_)) =>
+                {
+                    return HashSet::new();
+                }
+                _ if !same_type(lo, hi) => return HashSet::new(),
+                _ => {}
+            }
+        }
+        let to_key = |b: Bound<&Value>| b.map(|v| IndexKey(v.clone()));
+        self.keys
+            .range((to_key(lower), to_key(upper)))
+            .skip_while(|(k, _)| !same_type(&k.0, typed))
+            .take_while(|(k, _)| same_type(&k.0, typed))
+            .flat_map(|(_, ids)| ids.iter().cloned())
+            .collect()
+    }
+}
+
+fn bound_value(bound: Bound<&Value>) -> Option<&Value> {
+    match bound {
+        Bound::Included(v) | Bound::Excluded(v) => Some(v),
+        Bound::Unbounded => None,
+    }
+}
+
+/// The documents of a capped collection, oldest first, with their sizes,
+/// so eviction finds the oldest and the total size without a scan.
+#[derive(Debug, Clone, Default)]
+pub struct CreationOrder {
+    order: BTreeMap<(DateTime<Utc>, String), usize>,
+    /// Sizes are only measured when the collection is capped by bytes.
+    measure: bool,
+    bytes: usize,
+}
+
+impl CreationOrder {
+    fn insert(&mut self, doc: &Document) {
+        let size = if self.measure {
+            serde_json::to_vec(&doc.data).map_or(0, |data| data.len())
+        } else {
+            0
+        };
+        let key = (doc.created_at, doc.id.clone());
+        if let Some(old) = self.order.insert(key, size) {
+            self.bytes -= old;
+        }
+        self.bytes += size;
+    }
+
+    fn remove(&mut self, doc: &Document) {
+        if let Some(size) = self.order.remove(&(doc.created_at, doc.id.clone())) {
+            self.bytes -= size;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Size of the documents' data serialized as JSON.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn oldest(&self) -> Option<&str> {
+        self.order.keys().next().map(|(_, id)| id.as_str())
+    }
+
+    pub fn newest(&self) -> Option<DateTime<Utc>> {
+        self.order
+            .keys()
+            .next_back()
+            .map(|(created_at, _)| *created_at)
+    }
+}
+
+/// Secondary indexes of a collection, kept in sync with its documents.
+#[derive(Debug, Clone, Default)]
+pub struct Indexes {
+    value: HashMap<String, ValueIndex>,
+    geo: HashMap<String, GeoIndex>,
+    vector: HashMap<String, VectorIndex>,
+    expiry: ExpiryIndex,
+    /// Kept only while the collection is capped.
+    order: Option<CreationOrder>,
+}
+
+impl Indexes {
+    pub fn create_value<'a>(&mut self, field: &str, docs: impl Iterator<Item = &'a Document>) {
+        let mut index = ValueIndex::default();
+        for doc in docs {
+            if let Some(value) = field_value(doc, field) {
+                index.insert(&doc.id, &value);
+            }
+        }
+        self.value.insert(field.to_string(), index);
+    }
+
+    pub fn drop_value(&mut self, field: &str) -> bool {
+        self.value.remove(field).is_some()
+    }
+
+    pub fn value(&self, field: &str) -> Option<&ValueIndex> {
+        self.value.get(field)
+    }
+
+    pub fn geo(&self, field: &str) -> Option<&GeoIndex> {
+        self.geo.get(field)
+    }
+
+    pub fn create_geo<'a>(&mut self, field: &str, docs: impl Iterator<Item = &'a Document>) {
+        let mut index = GeoIndex::default();
+        for doc in docs {
+            if let Some(geometry) = field_value(doc, field).and_then(|v| Geometry::from_json(&v)) {
+                index.insert(&doc.id, &geometry);
+            }
+        }
+        self.geo.insert(field.to_string(), index);
+    }
+
+    pub fn drop_geo(&mut self, field: &str) -> bool {
+        self.geo.remove(field).is_some()
+    }
+
+    pub fn geo_fields(&self) -> Vec<String> {
+        self.geo.keys().cloned().collect()
+    }
+
+    pub fn create_vector<'a>(
+        &mut self,
+        field: &str,
+        options: VectorIndexOptions,
+        docs: impl Iterator<Item = &'a Document>,
+    ) {
+        let mut index = VectorIndex::new(options);
+        for doc in docs {
+            if let Some(vector) = field_value(doc, field).and_then(|v| vector_from_json(&v)) {
+                index.insert(&doc.id, vector);
+            }
+        }
+        self.vector.insert(field.to_string(), index);
+    }
+
+    pub fn drop_vector(&mut self, field: &str) -> bool {
+        self.vector.remove(field).is_some()
+    }
+
+    pub fn vector(&self, field: &str) -> Option<&VectorIndex> {
+        self.vector.get(field)
+    }
+
+    pub fn vector_fields(&self) -> Vec<(String, VectorIndexOptions)> {
+        self.vector
+            .iter()
+            .map(|(field, index)| (field.clone(), index.options().clone()))
+            .collect()
+    }
+
+    /// Rebuilds the expiry order, e.g. after the TTL indexes changed.
+    pub fn create_expiry<'a>(
+        &mut self,
+        ttl_indexes: Vec<TtlIndex>,
+        docs: impl Iterator<Item = &'a Document>,
+    ) {
+        self.expiry = ExpiryIndex::build(ttl_indexes, docs);
+    }
+
+    pub fn expiry(&self) -> &ExpiryIndex {
+        &self.expiry
+    }
+
+    /// Rebuilds the creation order for `capped`, or drops it with `None`.
+    pub fn create_order<'a>(
+        &mut self,
+        capped: Option<&Capped>,
+        docs: impl Iterator<Item = &'a Document>,
+    ) {
+        self.order = capped.map(|capped| {
+            let mut order = CreationOrder {
+                measure: capped.max_bytes.is_some(),
+                ..CreationOrder::default()
+            };
+            for doc in docs {
+                order.insert(doc);
+            }
+            order
+        });
+    }
+
+    pub fn order(&self) -> Option<&CreationOrder> {
+        self.order.as_ref()
+    }
+
+    pub fn stats(&self) -> Vec<IndexStats> {
+        let value = self.value.iter().map(|(field, index)| IndexStats {
+            field: field.clone(),
+            kind: IndexKind::Value,
+            entries: index.len(),
+            distinct_keys: Some(index.distinct_keys()),
+        });
+        let geo = self.geo.iter().map(|(field, index)| IndexStats {
+            field: field.clone(),
+            kind: IndexKind::Geo,
+            entries: index.len(),
+            distinct_keys: None,
+        });
+        let vector = self.vector.iter().map(|(field, index)| IndexStats {
+            field: field.clone(),
+            kind: IndexKind::Vector,
+            entries: index.len(),
+            distinct_keys: None,
+        });
+        let mut stats: Vec<IndexStats> = value.chain(geo).chain(vector).collect();
+        stats.sort_by(|a, b| a.field.cmp(&b.field));
+        stats
+    }
+
+    pub fn insert(&mut self, doc: &Document) {
+        for (field, index) in self.value.iter_mut() {
+            match field_value(doc, field) {
+                Some(value) => index.insert(&doc.id, &value),
+                None => index.remove(&doc.id),
+            }
+        }
+        for (field, index) in self.geo.iter_mut() {
+            match field_value(doc, field).and_then(|v| Geometry::from_json(&v)) {
+                Some(geometry) => index.insert(&doc.id, &geometry),
+                None => index.remove(&doc.id),
+            }
+        }
+        for (field, index) in self.vector.iter_mut() {
+            match field_value(doc, field).and_then(|v| vector_from_json(&v)) {
+                Some(vector) => index.insert(&doc.id, vector),
+                None => index.remove(&doc.id),
+            }
+        }
+        self.expiry.insert(doc);
+        if let Some(order) = &mut self.order {
+            order.insert(doc);
+        }
+    }
+
+    pub fn remove(&mut self, doc: &Document) {
+        for index in self.value.values_mut() {
+            index.remove(&doc.id);
+        }
+        for index in self.geo.values_mut() {
+            index.remove(&doc.id);
+        }
+        for index in self.vector.values_mut() {
+            index.remove(&doc.id);
+        }
+        self.expiry.remove(&doc.id);
+        if let Some(order) = &mut self.order {
+            order.remove(doc);
+        }
+    }
+
+    /// Ids produced by an index access path; `None` means scan everything.
+    pub fn fetch(&self, access: &Access) -> Option<HashSet<String>> {
+        match access {
+            Access::FullScan => None,
+            Access::Ids(ids) => Some(ids.iter().cloned().collect()),
+            Access::Eq(field, values) => {
+                let index = self.value.get(*field)?;
+                Some(values.iter().flat_map(|v| index.eq(v)).collect())
+            }
+            Access::Range(field, lower, upper) => {
+                Some(self.value.get(*field)?.range(*lower, *upper))
+            }
+            Access::Geo(field, cond) => {
+                let index = self.geo.get(*field)?;
+                match cond {
+                    Condition::GeoWithin(shape) => Some(index.within(shape)),
+                    Condition::Near(near) => Some(index.near(near)),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
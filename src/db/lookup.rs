@@ -0,0 +1,188 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use super::{
+    Database, DbError, Document, Query,
+    query::{field_value, set_path},
+};
+
+/// Embeds documents of `from` whose `foreign_field` equals this document's
+/// `local_field`, as an array under `as_field`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Lookup {
+    pub from: String,
+    pub local_field: String,
+    pub foreign_field: String,
+    #[serde(rename = "as")]
+    pub as_field: String,
+}
+
+impl Lookup {
+    pub fn new(from: &str, local_field: &str, foreign_field: &str, as_field: &str) -> Self {
+        Self {
+            from: from.to_string(),
+            local_field: local_field.to_string(),
+            foreign_field: foreign_field.to_string(),
+            as_field: as_field.to_string(),
+        }
+    }
+
+    /// Parses the REST `include=` syntax: a comma-separated list of
+    /// `localField:collection[.foreignField]`, e.g. `customer_id:customers` or
+    /// `_id:orders.customer_id`. The foreign field defaults to `_id` and the
+    /// result is embedded under the collection name.
+    pub fn parse_include(spec: &str) -> Result<Vec<Self>, DbError> {
+        spec.split(',')
+            .filter(|part| !part.trim().is_empty())
+            .map(|part| {
+                let (local, target) = part.trim().split_once(':').ok_or_else(|| {
+                    DbError::InvalidQuery(format!(
+                        "include '{}' must look like localField:collection[.foreignField]",
+                        part
+                    ))
+                })?;
+                let (from, foreign) = target.split_once('.').unwrap_or((target, "_id"));
+                if local.is_empty() || from.is_empty() || foreign.is_empty() {
+                    return Err(DbError::InvalidQuery(format!("invalid include '{}'", part)));
+                }
+                Ok(Self::new(from, local, foreign, from))
+            })
+            .collect()
+    }
+}
+
+// Numbers are keyed by value so that 1 and 1.0 join.
+fn join_key(value: &Value) -> String {
+    match value.as_f64() {
+        Some(n) if value.is_number() => format!("n:{}", n),
+        _ => value.to_string(),
+    }
+}
+
+fn keys(value: Option<&Value>) -> Vec<&Value> {
+    match value {
+        Some(Value::Array(items)) => items.iter().collect(),
+        Some(v) => vec![v],
+        None => Vec::new(),
+    }
+}
+
+pub fn join(
+    db: &Database,
+    mut docs: Vec<Document>,
+    lookups: &[Lookup],
+) -> Result<Vec<Document>, DbError> {
+    for lookup in lookups {
+        let foreign = db.get_collection(&lookup.from)?;
+
+        let locals: Vec<Vec<Value>> = docs
+            .iter()
+            .map(|doc| {
+                let value = field_value(doc, &lookup.local_field);
+                keys(value.as_deref()).into_iter().cloned().collect()
+            })
+            .collect();
+        let mut wanted: Vec<Value> = locals.iter().flatten().cloned().collect();
+        wanted.sort_by_key(join_key);
+        wanted.dedup_by(|a, b| join_key(a) == join_key(b));
+
+        // one query per lookup so the foreign collection can use its indexes
+        let query =
+            Query::new(serde_json::json!({ lookup.foreign_field.as_str(): { "$in": wanted } }));
+        let matches = foreign.query(&query)?;
+        let mut by_key: HashMap<String, Vec<usize>> = HashMap::new();
+        for (i, doc) in matches.iter().enumerate() {
+            let value = field_value(doc, &lookup.foreign_field);
+            for key in keys(value.as_deref()) {
+                let slot = by_key.entry(join_key(key)).or_default();
+                if slot.last() != Some(&i) {
+                    slot.push(i);
+                }
+            }
+        }
+
+        for (doc, local) in docs.iter_mut().zip(locals) {
+            let mut hits: Vec<usize> = local
+                .iter()
+                .flat_map(|v| by_key.get(&join_key(v)).into_iter().flatten().copied())
+                .collect();
+            hits.sort_unstable();
+            hits.dedup();
+            let embedded = hits
+                .into_iter()
+                .map(|i| serde_json::to_value(&matches[i]))
+                .collect::<Result<Vec<_>, _>>()?;
+            set_path(&mut doc.data, &lookup.as_field, Value::Array(embedded))?;
+        }
+    }
+    Ok(docs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_dir;
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn includes_parse_with_default_foreign_fields() {
+        let lookups = Lookup::parse_include(" customer:customers , _id:orders.customer,").unwrap();
+        assert_eq!(lookups.len(), 2);
+        assert_eq!(lookups[0].foreign_field, "_id");
+        assert_eq!(lookups[0].as_field, "customers");
+        assert_eq!(lookups[1].local_field, "_id");
+        assert_eq!(lookups[1].foreign_field, "customer");
+        assert!(Lookup::parse_include("").unwrap().is_empty());
+        for bad in ["customers", ":customers", "customer:", "customer:c."] {
+            assert!(Lookup::parse_include(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn joins_match_numbers_by_value_and_embed_each_document_once() {
+        let dir = test_dir("lookup");
+        let db = Database::new(&dir).unwrap();
+        let tags = db.collection("tags").unwrap();
+        tags.insert(json!({"code": 1, "name": "one"}), None)
+            .unwrap();
+        tags.insert(json!({"code": [2, 3], "name": "two-three"}), None)
+            .unwrap();
+        tags.insert(json!({"name": "no code"}), None).unwrap();
+        let posts = db.collection("posts").unwrap();
+        let tagged = [
+            json!(1.0),
+            json!([2, 3, 3]),
+            json!(null),
+            json!([]),
+            json!("1"),
+        ];
+        for (i, tags) in tagged.into_iter().enumerate() {
+            posts.insert(json!({"i": i, "tags": tags}), None).unwrap();
+        }
+        posts.insert(json!({"i": 5}), None).unwrap();
+
+        let mut docs = posts.find_all().unwrap();
+        docs.sort_by_key(|doc| doc.data["i"].as_u64());
+        let joined = join(&db, docs, &[Lookup::new("tags", "tags", "code", "t")]).unwrap();
+        let names: Vec<Vec<&str>> = joined
+            .iter()
+            .map(|doc| {
+                doc.data["t"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|t| t["data"]["name"].as_str().unwrap())
+                    .collect()
+            })
+            .collect();
+        let expected: [&[&str]; 6] = [&["one"], &["two-three"], &[], &[], &[], &[]];
+        assert_eq!(names, expected);
+
+        let missing = join(&db, joined, &[Lookup::new("nope", "tags", "_id", "x")]);
+        assert!(missing.is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
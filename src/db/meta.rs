@@ -0,0 +1,175 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    CollectionName, DbError, HistoryRetention, IdStrategy, IndexKind, IndexStats, Schema,
+    SoftDelete, file, ttl, vector::VectorIndexOptions,
+};
+
+/// Settings of a collection, chosen when it is created and adjustable later.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CollectionOptions {
+    #[serde(default)]
+    pub id_strategy: IdStrategy,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schema: Option<Schema>,
+    /// Seconds until documents inserted without their own TTL expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_ttl: Option<i64>,
+    #[serde(default)]
+    pub engine: StorageEngine,
+    #[serde(default)]
+    pub limits: Limits,
+    /// Keeps prior revisions of documents when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<HistoryRetention>,
+    /// Moves deleted documents and the dropped collection to the trash when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub soft_delete: Option<SoftDelete>,
+    /// Keeps only the newest documents when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub capped: Option<Capped>,
+}
+
+impl CollectionOptions {
+    /// Rejects out-of-range TTLs and retention periods.
+    pub fn check(&self) -> Result<(), DbError> {
+        self.default_ttl.map(ttl::check_ttl).transpose()?;
+        if let Some(history) = &self.history {
+            history.check()?;
+        }
+        if let Some(soft_delete) = &self.soft_delete {
+            soft_delete.check()?;
+        }
+        Ok(())
+    }
+}
+
+/// How a collection's documents are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageEngine {
+    /// The whole collection as one JSON file, rewritten on every write.
+    #[default]
+    Json,
+}
+
+/// Writes that would exceed a limit fail with `LimitExceeded`, or
+/// `DocumentTooLarge` for the size of a single document.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Limits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_documents: Option<usize>,
+    /// Size of a document's data serialized as JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_document_bytes: Option<usize>,
+}
+
+/// Bounds of a capped collection. Inserts past a bound evict the oldest
+/// documents, by `created_at`, instead of failing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capped {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_documents: Option<usize>,
+    /// Total size of the documents' data serialized as JSON.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<usize>,
+}
+
+impl Capped {
+    pub(super) fn exceeded(&self, documents: usize, bytes: usize) -> bool {
+        self.max_documents.is_some_and(|max| documents > max)
+            || self.max_bytes.is_some_and(|max| bytes > max)
+    }
+}
+
+/// An index to rebuild when the collection is loaded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexDefinition {
+    pub field: String,
+    pub kind: IndexKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector: Option<VectorIndexOptions>,
+    /// Seconds after the date in `field` that a TTL index expires documents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expire_after: Option<i64>,
+}
+
+/// What `describe` reports about a collection.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionInfo {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub documents: usize,
+    #[serde(flatten)]
+    pub options: CollectionOptions,
+    pub indexes: Vec<IndexStats>,
+}
+
+/// The metadata record, stored next to the data in `_meta/<name>.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct CollectionMeta {
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub options: CollectionOptions,
+    /// Next value handed out by [`IdStrategy::AutoIncrement`]. Never reused,
+    /// even after the documents are deleted.
+    #[serde(default = "first_id")]
+    pub next_id: u64,
+    /// Highest version a deleted document reached, its tombstone included.
+    /// Documents entering the collection start above it, so a recreated id
+    /// never repeats a version, and with it an ETag, of an earlier life.
+    #[serde(default)]
+    pub version_floor: u64,
+    #[serde(default)]
+    pub indexes: Vec<IndexDefinition>,
+}
+
+fn first_id() -> u64 {
+    1
+}
+
+impl Default for CollectionMeta {
+    fn default() -> Self {
+        Self {
+            created_at: Utc::now(),
+            options: CollectionOptions::default(),
+            next_id: first_id(),
+            version_floor: 0,
+            indexes: Vec::new(),
+        }
+    }
+}
+
+pub(super) fn path(db_path: &Path, name: &CollectionName) -> PathBuf {
+    name.file_in(&db_path.join("_meta"))
+}
+
+impl CollectionMeta {
+    /// Reads the record, or `None` for a collection that has never had one.
+    pub fn load(path: &Path) -> Result<Option<Self>, DbError> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), DbError> {
+        file::write_atomic(path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    pub fn remember_index(&mut self, definition: IndexDefinition) {
+        self.forget_index(&definition.field, definition.kind);
+        self.indexes.push(definition);
+    }
+
+    pub fn forget_index(&mut self, field: &str, kind: IndexKind) {
+        self.indexes
+            .retain(|index| !(index.field == field && index.kind == kind));
+    }
+}
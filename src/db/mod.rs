@@ -1,15 +1,69 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
-    fs::{self, OpenOptions},
-    io::Write,
+    collections::{BTreeMap, HashMap},
+    fs,
+    ops::{Deref, DerefMut},
     path::{Path, PathBuf},
-    sync::{Arc, RwLock},
+    sync::{
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 use thiserror::Error;
-use tracing::{debug, error, info};
-use uuid::Uuid;
+use tracing::{debug, error, info, warn};
+
+mod aggregate;
+mod bulk;
+mod catalog;
+mod counters;
+mod file;
+pub mod geo;
+mod history;
+mod ids;
+mod index;
+pub mod lookup;
+mod meta;
+mod name;
+mod patch;
+mod planner;
+mod pmap;
+pub mod query;
+mod quota;
+mod rename;
+mod schema;
+mod sql;
+mod transaction;
+mod trash;
+mod ttl;
+mod update;
+pub mod vector;
+
+pub use bulk::{BulkItem, BulkOp, BulkResult};
+pub use catalog::Catalog;
+use counters::Counters;
+use history::History;
+pub use history::{HistoryRetention, Revision};
+pub use ids::IdStrategy;
+use index::Indexes;
+pub use index::{IndexKind, IndexStats};
+pub use lookup::Lookup;
+use meta::CollectionMeta;
+pub use meta::{Capped, CollectionInfo, CollectionOptions, IndexDefinition, Limits, StorageEngine};
+pub use name::CollectionName;
+pub use planner::{Explain, PlanCandidate};
+use pmap::PMap;
+pub use query::{Filter, Query};
+use quota::{DatabaseSettings, QuotaState};
+pub use quota::{Quota, Usage};
+use rename::RenameJournal;
+pub use schema::{Schema, SchemaViolation, ValidationError};
+pub use transaction::{Transaction, TxOp};
+use trash::Trash;
+pub use trash::{SoftDelete, TrashedCollection, TrashedDocument};
+pub use ttl::{CollectionExpiry, ExpiryStats, MAX_TTL_SECS, TtlCleaner, check_ttl};
+use ttl::{ExpiryMetrics, TtlIndex};
+pub use vector::{KnnHit, KnnQuery};
 
 #[derive(Debug, Error)]
 pub enum DbError {
@@ -23,6 +77,45 @@ pub enum DbError {
     CollectionNotFound,
     #[error("Lock poisoned")]
     LockPoisoned,
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+    #[error("Invalid update: {0}")]
+    InvalidUpdate(String),
+    #[error("Patch test failed at {0}")]
+    PatchTestFailed(String),
+    #[error("Transaction conflict on {0}")]
+    TransactionConflict(String),
+    #[error("Version conflict: expected {expected}, found {actual}")]
+    VersionConflict { expected: u64, actual: u64 },
+    #[error("Invalid id: {0}")]
+    InvalidId(String),
+    #[error("Document with ID {0} already exists")]
+    DuplicateId(String),
+    #[error("History is not enabled for this collection")]
+    HistoryDisabled,
+    #[error("Collection already exists")]
+    CollectionAlreadyExists,
+    #[error("Database not found")]
+    DatabaseNotFound,
+    #[error("Database already exists")]
+    DatabaseAlreadyExists,
+    #[error("Invalid name: {0}")]
+    InvalidName(String),
+    /// A collection already holds as many documents as its limits allow.
+    #[error("Limit exceeded: {0}")]
+    LimitExceeded(String),
+    /// A document is larger than its collection allows.
+    #[error("Document too large: {0}")]
+    DocumentTooLarge(String),
+    /// The database is out of its quota.
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+    #[error("Invalid schema: {0}")]
+    InvalidSchema(String),
+    #[error("Invalid TTL: {0}")]
+    InvalidTtl(String),
+    #[error("Validation failed: {}", schema::describe(.0))]
+    ValidationFailed(Vec<ValidationError>),
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -32,21 +125,254 @@ pub struct Document {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
+    /// Increases by one with every write to the document. Starts at 1, or
+    /// above every version a deleted document of the collection reached, so
+    /// a version never repeats for an id.
+    #[serde(default = "first_version")]
+    pub version: u64,
+}
+
+fn first_version() -> u64 {
+    1
+}
+
+impl Document {
+    fn new(id: String, data: serde_json::Value, ttl: Option<i64>) -> Result<Self, DbError> {
+        let now = Utc::now();
+        Ok(Self {
+            id,
+            data,
+            created_at: now,
+            updated_at: now,
+            expires_at: ttl.map(|secs| ttl::expires_in(now, secs)).transpose()?,
+            version: first_version(),
+        })
+    }
+
+    // Records a write: bumps the version and `updated_at`.
+    fn touch(&mut self) {
+        self.version += 1;
+        self.updated_at = Utc::now();
+    }
+
+    /// The HTTP entity tag for this revision of the document.
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.version)
+    }
+}
+
+/// Picks the document an upsert targets: an exact id, or the oldest
+/// document matching a filter.
+#[derive(Debug, Clone)]
+pub enum Selector {
+    Id(String),
+    Filter(serde_json::Value),
+}
+
+/// A partial modification of a document's data.
+#[derive(Debug, Clone)]
+pub enum Patch {
+    /// Update operators such as `$set` and `$inc`.
+    Operators(serde_json::Value),
+    /// An RFC 7386 JSON Merge Patch.
+    Merge(serde_json::Value),
+    /// An RFC 6902 JSON Patch.
+    Json(serde_json::Value),
+}
+
+/// Whether find-and-modify returns the document as it was before or after
+/// the change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReturnDocument {
+    #[default]
+    Before,
+    After,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionStats {
+    pub name: String,
+    pub documents: usize,
+    pub indexes: Vec<IndexStats>,
+    /// Versions still pinned by open snapshots, including the current one.
+    pub versions: usize,
+}
+
+/// The documents of a collection. Cloning is O(1) and a write copies only
+/// the path to the changed document, so every snapshot keeps its own
+/// version while sharing whatever later writes left untouched.
+type Documents = PMap<Arc<Document>>;
+
+/// What writers change under the collection's lock: the current documents
+/// and the indexes over them. Indexes only ever describe the current
+/// documents; snapshots take the documents alone.
+#[derive(Debug, Default)]
+struct Store {
+    documents: Documents,
+    indexes: Indexes,
+    /// Earlier versions of `documents`, tracked weakly so stats can report
+    /// how many snapshots still pin; each is freed with its last snapshot.
+    retired: Vec<pmap::Retired<Arc<Document>>>,
+    /// Size of every stored document's data, which the quota counts.
+    bytes: u64,
+}
+
+// Expired documents stay in `documents` until the cleaner removes them, but
+// reads and writes never see them.
+impl Store {
+    fn get(&self, id: &str, now: DateTime<Utc>) -> Option<&Document> {
+        self.documents
+            .get(id)
+            .map(|doc| &**doc)
+            .filter(|doc| !self.indexes.expiry().is_expired(&doc.id, now))
+    }
+
+    fn live(&self, now: DateTime<Utc>) -> impl Iterator<Item = &Document> {
+        self.values()
+            .filter(move |doc| !self.indexes.expiry().is_expired(&doc.id, now))
+    }
+
+    /// Every stored document, expired or not.
+    fn values(&self) -> impl Iterator<Item = &Document> {
+        self.documents.values().map(|doc| &**doc)
+    }
+
+    fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Documents reads can see, which is what limits and quotas count.
+    fn live_len(&self, now: DateTime<Utc>) -> usize {
+        self.len() - self.indexes.expiry().overdue(now)
+    }
+
+    /// Stores `doc`, replacing any document with its id, and indexes it.
+    fn put(&mut self, doc: Document) {
+        self.retire_shared();
+        self.indexes.insert(&doc);
+        self.bytes += data_size(&doc);
+        if let Some(old) = self.documents.insert(doc.id.clone(), Arc::new(doc)) {
+            self.bytes -= data_size(&old);
+        }
+    }
+
+    fn take(&mut self, id: &str) -> Option<Document> {
+        if !self.documents.contains_key(id) {
+            return None;
+        }
+        self.retire_shared();
+        let doc = self.documents.remove(id)?;
+        self.indexes.remove(&doc);
+        self.bytes -= data_size(&doc);
+        Some(Arc::unwrap_or_clone(doc))
+    }
+
+    fn clear(&mut self) -> Vec<Arc<Document>> {
+        self.retire_shared();
+        let removed: Vec<Arc<Document>> = self.documents.values().cloned().collect();
+        for doc in &removed {
+            self.indexes.remove(doc);
+        }
+        self.documents.clear();
+        self.bytes = 0;
+        removed
+    }
+
+    // Called before each change: a version a snapshot still holds is left
+    // to that snapshot.
+    fn retire_shared(&mut self) {
+        if let Some(retired) = self.documents.shared() {
+            self.retired.retain(|v| v.is_alive());
+            self.retired.push(retired);
+        }
+    }
+
+    fn retained_versions(&mut self) -> usize {
+        self.retired.retain(|v| v.is_alive());
+        let current = &self.documents;
+        self.retired.iter().filter(|v| !current.is_root(v)).count() + 1
+    }
+
+    fn ttl_indexes(&self) -> Arc<[TtlIndex]> {
+        self.indexes.expiry().ttl_indexes()
+    }
+}
+
+fn data_size(doc: &Document) -> u64 {
+    serde_json::to_vec(&doc.data).map_or(0, |data| data.len() as u64)
+}
+
+/// The write lock on a collection's store. Releasing it records what the
+/// collection now uses with the database quota.
+struct StoreGuard<'a> {
+    store: RwLockWriteGuard<'a, Store>,
+    collection: &'a Collection,
+}
+
+impl Deref for StoreGuard<'_> {
+    type Target = Store;
+
+    fn deref(&self) -> &Store {
+        &self.store
+    }
+}
+
+impl DerefMut for StoreGuard<'_> {
+    fn deref_mut(&mut self) -> &mut Store {
+        &mut self.store
+    }
+}
+
+impl Drop for StoreGuard<'_> {
+    fn drop(&mut self) {
+        let collection = self.collection;
+        // a dropped collection no longer counts
+        if !collection.dropped.load(Ordering::Acquire) {
+            collection.quota.update(
+                &collection.name,
+                self.store.live_len(Utc::now()),
+                self.store.bytes,
+            );
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Collection {
     name: String,
-    documents: Arc<RwLock<HashMap<String, Document>>>,
+    current: Arc<RwLock<Store>>,
+    persist_lock: Arc<Mutex<()>>,
+    /// Set once the collection is dropped or renamed, so handles still held
+    /// elsewhere can't write its old files back.
+    dropped: Arc<AtomicBool>,
+    meta: Arc<Mutex<CollectionMeta>>,
+    history: Arc<Mutex<History>>,
+    trash: Arc<Mutex<Trash>>,
+    quota: Arc<QuotaState>,
     path: PathBuf,
+    meta_path: PathBuf,
+    history_path: PathBuf,
+    trash_path: PathBuf,
+    /// Recorded as the author of revisions written through this handle.
+    author: Option<String>,
 }
 
 impl Collection {
     pub fn new(name: &str, db_path: &Path) -> Result<Self, DbError> {
-        let path = db_path.join(format!("{}.json", name));
+        Self::open(&CollectionName::new(name)?, db_path, Arc::default())
+    }
+
+    fn open(
+        name: &CollectionName,
+        db_path: &Path,
+        quota: Arc<QuotaState>,
+    ) -> Result<Self, DbError> {
+        let path = name.file_in(db_path);
         debug!("Initializing collection at: {}", path.display());
 
-        let documents = if path.exists() {
+        let exists = path.exists();
+        let documents: HashMap<String, Document> = if exists {
             info!("Loading existing collection: {}", name);
             let raw = fs::read_to_string(&path)?;
             serde_json::from_str(&raw)?
@@ -54,11 +380,64 @@ impl Collection {
             fs::create_dir_all(db_path)?;
             HashMap::new()
         };
+        let meta_path = meta::path(db_path, name);
+        // saved with the first write, so opening a name leaves nothing behind
+        let meta = CollectionMeta::load(&meta_path)?.unwrap_or_default();
+
+        let history_path = history::path(db_path, name);
+        let history = History::load(&history_path, meta.options.history.as_ref())?;
+        let trash_path = trash::path(db_path, name);
+        let trash = Trash::load(&trash_path)?;
+
+        let mut indexes = Indexes::default();
+        for index in &meta.indexes {
+            match (index.kind, &index.vector) {
+                (IndexKind::Value, _) => indexes.create_value(&index.field, documents.values()),
+                (IndexKind::Geo, _) => indexes.create_geo(&index.field, documents.values()),
+                (IndexKind::Vector, Some(options)) => {
+                    indexes.create_vector(&index.field, options.clone(), documents.values())
+                }
+                (IndexKind::Vector, None) => {
+                    error!("Vector index {}.{} has no options", name, index.field)
+                }
+                // part of the expiry index below
+                (IndexKind::Ttl, _) => {}
+            }
+        }
+        indexes.create_expiry(
+            TtlIndex::from_definitions(&meta.indexes),
+            documents.values(),
+        );
+        indexes.create_order(meta.options.capped.as_ref(), documents.values());
+        let bytes = documents.values().map(data_size).sum();
+        let store = Store {
+            documents: documents
+                .into_iter()
+                .map(|(id, doc)| (id, Arc::new(doc)))
+                .collect(),
+            indexes,
+            bytes,
+            ..Store::default()
+        };
+        // a collection only counts once its first write or creation saves it
+        if exists {
+            quota.record(name.as_str(), store.live_len(Utc::now()), store.bytes);
+        }
 
         Ok(Self {
             name: name.to_string(),
-            documents: Arc::new(RwLock::new(documents)),
+            current: Arc::new(RwLock::new(store)),
+            persist_lock: Arc::new(Mutex::new(())),
+            dropped: Arc::default(),
+            meta: Arc::new(Mutex::new(meta)),
+            history: Arc::new(Mutex::new(history)),
+            trash: Arc::new(Mutex::new(trash)),
+            quota,
             path,
+            meta_path,
+            history_path,
+            trash_path,
+            author: None,
         })
     }
 
@@ -66,218 +445,2797 @@ impl Collection {
         &self.name
     }
 
-    pub fn insert(&self, data: serde_json::Value, ttl: Option<i64>) -> Result<Document, DbError> {
-        let id = Uuid::new_v4().to_string();
-        let now = Utc::now();
-        let expires_at = ttl.map(|secs| now + chrono::Duration::seconds(secs));
+    /// A handle whose writes are attributed to `author` in the history.
+    pub fn with_author(&self, author: &str) -> Collection {
+        Collection {
+            author: Some(author.to_string()),
+            ..self.clone()
+        }
+    }
 
-        let doc = Document {
-            id: id.clone(),
-            data,
-            created_at: now,
-            updated_at: now,
-            expires_at,
+    pub fn options(&self) -> Result<CollectionOptions, DbError> {
+        Ok(self.meta()?.options.clone())
+    }
+
+    /// Replaces every option at once. A new schema is not checked against
+    /// existing documents.
+    pub fn set_options(&self, options: CollectionOptions) -> Result<(), DbError> {
+        options.check()?;
+        let capped = options.capped.clone();
+        let mut meta = self.meta()?;
+        let had_history = meta.options.history.is_some();
+        let recap = meta.options.capped != capped;
+        meta.options = options;
+        meta.save(&self.meta_path)?;
+        let has_history = meta.options.history.is_some();
+        drop(meta);
+        info!("Updated options of {}", self.name);
+        self.sync_history(had_history, has_history)?;
+        if recap {
+            self.set_capped(capped)?;
+        }
+        Ok(())
+    }
+
+    pub fn info(&self) -> Result<CollectionInfo, DbError> {
+        let store = self.read()?;
+        let meta = self.meta()?;
+        Ok(CollectionInfo {
+            name: self.name.clone(),
+            created_at: meta.created_at,
+            documents: store.len(),
+            options: meta.options.clone(),
+            indexes: {
+                let mut stats = store.indexes.stats();
+                stats.extend(
+                    TtlIndex::from_definitions(&meta.indexes)
+                        .into_iter()
+                        .map(|index| IndexStats {
+                            entries: store
+                                .values()
+                                .filter(|doc| index.deadline(doc).is_some())
+                                .count(),
+                            field: index.field,
+                            kind: IndexKind::Ttl,
+                            distinct_keys: None,
+                        }),
+                );
+                stats.sort_by(|a, b| a.field.cmp(&b.field));
+                stats
+            },
+        })
+    }
+
+    pub fn id_strategy(&self) -> Result<IdStrategy, DbError> {
+        Ok(self.meta()?.options.id_strategy.clone())
+    }
+
+    pub fn schema(&self) -> Result<Option<Schema>, DbError> {
+        Ok(self.meta()?.options.schema.clone())
+    }
+
+    pub fn set_default_ttl(&self, ttl: Option<i64>) -> Result<(), DbError> {
+        ttl.map(ttl::check_ttl).transpose()?;
+        let mut meta = self.meta()?;
+        meta.options.default_ttl = ttl;
+        meta.save(&self.meta_path)?;
+        Ok(())
+    }
+
+    /// Expires documents `expire_after` seconds after the date in `field`,
+    /// an RFC 3339 string or Unix timestamp in seconds. Documents without a
+    /// date there are left alone. An `expire_after` of 0 expires them at
+    /// that date.
+    pub fn create_ttl_index(&self, field: &str, expire_after: i64) -> Result<(), DbError> {
+        if expire_after != 0 {
+            ttl::check_ttl(expire_after)?;
+        }
+        let mut store = self.write()?;
+        self.remember(IndexDefinition {
+            field: field.to_string(),
+            kind: IndexKind::Ttl,
+            vector: None,
+            expire_after: Some(expire_after),
+        })?;
+        self.rebuild_expiry(&mut store)?;
+        drop(store);
+        info!(
+            "Created TTL index on {}.{} ({}s)",
+            self.name, field, expire_after
+        );
+        Ok(())
+    }
+
+    pub fn drop_ttl_index(&self, field: &str) -> Result<(), DbError> {
+        let exists = self
+            .meta()?
+            .indexes
+            .iter()
+            .any(|index| index.kind == IndexKind::Ttl && index.field == field);
+        if !exists {
+            return Err(DbError::NotFound);
+        }
+        let mut store = self.write()?;
+        self.forget_index(field, IndexKind::Ttl)?;
+        self.rebuild_expiry(&mut store)?;
+        drop(store);
+        info!("Dropped TTL index on {}.{}", self.name, field);
+        Ok(())
+    }
+
+    fn rebuild_order(&self, store: &mut Store) -> Result<(), DbError> {
+        let capped = self.meta()?.options.capped.clone();
+        let Store {
+            documents, indexes, ..
+        } = store;
+        indexes.create_order(capped.as_ref(), documents.values().map(|doc| &**doc));
+        Ok(())
+    }
+
+    fn rebuild_expiry(&self, store: &mut Store) -> Result<(), DbError> {
+        let ttl_indexes = TtlIndex::from_definitions(&self.meta()?.indexes);
+        let Store {
+            documents, indexes, ..
+        } = store;
+        indexes.create_expiry(ttl_indexes, documents.values().map(|doc| &**doc));
+        Ok(())
+    }
+
+    /// Pushes a document's `expires_at` back by `secs`.
+    pub fn extend_ttl(&self, id: &str, secs: i64) -> Result<Document, DbError> {
+        self.set_expiry(id, |doc| match doc.expires_at {
+            Some(at) => ttl::expires_in(at, secs).map(Some),
+            None => Err(DbError::InvalidTtl(format!(
+                "document {} does not expire",
+                id
+            ))),
+        })
+    }
+
+    /// Restarts a document's TTL from now, with `secs` or else the
+    /// collection's default TTL.
+    pub fn refresh_ttl(&self, id: &str, secs: Option<i64>) -> Result<Document, DbError> {
+        let secs = match secs.or(self.meta()?.options.default_ttl) {
+            Some(secs) => secs,
+            None => {
+                return Err(DbError::InvalidTtl(format!(
+                    "no TTL given and {} has no default",
+                    self.name
+                )));
+            }
         };
+        let at = ttl::expires_in(Utc::now(), secs)?;
+        self.set_expiry(id, |_| Ok(Some(at)))
+    }
 
-        let mut docs = self.documents.write().map_err(|_| DbError::LockPoisoned)?;
-        docs.insert(id.clone(), doc.clone());
-        drop(docs);
+    /// Makes a document stop expiring, apart from any TTL index.
+    pub fn clear_ttl(&self, id: &str) -> Result<Document, DbError> {
+        self.set_expiry(id, |_| Ok(None))
+    }
+
+    // Expiry isn't part of the data, so the version stays the same.
+    fn set_expiry(
+        &self,
+        id: &str,
+        expiry: impl FnOnce(&Document) -> Result<Option<DateTime<Utc>>, DbError>,
+    ) -> Result<Document, DbError> {
+        let mut store = self.write()?;
+        let mut doc = store.get(id, Utc::now()).ok_or(DbError::NotFound)?.clone();
+        doc.expires_at = expiry(&doc)?;
+        self.put(&mut store, doc.clone())?;
+        drop(store);
         self.persist()?;
-        info!("Inserted document with ID: {}", id);
+        debug!("Set expiry of {} to {:?}", id, doc.expires_at);
         Ok(doc)
     }
 
-    pub fn find(&self, id: &str) -> Result<Option<Document>, DbError> {
-        let docs = self.documents.read().map_err(|_| DbError::LockPoisoned)?;
-        Ok(docs.get(id).cloned())
+    /// Starts keeping revisions with `retention`, or stops and discards the
+    /// history with `None`.
+    pub fn set_history(&self, retention: Option<HistoryRetention>) -> Result<(), DbError> {
+        retention
+            .as_ref()
+            .map(HistoryRetention::check)
+            .transpose()?;
+        let mut meta = self.meta()?;
+        let had_history = meta.options.history.is_some();
+        meta.options.history = retention;
+        meta.save(&self.meta_path)?;
+        let has_history = meta.options.history.is_some();
+        drop(meta);
+        self.sync_history(had_history, has_history)
     }
 
-    pub fn find_all(&self) -> Result<Vec<Document>, DbError> {
-        let docs = self.documents.read().map_err(|_| DbError::LockPoisoned)?;
-        Ok(docs.values().cloned().collect())
+    // Documents written before history was enabled start with their current
+    // state as the first revision.
+    fn sync_history(&self, had_history: bool, has_history: bool) -> Result<(), DbError> {
+        match (had_history, has_history) {
+            (false, true) => {
+                let store = self.read()?;
+                let mut history = self.history()?;
+                history.seed(store.values());
+                history.save_if_dirty(&self.history_path)?;
+                info!("Enabled history of {}", self.name);
+            }
+            (true, false) => {
+                self.history()?.clear();
+                if self.history_path.exists() {
+                    fs::remove_file(&self.history_path)?;
+                }
+                info!("Disabled history of {}", self.name);
+            }
+            _ => {}
+        }
+        Ok(())
     }
 
-    pub fn update(&self, id: &str, data: serde_json::Value) -> Result<Document, DbError> {
-        // 1. Lock documents
-        let mut docs = self.documents.write().map_err(|_| DbError::LockPoisoned)?;
+    /// Every kept revision of a document, oldest first.
+    pub fn revisions(&self, id: &str) -> Result<Vec<Revision>, DbError> {
+        self.ensure_history()?;
+        Ok(self
+            .history()?
+            .revisions(id)
+            .ok_or(DbError::NotFound)?
+            .to_vec())
+    }
 
-        // 2. Find and update
-        let doc = docs.get_mut(id).ok_or(DbError::NotFound)?;
-        doc.data = data;
-        doc.updated_at = Utc::now();
-        let updated_doc = doc.clone();
+    pub fn revision(&self, id: &str, version: u64) -> Result<Revision, DbError> {
+        self.revisions(id)?
+            .into_iter()
+            .rev()
+            .find(|revision| revision.version == version)
+            .ok_or(DbError::NotFound)
+    }
+
+    /// The document as it was at `at`; `None` if it didn't exist then.
+    pub fn find_as_of(&self, id: &str, at: DateTime<Utc>) -> Result<Option<Document>, DbError> {
+        self.ensure_history()?;
+        Ok(self.history()?.as_of(id, at).cloned())
+    }
+
+    /// Runs `query` against the collection as it was at `at`. Indexes only
+    /// describe the current state, so this always scans.
+    pub fn query_as_of(&self, query: &Query, at: DateTime<Utc>) -> Result<Vec<Document>, DbError> {
+        self.ensure_history()?;
+        let docs: Vec<Document> = self.history()?.all_as_of(at).cloned().collect();
+        Ok(scan(&self.name, docs.iter(), query)?.0)
+    }
+
+    /// A JSON Patch from one revision's data to another's. A deletion
+    /// counts as `null`.
+    pub fn diff(&self, id: &str, from: u64, to: u64) -> Result<serde_json::Value, DbError> {
+        let data = |revision: Revision| {
+            revision
+                .document
+                .map_or(serde_json::Value::Null, |doc| doc.data)
+        };
+        let from = data(self.revision(id, from)?);
+        let to = data(self.revision(id, to)?);
+        Ok(patch::diff(&from, &to))
+    }
 
-        drop(docs);
+    /// Writes an old revision's data back as a new revision, recreating the
+    /// document if it has since been deleted.
+    pub fn restore(&self, id: &str, version: u64) -> Result<Document, DbError> {
+        let revisions = self.revisions(id)?;
+        let latest = revisions.last().map_or(0, |revision| revision.version);
+        let data = revisions
+            .into_iter()
+            .rev()
+            .find(|revision| revision.version == version)
+            .ok_or(DbError::NotFound)?
+            .document
+            .ok_or_else(|| {
+                DbError::InvalidUpdate(format!("revision {} deleted the document", version))
+            })?
+            .data;
 
+        let mut store = self.write()?;
+        let doc = match store.get(id, Utc::now()) {
+            Some(doc) => {
+                self.validate(&data)?;
+                let mut doc = doc.clone();
+                doc.data = data;
+                doc.touch();
+                doc
+            }
+            None => {
+                let mut doc = self.new_document(&store, Some(id), data, None)?;
+                // continue the numbering of the deleted document
+                doc.version = latest + 1;
+                self.admit(&mut store, &mut doc)?;
+                doc
+            }
+        };
+        self.put(&mut store, doc.clone())?;
+        self.record_write(&doc)?;
+        self.evict(&mut store)?;
+        drop(store);
         self.persist()?;
+        info!("Restored document {} to version {}", id, version);
+        Ok(doc)
+    }
 
-        info!("Updated document with ID: {}", id);
-        Ok(updated_doc)
+    /// Sends later deletes to the trash with `soft_delete`, or deletes for
+    /// good again with `None`. Documents already in the trash stay there.
+    pub fn set_soft_delete(&self, soft_delete: Option<SoftDelete>) -> Result<(), DbError> {
+        soft_delete.as_ref().map(SoftDelete::check).transpose()?;
+        let mut meta = self.meta()?;
+        meta.options.soft_delete = soft_delete;
+        meta.save(&self.meta_path)?;
+        Ok(())
     }
 
-    pub fn delete(&self, id: &str) -> Result<(), DbError> {
-        let mut docs = self.documents.write().map_err(|_| DbError::LockPoisoned)?;
-        if docs.remove(id).is_some() {
-            self.persist()?;
-            info!("Deleted document with ID: {}", id);
-            Ok(())
-        } else {
-            Err(DbError::NotFound)
+    /// Deleted documents waiting in the trash, oldest deletion first.
+    pub fn trashed(&self) -> Result<Vec<TrashedDocument>, DbError> {
+        Ok(self.trash()?.list())
+    }
+
+    /// Moves a deleted document back into the collection as a new version.
+    pub fn undelete(&self, id: &str) -> Result<Document, DbError> {
+        let mut store = self.write()?;
+        if store.get(id, Utc::now()).is_some() {
+            return Err(DbError::DuplicateId(id.to_string()));
         }
+        let mut doc = self
+            .trash()?
+            .get(id)
+            .ok_or(DbError::NotFound)?
+            .document
+            .clone();
+        doc.touch();
+        self.admit(&mut store, &mut doc)?;
+        self.put(&mut store, doc.clone())?;
+        self.trash()?.take(id);
+        self.record_write(&doc)?;
+        self.evict(&mut store)?;
+        drop(store);
+        self.persist()?;
+        info!("Undeleted document with ID: {}", id);
+        Ok(doc)
     }
 
-    fn persist(&self) -> Result<(), DbError> {
-        let docs = self.documents.read().map_err(|_| DbError::LockPoisoned)?;
-        let data = serde_json::to_string_pretty(&*docs)?;
+    /// Removes one document from the trash for good.
+    pub fn purge(&self, id: &str) -> Result<(), DbError> {
+        self.trash()?.take(id).ok_or(DbError::NotFound)?;
+        self.persist()?;
+        info!("Purged document with ID: {}", id);
+        Ok(())
+    }
+
+    /// Empties the trash, returning how many documents were purged.
+    pub fn empty_trash(&self) -> Result<usize, DbError> {
+        let purged = self.trash()?.clear();
+        self.persist()?;
+        info!("Emptied trash of {}", self.name);
+        Ok(purged)
+    }
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .truncate(true)
-            .open(&self.path)?;
+    /// Purges documents that have been in the trash longer than the
+    /// retention allows.
+    pub fn purge_expired_trash(&self) -> Result<usize, DbError> {
+        let Some(soft_delete) = self.meta()?.options.soft_delete.clone() else {
+            return Ok(0);
+        };
+        let purged = self.trash()?.purge_expired(&soft_delete, Utc::now());
+        if purged > 0 {
+            self.persist()?;
+        }
+        Ok(purged)
+    }
 
-        file.write_all(data.as_bytes())?;
-        debug!("Persisted collection: {}", self.name);
+    pub fn set_limits(&self, limits: Limits) -> Result<(), DbError> {
+        let mut meta = self.meta()?;
+        meta.options.limits = limits;
+        meta.save(&self.meta_path)?;
         Ok(())
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct Database {
-    path: PathBuf,
-    collections: Arc<RwLock<HashMap<String, Collection>>>,
-}
+    /// Enforces `schema` on every later write, or removes validation with
+    /// `None`. Existing documents are not checked; see
+    /// [`Collection::validate_documents`].
+    pub fn set_schema(&self, schema: Option<Schema>) -> Result<(), DbError> {
+        let mut meta = self.meta()?;
+        meta.options.schema = schema;
+        meta.save(&self.meta_path)?;
+        info!(
+            "{} schema of {}",
+            if meta.options.schema.is_some() {
+                "Set"
+            } else {
+                "Removed"
+            },
+            self.name
+        );
+        Ok(())
+    }
 
-impl Database {
-    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
-        let path = path.as_ref().to_path_buf();
-        info!("Loading database from: {}", path.display());
+    /// Checks the stored documents against `schema` without enabling it.
+    pub fn validate_documents(&self, schema: &Schema) -> Result<Vec<SchemaViolation>, DbError> {
+        let mut violations: Vec<SchemaViolation> = self
+            .snapshot()?
+            .documents
+            .values()
+            .filter_map(|doc| {
+                let errors = schema.errors(&doc.data);
+                (!errors.is_empty()).then(|| SchemaViolation {
+                    id: doc.id.clone(),
+                    errors,
+                })
+            })
+            .collect();
+        violations.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(violations)
+    }
 
-        let mut collections = HashMap::new();
+    /// Changes how ids are picked for documents inserted from now on.
+    pub fn set_id_strategy(&self, strategy: IdStrategy) -> Result<(), DbError> {
+        let mut meta = self.meta()?;
+        meta.options.id_strategy = strategy;
+        meta.save(&self.meta_path)?;
+        info!(
+            "Set ID strategy of {} to {:?}",
+            self.name, meta.options.id_strategy
+        );
+        Ok(())
+    }
 
-        if path.exists() {
-            for entry in fs::read_dir(&path)? {
-                let entry = entry?;
-                let entry_path = entry.path();
+    pub fn insert(&self, data: serde_json::Value, ttl: Option<i64>) -> Result<Document, DbError> {
+        self.create(None, data, ttl)
+    }
 
-                if entry_path.extension().and_then(|s| s.to_str()) == Some("json") {
-                    let file_name = entry_path
-                        .file_stem()
-                        .unwrap()
-                        .to_string_lossy()
-                        .to_string();
-                    info!("Loading collection: {}", file_name);
-                    let collection = Collection::new(&file_name, &path)?;
-                    collections.insert(file_name, collection);
-                }
-            }
+    /// Inserts a document under `id` unless one is already there, checking
+    /// and inserting under one lock. Returns `None` when the id is taken.
+    pub fn create_if_absent(
+        &self,
+        id: &str,
+        data: serde_json::Value,
+    ) -> Result<Option<Document>, DbError> {
+        match self.create(Some(id), data, None) {
+            Err(DbError::DuplicateId(_)) => Ok(None),
+            result => result.map(Some),
         }
-
-        Ok(Self {
-            path,
-            collections: Arc::new(RwLock::new(collections)),
-        })
     }
 
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
-        let path = path.as_ref().to_path_buf();
-        info!("Initializing database at: {}", path.display());
+    /// Inserts a document under a caller-chosen id, failing with
+    /// `DuplicateId` if it is taken.
+    pub fn insert_with_id(
+        &self,
+        id: &str,
+        data: serde_json::Value,
+        ttl: Option<i64>,
+    ) -> Result<Document, DbError> {
+        self.create(Some(id), data, ttl)
+    }
 
-        Ok(Self {
-            path,
-            collections: Arc::new(RwLock::new(HashMap::new())),
-        })
+    fn create(
+        &self,
+        id: Option<&str>,
+        data: serde_json::Value,
+        ttl: Option<i64>,
+    ) -> Result<Document, DbError> {
+        let mut store = self.write()?;
+        let mut doc = self.new_document(&store, id, data, ttl)?;
+        self.admit(&mut store, &mut doc)?;
+        self.put(&mut store, doc.clone())?;
+        self.record_write(&doc)?;
+        self.evict(&mut store)?;
+        drop(store);
+        self.persist()?;
+        info!("Inserted document with ID: {}", doc.id);
+        Ok(doc)
     }
 
-    pub fn collection(&self, name: &str) -> Result<Collection, DbError> {
-        let mut collections = self
-            .collections
-            .write()
-            .map_err(|_| DbError::LockPoisoned)?;
+    // Everything an insert checks before `store` gains a document: schema,
+    // limits, the id and the default TTL.
+    fn new_document(
+        &self,
+        store: &Store,
+        id: Option<&str>,
+        data: serde_json::Value,
+        ttl: Option<i64>,
+    ) -> Result<Document, DbError> {
+        let doc = self.build_document(store, id, data, ttl)?;
+        if store.get(&doc.id, Utc::now()).is_some() {
+            return Err(DbError::DuplicateId(doc.id));
+        }
+        Ok(doc)
+    }
 
-        if let Some(col) = collections.get(name) {
-            Ok(col.clone())
-        } else {
-            let col = Collection::new(name, &self.path)?;
-            collections.insert(name.to_string(), col.clone());
-            Ok(col)
+    // `new_document` without the check that the id is free in `store`, for
+    // transactions, which check it against their own writes.
+    fn build_document(
+        &self,
+        store: &Store,
+        id: Option<&str>,
+        data: serde_json::Value,
+        ttl: Option<i64>,
+    ) -> Result<Document, DbError> {
+        self.validate(&data)?;
+        let (default_ttl, capped) = {
+            let meta = self.meta()?;
+            (meta.options.default_ttl, meta.options.capped.clone())
+        };
+        let id = self.new_id(id, &data)?;
+        let mut doc = Document::new(id, data, ttl.or(default_ttl))?;
+        // Tailing resumes from a `created_at`, so a capped collection never
+        // hands out the same one twice.
+        if capped.is_some()
+            && let Some(newest) = store.indexes.order().and_then(|order| order.newest())
+            && doc.created_at <= newest
+        {
+            doc.created_at = newest + chrono::Duration::nanoseconds(1);
+            doc.updated_at = doc.created_at;
         }
+        Ok(doc)
     }
 
-    pub fn drop_collection(&self, name: &str) -> Result<(), DbError> {
-        let mut collections = self
-            .collections
-            .write()
-            .map_err(|_| DbError::LockPoisoned)?;
+    // Drops the oldest documents until a capped collection is within its
+    // bounds again. Called under the write lock after every write that adds
+    // a document or changes one's size.
+    fn evict(&self, store: &mut Store) -> Result<usize, DbError> {
+        let Some(capped) = self.meta()?.options.capped.clone() else {
+            return Ok(0);
+        };
+        let mut evicted = 0;
+        while let Some(order) = store.indexes.order()
+            && capped.exceeded(order.len(), order.bytes())
+            && let Some(oldest) = order.oldest()
+        {
+            let oldest = oldest.to_string();
+            let Some(doc) = store.take(&oldest) else {
+                break;
+            };
+            self.record_delete(&doc)?;
+            evicted += 1;
+        }
+        if evicted > 0 {
+            debug!("Evicted {} documents from {}", evicted, self.name);
+        }
+        Ok(evicted)
+    }
 
-        if collections.remove(name).is_some() {
-            let path = self.path.join(format!("{}.json", name));
-            if path.exists() {
-                fs::remove_file(path)?;
-            }
-            info!("Dropped collection: {}", name);
-            Ok(())
-        } else {
-            Err(DbError::CollectionNotFound)
+    /// Makes the collection capped, or uncapped with `None`. Documents past
+    /// the new bounds are evicted right away.
+    pub fn set_capped(&self, capped: Option<Capped>) -> Result<usize, DbError> {
+        let mut store = self.write()?;
+        {
+            let mut meta = self.meta()?;
+            meta.options.capped = capped;
+            meta.save(&self.meta_path)?;
         }
+        self.rebuild_order(&mut store)?;
+        let evicted = self.evict(&mut store)?;
+        drop(store);
+        self.persist()?;
+        Ok(evicted)
     }
 
-    pub fn start_ttl_cleaner(&self, interval_secs: u64) {
-        let db = self.clone();
-        std::thread::spawn(move || {
-            let interval = std::time::Duration::from_secs(interval_secs);
-            loop {
-                std::thread::sleep(interval);
-                if let Ok(collections) = db.collections.read() {
-                    for col in collections.values() {
-                        if let Ok(mut docs) = col.documents.write() {
-                            let now = Utc::now();
-                            let before = docs.len();
-                            docs.retain(|_, doc| doc.expires_at.map_or(true, |exp| exp > now));
-                            let after = docs.len();
-
-                            drop(docs); // ✅ release write lock before persisting
-
-                            if before != after {
-                                let _ = col.persist(); // ✅ safe now
-                                debug!(
-                                    "Cleaned {} expired documents from {}",
-                                    before - after,
-                                    col.name()
-                                );
-                            }
-                        }
+    /// Up to `limit` documents created after `after`, oldest first. Pass the
+    /// `created_at` of the last document seen to follow the collection.
+    pub fn tail(
+        &self,
+        after: Option<DateTime<Utc>>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Document>, DbError> {
+        let snapshot = self.snapshot()?;
+        let mut docs: Vec<&Document> = snapshot
+            .live(Utc::now())
+            .filter(|doc| after.is_none_or(|after| doc.created_at > after))
+            .collect();
+        docs.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        Ok(docs
+            .into_iter()
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
 
-                        // if let Ok(mut docs) = col.documents.write() {
-                        //     let now = Utc::now();
-                        //     let before = docs.len();
-                        //     docs.retain(|_, doc| doc.expires_at.map_or(true, |exp| exp > now));
-                        //     let after = docs.len();
-
-                        //     if before != after {
-                        //         let _ = col.persist();
-                        //         debug!(
-                        //             "Cleaned {} expired documents from {}",
-                        //             before - after,
-                        //             col.name()
-                        //         );
-                        //     }
-                        // }
-                    }
-                }
-            }
-        });
+    pub fn find(&self, id: &str) -> Result<Option<Document>, DbError> {
+        Ok(self.read()?.get(id, Utc::now()).cloned())
     }
 
-    pub fn create_collection(&self, name: &str) -> Result<(), DbError> {
-        let path = self.path.join(format!("{}.json", name));
-        if path.exists() {
-            return Err(DbError::CollectionNotFound); // or custom error CollectionAlreadyExists
-        }
-        fs::create_dir_all(&self.path)?;
-        // Write empty JSON object to create empty collection file
-        fs::write(path, "{}")?;
-        info!("Created new empty collection file: {}", name);
-        Ok(())
+    pub fn find_all(&self) -> Result<Vec<Document>, DbError> {
+        Ok(self.snapshot()?.find_all())
+    }
+
+    pub fn update(&self, id: &str, data: serde_json::Value) -> Result<Document, DbError> {
+        // 1. Lock the store
+        let mut store = self.write()?;
+
+        // 2. Find and update
+        let mut updated_doc = store.get(id, Utc::now()).ok_or(DbError::NotFound)?.clone();
+        self.validate(&data)?;
+        updated_doc.data = data;
+        updated_doc.touch();
+        self.put(&mut store, updated_doc.clone())?;
+        self.record_write(&updated_doc)?;
+        self.evict(&mut store)?;
+
+        drop(store);
+
+        self.persist()?;
+
+        info!("Updated document with ID: {}", id);
+        Ok(updated_doc)
+    }
+
+    /// Replaces the document's data only if its version is still `expected`.
+    pub fn update_if_version(
+        &self,
+        id: &str,
+        expected: u64,
+        data: serde_json::Value,
+    ) -> Result<Document, DbError> {
+        self.modify(id, Some(expected), |current| {
+            *current = data;
+            Ok(())
+        })
+    }
+
+    /// Applies field-level operators (`$set`, `$unset`, `$inc`, `$push`,
+    /// `$pull`, `$rename`) atomically; nothing changes if any operator fails.
+    pub fn update_with(&self, id: &str, ops: &serde_json::Value) -> Result<Document, DbError> {
+        self.patch(id, &Patch::Operators(ops.clone()), None)
+    }
+
+    /// Applies an RFC 7386 JSON Merge Patch to the document's data.
+    pub fn merge_patch(&self, id: &str, patch: &serde_json::Value) -> Result<Document, DbError> {
+        self.patch(id, &Patch::Merge(patch.clone()), None)
+    }
+
+    /// Applies an RFC 6902 JSON Patch; a failing `test` leaves the document untouched.
+    pub fn json_patch(&self, id: &str, patch: &serde_json::Value) -> Result<Document, DbError> {
+        self.patch(id, &Patch::Json(patch.clone()), None)
+    }
+
+    /// Applies `patch`, optionally only if the document is still at version
+    /// `expected`.
+    pub fn patch(
+        &self,
+        id: &str,
+        patch: &Patch,
+        expected: Option<u64>,
+    ) -> Result<Document, DbError> {
+        match patch {
+            Patch::Operators(ops) => {
+                let ops = update::parse(ops)?;
+                self.modify(id, expected, |data| update::apply(&ops, data))
+            }
+            Patch::Merge(merge) => self.modify(id, expected, |data| {
+                patch::merge(data, merge);
+                Ok(())
+            }),
+            Patch::Json(ops) => self.modify(id, expected, |data| patch::apply(data, ops)),
+        }
+    }
+
+    /// Applies a batch of writes with a single persist. With `ordered` the
+    /// batch stops at the first failing operation; otherwise every operation
+    /// is attempted. Operations that succeeded are kept either way.
+    pub fn bulk_write(&self, ops: Vec<BulkOp>, ordered: bool) -> Result<BulkResult, DbError> {
+        let mut store = self.write()?;
+        let result = bulk::apply(self, &mut store, ops, ordered);
+        drop(store);
+        if result.items.len() > result.failed {
+            self.persist()?;
+        }
+        info!(
+            "Bulk write on {}: {} inserted, {} updated, {} deleted, {} failed",
+            self.name, result.inserted, result.updated, result.deleted, result.failed
+        );
+        Ok(result)
+    }
+
+    /// Replaces the selected document's data, or inserts it if nothing is
+    /// selected. Returns the stored document and whether it was created.
+    pub fn upsert(
+        &self,
+        selector: &Selector,
+        data: serde_json::Value,
+    ) -> Result<(Document, bool), DbError> {
+        let mut store = self.write()?;
+        let existing = match selector {
+            Selector::Id(id) => store.get(id, Utc::now()),
+            Selector::Filter(filter) => first_match(&store, &Filter::parse(filter)?),
+        };
+        let (doc, created) = match existing {
+            Some(doc) => {
+                self.validate(&data)?;
+                let mut doc = doc.clone();
+                doc.data = data;
+                doc.touch();
+                (doc, false)
+            }
+            None => {
+                let id = match selector {
+                    Selector::Id(id) => Some(id.as_str()),
+                    Selector::Filter(_) => None,
+                };
+                let mut doc = self.new_document(&store, id, data, None)?;
+                self.admit(&mut store, &mut doc)?;
+                (doc, true)
+            }
+        };
+        self.put(&mut store, doc.clone())?;
+        self.record_write(&doc)?;
+        self.evict(&mut store)?;
+        drop(store);
+        self.persist()?;
+        info!(
+            "Upserted document with ID: {} ({})",
+            doc.id,
+            if created { "created" } else { "replaced" }
+        );
+        Ok((doc, created))
+    }
+
+    /// Applies update operators to the oldest document matching `filter`.
+    pub fn find_one_and_update(
+        &self,
+        filter: &serde_json::Value,
+        ops: &serde_json::Value,
+        returning: ReturnDocument,
+    ) -> Result<Option<Document>, DbError> {
+        let filter = Filter::parse(filter)?;
+        let ops = update::parse(ops)?;
+        let mut store = self.write()?;
+        let Some(before) = first_match(&store, &filter).cloned() else {
+            return Ok(None);
+        };
+        let mut after = before.clone();
+        update::apply(&ops, &mut after.data)?;
+        self.validate(&after.data)?;
+        after.touch();
+        self.put(&mut store, after.clone())?;
+        self.record_write(&after)?;
+        self.evict(&mut store)?;
+        drop(store);
+        self.persist()?;
+        info!("Updated document with ID: {}", after.id);
+        Ok(Some(match returning {
+            ReturnDocument::Before => before,
+            ReturnDocument::After => after,
+        }))
+    }
+
+    /// Deletes the oldest document matching `filter` and returns it.
+    pub fn find_one_and_delete(
+        &self,
+        filter: &serde_json::Value,
+    ) -> Result<Option<Document>, DbError> {
+        let filter = Filter::parse(filter)?;
+        let mut store = self.write()?;
+        let Some(id) = first_match(&store, &filter).map(|doc| doc.id.clone()) else {
+            return Ok(None);
+        };
+        let doc = store.take(&id).ok_or(DbError::NotFound)?;
+        self.record_delete(&doc)?;
+        self.discard(doc.clone())?;
+        drop(store);
+        self.persist()?;
+        info!("Deleted document with ID: {}", doc.id);
+        Ok(Some(doc))
+    }
+
+    // Edits a copy of the data under the write lock and only stores it if `edit` succeeds.
+    fn modify(
+        &self,
+        id: &str,
+        expected: Option<u64>,
+        edit: impl FnOnce(&mut serde_json::Value) -> Result<(), DbError>,
+    ) -> Result<Document, DbError> {
+        let mut store = self.write()?;
+        let doc = store.get(id, Utc::now()).ok_or(DbError::NotFound)?;
+        check_version(doc, expected)?;
+        let mut updated_doc = doc.clone();
+        edit(&mut updated_doc.data)?;
+        self.validate(&updated_doc.data)?;
+        updated_doc.touch();
+        self.put(&mut store, updated_doc.clone())?;
+        self.record_write(&updated_doc)?;
+        self.evict(&mut store)?;
+        drop(store);
+        self.persist()?;
+        info!("Modified document with ID: {}", id);
+        Ok(updated_doc)
+    }
+
+    pub fn delete(&self, id: &str) -> Result<(), DbError> {
+        self.remove(id, None)
+    }
+
+    /// Deletes the document only if its version is still `expected`.
+    pub fn delete_if_version(&self, id: &str, expected: u64) -> Result<(), DbError> {
+        self.remove(id, Some(expected))
+    }
+
+    fn remove(&self, id: &str, expected: Option<u64>) -> Result<(), DbError> {
+        let mut store = self.write()?;
+        let doc = store.get(id, Utc::now()).ok_or(DbError::NotFound)?;
+        check_version(doc, expected)?;
+        if let Some(doc) = store.take(id) {
+            self.record_delete(&doc)?;
+            self.discard(doc)?;
+            drop(store);
+            self.persist()?;
+            info!("Deleted document with ID: {}", id);
+            Ok(())
+        } else {
+            Err(DbError::NotFound)
+        }
+    }
+
+    pub fn create_geo_index(&self, field: &str) -> Result<(), DbError> {
+        let mut store = self.write()?;
+        let Store {
+            documents, indexes, ..
+        } = &mut *store;
+        indexes.create_geo(field, documents.values().map(|doc| &**doc));
+        drop(store);
+        self.remember_index(field, IndexKind::Geo, None)?;
+        info!("Created geo index on {}.{}", self.name, field);
+        Ok(())
+    }
+
+    pub fn drop_geo_index(&self, field: &str) -> Result<(), DbError> {
+        if self.write()?.indexes.drop_geo(field) {
+            self.forget_index(field, IndexKind::Geo)?;
+            info!("Dropped geo index on {}.{}", self.name, field);
+            Ok(())
+        } else {
+            Err(DbError::NotFound)
+        }
+    }
+
+    pub fn geo_indexes(&self) -> Result<Vec<String>, DbError> {
+        Ok(self.read()?.indexes.geo_fields())
+    }
+
+    pub fn query(&self, query: &Query) -> Result<Vec<Document>, DbError> {
+        Ok(self.execute(query)?.0)
+    }
+
+    /// Runs `query` and reports the chosen plan instead of the documents.
+    pub fn explain(&self, query: &Query) -> Result<Explain, DbError> {
+        Ok(self.execute(query)?.1)
+    }
+
+    // Plans under the read lock. Index lookups fetch their few documents
+    // there too; a full scan runs on a snapshot so writers aren't held up.
+    fn execute(&self, query: &Query) -> Result<(Vec<Document>, Explain), DbError> {
+        let started = std::time::Instant::now();
+        let filter = Filter::parse(&query.filter)?;
+        let now = Utc::now();
+        let store = self.read()?;
+
+        let planned = planner::plan(&filter, &store.indexes, store.len());
+        let mut explain = planned.explain();
+        explain.collection_documents = store.len();
+        let matched: Vec<Document> = match store.indexes.fetch(&planned.access) {
+            Some(ids) => {
+                explain.documents_examined = ids.len();
+                ids.iter()
+                    .filter_map(|id| store.get(id, now))
+                    .filter(|doc| filter.matches(doc))
+                    .cloned()
+                    .collect()
+            }
+            None => {
+                let snapshot = self.snapshot_of(&store);
+                drop(store);
+                explain.documents_examined = snapshot.documents.len();
+                snapshot
+                    .live(now)
+                    .filter(|doc| filter.matches(doc))
+                    .cloned()
+                    .collect()
+            }
+        };
+        Ok(finish(
+            &self.name, &filter, query, matched, explain, started,
+        ))
+    }
+
+    /// Runs `query` and embeds related documents from other collections of `db`.
+    pub fn query_with_lookup(
+        &self,
+        db: &Database,
+        query: &Query,
+        lookups: &[Lookup],
+    ) -> Result<Vec<Document>, DbError> {
+        lookup::join(db, self.query(query)?, lookups)
+    }
+
+    pub fn find_with_lookup(
+        &self,
+        db: &Database,
+        id: &str,
+        lookups: &[Lookup],
+    ) -> Result<Option<Document>, DbError> {
+        match self.find(id)? {
+            Some(doc) => Ok(lookup::join(db, vec![doc], lookups)?.pop()),
+            None => Ok(None),
+        }
+    }
+
+    /// Runs an aggregation pipeline; a leading `$match` is planned like a query.
+    pub fn aggregate(
+        &self,
+        pipeline: &[serde_json::Value],
+    ) -> Result<Vec<serde_json::Value>, DbError> {
+        let stages = aggregate::parse_pipeline(pipeline)?;
+        let (docs, rest) = match pipeline.first() {
+            Some(first) if first.get("$match").is_some() => {
+                let query = Query::new(first["$match"].clone());
+                (self.query(&query)?, &stages[1..])
+            }
+            _ => (self.find_all()?, &stages[..]),
+        };
+        let records = docs.iter().map(aggregate::record).collect();
+        Ok(aggregate::run(rest, records))
+    }
+
+    pub fn create_index(&self, field: &str) -> Result<(), DbError> {
+        let mut store = self.write()?;
+        let Store {
+            documents, indexes, ..
+        } = &mut *store;
+        indexes.create_value(field, documents.values().map(|doc| &**doc));
+        drop(store);
+        self.remember_index(field, IndexKind::Value, None)?;
+        info!("Created index on {}.{}", self.name, field);
+        Ok(())
+    }
+
+    pub fn drop_index(&self, field: &str) -> Result<(), DbError> {
+        if self.write()?.indexes.drop_value(field) {
+            self.forget_index(field, IndexKind::Value)?;
+            info!("Dropped index on {}.{}", self.name, field);
+            Ok(())
+        } else {
+            Err(DbError::NotFound)
+        }
+    }
+
+    /// Removes every document, keeping the options and indexes. Returns how
+    /// many documents were removed.
+    pub fn truncate(&self) -> Result<usize, DbError> {
+        let mut store = self.write()?;
+        let removed = store.clear();
+        for doc in &removed {
+            self.record_delete(doc)?;
+        }
+        drop(store);
+        let removed = removed.len();
+        self.persist()?;
+        Ok(removed)
+    }
+
+    pub fn stats(&self) -> Result<CollectionStats, DbError> {
+        let mut store = self.write()?;
+        Ok(CollectionStats {
+            name: self.name.clone(),
+            documents: store.len(),
+            indexes: store.indexes.stats(),
+            versions: store.retained_versions(),
+        })
+    }
+
+    pub fn create_vector_index(
+        &self,
+        field: &str,
+        options: vector::VectorIndexOptions,
+    ) -> Result<(), DbError> {
+        if options.dims == 0 {
+            return Err(DbError::InvalidQuery("vector dims must be positive".into()));
+        }
+        let mut store = self.write()?;
+        let Store {
+            documents, indexes, ..
+        } = &mut *store;
+        indexes.create_vector(field, options.clone(), documents.values().map(|doc| &**doc));
+        drop(store);
+        self.remember_index(field, IndexKind::Vector, Some(options))?;
+        info!("Created vector index on {}.{}", self.name, field);
+        Ok(())
+    }
+
+    pub fn drop_vector_index(&self, field: &str) -> Result<(), DbError> {
+        if self.write()?.indexes.drop_vector(field) {
+            self.forget_index(field, IndexKind::Vector)?;
+            info!("Dropped vector index on {}.{}", self.name, field);
+            Ok(())
+        } else {
+            Err(DbError::NotFound)
+        }
+    }
+
+    pub fn vector_indexes(&self) -> Result<Vec<(String, vector::VectorIndexOptions)>, DbError> {
+        Ok(self.read()?.indexes.vector_fields())
+    }
+
+    pub fn knn(&self, query: &KnnQuery) -> Result<Vec<KnnHit>, DbError> {
+        let filter = Filter::parse(&query.filter)?;
+        let now = Utc::now();
+        let store = self.read()?;
+        let accept = |id: &str| store.get(id, now).is_some_and(|doc| filter.matches(doc));
+
+        let found = match store.indexes.vector(&query.field) {
+            Some(index) if query.metric.is_none_or(|m| m == index.options().metric) => {
+                vector::check_dims(&query.vector, index.options().dims)?;
+                index.search(&query.vector, query.k, query.ef, query.exact, accept)
+            }
+            _ => {
+                let metric = query.metric.unwrap_or_default();
+                let mut scored = Vec::new();
+                for doc in store.live(now).filter(|doc| filter.matches(doc)) {
+                    let Some(v) = query::field_value(doc, &query.field)
+                        .and_then(|v| vector::vector_from_json(&v))
+                    else {
+                        continue;
+                    };
+                    if v.len() == query.vector.len() {
+                        scored.push((doc.id.clone(), metric.distance(&query.vector, &v)));
+                    }
+                }
+                scored.sort_by(|a, b| a.1.total_cmp(&b.1));
+                scored.truncate(query.k);
+                scored
+            }
+        };
+
+        Ok(found
+            .into_iter()
+            .filter_map(|(id, distance)| {
+                store.get(&id, now).map(|doc| KnnHit {
+                    distance,
+                    document: doc.clone(),
+                })
+            })
+            .collect())
+    }
+
+    // Only touches documents the expiry index says are due, and only takes
+    // the write lock when there are some.
+    fn remove_expired(&self) -> Result<usize, DbError> {
+        let now = Utc::now();
+        let due = |store: &Store| {
+            store
+                .indexes
+                .expiry()
+                .next_deadline()
+                .is_some_and(|deadline| deadline <= now)
+        };
+        if !due(&*self.read()?) {
+            return Ok(0);
+        }
+
+        let mut store = self.write()?;
+        let mut removed = 0;
+        for id in store.indexes.expiry().due(now) {
+            if let Some(doc) = store.take(&id) {
+                self.record_delete(&doc)?;
+                removed += 1;
+            }
+        }
+        drop(store); // release write lock before persisting
+
+        self.persist()?;
+        Ok(removed)
+    }
+
+    pub fn expiry(&self) -> Result<CollectionExpiry, DbError> {
+        let store = self.read()?;
+        let expiry = store.indexes.expiry();
+        Ok(CollectionExpiry {
+            name: self.name.clone(),
+            pending: expiry.len(),
+            overdue: expiry.overdue(Utc::now()),
+            next_expiry: expiry.next_deadline(),
+        })
+    }
+
+    fn meta(&self) -> Result<MutexGuard<'_, CollectionMeta>, DbError> {
+        self.meta.lock().map_err(|_| DbError::LockPoisoned)
+    }
+
+    fn validate(&self, data: &serde_json::Value) -> Result<(), DbError> {
+        let meta = self.meta()?;
+        if let Some(max) = meta.options.limits.max_document_bytes {
+            let size = serde_json::to_vec(data)?.len();
+            if size > max {
+                return Err(DbError::DocumentTooLarge(format!(
+                    "document is {} bytes, {} allows {}",
+                    size, self.name, max
+                )));
+            }
+        }
+        // a capped collection can't hold it even after evicting everything else
+        if let Some(max) = meta.options.capped.as_ref().and_then(|c| c.max_bytes) {
+            let size = serde_json::to_vec(data)?.len();
+            if size > max {
+                return Err(DbError::DocumentTooLarge(format!(
+                    "document is {} bytes, capped collection {} holds {}",
+                    size, self.name, max
+                )));
+            }
+        }
+        match &meta.options.schema {
+            Some(schema) => schema.validate(data),
+            None => Ok(()),
+        }
+    }
+
+    fn ensure_history(&self) -> Result<(), DbError> {
+        match self.meta()?.options.history {
+            Some(_) => Ok(()),
+            None => Err(DbError::HistoryDisabled),
+        }
+    }
+
+    fn history(&self) -> Result<MutexGuard<'_, History>, DbError> {
+        self.history.lock().map_err(|_| DbError::LockPoisoned)
+    }
+
+    // Called under the write lock, so revisions are kept in commit order.
+    fn record_write(&self, doc: &Document) -> Result<(), DbError> {
+        self.record(&doc.id, doc.version, doc.updated_at, Some(doc.clone()))
+    }
+
+    // Readies `store` for `doc`, which is entering the collection: an expired
+    // document in its place is removed, and `doc` is numbered above every
+    // version a deleted document reached.
+    fn admit(&self, store: &mut Store, doc: &mut Document) -> Result<(), DbError> {
+        self.clear_expired(store, &doc.id)?;
+        doc.version = doc.version.max(self.meta()?.version_floor + 1);
+        Ok(())
+    }
+
+    // An expired document the cleaner hasn't reached yet is gone as far as
+    // writes go; one about to be replaced is removed the way the cleaner
+    // would remove it.
+    fn clear_expired(&self, store: &mut Store, id: &str) -> Result<(), DbError> {
+        if store.indexes.expiry().is_expired(id, Utc::now())
+            && let Some(doc) = store.take(id)
+        {
+            self.record_delete(&doc)?;
+        }
+        Ok(())
+    }
+
+    fn record_delete(&self, doc: &Document) -> Result<(), DbError> {
+        let tombstone = doc.version + 1;
+        {
+            let mut meta = self.meta()?;
+            if meta.version_floor < tombstone {
+                meta.version_floor = tombstone;
+                meta.save(&self.meta_path)?;
+            }
+        }
+        self.record(&doc.id, tombstone, Utc::now(), None)
+    }
+
+    fn record(
+        &self,
+        id: &str,
+        version: u64,
+        at: DateTime<Utc>,
+        document: Option<Document>,
+    ) -> Result<(), DbError> {
+        let Some(retention) = self.meta()?.options.history.clone() else {
+            return Ok(());
+        };
+        let revision = Revision {
+            version,
+            at,
+            author: self.author.clone(),
+            document,
+        };
+        self.history()?.record(id, revision, &retention);
+        Ok(())
+    }
+
+    // Keeps a deleted document in the trash when soft delete is enabled.
+    fn discard(&self, doc: Document) -> Result<(), DbError> {
+        if self.meta()?.options.soft_delete.is_some() {
+            self.trash()?.put(doc, self.author.clone());
+        }
+        Ok(())
+    }
+
+    fn trash(&self) -> Result<MutexGuard<'_, Trash>, DbError> {
+        self.trash.lock().map_err(|_| DbError::LockPoisoned)
+    }
+
+    fn remember_index(
+        &self,
+        field: &str,
+        kind: IndexKind,
+        vector: Option<vector::VectorIndexOptions>,
+    ) -> Result<(), DbError> {
+        self.remember(IndexDefinition {
+            field: field.to_string(),
+            kind,
+            vector,
+            expire_after: None,
+        })
+    }
+
+    fn remember(&self, definition: IndexDefinition) -> Result<(), DbError> {
+        let mut meta = self.meta()?;
+        meta.remember_index(definition);
+        meta.save(&self.meta_path)
+    }
+
+    fn forget_index(&self, field: &str, kind: IndexKind) -> Result<(), DbError> {
+        let mut meta = self.meta()?;
+        meta.forget_index(field, kind);
+        meta.save(&self.meta_path)
+    }
+
+    // Checks a caller-supplied id or generates one with the collection's
+    // strategy. The auto-increment counter is saved before the document is,
+    // so a crash can skip a number but never hand it out twice.
+    fn new_id(&self, supplied: Option<&str>, data: &serde_json::Value) -> Result<String, DbError> {
+        if let Some(id) = supplied {
+            return ids::check(id);
+        }
+        let mut meta = self.meta()?;
+        let mut counter = meta.next_id;
+        let id = meta.options.id_strategy.generate(data, &mut counter)?;
+        if counter != meta.next_id {
+            meta.next_id = counter;
+            meta.save(&self.meta_path)?;
+        }
+        Ok(id)
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, Store>, DbError> {
+        self.current.read().map_err(|_| DbError::LockPoisoned)
+    }
+
+    fn write(&self) -> Result<StoreGuard<'_>, DbError> {
+        Ok(StoreGuard {
+            store: self.current.write().map_err(|_| DbError::LockPoisoned)?,
+            collection: self,
+        })
+    }
+
+    // Stores `doc` if the database quota allows what it adds.
+    fn put(&self, store: &mut Store, doc: Document) -> Result<(), DbError> {
+        self.charge(store, [(doc.id.as_str(), Some(&doc))])?;
+        store.put(doc);
+        Ok(())
+    }
+
+    // Checks that `writes`, each replacing the document with its id or
+    // deleting it (`None`), keep the collection within its document limit
+    // and the database within its quota, and reserves what they add. Called
+    // under the write lock before `store` changes.
+    fn charge<'a>(
+        &self,
+        store: &Store,
+        writes: impl IntoIterator<Item = (&'a str, Option<&'a Document>)>,
+    ) -> Result<(), DbError> {
+        let now = Utc::now();
+        let mut documents = store.live_len(now);
+        let mut bytes = store.bytes;
+        for (id, doc) in writes {
+            if store.get(id, now).is_some() {
+                documents -= 1;
+            }
+            if let Some(old) = store.documents.get(id) {
+                bytes -= data_size(old);
+            }
+            if let Some(doc) = doc {
+                documents += 1;
+                bytes += data_size(doc);
+            }
+        }
+        if let Some(max) = self.meta()?.options.limits.max_documents
+            && documents > max
+            && documents > store.live_len(now)
+        {
+            return Err(DbError::LimitExceeded(format!(
+                "{} holds at most {} documents",
+                self.name, max
+            )));
+        }
+        self.quota.charge(&self.name, documents, bytes)
+    }
+
+    /// A consistent view of the collection that later writes don't affect.
+    pub fn snapshot(&self) -> Result<Snapshot, DbError> {
+        Ok(self.snapshot_of(&*self.read()?))
+    }
+
+    fn snapshot_of(&self, store: &Store) -> Snapshot {
+        Snapshot {
+            name: self.name.clone(),
+            documents: store.documents.clone(),
+            ttl_indexes: store.ttl_indexes(),
+        }
+    }
+
+    fn persist(&self) -> Result<(), DbError> {
+        // serialized so an older version can never overwrite a newer one
+        let _guard = self
+            .persist_lock
+            .lock()
+            .map_err(|_| DbError::LockPoisoned)?;
+        if self.dropped.load(Ordering::Acquire) {
+            return Err(DbError::CollectionNotFound);
+        }
+        let documents = self.read()?.documents.clone();
+        self.write_files(&documents)
+    }
+
+    // Stops handles held elsewhere from writing the collection's files again.
+    // Taking the write lock waits out writes already under way.
+    fn retire(&self) -> Result<(), DbError> {
+        let _guard = self
+            .persist_lock
+            .lock()
+            .map_err(|_| DbError::LockPoisoned)?;
+        let _store = self.current.write().map_err(|_| DbError::LockPoisoned)?;
+        self.dropped.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    // Writes `documents` and unsaved history and trash. Callers hold
+    // `persist_lock`.
+    fn write_files(&self, documents: &Documents) -> Result<(), DbError> {
+        let data = serde_json::to_string_pretty(&DocumentsFile(documents))?;
+
+        file::write_atomic(&self.path, data.as_bytes())?;
+        if !self.meta_path.exists() {
+            self.meta()?.save(&self.meta_path)?;
+        }
+        self.history()?.save_if_dirty(&self.history_path)?;
+        self.trash()?.save_if_dirty(&self.trash_path)?;
+        debug!("Persisted collection: {}", self.name);
+        Ok(())
+    }
+}
+
+/// A read-only, point-in-time view of a collection. Holding one never
+/// blocks writers; what it pins is freed when it is dropped.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    name: String,
+    documents: Documents,
+    ttl_indexes: Arc<[TtlIndex]>,
+}
+
+impl Snapshot {
+    pub fn find(&self, id: &str) -> Option<Document> {
+        let doc = self.documents.get(id)?;
+        (!self.is_expired(doc, Utc::now())).then(|| Document::clone(doc))
+    }
+
+    pub fn find_all(&self) -> Vec<Document> {
+        self.live(Utc::now()).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.live(Utc::now()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Snapshots keep no indexes, so this is always a full scan.
+    pub fn query(&self, query: &Query) -> Result<Vec<Document>, DbError> {
+        Ok(scan(&self.name, self.live(Utc::now()), query)?.0)
+    }
+
+    // Snapshots keep no expiry index either, so each document's deadline is
+    // worked out from the TTL indexes the collection had when it was taken.
+    fn live(&self, now: DateTime<Utc>) -> impl Iterator<Item = &Document> {
+        self.documents
+            .values()
+            .map(|doc| &**doc)
+            .filter(move |doc| !self.is_expired(doc, now))
+    }
+
+    fn is_expired(&self, doc: &Document, now: DateTime<Utc>) -> bool {
+        ttl::expiry(doc, &self.ttl_indexes).is_some_and(|deadline| deadline <= now)
+    }
+}
+
+// Serializes the documents as the `{id: document}` map of a collection file.
+struct DocumentsFile<'a>(&'a Documents);
+
+impl Serialize for DocumentsFile<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(id, doc)| (id, &**doc)))
+    }
+}
+
+// Runs `query` over `docs` without indexes.
+fn scan<'a>(
+    name: &str,
+    docs: impl Iterator<Item = &'a Document>,
+    query: &Query,
+) -> Result<(Vec<Document>, Explain), DbError> {
+    let started = std::time::Instant::now();
+    let filter = Filter::parse(&query.filter)?;
+    let docs: Vec<&Document> = docs.collect();
+    let mut explain = planner::plan(&filter, &Indexes::default(), docs.len()).explain();
+    explain.collection_documents = docs.len();
+    explain.documents_examined = docs.len();
+    let matched = docs
+        .into_iter()
+        .filter(|doc| filter.matches(doc))
+        .cloned()
+        .collect();
+    Ok(finish(name, &filter, query, matched, explain, started))
+}
+
+// Orders, skips and limits the matched documents and completes `explain`.
+fn finish(
+    name: &str,
+    filter: &Filter,
+    query: &Query,
+    mut matched: Vec<Document>,
+    mut explain: Explain,
+    started: std::time::Instant,
+) -> (Vec<Document>, Explain) {
+    if let Some((field, near)) = filter.near() {
+        let distance = |doc: &Document| {
+            query::field_value(doc, field)
+                .and_then(|v| geo::Geometry::from_json(&v))
+                .map_or(f64::MAX, |g| near.point.distance_to(&g.anchor()))
+        };
+        matched.sort_by(|a, b| distance(a).total_cmp(&distance(b)));
+    }
+
+    let matched: Vec<Document> = matched
+        .into_iter()
+        .skip(query.skip.unwrap_or(0))
+        .take(query.limit.unwrap_or(usize::MAX))
+        .collect();
+    explain.documents_returned = matched.len();
+    explain.elapsed_micros = started.elapsed().as_micros();
+    debug!(
+        "Query on {} used {} ({} examined, {} returned)",
+        name, explain.plan, explain.documents_examined, explain.documents_returned
+    );
+    (matched, explain)
+}
+
+fn check_version(doc: &Document, expected: Option<u64>) -> Result<(), DbError> {
+    match expected {
+        Some(expected) if expected != doc.version => Err(DbError::VersionConflict {
+            expected,
+            actual: doc.version,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Oldest matching document, so repeated find-and-modify calls are deterministic.
+fn first_match<'a>(store: &'a Store, filter: &Filter) -> Option<&'a Document> {
+    let now = Utc::now();
+    let planned = planner::plan(filter, &store.indexes, store.len());
+    let candidates: Box<dyn Iterator<Item = &Document>> = match store.indexes.fetch(&planned.access)
+    {
+        Some(ids) => Box::new(ids.into_iter().filter_map(move |id| store.get(&id, now))),
+        None => Box::new(store.live(now)),
+    };
+    candidates
+        .filter(|doc| filter.matches(doc))
+        .min_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)))
+}
+
+#[derive(Debug, Clone)]
+pub struct Database {
+    path: PathBuf,
+    collections: Arc<RwLock<HashMap<String, Collection>>>,
+    expiry: Arc<ExpiryMetrics>,
+    quota: Arc<QuotaState>,
+    settings: Arc<Mutex<DatabaseSettings>>,
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl Database {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
+        let path = path.as_ref().to_path_buf();
+        info!("Loading database from: {}", path.display());
+
+        let settings = DatabaseSettings::load(&quota::path(&path))?;
+        let counters = Counters::load(&counters::path(&path))?;
+        let quota = Arc::new(QuotaState::default());
+        quota.set(settings.quota.clone());
+        rename::recover(&path)?;
+        let mut collections = HashMap::new();
+
+        if path.exists() {
+            // listed up front, as migrating renames files in this directory
+            let mut stems = Vec::new();
+            for entry in fs::read_dir(&path)? {
+                let entry_path = entry?.path();
+                if entry_path.extension().and_then(|s| s.to_str()) == Some("json") {
+                    stems.push(
+                        entry_path
+                            .file_stem()
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string(),
+                    );
+                }
+            }
+            for stem in stems {
+                let name = match CollectionName::from_file_stem(&stem) {
+                    Some(name) => name,
+                    None => match migrate_stem(&path, &stem)? {
+                        Some(name) => name,
+                        None => {
+                            warn!("Skipping {}.json: not a collection file", stem);
+                            continue;
+                        }
+                    },
+                };
+                info!("Loading collection: {}", name);
+                let collection = Collection::open(&name, &path, Arc::clone(&quota))?;
+                collections.insert(name.to_string(), collection);
+            }
+        }
+
+        let db = Self {
+            path,
+            collections: Arc::new(RwLock::new(collections)),
+            expiry: Arc::default(),
+            quota,
+            settings: Arc::new(Mutex::new(settings)),
+            counters: Arc::new(Mutex::new(counters)),
+        };
+        transaction::recover(&db)?;
+        Ok(db)
+    }
+
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<Self, DbError> {
+        let path = path.as_ref().to_path_buf();
+        info!("Initializing database at: {}", path.display());
+        let settings = DatabaseSettings::load(&quota::path(&path))?;
+        let counters = Counters::load(&counters::path(&path))?;
+        let quota = Arc::new(QuotaState::default());
+        quota.set(settings.quota.clone());
+        // collections load lazily, so none may be caught half renamed
+        rename::recover(&path)?;
+
+        Ok(Self {
+            path,
+            collections: Arc::new(RwLock::new(HashMap::new())),
+            expiry: Arc::default(),
+            quota,
+            settings: Arc::new(Mutex::new(settings)),
+            counters: Arc::new(Mutex::new(counters)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Stops every collection from writing its files again, before the whole
+    // database is deleted.
+    pub(super) fn retire(&self) -> Result<(), DbError> {
+        let collections = self.collections.read().map_err(|_| DbError::LockPoisoned)?;
+        for col in collections.values() {
+            col.retire()?;
+        }
+        Ok(())
+    }
+
+    pub fn quota(&self) -> Quota {
+        self.quota.quota()
+    }
+
+    pub fn set_quota(&self, quota: Quota) -> Result<(), DbError> {
+        let mut settings = self.settings()?;
+        settings.quota = quota.clone();
+        settings.save(&quota::path(&self.path))?;
+        self.quota.set(quota);
+        Ok(())
+    }
+
+    /// What the loaded collections use, as of their last write.
+    pub fn usage(&self) -> Usage {
+        self.quota.usage()
+    }
+
+    /// Users allowed into this database only, sorted.
+    pub fn users(&self) -> Result<Vec<String>, DbError> {
+        Ok(self.settings()?.users.keys().cloned().collect())
+    }
+
+    /// Adds a user or changes their password.
+    pub fn set_user(&self, username: &str, password: &str) -> Result<(), DbError> {
+        let hash = bcrypt::hash(password, bcrypt::DEFAULT_COST).map_err(std::io::Error::other)?;
+        let mut settings = self.settings()?;
+        settings.users.insert(username.to_string(), hash);
+        settings.save(&quota::path(&self.path))
+    }
+
+    pub fn remove_user(&self, username: &str) -> Result<(), DbError> {
+        let mut settings = self.settings()?;
+        settings.users.remove(username).ok_or(DbError::NotFound)?;
+        settings.save(&quota::path(&self.path))
+    }
+
+    pub fn verify_user(&self, username: &str, password: &str) -> bool {
+        self.settings()
+            .ok()
+            .and_then(|settings| settings.users.get(username).cloned())
+            .is_some_and(|hash| bcrypt::verify(password, &hash).unwrap_or(false))
+    }
+
+    fn settings(&self) -> Result<MutexGuard<'_, DatabaseSettings>, DbError> {
+        self.settings.lock().map_err(|_| DbError::LockPoisoned)
+    }
+
+    /// Hands out the next value of a named sequence, starting at 1. Values
+    /// are never reused, even across restarts.
+    pub fn next_sequence(&self, name: &str) -> Result<u64, DbError> {
+        counters::check_name(name)?;
+        self.change_counters(|counters| counters.next_sequence(name))
+    }
+
+    /// The last value handed out by a sequence, 0 if none has been.
+    pub fn sequence(&self, name: &str) -> Result<u64, DbError> {
+        Ok(self
+            .lock_counters()?
+            .sequences
+            .get(name)
+            .copied()
+            .unwrap_or(0))
+    }
+
+    /// Makes a sequence continue after `last`, e.g. to start invoice numbers
+    /// at 1000. Moving it below its current value fails unless `force` is
+    /// set, as values would be handed out twice.
+    pub fn set_sequence(&self, name: &str, last: u64, force: bool) -> Result<(), DbError> {
+        counters::check_name(name)?;
+        self.change_counters(|counters| counters.set_sequence(name, last, force))
+    }
+
+    pub fn drop_sequence(&self, name: &str) -> Result<(), DbError> {
+        self.change_counters(|counters| {
+            counters.sequences.remove(name).ok_or(DbError::NotFound)?;
+            Ok(())
+        })
+    }
+
+    pub fn sequences(&self) -> Result<BTreeMap<String, u64>, DbError> {
+        Ok(self.lock_counters()?.sequences.clone())
+    }
+
+    /// Adds `by` to a counter, which starts at 0, and returns the new value.
+    pub fn increment_counter(&self, name: &str, by: i64) -> Result<i64, DbError> {
+        counters::check_name(name)?;
+        self.change_counters(|counters| counters.add(name, by))
+    }
+
+    pub fn decrement_counter(&self, name: &str, by: i64) -> Result<i64, DbError> {
+        let by = by
+            .checked_neg()
+            .ok_or_else(|| DbError::InvalidUpdate(format!("counter {} would overflow", name)))?;
+        self.increment_counter(name, by)
+    }
+
+    /// The value of a counter, 0 if it was never changed.
+    pub fn counter(&self, name: &str) -> Result<i64, DbError> {
+        Ok(self
+            .lock_counters()?
+            .counters
+            .get(name)
+            .copied()
+            .unwrap_or(0))
+    }
+
+    pub fn set_counter(&self, name: &str, value: i64) -> Result<(), DbError> {
+        counters::check_name(name)?;
+        self.change_counters(|counters| {
+            counters.counters.insert(name.to_string(), value);
+            Ok(())
+        })
+    }
+
+    pub fn delete_counter(&self, name: &str) -> Result<(), DbError> {
+        self.change_counters(|counters| {
+            counters.counters.remove(name).ok_or(DbError::NotFound)?;
+            Ok(())
+        })
+    }
+
+    pub fn counters(&self) -> Result<BTreeMap<String, i64>, DbError> {
+        Ok(self.lock_counters()?.counters.clone())
+    }
+
+    fn lock_counters(&self) -> Result<MutexGuard<'_, Counters>, DbError> {
+        self.counters.lock().map_err(|_| DbError::LockPoisoned)
+    }
+
+    // Applies `change` to a copy and only keeps it once it is saved, so a
+    // failed save never leaves a value in memory that the file doesn't have.
+    fn change_counters<T>(
+        &self,
+        change: impl FnOnce(&mut Counters) -> Result<T, DbError>,
+    ) -> Result<T, DbError> {
+        let mut counters = self.lock_counters()?;
+        let mut changed = counters.clone();
+        let value = change(&mut changed)?;
+        changed.save(&counters::path(&self.path))?;
+        *counters = changed;
+        Ok(value)
+    }
+
+    pub fn collection(&self, name: &str) -> Result<Collection, DbError> {
+        let name = CollectionName::new(name)?;
+        let mut collections = self
+            .collections
+            .write()
+            .map_err(|_| DbError::LockPoisoned)?;
+
+        if let Some(col) = collections.get(name.as_str()) {
+            Ok(col.clone())
+        } else {
+            let col = Collection::open(&name, &self.path, Arc::clone(&self.quota))?;
+            collections.insert(name.to_string(), col.clone());
+            Ok(col)
+        }
+    }
+
+    /// Like [`Database::collection`], but fails instead of creating a new collection.
+    pub fn get_collection(&self, name: &str) -> Result<Collection, DbError> {
+        let name = CollectionName::new(name)?;
+        let exists = self
+            .collections
+            .read()
+            .map_err(|_| DbError::LockPoisoned)?
+            .contains_key(name.as_str());
+        if exists || name.file_in(&self.path).exists() {
+            self.collection(name.as_str())
+        } else {
+            Err(DbError::CollectionNotFound)
+        }
+    }
+
+    /// Runs `f` as one transaction: its writes are applied atomically when it
+    /// returns `Ok`, and discarded when it returns `Err`. Fails with
+    /// `TransactionConflict` if a document it read changed before commit.
+    pub fn transaction<T>(
+        &self,
+        f: impl FnOnce(&mut Transaction) -> Result<T, DbError>,
+    ) -> Result<T, DbError> {
+        let mut tx = Transaction::new(self);
+        let value = f(&mut tx)?;
+        tx.commit()?;
+        Ok(value)
+    }
+
+    /// Applies `ops` all-or-nothing, returning each resulting document
+    /// (`None` for deletes). Revisions are attributed to `author`.
+    pub fn apply_transaction(
+        &self,
+        ops: Vec<TxOp>,
+        author: Option<&str>,
+    ) -> Result<Vec<Option<Document>>, DbError> {
+        self.transaction(|tx| {
+            if let Some(author) = author {
+                tx.set_author(author);
+            }
+            ops.into_iter().map(|op| tx.apply(op)).collect()
+        })
+    }
+
+    /// Runs a `SELECT` statement against one collection.
+    pub fn sql(&self, statement: &str) -> Result<Vec<serde_json::Value>, DbError> {
+        let parsed = sql::parse(statement)?;
+        self.get_collection(&parsed.collection)?
+            .aggregate(&parsed.pipeline)
+    }
+
+    pub fn drop_collection(&self, name: &str) -> Result<(), DbError> {
+        let name = CollectionName::new(name)?;
+        let mut collections = self
+            .collections
+            .write()
+            .map_err(|_| DbError::LockPoisoned)?;
+
+        let col = collections
+            .remove(name.as_str())
+            .ok_or(DbError::CollectionNotFound)?;
+        self.remove_files(&name, &col)
+    }
+
+    // Deletes the files of a collection already taken out of `collections`,
+    // or moves them to the trash when it has soft delete enabled.
+    fn remove_files(&self, name: &CollectionName, col: &Collection) -> Result<(), DbError> {
+        col.retire()?;
+        self.quota.forget(name.as_str());
+        if let Some(soft_delete) = col.options()?.soft_delete {
+            let trashed = trash::trash_collection(&self.path, name, soft_delete)?;
+            info!("Moved collection {} to the trash as {}", name, trashed.id);
+            return Ok(());
+        }
+        for (path, _) in trash::collection_files(&self.path, name) {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        info!("Dropped collection: {}", name);
+        Ok(())
+    }
+
+    // The collection called `name`, loading it if it exists only on disk.
+    // Files under names of earlier versions are only found by `load`.
+    fn loaded(
+        &self,
+        collections: &mut HashMap<String, Collection>,
+        name: &CollectionName,
+    ) -> Result<Option<Collection>, DbError> {
+        if let Some(col) = collections.get(name.as_str()) {
+            return Ok(Some(col.clone()));
+        }
+        if !name.file_in(&self.path).exists() {
+            return Ok(None);
+        }
+        let col = Collection::open(name, &self.path, Arc::clone(&self.quota))?;
+        collections.insert(name.to_string(), col.clone());
+        Ok(Some(col))
+    }
+
+    /// Renames a collection with its metadata, history and trash. An existing
+    /// `to` fails with `CollectionAlreadyExists` unless `replace` is set, in
+    /// which case it is dropped and `from` takes its place in one step.
+    pub fn rename_collection(&self, from: &str, to: &str, replace: bool) -> Result<(), DbError> {
+        let from = CollectionName::new(from)?;
+        let to = CollectionName::new(to)?;
+        if from == to {
+            return Err(DbError::CollectionAlreadyExists);
+        }
+        let mut collections = self
+            .collections
+            .write()
+            .map_err(|_| DbError::LockPoisoned)?;
+        let col = self
+            .loaded(&mut collections, &from)?
+            .ok_or(DbError::CollectionNotFound)?;
+        let target = self.loaded(&mut collections, &to)?;
+        if target.is_some() && !replace {
+            return Err(DbError::CollectionAlreadyExists);
+        }
+
+        // let writes in flight on `from` land on disk before its files move
+        let _persisting = col.persist_lock.lock().map_err(|_| DbError::LockPoisoned)?;
+        let store = col.write()?;
+        col.write_files(&store.documents)?;
+        let journal = RenameJournal::begin(&self.path, &from, &to)?;
+        // a replaced collection in soft-delete mode goes to the trash first;
+        // otherwise the move overwrites its files
+        if let Some(target) = &target {
+            match target.options()?.soft_delete {
+                Some(_) => self.remove_files(&to, target)?,
+                None => target.retire()?,
+            }
+        }
+        let moved = journal.finish(&self.path);
+        // from here on the files are, or will be after a restart, at `to`
+        col.dropped.store(true, Ordering::Release);
+        drop(store);
+        collections.remove(from.as_str());
+        collections.remove(to.as_str());
+        self.quota.forget(from.as_str());
+        self.quota.forget(to.as_str());
+        moved?;
+        let renamed = Collection::open(&to, &self.path, Arc::clone(&self.quota))?;
+        collections.insert(to.to_string(), renamed);
+        info!("Renamed collection {} to {}", from, to);
+        Ok(())
+    }
+
+    /// Copies a collection's options, indexes and live documents, or only the
+    /// documents matching `filter`, into a new collection `to`. Returns how
+    /// many documents were copied.
+    pub fn clone_collection(
+        &self,
+        from: &str,
+        to: &str,
+        filter: Option<&Filter>,
+    ) -> Result<usize, DbError> {
+        let source = self.get_collection(from)?;
+        let to = CollectionName::new(to)?;
+        let mut collections = self
+            .collections
+            .write()
+            .map_err(|_| DbError::LockPoisoned)?;
+        if self.loaded(&mut collections, &to)?.is_some() {
+            return Err(DbError::CollectionAlreadyExists);
+        }
+        let documents: HashMap<String, Document> = source
+            .snapshot()?
+            .find_all()
+            .into_iter()
+            .filter(|doc| filter.is_none_or(|filter| filter.matches(doc)))
+            .map(|doc| (doc.id.clone(), doc))
+            .collect();
+        let bytes = documents.values().map(data_size).sum();
+        self.quota.charge(to.as_str(), documents.len(), bytes)?;
+
+        let cloned = (|| {
+            let mut meta = source.meta()?.clone();
+            meta.created_at = Utc::now();
+            meta.save(&meta::path(&self.path, &to))?;
+            file::write_atomic(
+                &to.file_in(&self.path),
+                serde_json::to_string_pretty(&documents)?.as_bytes(),
+            )?;
+            Collection::open(&to, &self.path, Arc::clone(&self.quota))
+        })();
+        let col = cloned.inspect_err(|_| self.quota.forget(to.as_str()))?;
+        collections.insert(to.to_string(), col);
+        info!(
+            "Cloned {} documents from {} into {}",
+            documents.len(),
+            source.name(),
+            to
+        );
+        Ok(documents.len())
+    }
+
+    /// Removes every document of a collection, returning how many.
+    pub fn truncate_collection(&self, name: &str) -> Result<usize, DbError> {
+        let removed = self.get_collection(name)?.truncate()?;
+        info!("Truncated collection {} ({} documents)", name, removed);
+        Ok(removed)
+    }
+
+    /// Collections dropped with soft delete enabled, oldest drop first.
+    pub fn trashed_collections(&self) -> Result<Vec<TrashedCollection>, DbError> {
+        trash::trashed_collections(&self.path)
+    }
+
+    /// Brings back a dropped collection by its trash id, returning its name.
+    /// Fails if a collection of that name exists again.
+    pub fn restore_collection(&self, id: &str) -> Result<String, DbError> {
+        let mut collections = self
+            .collections
+            .write()
+            .map_err(|_| DbError::LockPoisoned)?;
+        let name = trash::trashed_collections(&self.path)?
+            .into_iter()
+            .find(|trashed| trashed.id == id)
+            .ok_or(DbError::CollectionNotFound)?
+            .name;
+        let name = CollectionName::new(&name)?;
+        if collections.contains_key(name.as_str()) || name.file_in(&self.path).exists() {
+            return Err(DbError::CollectionAlreadyExists);
+        }
+        trash::restore_collection(&self.path, id)?;
+        let col = Collection::open(&name, &self.path, Arc::clone(&self.quota))?;
+        collections.insert(name.to_string(), col);
+        info!("Restored collection {} from the trash", name);
+        Ok(name.to_string())
+    }
+
+    /// Deletes a dropped collection for good.
+    pub fn purge_collection(&self, id: &str) -> Result<(), DbError> {
+        trash::purge_collection(&self.path, id)?;
+        info!("Purged collection {} from the trash", id);
+        Ok(())
+    }
+
+    /// Purges trashed documents and dropped collections past their retention.
+    pub fn purge_expired_trash(&self) -> Result<usize, DbError> {
+        let collections: Vec<Collection> = self
+            .collections
+            .read()
+            .map_err(|_| DbError::LockPoisoned)?
+            .values()
+            .cloned()
+            .collect();
+        let mut purged = 0;
+        for col in collections {
+            purged += col.purge_expired_trash()?;
+        }
+        let now = Utc::now();
+        for trashed in trash::trashed_collections(&self.path)? {
+            if trashed.soft_delete.expired(trashed.dropped_at, now) {
+                trash::purge_collection(&self.path, &trashed.id)?;
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    /// Removes every expired document now, returning how many.
+    pub fn remove_expired(&self) -> Result<usize, DbError> {
+        let started = std::time::Instant::now();
+        let collections: Vec<Collection> = self
+            .collections
+            .read()
+            .map_err(|_| DbError::LockPoisoned)?
+            .values()
+            .cloned()
+            .collect();
+        let mut removed = 0;
+        for col in collections {
+            match col.remove_expired() {
+                Ok(0) => {}
+                Ok(n) => {
+                    debug!("Cleaned {} expired documents from {}", n, col.name());
+                    removed += n;
+                }
+                Err(e) => error!("TTL cleanup failed for {}: {}", col.name(), e),
+            }
+        }
+        let micros = started.elapsed().as_micros() as u64;
+        self.expiry.record_sweep(removed, Utc::now(), micros);
+        Ok(removed)
+    }
+
+    /// Runs [`Database::remove_expired`] and trash retention every
+    /// `interval_secs` as a task on the current tokio runtime.
+    pub fn start_ttl_cleaner(&self, interval_secs: u64) -> TtlCleaner {
+        let db = self.clone();
+        ttl::spawn_cleaner(interval_secs, move || db.sweep())
+    }
+
+    // One cleaner pass, returning how many trash items were purged.
+    fn sweep(&self) -> Result<usize, DbError> {
+        self.remove_expired()?;
+        self.purge_expired_trash()
+    }
+
+    /// What the cleaner has done so far and what is waiting to expire.
+    pub fn expiry_stats(&self) -> Result<ExpiryStats, DbError> {
+        let mut collections = self
+            .collections
+            .read()
+            .map_err(|_| DbError::LockPoisoned)?
+            .values()
+            .map(Collection::expiry)
+            .collect::<Result<Vec<_>, DbError>>()?;
+        collections.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(self.expiry.stats(collections))
+    }
+
+    pub fn create_collection(&self, name: &str) -> Result<(), DbError> {
+        self.create_collection_with(name, CollectionOptions::default())
+    }
+
+    pub fn create_collection_with(
+        &self,
+        name: &str,
+        options: CollectionOptions,
+    ) -> Result<(), DbError> {
+        let name = CollectionName::new(name)?;
+        options.check()?;
+        let mut collections = self
+            .collections
+            .write()
+            .map_err(|_| DbError::LockPoisoned)?;
+        let path = name.file_in(&self.path);
+        if collections.contains_key(name.as_str()) || path.exists() {
+            return Err(DbError::CollectionAlreadyExists);
+        }
+        self.quota.check_new_collection()?;
+        // Write empty JSON object to create empty collection file
+        file::write_atomic(&path, b"{}")?;
+        let col = Collection::open(&name, &self.path, Arc::clone(&self.quota))?;
+        col.set_options(options)?;
+        collections.insert(name.to_string(), col);
+        info!("Created new empty collection file: {}", name);
+        Ok(())
+    }
+
+    /// Names of every collection, sorted.
+    pub fn collection_names(&self) -> Result<Vec<String>, DbError> {
+        let mut names: Vec<String> = self
+            .collections
+            .read()
+            .map_err(|_| DbError::LockPoisoned)?
+            .keys()
+            .cloned()
+            .collect();
+        names.sort();
+        Ok(names)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_dir(prefix: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("darkdb-{}-{}", prefix, uuid::Uuid::new_v4()))
+}
+
+// Moves the files of a collection stored as `<stem>.json` by an earlier
+// version to the file names its name maps to now, or returns `None` when no
+// collection was ever stored there. The data file goes last, so a crash part
+// way is picked up by the next load.
+fn migrate_stem(db_path: &Path, stem: &str) -> Result<Option<CollectionName>, DbError> {
+    let Some(name) = CollectionName::from_legacy_stem(stem) else {
+        return Ok(None);
+    };
+    let moves: Vec<(PathBuf, PathBuf)> = trash::collection_files(db_path, &name)
+        .into_iter()
+        .rev()
+        .map(|(path, _)| (path.with_file_name(format!("{}.json", stem)), path))
+        .filter(|(old, _)| old.exists())
+        .collect();
+    if let Some((old, new)) = moves.iter().find(|(_, new)| new.exists()) {
+        return Err(DbError::InvalidName(format!(
+            "{} and {} both hold collection {:?}",
+            old.display(),
+            new.display(),
+            name.as_str()
+        )));
+    }
+    for (old, new) in moves {
+        fs::rename(old, new)?;
+    }
+    warn!(
+        "Moved the files of collection {} from {}.json to {}.json",
+        name,
+        stem,
+        name.file_stem()
+    );
+    Ok(Some(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn concurrent_upserts_of_one_id_create_it_once() {
+        let dir = test_dir("upsert-race");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let created = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..8)
+                .map(|n| {
+                    let col = &col;
+                    s.spawn(move || {
+                        col.upsert(&Selector::Id("a".into()), json!({"n": n}))
+                            .unwrap()
+                            .1
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap())
+                .filter(|created| *created)
+                .count()
+        });
+        assert_eq!(created, 1);
+        assert_eq!(col.find_all().unwrap().len(), 1);
+        assert_eq!(col.find("a").unwrap().unwrap().version, 8);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn upserts_by_filter_replace_the_oldest_match() {
+        let dir = test_dir("upsert-filter");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        // `b` is older, so it wins over the lower id
+        let (first, _) = col
+            .upsert(&Selector::Id("b".into()), json!({"k": 1}))
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        col.upsert(&Selector::Id("a".into()), json!({"k": 1}))
+            .unwrap();
+        let selector = Selector::Filter(json!({"k": 1}));
+
+        let (doc, created) = col.upsert(&selector, json!({"k": 2})).unwrap();
+        assert!(!created);
+        assert_eq!((doc.id.as_str(), doc.created_at), ("b", first.created_at));
+        assert_eq!(doc.version, first.version + 1);
+        // the filter's fields are not copied into a created document
+        let (doc, created) = col
+            .upsert(&Selector::Filter(json!({"k": 3})), json!({}))
+            .unwrap();
+        assert!(created);
+        assert_eq!(doc.data, json!({}));
+        assert_eq!(col.find_all().unwrap().len(), 3);
+
+        let schema = Schema::new(json!({"type": "object", "required": ["k"]})).unwrap();
+        col.set_schema(Some(schema)).unwrap();
+        for selector in [Selector::Id("b".into()), Selector::Id("new".into())] {
+            let err = col.upsert(&selector, json!({})).unwrap_err();
+            assert!(matches!(err, DbError::ValidationFailed(_)), "{err}");
+        }
+        assert!(col.find("new").unwrap().is_none());
+        assert_eq!(col.find("b").unwrap().unwrap().data, json!({"k": 2}));
+        assert!(
+            col.upsert(&Selector::Filter(json!([])), json!({"k": 1}))
+                .is_err()
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn find_and_modify_return_the_chosen_side_of_the_change() {
+        let dir = test_dir("find-and-modify");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        col.upsert(&Selector::Id("b".into()), json!({"n": 1}))
+            .unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        col.upsert(&Selector::Id("a".into()), json!({"n": 1}))
+            .unwrap();
+        let inc = json!({"$inc": {"n": 1}});
+
+        let before = col
+            .find_one_and_update(&json!({"n": 1}), &inc, ReturnDocument::Before)
+            .unwrap()
+            .unwrap();
+        assert_eq!((before.id.as_str(), &before.data), ("b", &json!({"n": 1})));
+        let after = col
+            .find_one_and_update(&json!({"n": 1}), &inc, ReturnDocument::After)
+            .unwrap()
+            .unwrap();
+        assert_eq!((after.id.as_str(), &after.data), ("a", &json!({"n": 2})));
+        assert!(
+            col.find_one_and_update(&json!({"n": 1}), &inc, ReturnDocument::After)
+                .unwrap()
+                .is_none()
+        );
+        let bad = json!({"$push": {"n": 1}});
+        assert!(
+            col.find_one_and_update(&json!({}), &bad, ReturnDocument::After)
+                .is_err()
+        );
+        assert_eq!(col.find("b").unwrap().unwrap().data, json!({"n": 2}));
+
+        let deleted = col.find_one_and_delete(&json!({"n": 2})).unwrap().unwrap();
+        assert_eq!(deleted.id, "b");
+        assert!(col.find("b").unwrap().is_none());
+        assert_eq!(col.find_all().unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_keep_their_version_while_writes_go_on() {
+        let dir = test_dir("mvcc");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        col.create_index("n").unwrap();
+        let a = col.insert(json!({"n": 1}), None).unwrap();
+        let snap = col.snapshot().unwrap();
+        assert_eq!(col.stats().unwrap().versions, 1);
+
+        col.update(&a.id, json!({"n": 2})).unwrap();
+        col.insert(json!({"n": 3}), None).unwrap();
+        assert_eq!(col.stats().unwrap().versions, 2);
+        assert_eq!(snap.len(), 1);
+        assert_eq!(snap.find(&a.id).unwrap().data["n"], 1);
+        assert_eq!(snap.query(&Query::new(json!({"n": 1}))).unwrap().len(), 1);
+        assert_eq!(snap.query(&Query::new(json!({"n": 2}))).unwrap().len(), 0);
+        assert_eq!(col.query(&Query::new(json!({"n": 2}))).unwrap().len(), 1);
+        assert_eq!(col.find_all().unwrap().len(), 2);
+
+        drop(snap);
+        assert_eq!(col.stats().unwrap().versions, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writes_share_untouched_documents_with_snapshots() {
+        let dir = test_dir("mvcc-share");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let ids: Vec<String> = (0..100)
+            .map(|n| col.insert(json!({"n": n}), None).unwrap().id)
+            .collect();
+        let snap = col.snapshot().unwrap();
+        col.update(&ids[0], json!({"n": -1})).unwrap();
+
+        let store = col.read().unwrap();
+        let old = snap.documents.get(&ids[0]).unwrap();
+        assert!(!Arc::ptr_eq(old, store.documents.get(&ids[0]).unwrap()));
+        for id in &ids[1..] {
+            let (old, new) = (snap.documents.get(id), store.documents.get(id));
+            assert!(Arc::ptr_eq(old.unwrap(), new.unwrap()));
+        }
+        drop(store);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn each_pinned_version_is_freed_with_its_last_snapshot() {
+        let dir = test_dir("mvcc-gc");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let a = col.insert(json!({"n": 1}), None).unwrap();
+        let first = col.snapshot().unwrap();
+        let copy = first.clone();
+        col.update(&a.id, json!({"n": 2})).unwrap();
+        let second = col.snapshot().unwrap();
+        // a snapshot taken between writes pins nothing new
+        let current = col.snapshot().unwrap();
+        drop(current);
+        col.delete(&a.id).unwrap();
+        assert_eq!(col.stats().unwrap().versions, 3);
+
+        drop(first);
+        assert_eq!(col.stats().unwrap().versions, 3);
+        assert_eq!(copy.find(&a.id).unwrap().data["n"], 1);
+        drop(copy);
+        assert_eq!(col.stats().unwrap().versions, 2);
+
+        col.insert(json!({}), None).unwrap();
+        assert_eq!(col.truncate().unwrap(), 1);
+        assert_eq!(second.find(&a.id).unwrap().data["n"], 2);
+        assert_eq!(second.len(), 1);
+        drop(second);
+        assert_eq!(col.stats().unwrap().versions, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn expire(col: &Collection, id: &str) {
+        let past = Utc::now() - chrono::Duration::seconds(1);
+        col.set_expiry(id, |_| Ok(Some(past))).unwrap();
+    }
+
+    #[test]
+    fn writes_treat_expired_documents_as_absent() {
+        let dir = test_dir("lazy-expiry");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        col.set_limits(Limits {
+            max_documents: Some(1),
+            ..Default::default()
+        })
+        .unwrap();
+        let doc = col.insert_with_id("a", json!({"n": 1}), None).unwrap();
+        expire(&col, &doc.id);
+
+        let patch = Patch::Operators(json!({"$inc": {"n": 1}}));
+        assert!(matches!(col.update("a", json!({})), Err(DbError::NotFound)));
+        assert!(matches!(
+            col.patch("a", &patch, None),
+            Err(DbError::NotFound)
+        ));
+        assert!(matches!(col.delete("a"), Err(DbError::NotFound)));
+        let ops = json!({"$inc": {"n": 1}});
+        let found = col.find_one_and_update(&json!({"n": 1}), &ops, ReturnDocument::After);
+        assert!(found.unwrap().is_none());
+        assert!(col.find_one_and_delete(&json!({})).unwrap().is_none());
+
+        // the limit doesn't count it, and its id is free again
+        let again = col.insert_with_id("a", json!({"n": 2}), None).unwrap();
+        assert!(again.expires_at.is_none());
+        assert_eq!(col.find("a").unwrap().unwrap().data["n"], 2);
+        assert!(matches!(
+            col.insert(json!({}), None),
+            Err(DbError::LimitExceeded(_))
+        ));
+
+        expire(&col, "a");
+        let (upserted, created) = col
+            .upsert(&Selector::Id("a".into()), json!({"n": 3}))
+            .unwrap();
+        assert!(created);
+        assert_eq!(upserted.data["n"], 3);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recreated_documents_never_repeat_a_version() {
+        let dir = test_dir("versions");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let first = col.insert_with_id("a", json!({"n": 1}), None).unwrap();
+        col.update("a", json!({"n": 2})).unwrap();
+        col.delete("a").unwrap();
+        let second = col.insert_with_id("a", json!({"n": 1}), None).unwrap();
+        assert!(second.version > first.version + 1);
+        assert_ne!(second.etag(), first.etag());
+        assert!(matches!(
+            col.update_if_version("a", first.version, json!({})),
+            Err(DbError::VersionConflict { .. })
+        ));
+
+        col.delete("a").unwrap();
+        let col = Database::load(&dir).unwrap().collection("c").unwrap();
+        let third = col.insert_with_id("a", json!({}), None).unwrap();
+        assert!(third.version > second.version + 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn create_if_absent_leaves_an_existing_document_alone() {
+        let dir = test_dir("create-if-absent");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let doc = col.create_if_absent("a", json!({"n": 1})).unwrap().unwrap();
+        assert!(
+            col.create_if_absent("a", json!({"n": 2}))
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(col.find("a").unwrap().unwrap().data, doc.data);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn every_write_path_is_held_to_the_quota() {
+        let dir = test_dir("quota");
+        let db = Database::new(&dir).unwrap();
+        db.set_quota(Quota {
+            max_collections: None,
+            max_documents: Some(2),
+            max_bytes: Some(20),
+        })
+        .unwrap();
+        let a = db.collection("a").unwrap();
+        let b = db.collection("b").unwrap();
+        let first = a.insert(json!({"n": 1}), None).unwrap();
+        b.insert(json!({"n": 2}), None).unwrap();
+        // usage is current without waiting for a persist
+        assert_eq!(db.usage().documents, 2);
+        assert_eq!(db.usage().bytes, 14);
+        let full = |result: Result<Document, DbError>| {
+            assert!(matches!(result, Err(DbError::QuotaExceeded(_))));
+        };
+        full(a.insert(json!({}), None));
+        full(
+            b.upsert(&Selector::Id("x".into()), json!({}))
+                .map(|(doc, _)| doc),
+        );
+        let insert = BulkOp::Insert {
+            id: None,
+            data: json!({}),
+            ttl: None,
+        };
+        assert_eq!(a.bulk_write(vec![insert], true).unwrap().failed, 1);
+        // growing past the byte quota fails, shrinking doesn't
+        full(a.update(&first.id, json!({"n": "a long string"})));
+        full(a.merge_patch(&first.id, &json!({"s": "a long string"})));
+        a.update(&first.id, json!({})).unwrap();
+        assert_eq!(db.usage().bytes, 9);
+
+        // a delete frees its slot for every collection
+        a.set_soft_delete(Some(SoftDelete::default())).unwrap();
+        a.delete(&first.id).unwrap();
+        assert_eq!(db.usage().documents, 1);
+        b.insert(json!({}), None).unwrap();
+        full(a.undelete(&first.id));
+        assert_eq!(a.trashed().unwrap().len(), 1);
+        full(db.transaction(|tx| tx.insert("a", json!({}), None)));
+        assert!(a.find_all().unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn collections_count_against_the_quota_once_created() {
+        let dir = test_dir("quota-collections");
+        let db = Database::new(&dir).unwrap();
+        db.set_quota(Quota {
+            max_collections: Some(1),
+            ..Quota::default()
+        })
+        .unwrap();
+        // opening a name costs nothing
+        let a = db.collection("a").unwrap();
+        let b = db.collection("b").unwrap();
+        assert_eq!(db.usage().collections, 0);
+        a.insert(json!({}), None).unwrap();
+        assert_eq!(db.usage().collections, 1);
+        let err = b.insert(json!({}), None).unwrap_err();
+        assert!(matches!(err, DbError::QuotaExceeded(_)), "{err}");
+        let err = db.create_collection("c").unwrap_err();
+        assert!(matches!(err, DbError::QuotaExceeded(_)), "{err}");
+        assert_eq!(db.usage().collections, 1);
+        assert_eq!(Database::load(&dir).unwrap().usage().collections, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn loading_moves_files_stored_under_legacy_names() {
+        let dir = test_dir("legacy-names");
+        let db = Database::new(&dir).unwrap();
+        let doc = db
+            .collection("My Coll")
+            .unwrap()
+            .insert(json!({"n": 1}), None)
+            .unwrap();
+        let name = CollectionName::new("My Coll").unwrap();
+        for (path, _) in trash::collection_files(&dir, &name) {
+            if path.exists() {
+                fs::rename(&path, path.with_file_name("My Coll.json")).unwrap();
+            }
+        }
+
+        let db = Database::load(&dir).unwrap();
+        let col = db.get_collection("My Coll").unwrap();
+        assert_eq!(col.find(&doc.id).unwrap().unwrap().data, doc.data);
+        assert!(name.file_in(&dir).exists());
+        assert!(!dir.join("My Coll.json").exists());
+
+        // a file that is no collection's is left alone
+        fs::write(dir.join("_stray.json"), "{}").unwrap();
+        let db = Database::load(&dir).unwrap();
+        assert_eq!(db.collection_names().unwrap(), ["My Coll"]);
+        assert!(dir.join("_stray.json").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn readers_see_consistent_snapshots_during_writes() {
+        let dir = test_dir("mvcc-threads");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let writer = {
+            let col = col.clone();
+            std::thread::spawn(move || {
+                for n in 0..200 {
+                    col.insert(json!({"n": n}), None).unwrap();
+                }
+            })
+        };
+        let mut last = 0;
+        while !writer.is_finished() {
+            let snap = col.snapshot().unwrap();
+            let len = snap.len();
+            assert!(len >= last);
+            assert_eq!(snap.find_all().len(), len);
+            last = len;
+        }
+        writer.join().unwrap();
+        let reloaded = Database::load(&dir).unwrap().collection("c").unwrap();
+        assert_eq!(reloaded.find_all().unwrap().len(), 200);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_hide_documents_once_they_expire() {
+        let dir = test_dir("mvcc-ttl");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let doc = col.insert(json!({}), None).unwrap();
+        let past = Utc::now() - chrono::Duration::seconds(1);
+        col.set_expiry(&doc.id, |_| Ok(Some(past))).unwrap();
+        let snap = col.snapshot().unwrap();
+        assert!(snap.find(&doc.id).is_none());
+        assert!(snap.is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn snapshots_never_see_half_a_transaction() {
+        let dir = test_dir("mvcc-tx");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        for id in ["a", "b"] {
+            col.upsert(&Selector::Id(id.into()), json!({"bal": 50}))
+                .unwrap();
+        }
+        let total = |docs: Vec<Document>| -> i64 {
+            docs.iter().map(|d| d.data["bal"].as_i64().unwrap()).sum()
+        };
+        std::thread::scope(|s| {
+            let writer = s.spawn(|| {
+                for n in 0..200 {
+                    let (from, to) = if n % 2 == 0 { ("a", "b") } else { ("b", "a") };
+                    db.transaction(|tx| {
+                        tx.update_with("c", from, &json!({"$inc": {"bal": -5}}))?;
+                        tx.update_with("c", to, &json!({"$inc": {"bal": 5}}))?;
+                        Ok(())
+                    })
+                    .unwrap();
+                }
+            });
+            while !writer.is_finished() {
+                assert_eq!(total(col.snapshot().unwrap().find_all()), 100);
+                assert_eq!(total(col.find_all().unwrap()), 100);
+            }
+        });
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn options_and_indexes_survive_a_reopen() {
+        let dir = test_dir("options");
+        let options = CollectionOptions {
+            id_strategy: IdStrategy::AutoIncrement,
+            default_ttl: Some(3600),
+            limits: Limits {
+                max_documents: Some(2),
+                max_document_bytes: Some(32),
+            },
+            ..Default::default()
+        };
+        {
+            let db = Database::new(&dir).unwrap();
+            db.create_collection_with("c", options.clone()).unwrap();
+            assert!(matches!(
+                db.create_collection("c"),
+                Err(DbError::CollectionAlreadyExists)
+            ));
+            let col = db.collection("c").unwrap();
+            col.create_index("n").unwrap();
+            let doc = col.insert(json!({"n": 1}), None).unwrap();
+            assert_eq!(doc.id, "00000000000000000001");
+            assert!(doc.expires_at.is_some());
+            let err = col.insert(json!({"n": "x".repeat(40)}), None).unwrap_err();
+            assert!(matches!(err, DbError::DocumentTooLarge(_)), "{err}");
+            col.insert(json!({"n": 2}), None).unwrap();
+            let err = col.insert(json!({"n": 3}), None).unwrap_err();
+            assert!(matches!(err, DbError::LimitExceeded(_)), "{err}");
+        }
+        let db = Database::new(&dir).unwrap();
+        let info = db.collection("c").unwrap().info().unwrap();
+        assert_eq!(db.collection_names().unwrap(), ["c"]);
+        assert_eq!((info.documents, info.options), (2, options));
+        assert_eq!(info.indexes.len(), 1);
+        assert_eq!(info.indexes[0].field, "n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn collections_are_saved_with_their_first_write() {
+        let dir = test_dir("lazy");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        assert!(!CollectionName::new("c").unwrap().file_in(&dir).exists());
+        assert!(!dir.join("_meta").exists());
+        assert!(matches!(
+            db.get_collection("d"),
+            Err(DbError::CollectionNotFound)
+        ));
+        col.insert(json!({}), None).unwrap();
+        let meta_path = meta::path(&dir, &CollectionName::new("c").unwrap());
+        assert!(meta_path.exists());
+        assert_eq!(
+            Database::load(&dir).unwrap().collection_names().unwrap(),
+            ["c"]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn capped_collections_evict_the_oldest_documents() {
+        let dir = test_dir("capped");
+        let db = Database::new(&dir).unwrap();
+        let options = CollectionOptions {
+            capped: Some(Capped {
+                max_documents: Some(3),
+                max_bytes: None,
+            }),
+            ..Default::default()
+        };
+        db.create_collection_with("log", options).unwrap();
+        let col = db.get_collection("log").unwrap();
+        let ids: Vec<String> = (0..5)
+            .map(|n| col.insert(json!({"n": n}), None).unwrap().id)
+            .collect();
+        let tail: Vec<String> = col
+            .tail(None, None)
+            .unwrap()
+            .into_iter()
+            .map(|d| d.id)
+            .collect();
+        assert_eq!(tail, ids[2..]);
+        let after = col.find(&ids[3]).unwrap().unwrap().created_at;
+        assert_eq!(col.tail(Some(after), None).unwrap()[0].id, ids[4]);
+
+        let evicted = col
+            .set_capped(Some(Capped {
+                max_documents: None,
+                max_bytes: Some(16),
+            }))
+            .unwrap();
+        assert_eq!(evicted, 1);
+        let order_bytes = |col: &Collection| col.read().unwrap().indexes.order().unwrap().bytes();
+        assert_eq!(order_bytes(&col), 14);
+        col.update(&ids[4], json!({})).unwrap();
+        assert_eq!(order_bytes(&col), 9);
+        let err = col.insert(json!({"s": "x".repeat(20)}), None).unwrap_err();
+        assert!(matches!(err, DbError::DocumentTooLarge(_)), "{err}");
+        col.delete(&ids[4]).unwrap();
+        assert_eq!(order_bytes(&col), 7);
+        // growing a document evicts like an insert, and is held to the bound
+        let newest = col.insert(json!({"n": 5}), None).unwrap().id;
+        col.update_with(&newest, &json!({"$set": {"s": "ab"}}))
+            .unwrap();
+        assert!(col.find(&ids[3]).unwrap().is_none());
+        assert_eq!(order_bytes(&col), 16);
+        let err = col
+            .update(&newest, json!({"s": "x".repeat(20)}))
+            .unwrap_err();
+        assert!(matches!(err, DbError::DocumentTooLarge(_)), "{err}");
+
+        // reloading rebuilds the order from the stored documents
+        let col = Database::load(&dir).unwrap().collection("log").unwrap();
+        assert_eq!(order_bytes(&col), 16);
+        col.set_capped(None).unwrap();
+        assert!(col.read().unwrap().indexes.order().is_none());
+        col.insert(json!({"s": "x".repeat(20)}), None).unwrap();
+        assert_eq!(col.find_all().unwrap().len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn collection_names_are_checked_and_escaped_on_disk() {
+        let dir = test_dir("names");
+        let db = Database::new(&dir).unwrap();
+        assert!(matches!(
+            db.collection("../evil"),
+            Err(DbError::InvalidName(_))
+        ));
+        db.collection("my coll")
+            .unwrap()
+            .insert(json!({"n": 1}), None)
+            .unwrap();
+        assert!(dir.join("my%20coll.json").exists());
+
+        let db = Database::load(&dir).unwrap();
+        assert_eq!(db.collection_names().unwrap(), vec!["my coll".to_string()]);
+        assert_eq!(
+            db.collection("my coll").unwrap().find_all().unwrap().len(),
+            1
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn collections_are_renamed_with_their_options() {
+        let dir = test_dir("rename");
+        let db = Database::new(&dir).unwrap();
+        let old = db.collection("a").unwrap();
+        old.create_index("n").unwrap();
+        old.insert_with_id("x", json!({"n": 1}), None).unwrap();
+        db.collection("b").unwrap();
+
+        assert!(matches!(
+            db.rename_collection("a", "b", false),
+            Err(DbError::CollectionAlreadyExists)
+        ));
+        db.rename_collection("a", "b", true).unwrap();
+        assert!(matches!(
+            db.get_collection("a"),
+            Err(DbError::CollectionNotFound)
+        ));
+        // a handle from before the rename can't write the old files back
+        assert!(old.insert(json!({}), None).is_err());
+        assert!(!dir.join("a.json").exists());
+
+        let db = Database::load(&dir).unwrap();
+        let renamed = db.get_collection("b").unwrap();
+        assert_eq!(renamed.find("x").unwrap().unwrap().data["n"], 1);
+        assert_eq!(renamed.info().unwrap().indexes[0].field, "n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn clones_copy_matching_documents_and_truncate_empties() {
+        let dir = test_dir("clone");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("a").unwrap();
+        col.create_index("n").unwrap();
+        for n in 0..4 {
+            col.insert(json!({"n": n}), None).unwrap();
+        }
+        let filter = Filter::parse(&json!({"n": {"$gte": 2}})).unwrap();
+        assert_eq!(db.clone_collection("a", "b", Some(&filter)).unwrap(), 2);
+        assert!(matches!(
+            db.clone_collection("a", "b", None),
+            Err(DbError::CollectionAlreadyExists)
+        ));
+        let copy = db.get_collection("b").unwrap();
+        assert_eq!(copy.find_all().unwrap().len(), 2);
+        assert_eq!(copy.info().unwrap().indexes[0].field, "n");
+
+        assert_eq!(db.truncate_collection("a").unwrap(), 4);
+        assert!(col.find_all().unwrap().is_empty());
+        assert_eq!(col.info().unwrap().indexes[0].entries, 0);
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,209 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use super::DbError;
+
+/// A checked collection name, and the file name it is stored under.
+///
+/// Names are 1-64 bytes of letters, digits, spaces and ASCII punctuation
+/// other than `/` and `\`. They can't start with `.`, or with `_`, which is
+/// reserved for the database's own directories, nor have leading or trailing
+/// spaces. Anything but lowercase ASCII letters, digits, `_`, `-` and `.` is
+/// percent-encoded in the file name, as is the first letter of names that
+/// Windows reserves for devices (`con`, `nul`, `com1`, ...). Encoding
+/// uppercase letters keeps `Users` and `users` apart on file systems that
+/// ignore case.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CollectionName {
+    name: String,
+    stem: String,
+}
+
+impl CollectionName {
+    pub const MAX_LEN: usize = 64;
+
+    pub fn new(name: &str) -> Result<Self, DbError> {
+        let invalid = |reason: &str| Err(DbError::InvalidName(format!("{:?}: {}", name, reason)));
+        if name.is_empty() || name.len() > Self::MAX_LEN {
+            return invalid("collection names are 1-64 bytes long");
+        }
+        if let Some(c) = name.chars().find(|c| !allowed(*c)) {
+            return invalid(&format!("{:?} is not allowed in collection names", c));
+        }
+        if name.starts_with('_') {
+            return invalid("names starting with '_' are reserved");
+        }
+        if name.starts_with('.') {
+            return invalid("collection names can't start with '.'");
+        }
+        if name.trim() != name {
+            return invalid("collection names can't start or end with a space");
+        }
+        Ok(Self {
+            name: name.to_string(),
+            stem: escape(name),
+        })
+    }
+
+    /// The collection stored as `<stem>.json`, if `stem` is the file name of
+    /// a valid collection.
+    pub fn from_file_stem(stem: &str) -> Option<Self> {
+        let name = Self::new(&unescape(stem)?).ok()?;
+        (name.stem == stem).then_some(name)
+    }
+
+    /// The collection an earlier version stored as `<stem>.json`, when that
+    /// is no longer the file name it maps to: either the name itself, or its
+    /// escaping before uppercase letters were encoded.
+    pub fn from_legacy_stem(stem: &str) -> Option<Self> {
+        unescape(stem)
+            .and_then(|name| Self::new(&name).ok())
+            .or_else(|| Self::new(stem).ok())
+            .filter(|name| name.stem != stem)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+
+    /// The file name without extension, safe on any file system.
+    pub fn file_stem(&self) -> &str {
+        &self.stem
+    }
+
+    /// `<dir>/<stem>.json`.
+    pub fn file_in(&self, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.json", self.stem))
+    }
+}
+
+impl FromStr for CollectionName {
+    type Err = DbError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::new(s)
+    }
+}
+
+impl fmt::Display for CollectionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl AsRef<str> for CollectionName {
+    fn as_ref(&self) -> &str {
+        &self.name
+    }
+}
+
+fn allowed(c: char) -> bool {
+    c.is_alphanumeric() || c == ' ' || (c.is_ascii_punctuation() && c != '/' && c != '\\')
+}
+
+const DEVICE_NAMES: [&str; 4] = ["con", "prn", "aux", "nul"];
+
+fn is_device_name(name: &str) -> bool {
+    let base = name.split('.').next().unwrap_or(name).to_ascii_lowercase();
+    DEVICE_NAMES.contains(&base.as_str())
+        || ((base.starts_with("com") || base.starts_with("lpt"))
+            && base.len() == 4
+            && base.ends_with(|c: char| c.is_ascii_digit()))
+}
+
+fn escape(name: &str) -> String {
+    let mut stem = String::with_capacity(name.len());
+    for (i, c) in name.char_indices() {
+        let verbatim = c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '-' | '.');
+        if verbatim && !(i == 0 && is_device_name(name)) {
+            stem.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                stem.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    stem
+}
+
+fn unescape(stem: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(stem.len());
+    let mut rest = stem.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stem(name: &str) -> String {
+        CollectionName::new(name).unwrap().file_stem().to_string()
+    }
+
+    #[test]
+    fn rejects_names_that_leave_the_directory_or_are_reserved() {
+        for name in [
+            "", "..", ".", ".hidden", "a/b", "a\\b", "_meta", " a", "a ", "a\tb",
+        ] {
+            assert!(CollectionName::new(name).is_err(), "{:?}", name);
+        }
+        assert!(CollectionName::new(&"a".repeat(65)).is_err());
+        assert!(CollectionName::new(&"a".repeat(64)).is_ok());
+    }
+
+    #[test]
+    fn escaped_names_round_trip() {
+        for name in [
+            "users", "my coll", "a%2Fb", "100%", "日本", "Users", "a.b-c_d", "con", "COM1.x",
+        ] {
+            let parsed = CollectionName::new(name).unwrap();
+            let stem = parsed.file_stem();
+            assert!(!stem.contains(['/', '\\', ' ']), "{:?}", stem);
+            assert_eq!(CollectionName::from_file_stem(stem), Some(parsed));
+        }
+        // a percent sign is escaped itself, so `%2F` never turns into `/`
+        assert_eq!(stem("a%2Fb"), "a%252%46b");
+        assert_eq!(stem("con"), "%63on");
+        assert_eq!(stem("my coll"), "my%20coll");
+    }
+
+    #[test]
+    fn names_differing_in_case_get_different_files_on_any_file_system() {
+        let upper = stem("Users");
+        let lower = stem("users");
+        assert_ne!(upper.to_lowercase(), lower.to_lowercase());
+    }
+
+    #[test]
+    fn stems_that_do_not_round_trip_are_not_collections() {
+        assert_eq!(CollectionName::from_file_stem("a%2fb"), None);
+        assert_eq!(CollectionName::from_file_stem("a%2Fb"), None);
+        assert_eq!(CollectionName::from_file_stem("%5Fmeta"), None);
+        assert_eq!(CollectionName::from_file_stem("Users"), None);
+    }
+
+    #[test]
+    fn legacy_stems_map_to_their_collection() {
+        let legacy = |stem: &str| CollectionName::from_legacy_stem(stem).map(|n| n.to_string());
+        assert_eq!(legacy("my coll").as_deref(), Some("my coll"));
+        assert_eq!(legacy("Users").as_deref(), Some("Users"));
+        assert_eq!(legacy("My%20Coll").as_deref(), Some("My Coll"));
+        assert_eq!(legacy("100%").as_deref(), Some("100%"));
+        assert_eq!(legacy("users"), None);
+        assert_eq!(legacy("_meta"), None);
+    }
+}
@@ -0,0 +1,351 @@
+use serde_json::{Map, Value, json};
+
+use super::{DbError, query::values_equal};
+
+fn invalid(msg: impl Into<String>) -> DbError {
+    DbError::InvalidUpdate(msg.into())
+}
+
+/// RFC 7386 JSON Merge Patch: objects merge recursively, `null` removes a
+/// member and anything else replaces the target.
+pub fn merge(target: &mut Value, patch: &Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(map) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                map.remove(key);
+            } else {
+                merge(map.entry(key.as_str()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+/// Splits an RFC 6901 JSON Pointer into unescaped reference tokens.
+fn pointer(path: &str) -> Result<Vec<String>, DbError> {
+    if path.is_empty() {
+        return Ok(Vec::new());
+    }
+    let rest = path
+        .strip_prefix('/')
+        .ok_or_else(|| invalid(format!("JSON pointer '{}' must start with '/'", path)))?;
+    Ok(rest
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect())
+}
+
+fn array_index(token: &str, len: usize, path: &str) -> Result<usize, DbError> {
+    if token.len() > 1 && token.starts_with('0') {
+        return Err(invalid(format!("invalid array index in {}", path)));
+    }
+    token
+        .parse::<usize>()
+        .ok()
+        .filter(|i| *i < len)
+        .ok_or_else(|| invalid(format!("array index out of range in {}", path)))
+}
+
+fn resolve<'a>(value: &'a Value, tokens: &[String], path: &str) -> Result<&'a Value, DbError> {
+    let mut current = value;
+    for token in tokens {
+        current = match current {
+            Value::Object(map) => map.get(token),
+            Value::Array(items) => items.get(array_index(token, items.len(), path)?),
+            _ => None,
+        }
+        .ok_or_else(|| invalid(format!("path {} does not exist", path)))?;
+    }
+    Ok(current)
+}
+
+fn parent_mut<'a>(
+    value: &'a mut Value,
+    tokens: &[String],
+    path: &str,
+) -> Result<&'a mut Value, DbError> {
+    let mut current = value;
+    for token in tokens {
+        current = match current {
+            Value::Object(map) => map.get_mut(token),
+            Value::Array(items) => {
+                let len = items.len();
+                items.get_mut(array_index(token, len, path)?)
+            }
+            _ => None,
+        }
+        .ok_or_else(|| invalid(format!("path {} does not exist", path)))?;
+    }
+    Ok(current)
+}
+
+fn add(target: &mut Value, path: &str, value: Value) -> Result<(), DbError> {
+    let tokens = pointer(path)?;
+    let Some((last, parent)) = tokens.split_last() else {
+        *target = value;
+        return Ok(());
+    };
+    match parent_mut(target, parent, path)? {
+        Value::Object(map) => {
+            map.insert(last.clone(), value);
+        }
+        Value::Array(items) => {
+            let index = if last == "-" {
+                items.len()
+            } else {
+                array_index(last, items.len() + 1, path)?
+            };
+            items.insert(index, value);
+        }
+        _ => return Err(invalid(format!("cannot add {}", path))),
+    }
+    Ok(())
+}
+
+fn remove(target: &mut Value, path: &str) -> Result<Value, DbError> {
+    let tokens = pointer(path)?;
+    let Some((last, parent)) = tokens.split_last() else {
+        return Err(invalid("cannot remove the whole document"));
+    };
+    match parent_mut(target, parent, path)? {
+        Value::Object(map) => map
+            .remove(last)
+            .ok_or_else(|| invalid(format!("path {} does not exist", path))),
+        Value::Array(items) => {
+            let index = array_index(last, items.len(), path)?;
+            Ok(items.remove(index))
+        }
+        _ => Err(invalid(format!("path {} does not exist", path))),
+    }
+}
+
+fn field<'a>(op: &'a Value, name: &str) -> Result<&'a str, DbError> {
+    op.get(name)
+        .and_then(Value::as_str)
+        .ok_or_else(|| invalid(format!("patch operation is missing '{}'", name)))
+}
+
+fn operand(op: &Value) -> Result<Value, DbError> {
+    op.get("value")
+        .cloned()
+        .ok_or_else(|| invalid("patch operation is missing 'value'"))
+}
+
+/// RFC 6902 JSON Patch. On error `target` may be partially modified, so
+/// callers work on a copy.
+pub fn apply(target: &mut Value, patch: &Value) -> Result<(), DbError> {
+    let ops = patch
+        .as_array()
+        .ok_or_else(|| invalid("JSON Patch must be an array of operations"))?;
+    for op in ops {
+        let path = field(op, "path")?;
+        match field(op, "op")? {
+            "add" => add(target, path, operand(op)?)?,
+            "remove" => {
+                remove(target, path)?;
+            }
+            "replace" => {
+                let tokens = pointer(path)?;
+                *parent_mut(target, &tokens, path)? = operand(op)?;
+            }
+            "move" => {
+                let from = field(op, "from")?;
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(invalid(format!("cannot move {} into itself", from)));
+                }
+                let value = remove(target, from)?;
+                add(target, path, value)?;
+            }
+            "copy" => {
+                let from = field(op, "from")?;
+                let value = resolve(target, &pointer(from)?, from)?.clone();
+                add(target, path, value)?;
+            }
+            "test" => {
+                let expected = operand(op)?;
+                let actual = resolve(target, &pointer(path)?, path)
+                    .map_err(|_| DbError::PatchTestFailed(path.to_string()))?;
+                if !values_equal(actual, &expected) {
+                    return Err(DbError::PatchTestFailed(path.to_string()));
+                }
+            }
+            other => return Err(invalid(format!("unknown patch operation '{}'", other))),
+        }
+    }
+    Ok(())
+}
+
+fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// An RFC 6902 JSON Patch that turns `from` into `to`. Objects are compared
+/// member by member; anything else that differs is replaced whole.
+pub fn diff(from: &Value, to: &Value) -> Value {
+    let mut ops = Vec::new();
+    diff_at("", from, to, &mut ops);
+    Value::Array(ops)
+}
+
+fn diff_at(path: &str, from: &Value, to: &Value, ops: &mut Vec<Value>) {
+    if values_equal(from, to) {
+        return;
+    }
+    let (Value::Object(old), Value::Object(new)) = (from, to) else {
+        ops.push(json!({"op": "replace", "path": path, "value": to}));
+        return;
+    };
+    for key in old.keys().filter(|key| !new.contains_key(*key)) {
+        ops.push(json!({"op": "remove", "path": format!("{}/{}", path, escape(key))}));
+    }
+    for (key, value) in new {
+        let child = format!("{}/{}", path, escape(key));
+        match old.get(key) {
+            Some(previous) => diff_at(&child, previous, value, ops),
+            None => ops.push(json!({"op": "add", "path": child, "value": value})),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, test_dir};
+    use serde_json::json;
+    use std::fs;
+
+    fn patched(mut target: Value, patch: Value) -> Result<Value, DbError> {
+        apply(&mut target, &patch)?;
+        Ok(target)
+    }
+
+    #[test]
+    fn merge_patches_follow_rfc_7386() {
+        let mut target = json!({"a": {"b": 1, "c": 2}, "list": [1, 2], "s": "x"});
+        merge(
+            &mut target,
+            &json!({"a": {"b": null, "d": {"e": 1}}, "list": [3], "s": {"t": 1}, "gone": null}),
+        );
+        assert_eq!(
+            target,
+            json!({"a": {"c": 2, "d": {"e": 1}}, "list": [3], "s": {"t": 1}})
+        );
+        merge(&mut target, &json!([1]));
+        assert_eq!(target, json!([1]));
+        merge(&mut target, &json!({"a": null}));
+        assert_eq!(target, json!({}));
+    }
+
+    #[test]
+    fn pointers_unescape_and_index_strictly() {
+        let doc = json!({"a/b": {"m~n": 1}, "~1": 2, "list": [0, 1]});
+        let test = json!([{"op": "test", "path": "/a~1b/m~0n", "value": 1}]);
+        assert_eq!(patched(doc.clone(), test).unwrap(), doc);
+        // `~01` unescapes to `~1`, not `/`
+        assert!(
+            patched(
+                doc.clone(),
+                json!([{"op": "test", "path": "/~01", "value": 2}])
+            )
+            .is_ok()
+        );
+        let out = patched(
+            doc.clone(),
+            json!([
+                {"op": "add", "path": "/list/-", "value": 3},
+                {"op": "add", "path": "/list/3", "value": 4},
+                {"op": "add", "path": "/list/0", "value": -1},
+            ]),
+        )
+        .unwrap();
+        assert_eq!(out["list"], json!([-1, 0, 1, 3, 4]));
+        for path in ["/list/3", "/list/01", "/list/-1", "/list/x", "list/0"] {
+            let op = json!([{"op": "add", "path": path, "value": 9}]);
+            assert!(patched(doc.clone(), op).is_err(), "{path}");
+        }
+    }
+
+    #[test]
+    fn operations_require_existing_paths() {
+        let doc = json!({"a": {"b": 1}, "n": 1});
+        for op in [
+            json!({"op": "remove", "path": "/x"}),
+            json!({"op": "remove", "path": ""}),
+            json!({"op": "replace", "path": "/x", "value": 1}),
+            json!({"op": "add", "path": "/x/y", "value": 1}),
+            json!({"op": "move", "from": "/a", "path": "/a/b"}),
+            json!({"op": "copy", "from": "/x", "path": "/y"}),
+            json!({"op": "replace", "path": "/n"}),
+            json!({"op": "frobnicate", "path": "/n"}),
+        ] {
+            let err = patched(doc.clone(), json!([op])).unwrap_err();
+            assert!(matches!(err, DbError::InvalidUpdate(_)), "{op}");
+        }
+        for op in [
+            json!({"op": "test", "path": "/n", "value": 2}),
+            json!({"op": "test", "path": "/x", "value": null}),
+        ] {
+            let err = patched(doc.clone(), json!([op])).unwrap_err();
+            assert!(matches!(err, DbError::PatchTestFailed(_)));
+        }
+        let out = patched(
+            doc,
+            json!([
+                {"op": "test", "path": "/n", "value": 1.0},
+                {"op": "move", "from": "/a/b", "path": "/a/c"},
+                {"op": "copy", "from": "/a", "path": "/d"},
+                {"op": "replace", "path": "", "value": {"r": 1}},
+            ]),
+        )
+        .unwrap();
+        assert_eq!(out, json!({"r": 1}));
+    }
+
+    #[test]
+    fn a_failed_patch_leaves_the_document_alone() {
+        let dir = test_dir("patch");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let doc = col.insert(json!({"n": 1}), None).unwrap();
+        let patch = json!([
+            {"op": "replace", "path": "/n", "value": 2},
+            {"op": "test", "path": "/n", "value": 1},
+        ]);
+        assert!(matches!(
+            col.json_patch(&doc.id, &patch),
+            Err(DbError::PatchTestFailed(_))
+        ));
+        let stored = col.find(&doc.id).unwrap().unwrap();
+        assert_eq!((stored.data, stored.version), (doc.data, doc.version));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn diffs_round_trip() {
+        let from = json!({"a/b": 1, "~": {"x": [1, 2]}, "gone": true, "same": {"k": 1}});
+        let to = json!({"a/b": 2, "~": {"x": [2]}, "new": null, "same": {"k": 1.0}});
+        let ops = diff(&from, &to);
+        // numbers are compared by value, so 1 and 1.0 need no operation
+        let paths: Vec<&str> = ops
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|op| op["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(paths, ["/gone", "/a~1b", "/new", "/~0/x"]);
+        let mut expected = to.clone();
+        expected["same"]["k"] = json!(1);
+        assert_eq!(patched(from, ops).unwrap(), expected);
+        assert_eq!(diff(&to, &to), json!([]));
+        assert_eq!(
+            diff(&json!(1), &json!([1])),
+            json!([{"op": "replace", "path": "", "value": [1]}])
+        );
+    }
+}
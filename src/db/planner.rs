@@ -0,0 +1,295 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::ops::Bound;
+
+use super::{
+    index::Indexes,
+    query::{Condition, Filter},
+};
+
+// Fraction of index entries a one- or two-sided range is assumed to match.
+const OPEN_RANGE_SELECTIVITY: f64 = 0.33;
+const CLOSED_RANGE_SELECTIVITY: f64 = 0.1;
+const GEO_SELECTIVITY: f64 = 0.1;
+// Fetching through an index costs a little more per document than a scan.
+const INDEX_FETCH_COST: f64 = 1.2;
+
+/// How the matching documents are located before the filter is applied.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access<'a> {
+    FullScan,
+    Ids(Vec<String>),
+    Eq(&'a str, Vec<&'a Value>),
+    Range(&'a str, Bound<&'a Value>, Bound<&'a Value>),
+    Geo(&'a str, &'a Condition),
+}
+
+impl Access<'_> {
+    fn name(&self) -> &'static str {
+        match self {
+            Access::FullScan => "full_scan",
+            Access::Ids(_) => "id_lookup",
+            Access::Eq(..) => "index_eq",
+            Access::Range(..) => "index_range",
+            Access::Geo(..) => "geo_index",
+        }
+    }
+
+    fn cost(&self, estimate: f64) -> f64 {
+        match self {
+            Access::FullScan | Access::Ids(_) => estimate,
+            _ => estimate * INDEX_FETCH_COST,
+        }
+    }
+
+    fn index(&self) -> Option<String> {
+        match self {
+            Access::FullScan => None,
+            Access::Ids(_) => Some("_id".to_string()),
+            Access::Eq(field, _) | Access::Range(field, ..) | Access::Geo(field, _) => {
+                Some(field.to_string())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanCandidate {
+    pub plan: String,
+    pub index: Option<String>,
+    pub estimated_documents: f64,
+    pub cost: f64,
+}
+
+/// Report of how a query was executed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Explain {
+    pub plan: String,
+    pub index: Option<String>,
+    pub considered: Vec<PlanCandidate>,
+    pub collection_documents: usize,
+    pub documents_examined: usize,
+    pub documents_returned: usize,
+    pub elapsed_micros: u128,
+}
+
+pub struct Planned<'a> {
+    pub access: Access<'a>,
+    pub considered: Vec<PlanCandidate>,
+}
+
+/// Picks the cheapest access path for `filter` using index statistics.
+pub fn plan<'a>(filter: &'a Filter, indexes: &Indexes, documents: usize) -> Planned<'a> {
+    let total = documents as f64;
+    let mut options: Vec<(Access<'a>, f64)> = vec![(Access::FullScan, total)];
+
+    let conjuncts = filter.conjuncts();
+    for (field, cond) in &conjuncts {
+        match (*field, cond) {
+            ("_id", Condition::Eq(Value::String(id))) => {
+                options.push((Access::Ids(vec![id.clone()]), 1.0));
+            }
+            ("_id", Condition::In(list)) => {
+                let ids: Vec<String> = list
+                    .iter()
+                    .filter_map(|v| v.as_str().map(str::to_string))
+                    .collect();
+                let n = ids.len() as f64;
+                options.push((Access::Ids(ids), n));
+            }
+            (field, Condition::Eq(v)) if !v.is_array() => {
+                if let Some(index) = indexes.value(field) {
+                    let per_key = index.len() as f64 / index.distinct_keys().max(1) as f64;
+                    options.push((Access::Eq(field, vec![v]), per_key));
+                }
+            }
+            (field, Condition::In(list)) if !list.iter().any(Value::is_array) => {
+                if let Some(index) = indexes.value(field) {
+                    let per_key = index.len() as f64 / index.distinct_keys().max(1) as f64;
+                    let estimate = per_key * list.len() as f64;
+                    options.push((Access::Eq(field, list.iter().collect()), estimate));
+                }
+            }
+            (field, Condition::GeoWithin(_) | Condition::Near(_)) => {
+                if let Some(index) = indexes.geo(field) {
+                    let bounded = !matches!(cond, Condition::Near(n) if n.max_distance.is_none());
+                    let estimate = if bounded {
+                        index.len() as f64 * GEO_SELECTIVITY
+                    } else {
+                        index.len() as f64
+                    };
+                    options.push((Access::Geo(field, cond), estimate));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // merge range operators on the same indexed field into one scan
+    let mut ranged: Vec<&str> = Vec::new();
+    for (field, _) in &conjuncts {
+        if ranged.contains(field) {
+            continue;
+        }
+        let Some(index) = indexes.value(field) else {
+            continue;
+        };
+        let (mut lower, mut upper) = (Bound::Unbounded, Bound::Unbounded);
+        for (_, cond) in conjuncts.iter().filter(|(f, _)| f == field) {
+            match cond {
+                Condition::Gt(v) if lower == Bound::Unbounded => lower = Bound::Excluded(v),
+                Condition::Gte(v) if lower == Bound::Unbounded => lower = Bound::Included(v),
+                Condition::Lt(v) if upper == Bound::Unbounded => upper = Bound::Excluded(v),
+                Condition::Lte(v) if upper == Bound::Unbounded => upper = Bound::Included(v),
+                _ => {}
+            }
+        }
+        let selectivity = match (lower, upper) {
+            (Bound::Unbounded, Bound::Unbounded) => continue,
+            (Bound::Unbounded, _) | (_, Bound::Unbounded) => OPEN_RANGE_SELECTIVITY,
+            _ => CLOSED_RANGE_SELECTIVITY,
+        };
+        ranged.push(field);
+        let estimate = index.len() as f64 * selectivity;
+        options.push((Access::Range(field, lower, upper), estimate));
+    }
+
+    let considered: Vec<PlanCandidate> = options
+        .iter()
+        .map(|(access, estimate)| PlanCandidate {
+            plan: access.name().to_string(),
+            index: access.index(),
+            estimated_documents: *estimate,
+            cost: access.cost(*estimate),
+        })
+        .collect();
+    let access = options
+        .into_iter()
+        .min_by(|a, b| a.0.cost(a.1).total_cmp(&b.0.cost(b.1)))
+        .map(|(access, _)| access)
+        .unwrap_or(Access::FullScan);
+
+    Planned { access, considered }
+}
+
+impl Planned<'_> {
+    pub fn explain(&self) -> Explain {
+        Explain {
+            plan: self.access.name().to_string(),
+            index: self.access.index(),
+            considered: self.considered.clone(),
+            collection_documents: 0,
+            documents_examined: 0,
+            documents_returned: 0,
+            elapsed_micros: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::db::{Collection, Database, Query, test_dir};
+    use serde_json::{Value, json};
+    use std::fs;
+
+    fn numbers(db: &Database) -> Collection {
+        let col = db.collection("numbers").unwrap();
+        for n in 0..100 {
+            col.insert(json!({"n": n, "kind": "a", "tags": [n % 3]}), None)
+                .unwrap();
+        }
+        col.create_index("n").unwrap();
+        col.create_index("kind").unwrap();
+        col
+    }
+
+    fn ns(col: &Collection, filter: Value) -> Vec<i64> {
+        let mut ns: Vec<i64> = col
+            .query(&Query::new(filter))
+            .unwrap()
+            .iter()
+            .map(|doc| doc.data["n"].as_i64().unwrap())
+            .collect();
+        ns.sort_unstable();
+        ns
+    }
+
+    #[test]
+    fn indexes_are_only_used_when_cheaper_than_a_scan() {
+        let dir = test_dir("planner-cost");
+        let db = Database::new(&dir).unwrap();
+        let col = numbers(&db);
+        let plan = |filter: Value| col.explain(&Query::new(filter)).unwrap();
+
+        let eq = plan(json!({"n": 5}));
+        assert_eq!(
+            (eq.plan.as_str(), eq.index.as_deref()),
+            ("index_eq", Some("n"))
+        );
+        assert_eq!(eq.documents_examined, 1);
+        // every document has the same kind, so the index saves nothing
+        assert_eq!(plan(json!({"kind": "a"})).plan, "full_scan");
+        assert_eq!(plan(json!({"n": 5, "kind": "a"})).plan, "index_eq");
+        // arrays and disjunctions are not planned against the index
+        assert_eq!(plan(json!({"n": [5]})).plan, "full_scan");
+        assert_eq!(plan(json!({"$or": [{"n": 5}, {"n": 6}]})).plan, "full_scan");
+        assert_eq!(ns(&col, json!({"$or": [{"n": 5}, {"n": 6}]})), [5, 6]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ranges_on_one_field_merge_into_one_scan() {
+        let dir = test_dir("planner-range");
+        let db = Database::new(&dir).unwrap();
+        let col = numbers(&db);
+
+        let filter = json!({"n": {"$gt": 10, "$lte": 20}});
+        let explain = col.explain(&Query::new(filter.clone())).unwrap();
+        assert_eq!(explain.plan, "index_range");
+        let ranges = explain
+            .considered
+            .iter()
+            .filter(|c| c.plan == "index_range")
+            .count();
+        assert_eq!(ranges, 1);
+        assert_eq!(explain.documents_examined, 10);
+        assert_eq!(ns(&col, filter), (11..=20).collect::<Vec<_>>());
+
+        // only the first bound on each side narrows the scan; the filter
+        // still applies the rest
+        let both = json!({"$and": [{"n": {"$gt": 10}}, {"n": {"$gt": 90}}]});
+        assert_eq!(ns(&col, both), (91..100).collect::<Vec<_>>());
+        assert_eq!(ns(&col, json!({"n": {"$gte": 98}})), [98, 99]);
+        assert!(ns(&col, json!({"n": {"$gt": 50, "$lt": 40}})).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn id_lookups_skip_missing_and_repeated_ids() {
+        let dir = test_dir("planner-ids");
+        let db = Database::new(&dir).unwrap();
+        let col = numbers(&db);
+        let id = col.query(&Query::new(json!({"n": 7}))).unwrap()[0]
+            .id
+            .clone();
+
+        let filter = json!({"_id": {"$in": [id, id, "missing", 3]}});
+        let explain = col.explain(&Query::new(filter.clone())).unwrap();
+        assert_eq!(explain.plan, "id_lookup");
+        assert_eq!(ns(&col, filter), [7]);
+        assert!(ns(&col, json!({"_id": "missing"})).is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn empty_collections_scan() {
+        let dir = test_dir("planner-empty");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("empty").unwrap();
+        col.create_index("n").unwrap();
+        let explain = col.explain(&Query::new(json!({"n": 1}))).unwrap();
+        assert_eq!(explain.plan, "full_scan");
+        assert_eq!(explain.documents_returned, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,395 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::BuildHasher,
+    slice,
+    sync::{Arc, Weak},
+};
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+/// A persistent hash map keyed by strings: a hash array mapped trie whose
+/// nodes are shared through `Arc`s. Cloning is O(1), and a write copies only
+/// the nodes on the path to the changed entry, so clones taken earlier keep
+/// seeing their own state and share everything the write didn't touch.
+pub(super) struct PMap<V> {
+    root: Arc<Node<V>>,
+    len: usize,
+    hasher: RandomState,
+}
+
+struct Node<V> {
+    bitmap: u32,
+    entries: Vec<Entry<V>>,
+}
+
+enum Entry<V> {
+    /// Keys whose whole hash is the same.
+    Leaf(u64, Vec<(String, V)>),
+    Node(Arc<Node<V>>),
+}
+
+/// A root a writer replaced, to tell whether a clone still holds it.
+pub(super) struct Retired<V>(Weak<Node<V>>);
+
+impl<V> Retired<V> {
+    pub fn is_alive(&self) -> bool {
+        self.0.strong_count() > 0
+    }
+}
+
+impl<V> fmt::Debug for Retired<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Retired")
+            .field("alive", &self.is_alive())
+            .finish()
+    }
+}
+
+impl<V> Default for PMap<V> {
+    fn default() -> Self {
+        Self {
+            root: Arc::new(Node::default()),
+            len: 0,
+            hasher: RandomState::new(),
+        }
+    }
+}
+
+impl<V> Default for Node<V> {
+    fn default() -> Self {
+        Self {
+            bitmap: 0,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl<V> Clone for PMap<V> {
+    fn clone(&self) -> Self {
+        Self {
+            root: Arc::clone(&self.root),
+            len: self.len,
+            hasher: self.hasher.clone(),
+        }
+    }
+}
+
+impl<V: Clone> Clone for Node<V> {
+    fn clone(&self) -> Self {
+        Self {
+            bitmap: self.bitmap,
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<V: Clone> Clone for Entry<V> {
+    fn clone(&self) -> Self {
+        match self {
+            Entry::Leaf(hash, bucket) => Entry::Leaf(*hash, bucket.clone()),
+            Entry::Node(node) => Entry::Node(Arc::clone(node)),
+        }
+    }
+}
+
+impl<V: Clone + fmt::Debug> fmt::Debug for PMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<V> Node<V> {
+    fn slot(&self, hash: u64, shift: u32) -> (u32, usize) {
+        let bit = 1 << ((hash >> shift) & MASK);
+        (bit, (self.bitmap & (bit - 1)).count_ones() as usize)
+    }
+}
+
+impl<V: Clone> PMap<V> {
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, key: &str) -> Option<&V> {
+        let hash = self.hasher.hash_one(key);
+        let mut node = &self.root;
+        let mut shift = 0;
+        loop {
+            let (bit, pos) = node.slot(hash, shift);
+            if node.bitmap & bit == 0 {
+                return None;
+            }
+            match &node.entries[pos] {
+                Entry::Leaf(h, bucket) if *h == hash => {
+                    return bucket.iter().find(|(k, _)| k == key).map(|(_, v)| v);
+                }
+                Entry::Leaf(..) => return None,
+                Entry::Node(child) => {
+                    node = child;
+                    shift += BITS;
+                }
+            }
+        }
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Inserts or replaces the value for `key`, returning the old one.
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        let hash = self.hasher.hash_one(key.as_str());
+        let old = insert(&mut self.root, hash, 0, key, value);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        // checked first so a miss never copies the path
+        if !self.contains_key(key) {
+            return None;
+        }
+        let hash = self.hasher.hash_one(key);
+        let old = remove(&mut self.root, hash, 0, key);
+        if old.is_some() {
+            self.len -= 1;
+        }
+        old
+    }
+
+    /// Empties the map without touching what clones of it hold.
+    pub fn clear(&mut self) {
+        self.root = Arc::new(Node::default());
+        self.len = 0;
+    }
+
+    /// The current root if a clone shares it, i.e. the next write will
+    /// leave that clone behind on its own version.
+    pub fn shared(&self) -> Option<Retired<V>> {
+        (Arc::strong_count(&self.root) > 1).then(|| Retired(Arc::downgrade(&self.root)))
+    }
+
+    pub fn is_root(&self, retired: &Retired<V>) -> bool {
+        Weak::ptr_eq(&retired.0, &Arc::downgrade(&self.root))
+    }
+
+    pub fn iter(&self) -> Iter<'_, V> {
+        Iter {
+            stack: vec![self.root.entries.iter()],
+            bucket: [].iter(),
+        }
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+fn insert<V: Clone>(
+    node: &mut Arc<Node<V>>,
+    hash: u64,
+    shift: u32,
+    key: String,
+    value: V,
+) -> Option<V> {
+    let node = Arc::make_mut(node);
+    let (bit, pos) = node.slot(hash, shift);
+    if node.bitmap & bit == 0 {
+        node.bitmap |= bit;
+        node.entries
+            .insert(pos, Entry::Leaf(hash, vec![(key, value)]));
+        return None;
+    }
+    match &mut node.entries[pos] {
+        Entry::Leaf(h, bucket) if *h == hash => match bucket.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => Some(std::mem::replace(v, value)),
+            None => {
+                bucket.push((key, value));
+                None
+            }
+        },
+        Entry::Leaf(other, bucket) => {
+            // Two hashes share this slot: push the existing leaf one level
+            // down. Distinct hashes part ways before the bits run out.
+            let (other, bucket) = (*other, std::mem::take(bucket));
+            let mut child = Node::default();
+            let (other_bit, _) = child.slot(other, shift + BITS);
+            child.bitmap = other_bit;
+            child.entries.push(Entry::Leaf(other, bucket));
+            let mut child = Arc::new(child);
+            let old = insert(&mut child, hash, shift + BITS, key, value);
+            node.entries[pos] = Entry::Node(child);
+            old
+        }
+        Entry::Node(child) => insert(child, hash, shift + BITS, key, value),
+    }
+}
+
+fn remove<V: Clone>(node: &mut Arc<Node<V>>, hash: u64, shift: u32, key: &str) -> Option<V> {
+    let node = Arc::make_mut(node);
+    let (bit, pos) = node.slot(hash, shift);
+    if node.bitmap & bit == 0 {
+        return None;
+    }
+    let (old, emptied) = match &mut node.entries[pos] {
+        Entry::Leaf(h, bucket) if *h == hash => {
+            let i = bucket.iter().position(|(k, _)| k == key)?;
+            let (_, old) = bucket.remove(i);
+            (Some(old), bucket.is_empty())
+        }
+        Entry::Leaf(..) => return None,
+        Entry::Node(child) => {
+            let old = remove(child, hash, shift + BITS, key);
+            // a node left with a single leaf is folded back into its parent
+            if child.entries.len() == 1 && matches!(child.entries[0], Entry::Leaf(..)) {
+                let leaf = Arc::make_mut(child).entries.pop();
+                if let Some(leaf) = leaf {
+                    node.entries[pos] = leaf;
+                }
+            }
+            (old, false)
+        }
+    };
+    if emptied {
+        node.bitmap &= !bit;
+        node.entries.remove(pos);
+    }
+    old
+}
+
+pub(super) struct Iter<'a, V> {
+    stack: Vec<slice::Iter<'a, Entry<V>>>,
+    bucket: slice::Iter<'a, (String, V)>,
+}
+
+impl<'a, V> Iterator for Iter<'a, V> {
+    type Item = (&'a String, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((k, v)) = self.bucket.next() {
+                return Some((k, v));
+            }
+            match self.stack.last_mut()?.next() {
+                Some(Entry::Leaf(_, bucket)) => self.bucket = bucket.iter(),
+                Some(Entry::Node(node)) => self.stack.push(node.entries.iter()),
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+}
+
+impl<V: Clone> FromIterator<(String, V)> for PMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(iter: I) -> Self {
+        let mut map = Self::default();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn behaves_like_a_hash_map() {
+        let mut map = PMap::default();
+        let mut model = HashMap::new();
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        for _ in 0..20_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let key = format!("k{}", state % 3_000);
+            if state.is_multiple_of(3) {
+                assert_eq!(map.remove(&key), model.remove(&key));
+            } else {
+                let value = (state % 1_000) as u32;
+                assert_eq!(map.insert(key.clone(), value), model.insert(key, value));
+            }
+            assert_eq!(map.len(), model.len());
+        }
+        for (key, value) in &model {
+            assert_eq!(map.get(key), Some(value));
+        }
+        let mut seen: Vec<(String, u32)> = map.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        let mut expected: Vec<(String, u32)> = model.into_iter().collect();
+        seen.sort();
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+
+    #[test]
+    fn clones_keep_their_own_version() {
+        let mut map: PMap<u32> = (0..1_000).map(|i| (i.to_string(), i)).collect();
+        let snapshot = map.clone();
+        assert!(map.shared().is_some());
+        map.insert("5".into(), 50);
+        map.remove("6");
+        map.insert("new".into(), 1);
+        assert!(map.shared().is_none());
+
+        assert_eq!(snapshot.get("5"), Some(&5));
+        assert_eq!(snapshot.get("6"), Some(&6));
+        assert_eq!(snapshot.get("new"), None);
+        assert_eq!(snapshot.len(), 1_000);
+        assert_eq!(snapshot.iter().count(), 1_000);
+        assert_eq!(map.get("5"), Some(&50));
+        assert_eq!(map.get("6"), None);
+        assert_eq!(map.len(), 1_000);
+    }
+
+    #[test]
+    fn retired_roots_die_with_their_last_clone() {
+        let mut map: PMap<u32> = PMap::default();
+        map.insert("a".into(), 1);
+        let snapshot = map.clone();
+        let retired = map.shared().unwrap();
+        assert!(map.is_root(&retired));
+        map.insert("b".into(), 2);
+        assert!(!map.is_root(&retired));
+        assert!(retired.is_alive());
+        drop(snapshot);
+        assert!(!retired.is_alive());
+    }
+
+    #[test]
+    fn removing_everything_leaves_an_empty_map() {
+        let mut map: PMap<u32> = (0..500).map(|i| (format!("id-{}", i), i)).collect();
+        for i in 0..500 {
+            assert_eq!(map.remove(&format!("id-{}", i)), Some(i));
+        }
+        assert_eq!(map.len(), 0);
+        assert!(map.root.entries.is_empty());
+        assert_eq!(map.remove("id-0"), None);
+    }
+
+    #[test]
+    fn colliding_hashes_are_kept_apart() {
+        // exercises the trie directly with chosen hashes: a full collision
+        // shares a bucket, a near one goes down to the last level
+        let mut root: Arc<Node<u32>> = Arc::default();
+        let deep = 1 << 63;
+        assert_eq!(insert(&mut root, 7, 0, "a".into(), 1), None);
+        assert_eq!(insert(&mut root, 7, 0, "b".into(), 2), None);
+        assert_eq!(insert(&mut root, 7 | deep, 0, "c".into(), 3), None);
+        assert_eq!(insert(&mut root, 7, 0, "a".into(), 10), Some(1));
+
+        assert_eq!(remove(&mut root, 7, 0, "b"), Some(2));
+        assert_eq!(remove(&mut root, 7, 0, "b"), None);
+        assert_eq!(remove(&mut root, 7, 0, "a"), Some(10));
+        // the lone remaining leaf is folded back up to the root
+        assert!(matches!(&root.entries[..], [Entry::Leaf(h, _)] if *h == 7 | deep));
+        assert_eq!(remove(&mut root, 7 | deep, 0, "c"), Some(3));
+        assert!(root.entries.is_empty());
+    }
+}
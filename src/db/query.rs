@@ -0,0 +1,456 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::{borrow::Cow, cmp::Ordering};
+
+use super::{
+    DbError, Document,
+    geo::{GeoShape, Geometry, Near, Point},
+};
+
+/// A filtered read against a collection.
+///
+/// `filter` uses a MongoDB-style syntax over `Document.data`, e.g.
+/// `{"age": {"$gte": 18}, "tags": "admin"}`. `_id` refers to the document id.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Query {
+    #[serde(default)]
+    pub filter: Value,
+    #[serde(default)]
+    pub skip: Option<usize>,
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+impl Query {
+    pub fn new(filter: Value) -> Self {
+        Self {
+            filter,
+            ..Default::default()
+        }
+    }
+
+    pub fn skip(mut self, skip: usize) -> Self {
+        self.skip = Some(skip);
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Nor(Vec<Filter>),
+    Field(String, Condition),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    Eq(Value),
+    Ne(Value),
+    Gt(Value),
+    Gte(Value),
+    Lt(Value),
+    Lte(Value),
+    In(Vec<Value>),
+    Nin(Vec<Value>),
+    Exists(bool),
+    Not(Box<Condition>),
+    GeoWithin(GeoShape),
+    Near(Near),
+}
+
+fn invalid(msg: impl Into<String>) -> DbError {
+    DbError::InvalidQuery(msg.into())
+}
+
+impl Filter {
+    pub fn parse(value: &Value) -> Result<Self, DbError> {
+        match value {
+            Value::Null => Ok(Filter::And(Vec::new())),
+            Value::Object(map) => Self::parse_object(map),
+            _ => Err(invalid("filter must be an object")),
+        }
+    }
+
+    fn parse_object(map: &Map<String, Value>) -> Result<Self, DbError> {
+        let mut clauses = Vec::new();
+        for (key, value) in map {
+            match key.as_str() {
+                "$and" | "$or" | "$nor" => {
+                    let items = value
+                        .as_array()
+                        .ok_or_else(|| invalid(format!("{} expects an array", key)))?
+                        .iter()
+                        .map(Self::parse)
+                        .collect::<Result<Vec<_>, _>>()?;
+                    clauses.push(match key.as_str() {
+                        "$and" => Filter::And(items),
+                        "$or" => Filter::Or(items),
+                        _ => Filter::Nor(items),
+                    });
+                }
+                op if op.starts_with('$') => {
+                    return Err(invalid(format!("unknown operator {}", op)));
+                }
+                field => match value {
+                    Value::Object(ops) if ops.keys().next().is_some_and(|k| k.starts_with('$')) => {
+                        for (op, arg) in ops {
+                            if is_near_option(op) && ops.contains_key("$near") {
+                                continue;
+                            }
+                            clauses.push(Filter::Field(
+                                field.to_string(),
+                                Condition::parse(op, arg, ops)?,
+                            ));
+                        }
+                    }
+                    _ => clauses.push(Filter::Field(
+                        field.to_string(),
+                        Condition::Eq(value.clone()),
+                    )),
+                },
+            }
+        }
+        Ok(if clauses.len() == 1 {
+            clauses.remove(0)
+        } else {
+            Filter::And(clauses)
+        })
+    }
+
+    pub fn matches(&self, doc: &Document) -> bool {
+        self.eval(&|path| field_value(doc, path))
+    }
+
+    /// Matches a plain JSON object, e.g. a record inside an aggregation pipeline.
+    pub fn matches_value(&self, value: &Value) -> bool {
+        self.eval(&|path| path_value(value, path).map(Cow::Borrowed))
+    }
+
+    fn eval<'a>(&self, lookup: &dyn Fn(&str) -> Option<Cow<'a, Value>>) -> bool {
+        match self {
+            Filter::And(items) => items.iter().all(|f| f.eval(lookup)),
+            Filter::Or(items) => items.iter().any(|f| f.eval(lookup)),
+            Filter::Nor(items) => !items.iter().any(|f| f.eval(lookup)),
+            Filter::Field(path, cond) => cond.matches(lookup(path).as_deref()),
+        }
+    }
+
+    /// Conditions that must hold for every match, i.e. the top-level conjunction.
+    pub fn conjuncts(&self) -> Vec<(&str, &Condition)> {
+        match self {
+            Filter::Field(path, cond) => vec![(path.as_str(), cond)],
+            Filter::And(items) => items.iter().flat_map(|f| f.conjuncts()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The `$near` condition that orders results, if any.
+    pub fn near(&self) -> Option<(&str, &Near)> {
+        self.conjuncts()
+            .into_iter()
+            .find_map(|(path, cond)| match cond {
+                Condition::Near(near) => Some((path, near)),
+                _ => None,
+            })
+    }
+}
+
+impl Condition {
+    fn parse(op: &str, arg: &Value, siblings: &Map<String, Value>) -> Result<Self, DbError> {
+        Ok(match op {
+            "$eq" => Condition::Eq(arg.clone()),
+            "$ne" => Condition::Ne(arg.clone()),
+            "$gt" => Condition::Gt(arg.clone()),
+            "$gte" => Condition::Gte(arg.clone()),
+            "$lt" => Condition::Lt(arg.clone()),
+            "$lte" => Condition::Lte(arg.clone()),
+            "$in" | "$nin" => {
+                let list = arg
+                    .as_array()
+                    .ok_or_else(|| invalid(format!("{} expects an array", op)))?
+                    .clone();
+                if op == "$in" {
+                    Condition::In(list)
+                } else {
+                    Condition::Nin(list)
+                }
+            }
+            "$exists" => Condition::Exists(
+                arg.as_bool()
+                    .ok_or_else(|| invalid("$exists expects a boolean"))?,
+            ),
+            "$not" => {
+                let (inner_op, inner_arg) = arg
+                    .as_object()
+                    .and_then(|m| m.iter().next())
+                    .ok_or_else(|| invalid("$not expects an operator object"))?;
+                Condition::Not(Box::new(Self::parse(inner_op, inner_arg, siblings)?))
+            }
+            "$geoWithin" => Condition::GeoWithin(parse_shape(arg)?),
+            "$near" => Condition::Near(parse_near(arg, siblings)?),
+            _ => return Err(invalid(format!("unknown operator {}", op))),
+        })
+    }
+
+    pub fn matches(&self, value: Option<&Value>) -> bool {
+        match self {
+            Condition::Eq(target) => value.is_some_and(|v| eq_or_contains(v, target)),
+            Condition::Ne(target) => !value.is_some_and(|v| eq_or_contains(v, target)),
+            Condition::Gt(t) => compares(value, t, |o| o == Ordering::Greater),
+            Condition::Gte(t) => compares(value, t, |o| o != Ordering::Less),
+            Condition::Lt(t) => compares(value, t, |o| o == Ordering::Less),
+            Condition::Lte(t) => compares(value, t, |o| o != Ordering::Greater),
+            Condition::In(list) => value.is_some_and(|v| list.iter().any(|t| eq_or_contains(v, t))),
+            Condition::Nin(list) => {
+                !value.is_some_and(|v| list.iter().any(|t| eq_or_contains(v, t)))
+            }
+            Condition::Exists(expected) => value.is_some() == *expected,
+            Condition::Not(inner) => !inner.matches(value),
+            Condition::GeoWithin(shape) => value
+                .and_then(Geometry::from_json)
+                .is_some_and(|g| shape.contains(&g)),
+            Condition::Near(near) => value
+                .and_then(Geometry::from_json)
+                .is_some_and(|g| near.matches(&g)),
+        }
+    }
+}
+
+fn parse_point(v: &Value) -> Result<Point, DbError> {
+    match Geometry::from_json(v) {
+        Some(Geometry::Point(p)) => Ok(p),
+        _ => Err(invalid("expected a GeoJSON Point or [lng, lat]")),
+    }
+}
+
+fn parse_shape(arg: &Value) -> Result<GeoShape, DbError> {
+    if let Some(geometry) = arg.get("$geometry") {
+        return match Geometry::from_json(geometry) {
+            Some(Geometry::Polygon(poly)) => Ok(GeoShape::Polygon(poly)),
+            _ => Err(invalid("$geoWithin $geometry must be a GeoJSON Polygon")),
+        };
+    }
+    if let Some(center) = arg.get("$center") {
+        // [[lng, lat], radius in meters]
+        let parts = center
+            .as_array()
+            .filter(|a| a.len() == 2)
+            .ok_or_else(|| invalid("$center expects [[lng, lat], meters]"))?;
+        let radius = parts[1]
+            .as_f64()
+            .filter(|r| *r >= 0.0)
+            .ok_or_else(|| invalid("$center radius must be a non-negative number"))?;
+        return Ok(GeoShape::Circle(parse_point(&parts[0])?, radius));
+    }
+    if let Some(ring) = arg.get("$polygon") {
+        let mut ring = ring.clone();
+        if let Some(points) = ring.as_array_mut()
+            && let Some(first) = points.first().cloned()
+            && points.last() != Some(&first)
+        {
+            points.push(first);
+        }
+        let geojson = serde_json::json!({"type": "Polygon", "coordinates": [ring]});
+        return match Geometry::from_json(&geojson) {
+            Some(Geometry::Polygon(poly)) => Ok(GeoShape::Polygon(poly)),
+            _ => Err(invalid("$polygon expects at least three [lng, lat] points")),
+        };
+    }
+    Err(invalid("$geoWithin expects $geometry, $center or $polygon"))
+}
+
+fn is_near_option(op: &str) -> bool {
+    op == "$maxDistance" || op == "$minDistance"
+}
+
+// Distances may sit inside the $near object or next to it, as in MongoDB.
+fn parse_near(arg: &Value, siblings: &Map<String, Value>) -> Result<Near, DbError> {
+    let distance = |key: &str| -> Result<Option<f64>, DbError> {
+        match arg.get(key).or_else(|| siblings.get(key)) {
+            None => Ok(None),
+            Some(v) => v
+                .as_f64()
+                .filter(|d| *d >= 0.0)
+                .map(Some)
+                .ok_or_else(|| invalid(format!("{} must be a non-negative number", key))),
+        }
+    };
+    let point = match arg.get("$geometry") {
+        Some(geometry) => parse_point(geometry)?,
+        None => parse_point(arg)?,
+    };
+    Ok(Near {
+        point,
+        min_distance: distance("$minDistance")?,
+        max_distance: distance("$maxDistance")?,
+    })
+}
+
+/// Resolves a dotted path against `doc.data`; `_id` maps to the document id.
+pub fn field_value<'a>(doc: &'a Document, path: &str) -> Option<Cow<'a, Value>> {
+    if path == "_id" {
+        return Some(Cow::Owned(Value::String(doc.id.clone())));
+    }
+    path_value(&doc.data, path).map(Cow::Borrowed)
+}
+
+pub fn path_value<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let mut current = value;
+    for segment in path.split('.') {
+        current = match current {
+            Value::Object(map) => map.get(segment)?,
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+/// Sets a dotted path, creating intermediate objects as needed.
+pub fn set_path(value: &mut Value, path: &str, new: Value) -> Result<(), DbError> {
+    let (parent, last) = match path.rsplit_once('.') {
+        Some((parent, last)) => (Some(parent), last),
+        None => (None, path),
+    };
+    let mut current = value;
+    for segment in parent.into_iter().flat_map(|p| p.split('.')) {
+        if current.is_null() {
+            *current = Value::Object(Map::new());
+        }
+        current = match current {
+            Value::Object(map) => map
+                .entry(segment)
+                .or_insert_with(|| Value::Object(Map::new())),
+            Value::Array(items) => segment
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| items.get_mut(i))
+                .ok_or_else(|| invalid(format!("cannot traverse {} in {}", segment, path)))?,
+            _ => return Err(invalid(format!("cannot traverse {} in {}", segment, path))),
+        };
+    }
+    if current.is_null() {
+        *current = Value::Object(Map::new());
+    }
+    match current {
+        Value::Object(map) => {
+            map.insert(last.to_string(), new);
+        }
+        Value::Array(items) => {
+            let slot = last
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| items.get_mut(i))
+                .ok_or_else(|| invalid(format!("cannot set {} in {}", last, path)))?;
+            *slot = new;
+        }
+        _ => return Err(invalid(format!("cannot set {} in {}", last, path))),
+    }
+    Ok(())
+}
+
+/// Removes a dotted path, returning the removed value.
+pub fn remove_path(value: &mut Value, path: &str) -> Option<Value> {
+    let (parent, last) = match path.rsplit_once('.') {
+        Some((parent, last)) => (path_value_mut(value, parent)?, last),
+        None => (value, path),
+    };
+    match parent {
+        Value::Object(map) => map.remove(last),
+        _ => None,
+    }
+}
+
+pub fn path_value_mut<'a>(value: &'a mut Value, path: &str) -> Option<&'a mut Value> {
+    let mut current = value;
+    for segment in path.split('.') {
+        current = match current {
+            Value::Object(map) => map.get_mut(segment)?,
+            Value::Array(items) => items.get_mut(segment.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some(current)
+}
+
+pub fn values_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        (Value::Array(x), Value::Array(y)) => {
+            x.len() == y.len() && x.iter().zip(y).all(|(a, b)| values_equal(a, b))
+        }
+        _ => a == b,
+    }
+}
+
+fn eq_or_contains(value: &Value, target: &Value) -> bool {
+    values_equal(value, target)
+        || matches!(value, Value::Array(items) if items.iter().any(|v| values_equal(v, target)))
+}
+
+/// Ordering between values of the same JSON type; `None` across types.
+pub fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64()?.partial_cmp(&y.as_f64()?),
+        (Value::String(x), Value::String(y)) => Some(x.cmp(y)),
+        (Value::Bool(x), Value::Bool(y)) => Some(x.cmp(y)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+fn type_rank(v: &Value) -> u8 {
+    match v {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+/// Total order across all JSON values, ranking by type first. Used for index keys.
+pub fn total_cmp(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x
+            .as_f64()
+            .unwrap_or(0.0)
+            .total_cmp(&y.as_f64().unwrap_or(0.0)),
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Array(x), Value::Array(y)) => x
+            .iter()
+            .zip(y)
+            .map(|(a, b)| total_cmp(a, b))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| x.len().cmp(&y.len())),
+        (Value::Object(x), Value::Object(y)) => x
+            .iter()
+            .zip(y)
+            .map(|((ka, va), (kb, vb))| ka.cmp(kb).then_with(|| total_cmp(va, vb)))
+            .find(|o| o.is_ne())
+            .unwrap_or_else(|| x.len().cmp(&y.len())),
+        _ => type_rank(a).cmp(&type_rank(b)),
+    }
+}
+
+pub fn same_type(a: &Value, b: &Value) -> bool {
+    type_rank(a) == type_rank(b)
+}
+
+fn compares(value: Option<&Value>, target: &Value, pred: impl Fn(Ordering) -> bool) -> bool {
+    match value {
+        Some(Value::Array(items)) if !target.is_array() => items
+            .iter()
+            .any(|v| compare_values(v, target).is_some_and(&pred)),
+        Some(v) => compare_values(v, target).is_some_and(pred),
+        None => false,
+    }
+}
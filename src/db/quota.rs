@@ -0,0 +1,166 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, RwLock},
+};
+
+use super::{DbError, file};
+
+/// Limits on a whole database. Writes past them fail with `QuotaExceeded`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_collections: Option<usize>,
+    /// Documents across every collection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_documents: Option<usize>,
+    /// Size of the documents' data serialized as JSON, across every
+    /// collection.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub collections: usize,
+    pub documents: usize,
+    pub bytes: u64,
+}
+
+/// The quota of a database and what each of its collections uses, shared
+/// by the database and its collections. Collections update their usage
+/// under their write lock, so it is never behind a write that went through.
+#[derive(Debug, Default)]
+pub(super) struct QuotaState {
+    quota: RwLock<Quota>,
+    collections: Mutex<HashMap<String, (usize, u64)>>,
+}
+
+impl QuotaState {
+    pub fn quota(&self) -> Quota {
+        self.quota.read().map(|q| q.clone()).unwrap_or_default()
+    }
+
+    pub fn set(&self, quota: Quota) {
+        if let Ok(mut current) = self.quota.write() {
+            *current = quota;
+        }
+    }
+
+    pub fn record(&self, collection: &str, documents: usize, bytes: u64) {
+        if let Ok(mut collections) = self.collections.lock() {
+            collections.insert(collection.to_string(), (documents, bytes));
+        }
+    }
+
+    /// Like [`QuotaState::record`], but only for a collection already
+    /// counted, so a name that was opened and never written stays free.
+    pub fn update(&self, collection: &str, documents: usize, bytes: u64) {
+        if let Ok(mut collections) = self.collections.lock()
+            && let Some(usage) = collections.get_mut(collection)
+        {
+            *usage = (documents, bytes);
+        }
+    }
+
+    pub fn forget(&self, collection: &str) {
+        if let Ok(mut collections) = self.collections.lock() {
+            collections.remove(collection);
+        }
+    }
+
+    pub fn usage(&self) -> Usage {
+        let collections = self.collections.lock().map_err(|_| ()).ok();
+        let Some(collections) = collections else {
+            return Usage::default();
+        };
+        Usage {
+            collections: collections.len(),
+            documents: collections.values().map(|(docs, _)| docs).sum(),
+            bytes: collections.values().map(|(_, bytes)| bytes).sum(),
+        }
+    }
+
+    pub fn check_new_collection(&self) -> Result<(), DbError> {
+        let collections = self.collections.lock().map_err(|_| DbError::LockPoisoned)?;
+        check_collections(&self.quota(), collections.len())
+    }
+
+    /// Records that `collection` is about to hold `documents` of `bytes`,
+    /// unless that grows the database past its quota. Checking and recording
+    /// happen under one lock, so concurrent writers to different collections
+    /// can't both take the last of it. Shrinking is always allowed. The first
+    /// write to a collection creates it, so it also counts against the
+    /// number of collections.
+    pub fn charge(&self, collection: &str, documents: usize, bytes: u64) -> Result<(), DbError> {
+        let quota = self.quota();
+        let mut collections = self.collections.lock().map_err(|_| DbError::LockPoisoned)?;
+        if !collections.contains_key(collection) {
+            check_collections(&quota, collections.len())?;
+        }
+        let (own_documents, own_bytes) = collections.get(collection).copied().unwrap_or_default();
+        let (other_documents, other_bytes) = collections
+            .iter()
+            .filter(|(name, _)| name.as_str() != collection)
+            .fold((0, 0), |(docs, size), (_, (d, s))| (docs + d, size + s));
+        if let Some(max) = quota.max_documents
+            && documents > own_documents
+            && other_documents + documents > max
+        {
+            return Err(DbError::QuotaExceeded(format!(
+                "the database holds at most {} documents",
+                max
+            )));
+        }
+        if let Some(max) = quota.max_bytes
+            && bytes > own_bytes
+            && other_bytes + bytes > max
+        {
+            return Err(DbError::QuotaExceeded(format!(
+                "the database holds at most {} bytes",
+                max
+            )));
+        }
+        collections.insert(collection.to_string(), (documents, bytes));
+        Ok(())
+    }
+}
+
+fn check_collections(quota: &Quota, collections: usize) -> Result<(), DbError> {
+    match quota.max_collections {
+        Some(max) if collections >= max => Err(DbError::QuotaExceeded(format!(
+            "the database holds at most {} collections",
+            max
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Settings of a database as a whole, stored in `_database/settings.json`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(super) struct DatabaseSettings {
+    #[serde(default)]
+    pub quota: Quota,
+    /// Users allowed into this database only: username -> bcrypt hash.
+    #[serde(default)]
+    pub users: BTreeMap<String, String>,
+}
+
+pub(super) fn path(db_path: &Path) -> PathBuf {
+    db_path.join("_database").join("settings.json")
+}
+
+impl DatabaseSettings {
+    pub fn load(path: &Path) -> Result<Self, DbError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), DbError> {
+        file::write_atomic(path, serde_json::to_string_pretty(self)?.as_bytes())
+    }
+}
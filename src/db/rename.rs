@@ -0,0 +1,117 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+use super::{CollectionName, DbError, file, trash};
+
+/// A collection rename in progress, stored in `_database/rename.json` while
+/// the collection's files move. A crash part way leaves it behind and
+/// loading the database finishes the move, so a rename is never half done.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(super) struct RenameJournal {
+    pub from: String,
+    pub to: String,
+    /// Which of `from`'s files existed when the rename began. Files of `to`
+    /// missing from the list belong to the collection being replaced.
+    pub files: Vec<String>,
+}
+
+fn path(db_path: &Path) -> PathBuf {
+    db_path.join("_database").join("rename.json")
+}
+
+impl RenameJournal {
+    /// Records the rename of `from` to `to` before any file moves.
+    pub fn begin(
+        db_path: &Path,
+        from: &CollectionName,
+        to: &CollectionName,
+    ) -> Result<Self, DbError> {
+        let journal = Self {
+            from: from.to_string(),
+            to: to.to_string(),
+            files: trash::collection_files(db_path, from)
+                .into_iter()
+                .filter(|(path, _)| path.exists())
+                .map(|(_, kind)| kind.to_string())
+                .collect(),
+        };
+        file::write_atomic(&path(db_path), serde_json::to_string(&journal)?.as_bytes())?;
+        Ok(journal)
+    }
+
+    /// Moves the files and removes the journal. Safe to repeat after a crash:
+    /// a file already moved is no longer at its source.
+    pub fn finish(&self, db_path: &Path) -> Result<(), DbError> {
+        let from = CollectionName::new(&self.from)?;
+        let to = CollectionName::new(&self.to)?;
+        let moves = trash::collection_files(db_path, &from)
+            .into_iter()
+            .zip(trash::collection_files(db_path, &to));
+        for ((source, kind), (destination, _)) in moves {
+            if !self.files.iter().any(|file| file == kind) {
+                if destination.exists() {
+                    fs::remove_file(destination)?;
+                }
+            } else if source.exists() {
+                fs::rename(source, destination)?;
+            }
+        }
+        fs::remove_file(path(db_path))?;
+        Ok(())
+    }
+}
+
+/// Finishes a rename interrupted by a crash.
+pub(super) fn recover(db_path: &Path) -> Result<(), DbError> {
+    let path = path(db_path);
+    if !path.exists() {
+        return Ok(());
+    }
+    let journal: RenameJournal = serde_json::from_str(&fs::read_to_string(&path)?)?;
+    journal.finish(db_path)?;
+    info!(
+        "Finished renaming collection {} to {}",
+        journal.from, journal.to
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_interrupted_rename_finishes_on_recovery() {
+        let dir = crate::db::test_dir("rename");
+        let from = CollectionName::new("a").unwrap();
+        let to = CollectionName::new("b").unwrap();
+        let files = |name: &CollectionName| trash::collection_files(&dir, name);
+        // `a` has data and metadata, the `b` it replaces also has history
+        for (path, _) in files(&from).iter().take(2) {
+            file::write_atomic(path, b"a").unwrap();
+        }
+        for (path, _) in files(&to).iter().take(3) {
+            file::write_atomic(path, b"b").unwrap();
+        }
+        let journal = RenameJournal::begin(&dir, &from, &to).unwrap();
+        assert_eq!(journal.files, ["data.json", "meta.json"]);
+        // the crash hit after the data file moved
+        fs::rename(&files(&from)[0].0, &files(&to)[0].0).unwrap();
+
+        recover(&dir).unwrap();
+        assert!(files(&from).iter().all(|(path, _)| !path.exists()));
+        let contents: Vec<String> = files(&to)
+            .iter()
+            .filter(|(path, _)| path.exists())
+            .map(|(path, _)| fs::read_to_string(path).unwrap())
+            .collect();
+        assert_eq!(contents, ["a", "a"]);
+        assert!(!path(&dir).exists());
+        recover(&dir).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
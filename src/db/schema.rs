@@ -0,0 +1,722 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+use super::{DbError, query::values_equal};
+
+/// A JSON Schema (draft 2020-12) that documents of a collection must match.
+///
+/// Supports the type, enum/const, numeric, string length, array, object,
+/// applicator (`allOf`, `anyOf`, `oneOf`, `not`, `if`/`then`/`else`) and
+/// local `$ref` keywords. Keywords that need regular expressions or
+/// evaluation tracking (`pattern`, `patternProperties`, `unevaluated*`) are
+/// rejected rather than silently ignored; `format` is an annotation only.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "Value", into = "Value")]
+pub struct Schema(Value);
+
+/// One reason a document failed validation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ValidationError {
+    /// JSON Pointer to the offending value inside the document's data.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() {
+            "/"
+        } else {
+            &self.path
+        };
+        write!(f, "{}: {}", path, self.message)
+    }
+}
+
+/// The documents that fail a schema, from [`super::Collection::validate_documents`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SchemaViolation {
+    pub id: String,
+    pub errors: Vec<ValidationError>,
+}
+
+pub(super) fn describe(errors: &[ValidationError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+const UNSUPPORTED: &[&str] = &[
+    "pattern",
+    "patternProperties",
+    "unevaluatedProperties",
+    "unevaluatedItems",
+    "$dynamicRef",
+    "$recursiveRef",
+];
+
+// Keywords whose value is a single subschema, an array of them, or a map of them.
+const SUBSCHEMA: &[&str] = &[
+    "items",
+    "contains",
+    "additionalProperties",
+    "propertyNames",
+    "not",
+    "if",
+    "then",
+    "else",
+];
+const SUBSCHEMA_ARRAYS: &[&str] = &["prefixItems", "allOf", "anyOf", "oneOf"];
+const SUBSCHEMA_MAPS: &[&str] = &["properties", "$defs", "definitions", "dependentSchemas"];
+
+// Bounds `$ref` recursion so a self-referencing schema can't overflow the stack.
+const MAX_DEPTH: usize = 64;
+
+fn invalid(msg: impl Into<String>) -> DbError {
+    DbError::InvalidSchema(msg.into())
+}
+
+impl TryFrom<Value> for Schema {
+    type Error = DbError;
+
+    fn try_from(value: Value) -> Result<Self, DbError> {
+        Schema::new(value)
+    }
+}
+
+impl From<Schema> for Value {
+    fn from(schema: Schema) -> Self {
+        schema.0
+    }
+}
+
+impl Schema {
+    pub fn new(schema: Value) -> Result<Self, DbError> {
+        check_schema(&schema, &schema, "")?;
+        Ok(Self(schema))
+    }
+
+    pub fn as_value(&self) -> &Value {
+        &self.0
+    }
+
+    /// Every violation in `data`, empty when it is valid.
+    pub fn errors(&self, data: &Value) -> Vec<ValidationError> {
+        let mut errors = Vec::new();
+        self.check(&self.0, data, String::new(), 0, &mut errors);
+        errors
+    }
+
+    pub fn validate(&self, data: &Value) -> Result<(), DbError> {
+        let errors = self.errors(data);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(DbError::ValidationFailed(errors))
+        }
+    }
+
+    fn passes(&self, schema: &Value, data: &Value, path: &str, depth: usize) -> bool {
+        let mut errors = Vec::new();
+        self.check(schema, data, path.to_string(), depth, &mut errors);
+        errors.is_empty()
+    }
+
+    fn check(
+        &self,
+        schema: &Value,
+        data: &Value,
+        path: String,
+        depth: usize,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let mut fail = |message: String| {
+            errors.push(ValidationError {
+                path: path.clone(),
+                message,
+            })
+        };
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => return fail("no value is allowed here".into()),
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+        if depth > MAX_DEPTH {
+            return fail("schema nests too deeply".into());
+        }
+
+        if let Some(expected) = schema.get("type")
+            && !type_matches(expected, data)
+        {
+            fail(format!(
+                "expected {}, found {}",
+                describe_type(expected),
+                type_name(data)
+            ));
+        }
+        if let Some(Value::Array(options)) = schema.get("enum")
+            && !options.iter().any(|o| values_equal(o, data))
+        {
+            fail(format!("must be one of {}", Value::Array(options.clone())));
+        }
+        if let Some(expected) = schema.get("const")
+            && !values_equal(expected, data)
+        {
+            fail(format!("must equal {}", expected));
+        }
+
+        match data {
+            Value::Number(n) => {
+                let n = n.as_f64().unwrap_or(f64::NAN);
+                if let Some(min) = number(schema, "minimum")
+                    && n < min
+                {
+                    fail(format!("must be at least {}", min));
+                }
+                if let Some(max) = number(schema, "maximum")
+                    && n > max
+                {
+                    fail(format!("must be at most {}", max));
+                }
+                if let Some(min) = number(schema, "exclusiveMinimum")
+                    && n <= min
+                {
+                    fail(format!("must be greater than {}", min));
+                }
+                if let Some(max) = number(schema, "exclusiveMaximum")
+                    && n >= max
+                {
+                    fail(format!("must be less than {}", max));
+                }
+                if let Some(step) = number(schema, "multipleOf")
+                    && !is_multiple(n, step)
+                {
+                    fail(format!("must be a multiple of {}", step));
+                }
+            }
+            Value::String(s) => {
+                let len = s.chars().count();
+                if let Some(min) = count(schema, "minLength")
+                    && len < min
+                {
+                    fail(format!("must be at least {} characters long", min));
+                }
+                if let Some(max) = count(schema, "maxLength")
+                    && len > max
+                {
+                    fail(format!("must be at most {} characters long", max));
+                }
+            }
+            Value::Array(items) => {
+                if let Some(min) = count(schema, "minItems")
+                    && items.len() < min
+                {
+                    fail(format!("must have at least {} items", min));
+                }
+                if let Some(max) = count(schema, "maxItems")
+                    && items.len() > max
+                {
+                    fail(format!("must have at most {} items", max));
+                }
+                if schema.get("uniqueItems") == Some(&Value::Bool(true))
+                    && let Some(i) = (1..items.len())
+                        .find(|&i| items[..i].iter().any(|prev| values_equal(prev, &items[i])))
+                {
+                    fail(format!("items must be unique, item {} is repeated", i));
+                }
+                if let Some(contains) = schema.get("contains") {
+                    let matched = items
+                        .iter()
+                        .enumerate()
+                        .filter(|(i, item)| {
+                            self.passes(contains, item, &child(&path, &i.to_string()), depth + 1)
+                        })
+                        .count();
+                    let min = count(schema, "minContains").unwrap_or(1);
+                    if matched < min {
+                        fail(format!(
+                            "must contain at least {} matching item{}",
+                            min,
+                            if min == 1 { "" } else { "s" }
+                        ));
+                    }
+                    if let Some(max) = count(schema, "maxContains")
+                        && matched > max
+                    {
+                        fail(format!("must contain at most {} matching items", max));
+                    }
+                }
+            }
+            Value::Object(map) => {
+                if let Some(min) = count(schema, "minProperties")
+                    && map.len() < min
+                {
+                    fail(format!("must have at least {} properties", min));
+                }
+                if let Some(max) = count(schema, "maxProperties")
+                    && map.len() > max
+                {
+                    fail(format!("must have at most {} properties", max));
+                }
+                if let Some(Value::Array(required)) = schema.get("required") {
+                    for name in required.iter().filter_map(Value::as_str) {
+                        if !map.contains_key(name) {
+                            fail(format!("missing required property '{}'", name));
+                        }
+                    }
+                }
+                if let Some(Value::Object(dependent)) = schema.get("dependentRequired") {
+                    for (name, needs) in dependent.iter().filter(|(k, _)| map.contains_key(*k)) {
+                        for need in needs.as_array().into_iter().flatten() {
+                            if let Some(need) = need.as_str()
+                                && !map.contains_key(need)
+                            {
+                                fail(format!("property '{}' requires '{}'", name, need));
+                            }
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        // applicators collect nested errors at the nested paths
+        if let Some(Value::String(reference)) = schema.get("$ref") {
+            match resolve(&self.0, reference) {
+                Some(target) => self.check(target, data, path.clone(), depth + 1, errors),
+                None => errors.push(ValidationError {
+                    path: path.clone(),
+                    message: format!("unresolvable $ref {}", reference),
+                }),
+            }
+        }
+        if let Some(Value::Array(all)) = schema.get("allOf") {
+            for sub in all {
+                self.check(sub, data, path.clone(), depth + 1, errors);
+            }
+        }
+        let mut fail = |message: String| {
+            errors.push(ValidationError {
+                path: path.clone(),
+                message,
+            })
+        };
+        if let Some(Value::Array(any)) = schema.get("anyOf")
+            && !any
+                .iter()
+                .any(|sub| self.passes(sub, data, &path, depth + 1))
+        {
+            fail("must match at least one schema in anyOf".into());
+        }
+        if let Some(Value::Array(one)) = schema.get("oneOf") {
+            let matched = one
+                .iter()
+                .filter(|sub| self.passes(sub, data, &path, depth + 1))
+                .count();
+            if matched != 1 {
+                fail(format!(
+                    "must match exactly one schema in oneOf, matched {}",
+                    matched
+                ));
+            }
+        }
+        if let Some(not) = schema.get("not")
+            && self.passes(not, data, &path, depth + 1)
+        {
+            fail("must not match the schema in not".into());
+        }
+        if let Some(condition) = schema.get("if") {
+            let branch = if self.passes(condition, data, &path, depth + 1) {
+                schema.get("then")
+            } else {
+                schema.get("else")
+            };
+            if let Some(branch) = branch {
+                self.check(branch, data, path.clone(), depth + 1, errors);
+            }
+        }
+
+        match data {
+            Value::Array(items) => {
+                let prefix = match schema.get("prefixItems") {
+                    Some(Value::Array(prefix)) => prefix.as_slice(),
+                    _ => &[],
+                };
+                for (i, item) in items.iter().enumerate() {
+                    if let Some(sub) = prefix.get(i).or(schema.get("items")) {
+                        self.check(sub, item, child(&path, &i.to_string()), depth + 1, errors);
+                    }
+                }
+            }
+            Value::Object(map) => self.check_object(schema, data, map, &path, depth, errors),
+            _ => {}
+        }
+    }
+
+    fn check_object(
+        &self,
+        schema: &Map<String, Value>,
+        data: &Value,
+        map: &Map<String, Value>,
+        path: &str,
+        depth: usize,
+        errors: &mut Vec<ValidationError>,
+    ) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (name, value) in map {
+            let at = child(path, name);
+            if let Some(names) = schema.get("propertyNames")
+                && !self.passes(names, &Value::String(name.clone()), &at, depth + 1)
+            {
+                errors.push(ValidationError {
+                    path: at.clone(),
+                    message: format!("property name '{}' is not allowed", name),
+                });
+            }
+            match properties.and_then(|p| p.get(name)) {
+                Some(sub) => self.check(sub, value, at, depth + 1, errors),
+                None => {
+                    if let Some(additional) = schema.get("additionalProperties") {
+                        if additional == &Value::Bool(false) {
+                            errors.push(ValidationError {
+                                path: at,
+                                message: format!("unexpected property '{}'", name),
+                            });
+                        } else {
+                            self.check(additional, value, at, depth + 1, errors);
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(Value::Object(dependent)) = schema.get("dependentSchemas") {
+            for (_, sub) in dependent.iter().filter(|(k, _)| map.contains_key(*k)) {
+                self.check(sub, data, path.to_string(), depth + 1, errors);
+            }
+        }
+    }
+}
+
+fn child(path: &str, token: &str) -> String {
+    format!("{}/{}", path, token.replace('~', "~0").replace('/', "~1"))
+}
+
+fn number(schema: &Map<String, Value>, keyword: &str) -> Option<f64> {
+    schema.get(keyword).and_then(Value::as_f64)
+}
+
+// Decimal steps aren't exact in binary, so 0.29 / 0.01 comes out just below
+// 29: the quotient counts as whole when it is within rounding error of
+// either neighbour.
+fn is_multiple(n: f64, step: f64) -> bool {
+    let quotient = n / step;
+    let fract = quotient.fract().abs();
+    quotient.is_finite() && fract.min(1.0 - fract) <= 2.0 * f64::EPSILON * quotient.abs().max(1.0)
+}
+
+fn count(schema: &Map<String, Value>, keyword: &str) -> Option<usize> {
+    schema
+        .get(keyword)
+        .and_then(Value::as_u64)
+        .map(|n| n as usize)
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn is_type(name: &str, value: &Value) -> bool {
+    match name {
+        "integer" => value
+            .as_f64()
+            .is_some_and(|n| n.fract() == 0.0 && n.is_finite()),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_matches(expected: &Value, value: &Value) -> bool {
+    match expected {
+        Value::String(name) => is_type(name, value),
+        Value::Array(names) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .any(|name| is_type(name, value)),
+        _ => true,
+    }
+}
+
+fn describe_type(expected: &Value) -> String {
+    match expected {
+        Value::Array(names) => names
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(" or "),
+        other => other.as_str().unwrap_or("?").to_string(),
+    }
+}
+
+// Only references within the schema itself: `#` or `#/json/pointer`.
+fn resolve<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    root.pointer(pointer)
+}
+
+fn check_schema(schema: &Value, root: &Value, at: &str) -> Result<(), DbError> {
+    let map = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(map) => map,
+        _ => {
+            return Err(invalid(format!(
+                "{} must be an object or boolean",
+                at_or_root(at)
+            )));
+        }
+    };
+    for (keyword, value) in map {
+        let here = format!("{}/{}", at, keyword);
+        if UNSUPPORTED.contains(&keyword.as_str()) {
+            return Err(invalid(format!("keyword {} is not supported", here)));
+        }
+        if keyword == "$ref" {
+            let reference = value
+                .as_str()
+                .ok_or_else(|| invalid(format!("{} must be a string", here)))?;
+            if resolve(root, reference).is_none() {
+                return Err(invalid(format!(
+                    "{} points to {}, which is not in this schema",
+                    here, reference
+                )));
+            }
+        } else if keyword == "multipleOf" {
+            if !value.as_f64().is_some_and(|step| step > 0.0) {
+                return Err(invalid(format!("{} must be a number above 0", here)));
+            }
+        } else if SUBSCHEMA.contains(&keyword.as_str()) {
+            check_schema(value, root, &here)?;
+        } else if SUBSCHEMA_ARRAYS.contains(&keyword.as_str()) {
+            let items = value
+                .as_array()
+                .ok_or_else(|| invalid(format!("{} must be an array", here)))?;
+            for (i, sub) in items.iter().enumerate() {
+                check_schema(sub, root, &format!("{}/{}", here, i))?;
+            }
+        } else if SUBSCHEMA_MAPS.contains(&keyword.as_str()) {
+            let subs = value
+                .as_object()
+                .ok_or_else(|| invalid(format!("{} must be an object", here)))?;
+            for (name, sub) in subs {
+                check_schema(sub, root, &format!("{}/{}", here, name))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn at_or_root(at: &str) -> &str {
+    if at.is_empty() { "schema" } else { at }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, test_dir};
+    use serde_json::json;
+    use std::fs;
+
+    fn schema(value: Value) -> Schema {
+        Schema::new(value).unwrap()
+    }
+
+    fn paths(schema: &Schema, data: Value) -> Vec<String> {
+        schema.errors(&data).into_iter().map(|e| e.path).collect()
+    }
+
+    #[test]
+    fn errors_point_at_every_offending_value() {
+        let person = schema(json!({
+            "type": "object",
+            "required": ["age"],
+            "properties": {
+                "age": {"type": "integer", "minimum": 0},
+                "tags": {"type": "array", "items": {"type": "string", "maxLength": 2}},
+                "a/b~c": {"const": 1},
+            },
+            "additionalProperties": false,
+        }));
+        assert!(person.errors(&json!({"age": 3.0})).is_empty());
+        let data = json!({"age": -1.5, "tags": ["ok", "long", 3], "a/b~c": 2, "x": 1});
+        assert_eq!(
+            paths(&person, data),
+            ["/a~1b~0c", "/age", "/age", "/tags/1", "/tags/2", "/x"]
+        );
+        assert_eq!(paths(&person, json!([])), [""]);
+        assert_eq!(paths(&person, json!({})), [""]);
+        // lengths count characters, not bytes
+        let short = schema(json!({"maxLength": 2}));
+        assert!(short.errors(&json!("é😀")).is_empty());
+    }
+
+    #[test]
+    fn applicators_combine_subschemas() {
+        let shape = schema(json!({
+            "oneOf": [{"type": "integer"}, {"maximum": 0}],
+            "not": {"const": 42},
+            "if": {"minimum": 100},
+            "then": {"multipleOf": 100},
+            "else": {"maximum": 50},
+        }));
+        assert!(shape.errors(&json!(5)).is_empty());
+        assert!(shape.errors(&json!(200)).is_empty());
+        assert!(shape.errors(&json!(-0.5)).is_empty());
+        // -1 is an integer and at most 0, so it matches both branches
+        assert_eq!(shape.errors(&json!(-1)).len(), 1);
+        assert_eq!(shape.errors(&json!(10.5)).len(), 1);
+        assert_eq!(shape.errors(&json!(42)).len(), 1);
+        assert_eq!(shape.errors(&json!(150)).len(), 1);
+        assert_eq!(shape.errors(&json!(60)).len(), 1);
+
+        let list = schema(json!({
+            "prefixItems": [{"type": "string"}],
+            "items": false,
+            "uniqueItems": true,
+        }));
+        assert!(list.errors(&json!(["a"])).is_empty());
+        assert_eq!(paths(&list, json!(["a", 1])), ["/1"]);
+        let list = schema(
+            json!({"contains": {"type": "string"}, "minContains": 2, "maxContains": 3, "uniqueItems": true}),
+        );
+        assert_eq!(list.errors(&json!(["a", 1, 1.0])).len(), 2);
+        assert!(list.errors(&json!(["a", "b", 1])).is_empty());
+
+        let object = schema(json!({
+            "propertyNames": {"maxLength": 3},
+            "dependentRequired": {"cc": ["cvv"]},
+            "dependentSchemas": {"cc": {"required": ["nm"]}},
+        }));
+        assert_eq!(
+            paths(&object, json!({"cc": 1, "long": 1})),
+            ["", "/long", ""]
+        );
+        assert!(
+            object
+                .errors(&json!({"cc": 1, "cvv": 2, "nm": 3}))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn references_recurse_but_not_forever() {
+        let tree = schema(json!({
+            "$defs": {"node": {
+                "type": "object",
+                "properties": {"children": {"items": {"$ref": "#/$defs/node"}}},
+                "required": ["v"],
+            }},
+            "$ref": "#/$defs/node",
+        }));
+        let leaf = json!({"v": 1, "children": [{"v": 2, "children": [{}]}]});
+        assert_eq!(paths(&tree, leaf), ["/children/0/children/0"]);
+        let endless = schema(json!({"$ref": "#"}));
+        let errors = endless.errors(&json!(1));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("too deeply"));
+    }
+
+    #[test]
+    fn unusable_schemas_are_rejected() {
+        for bad in [
+            json!(1),
+            json!({"pattern": "^a"}),
+            json!({"properties": {"a": {"patternProperties": {}}}}),
+            json!({"$ref": "#/$defs/missing"}),
+            json!({"$ref": "http://example.com/schema"}),
+            json!({"allOf": {"type": "string"}}),
+            json!({"items": "string"}),
+            json!({"properties": []}),
+        ] {
+            assert!(
+                matches!(Schema::new(bad.clone()), Err(DbError::InvalidSchema(_))),
+                "{bad}"
+            );
+        }
+        assert!(Schema::new(json!(false)).is_ok());
+        assert!(Schema::new(json!({"format": "email", "title": "x"})).is_ok());
+    }
+
+    #[test]
+    fn every_write_path_is_validated() {
+        let dir = test_dir("schema-writes");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let old = col.insert(json!({"age": "eighty"}), None).unwrap();
+        let doc = col.insert(json!({"age": 80}), None).unwrap();
+        let ages = schema(json!({"properties": {"age": {"type": "number"}}}));
+
+        // checking existing documents doesn't enable the schema
+        let violations = col.validate_documents(&ages).unwrap();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].id, old.id);
+        assert_eq!(violations[0].errors[0].path, "/age");
+        col.insert(json!({"age": "ninety"}), None).unwrap();
+
+        col.set_schema(Some(ages)).unwrap();
+        let bad = json!({"age": "x"});
+        let failed = [
+            col.insert(bad.clone(), None).map(|_| ()),
+            col.update(&doc.id, bad.clone()).map(|_| ()),
+            col.update_with(&doc.id, &json!({"$set": bad.clone()}))
+                .map(|_| ()),
+            col.merge_patch(&doc.id, &bad).map(|_| ()),
+            col.json_patch(
+                &doc.id,
+                &json!([{"op": "replace", "path": "/age", "value": "x"}]),
+            )
+            .map(|_| ()),
+        ];
+        for result in failed {
+            assert!(matches!(result, Err(DbError::ValidationFailed(_))));
+        }
+        let stored = col.find(&doc.id).unwrap().unwrap();
+        assert_eq!((stored.data, stored.version), (doc.data, doc.version));
+        // documents stored before the schema may still be deleted
+        col.delete(&old.id).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn multiple_of_tolerates_decimal_steps() {
+        let cents = schema(json!({"multipleOf": 0.01}));
+        for n in [0.29, 0.07, 1.1, 19.99, 100.0, -0.57, 0.0] {
+            assert!(cents.errors(&json!(n)).is_empty(), "{}", n);
+        }
+        assert_eq!(cents.errors(&json!(0.291)).len(), 1);
+        let tenths = schema(json!({"multipleOf": 0.1}));
+        assert!(tenths.errors(&json!(0.3)).is_empty());
+        assert_eq!(tenths.errors(&json!(0.35)).len(), 1);
+        let big = schema(json!({"multipleOf": 3}));
+        assert!(big.errors(&json!(3e15)).is_empty());
+        assert_eq!(big.errors(&json!(1e15 + 1.0)).len(), 1);
+    }
+
+    #[test]
+    fn multiple_of_must_be_positive() {
+        for step in [json!(0), json!(-1), json!("2"), json!(null)] {
+            let result = Schema::new(json!({"properties": {"n": {"multipleOf": step}}}));
+            assert!(matches!(result, Err(DbError::InvalidSchema(_))));
+        }
+    }
+}
@@ -0,0 +1,728 @@
+use serde_json::{Map, Value, json};
+
+use super::DbError;
+
+/// A parsed `SELECT`, compiled down to an aggregation pipeline.
+#[derive(Debug, Clone)]
+pub struct SqlQuery {
+    pub collection: String,
+    pub pipeline: Vec<Value>,
+}
+
+fn syntax(msg: impl Into<String>) -> DbError {
+    DbError::InvalidQuery(format!("SQL: {}", msg.into()))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Keyword(String),
+    Str(String),
+    Num(Value),
+    Op(String),
+    Comma,
+    LParen,
+    RParen,
+    Star,
+}
+
+const KEYWORDS: &[&str] = &[
+    "SELECT", "FROM", "WHERE", "GROUP", "BY", "ORDER", "ASC", "DESC", "LIMIT", "OFFSET", "AND",
+    "OR", "NOT", "IN", "IS", "NULL", "TRUE", "FALSE", "AS", "BETWEEN",
+];
+
+fn tokenize(input: &str) -> Result<Vec<Token>, DbError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            ';' if chars[i + 1..].iter().all(|c| c.is_whitespace()) => break,
+            ',' => {
+                tokens.push(Token::Comma);
+                i += 1;
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            '*' => {
+                tokens.push(Token::Star);
+                i += 1;
+            }
+            '\'' => {
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        Some('\'') if chars.get(i + 1) == Some(&'\'') => {
+                            s.push('\'');
+                            i += 2;
+                        }
+                        Some('\'') => {
+                            i += 1;
+                            break;
+                        }
+                        Some(c) => {
+                            s.push(*c);
+                            i += 1;
+                        }
+                        None => return Err(syntax("unterminated string literal")),
+                    }
+                }
+                tokens.push(Token::Str(s));
+            }
+            '"' | '`' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|&x| x == c)
+                    .ok_or_else(|| syntax("unterminated quoted identifier"))?;
+                tokens.push(Token::Ident(chars[i + 1..i + 1 + end].iter().collect()));
+                i += end + 2;
+            }
+            '=' | '<' | '>' | '!' => {
+                let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+                let op = if ["<=", ">=", "<>", "!="].contains(&two.as_str()) {
+                    two
+                } else if c == '!' {
+                    return Err(syntax("unexpected '!'"));
+                } else {
+                    c.to_string()
+                };
+                i += op.len();
+                tokens.push(Token::Op(op));
+            }
+            c if c.is_ascii_digit()
+                || (c == '-' && chars.get(i + 1).is_some_and(|n| n.is_ascii_digit())) =>
+            {
+                let start = i;
+                i += 1;
+                while chars
+                    .get(i)
+                    .is_some_and(|c| c.is_ascii_digit() || *c == '.')
+                {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let num = match text.parse::<i64>() {
+                    Ok(n) => json!(n),
+                    Err(_) => text
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(serde_json::Number::from_f64)
+                        .map(Value::Number)
+                        .ok_or_else(|| syntax(format!("invalid number {}", text)))?,
+                };
+                tokens.push(Token::Num(num));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while chars
+                    .get(i)
+                    .is_some_and(|c| c.is_alphanumeric() || *c == '_' || *c == '.')
+                {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let upper = word.to_ascii_uppercase();
+                if KEYWORDS.contains(&upper.as_str()) {
+                    tokens.push(Token::Keyword(upper));
+                } else {
+                    tokens.push(Token::Ident(word));
+                }
+            }
+            other => return Err(syntax(format!("unexpected character '{}'", other))),
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Debug, Clone)]
+enum SelectItem {
+    Column {
+        path: String,
+        alias: Option<String>,
+    },
+    Aggregate {
+        func: String,
+        arg: Option<String>,
+        alias: Option<String>,
+    },
+}
+
+impl SelectItem {
+    fn output_name(&self) -> String {
+        match self {
+            SelectItem::Column { path, alias } => alias.clone().unwrap_or_else(|| path.clone()),
+            SelectItem::Aggregate { func, arg, alias } => alias.clone().unwrap_or_else(|| {
+                // dots would nest the output field, so `sum(a.b)` becomes `sum(a_b)`
+                let arg = arg.as_deref().unwrap_or("*").replace('.', "_");
+                format!("{}({})", func.to_ascii_lowercase(), arg)
+            }),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn keyword(&mut self, kw: &str) -> bool {
+        if self.peek() == Some(&Token::Keyword(kw.to_string())) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, kw: &str) -> Result<(), DbError> {
+        if self.keyword(kw) {
+            Ok(())
+        } else {
+            Err(syntax(format!("expected {}", kw)))
+        }
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), DbError> {
+        match self.next() {
+            Some(t) if t == token => Ok(()),
+            other => Err(syntax(format!("expected {:?}, found {:?}", token, other))),
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, DbError> {
+        match self.next() {
+            Some(Token::Ident(name)) => Ok(name),
+            other => Err(syntax(format!("expected identifier, found {:?}", other))),
+        }
+    }
+
+    fn column(&mut self) -> Result<String, DbError> {
+        let name = self.ident()?;
+        // `data.` addresses Document.data explicitly
+        Ok(name
+            .strip_prefix("data.")
+            .map(str::to_string)
+            .unwrap_or(name))
+    }
+
+    fn literal(&mut self) -> Result<Value, DbError> {
+        match self.next() {
+            Some(Token::Str(s)) => Ok(Value::String(s)),
+            Some(Token::Num(n)) => Ok(n),
+            Some(Token::Keyword(k)) if k == "TRUE" => Ok(Value::Bool(true)),
+            Some(Token::Keyword(k)) if k == "FALSE" => Ok(Value::Bool(false)),
+            Some(Token::Keyword(k)) if k == "NULL" => Ok(Value::Null),
+            other => Err(syntax(format!("expected literal, found {:?}", other))),
+        }
+    }
+
+    fn count(&mut self) -> Result<usize, DbError> {
+        match self.next() {
+            Some(Token::Num(n)) if n.as_u64().is_some() => Ok(n.as_u64().unwrap_or(0) as usize),
+            other => Err(syntax(format!(
+                "expected a non-negative integer, found {:?}",
+                other
+            ))),
+        }
+    }
+
+    fn alias(&mut self) -> Result<Option<String>, DbError> {
+        if self.keyword("AS") {
+            return self.ident().map(Some);
+        }
+        match self.peek() {
+            Some(Token::Ident(_)) => self.ident().map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn select_item(&mut self) -> Result<SelectItem, DbError> {
+        let name = self.ident()?;
+        let upper = name.to_ascii_uppercase();
+        if self.peek() == Some(&Token::LParen)
+            && ["COUNT", "SUM", "AVG", "MIN", "MAX"].contains(&upper.as_str())
+        {
+            self.next();
+            let arg = if self.peek() == Some(&Token::Star) && upper == "COUNT" {
+                self.next();
+                None
+            } else {
+                Some(self.column()?)
+            };
+            self.expect(Token::RParen)?;
+            return Ok(SelectItem::Aggregate {
+                func: upper,
+                arg,
+                alias: self.alias()?,
+            });
+        }
+        let path = name
+            .strip_prefix("data.")
+            .map(str::to_string)
+            .unwrap_or(name);
+        Ok(SelectItem::Column {
+            path,
+            alias: self.alias()?,
+        })
+    }
+
+    fn or_expr(&mut self) -> Result<Value, DbError> {
+        let mut items = vec![self.and_expr()?];
+        while self.keyword("OR") {
+            items.push(self.and_expr()?);
+        }
+        Ok(if items.len() == 1 {
+            items.remove(0)
+        } else {
+            json!({ "$or": items })
+        })
+    }
+
+    fn and_expr(&mut self) -> Result<Value, DbError> {
+        let mut items = vec![self.not_expr()?];
+        while self.keyword("AND") {
+            items.push(self.not_expr()?);
+        }
+        Ok(if items.len() == 1 {
+            items.remove(0)
+        } else {
+            json!({ "$and": items })
+        })
+    }
+
+    fn not_expr(&mut self) -> Result<Value, DbError> {
+        if self.keyword("NOT") {
+            let inner = self.not_expr()?;
+            return Ok(json!({ "$nor": [inner] }));
+        }
+        self.predicate()
+    }
+
+    fn predicate(&mut self) -> Result<Value, DbError> {
+        if self.peek() == Some(&Token::LParen) {
+            self.next();
+            let inner = self.or_expr()?;
+            self.expect(Token::RParen)?;
+            return Ok(inner);
+        }
+
+        // `literal op column` is flipped into `column op' literal`
+        let (column, flipped_literal) = match self.peek() {
+            Some(Token::Ident(_)) => (self.column()?, None),
+            _ => {
+                let literal = self.literal()?;
+                (String::new(), Some(literal))
+            }
+        };
+
+        if let Some(literal) = flipped_literal {
+            let op = match self.next() {
+                Some(Token::Op(op)) => op,
+                other => return Err(syntax(format!("expected comparison, found {:?}", other))),
+            };
+            let column = self.column()?;
+            let flipped = match op.as_str() {
+                "<" => ">",
+                "<=" => ">=",
+                ">" => "<",
+                ">=" => "<=",
+                other => other,
+            };
+            return comparison(&column, flipped, literal);
+        }
+
+        if self.keyword("IS") {
+            let negated = self.keyword("NOT");
+            self.expect_keyword("NULL")?;
+            return Ok(if negated {
+                json!({ column: { "$exists": true, "$ne": null } })
+            } else {
+                json!({ "$or": [{ column.clone(): { "$exists": false } }, { column: null }] })
+            });
+        }
+        let negated = self.keyword("NOT");
+        if self.keyword("IN") {
+            self.expect(Token::LParen)?;
+            let mut values = vec![self.literal()?];
+            while self.peek() == Some(&Token::Comma) {
+                self.next();
+                values.push(self.literal()?);
+            }
+            self.expect(Token::RParen)?;
+            let op = if negated { "$nin" } else { "$in" };
+            return Ok(json!({ column: { op: values } }));
+        }
+        if self.keyword("BETWEEN") {
+            let low = self.literal()?;
+            self.expect_keyword("AND")?;
+            let high = self.literal()?;
+            let range = json!({ column.clone(): { "$gte": low, "$lte": high } });
+            return Ok(if negated {
+                json!({ "$nor": [range] })
+            } else {
+                range
+            });
+        }
+        if negated {
+            return Err(syntax("expected IN or BETWEEN after NOT"));
+        }
+
+        let op = match self.next() {
+            Some(Token::Op(op)) => op,
+            other => return Err(syntax(format!("expected comparison, found {:?}", other))),
+        };
+        let literal = self.literal()?;
+        comparison(&column, &op, literal)
+    }
+}
+
+fn comparison(column: &str, op: &str, literal: Value) -> Result<Value, DbError> {
+    let mongo = match op {
+        "=" => "$eq",
+        "!=" | "<>" => "$ne",
+        "<" => "$lt",
+        "<=" => "$lte",
+        ">" => "$gt",
+        ">=" => "$gte",
+        other => return Err(syntax(format!("unknown operator {}", other))),
+    };
+    Ok(json!({ column: { mongo: literal } }))
+}
+
+// `$sort` objects don't keep key order, so emit one stable sort per key,
+// least significant first.
+fn push_sort(pipeline: &mut Vec<Value>, keys: Vec<(String, i32)>) {
+    for (field, dir) in keys.into_iter().rev() {
+        pipeline.push(json!({ "$sort": { field: dir } }));
+    }
+}
+
+fn field_ref(path: &str) -> Value {
+    Value::String(format!("${}", path))
+}
+
+/// Parses `SELECT ... FROM ... [WHERE] [GROUP BY] [ORDER BY] [LIMIT [OFFSET]]`.
+pub fn parse(input: &str) -> Result<SqlQuery, DbError> {
+    let mut p = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    p.expect_keyword("SELECT")?;
+    let mut items = Vec::new();
+    let star = p.peek() == Some(&Token::Star);
+    if star {
+        p.next();
+    } else {
+        items.push(p.select_item()?);
+        while p.peek() == Some(&Token::Comma) {
+            p.next();
+            items.push(p.select_item()?);
+        }
+    }
+    p.expect_keyword("FROM")?;
+    let collection = p.ident()?;
+
+    let filter = if p.keyword("WHERE") {
+        Some(p.or_expr()?)
+    } else {
+        None
+    };
+
+    let mut group_by = Vec::new();
+    if p.keyword("GROUP") {
+        p.expect_keyword("BY")?;
+        group_by.push(p.column()?);
+        while p.peek() == Some(&Token::Comma) {
+            p.next();
+            group_by.push(p.column()?);
+        }
+    }
+
+    let mut order_by = Vec::new();
+    if p.keyword("ORDER") {
+        p.expect_keyword("BY")?;
+        loop {
+            let column = p.column()?;
+            let dir = if p.keyword("DESC") {
+                -1
+            } else {
+                p.keyword("ASC");
+                1
+            };
+            order_by.push((column, dir));
+            if p.peek() != Some(&Token::Comma) {
+                break;
+            }
+            p.next();
+        }
+    }
+
+    let (mut limit, mut offset) = (None, None);
+    if p.keyword("LIMIT") {
+        limit = Some(p.count()?);
+        if p.keyword("OFFSET") {
+            offset = Some(p.count()?);
+        }
+    }
+    if let Some(token) = p.peek() {
+        return Err(syntax(format!("unexpected {:?}", token)));
+    }
+
+    let mut pipeline = Vec::new();
+    if let Some(filter) = filter {
+        pipeline.push(json!({ "$match": filter }));
+    }
+
+    let grouped = !group_by.is_empty()
+        || items
+            .iter()
+            .any(|i| matches!(i, SelectItem::Aggregate { .. }));
+    if grouped {
+        if star {
+            return Err(syntax("SELECT * cannot be combined with GROUP BY"));
+        }
+        let mut key = Map::new();
+        for (i, column) in group_by.iter().enumerate() {
+            key.insert(format!("k{}", i), field_ref(column));
+        }
+        let mut group = Map::new();
+        group.insert(
+            "_id".to_string(),
+            if key.is_empty() {
+                Value::Null
+            } else {
+                Value::Object(key)
+            },
+        );
+        let mut project = Map::new();
+        project.insert("_id".to_string(), json!(0));
+        for (i, item) in items.iter().enumerate() {
+            let name = item.output_name();
+            match item {
+                SelectItem::Aggregate { func, arg, .. } => {
+                    let slot = format!("a{}", i);
+                    let acc = match (func.as_str(), arg) {
+                        ("COUNT", None) => json!({ "$count": {} }),
+                        ("COUNT", Some(col)) => json!({ "$count": field_ref(col) }),
+                        (func, Some(col)) => {
+                            json!({ format!("${}", func.to_ascii_lowercase()): field_ref(col) })
+                        }
+                        (func, None) => return Err(syntax(format!("{} needs a column", func))),
+                    };
+                    group.insert(slot.clone(), acc);
+                    project.insert(name, field_ref(&slot));
+                }
+                SelectItem::Column { path, .. } => {
+                    let k = group_by.iter().position(|g| g == path).ok_or_else(|| {
+                        syntax(format!("column {} must appear in GROUP BY", path))
+                    })?;
+                    project.insert(name, field_ref(&format!("_id.k{}", k)));
+                }
+            }
+        }
+        pipeline.push(json!({ "$group": group }));
+        pipeline.push(json!({ "$project": project }));
+        // grouped rows only carry output columns; sort by output names
+        let keys = order_by.into_iter().map(|(column, dir)| {
+            let name = items
+                .iter()
+                .find(|i| matches!(i, SelectItem::Column { path, .. } if *path == column))
+                .map(SelectItem::output_name)
+                .unwrap_or(column);
+            (name, dir)
+        });
+        push_sort(&mut pipeline, keys.collect());
+    } else {
+        // sort on source columns so ORDER BY may use unselected fields; aliases
+        // resolve to the column they name
+        let keys = order_by.into_iter().map(|(column, dir)| {
+            let source = items
+                .iter()
+                .find_map(|i| match i {
+                    SelectItem::Column {
+                        path,
+                        alias: Some(alias),
+                    } if *alias == column => Some(path.clone()),
+                    _ => None,
+                })
+                .unwrap_or(column);
+            (source, dir)
+        });
+        push_sort(&mut pipeline, keys.collect());
+    }
+
+    if let Some(offset) = offset {
+        pipeline.push(json!({ "$skip": offset }));
+    }
+    if let Some(limit) = limit {
+        pipeline.push(json!({ "$limit": limit }));
+    }
+
+    if !grouped && !star {
+        let mut project = Map::new();
+        if !items
+            .iter()
+            .any(|i| matches!(i, SelectItem::Column { path, .. } if path == "_id"))
+        {
+            project.insert("_id".to_string(), json!(0));
+        }
+        for item in &items {
+            if let SelectItem::Column { path, .. } = item {
+                project.insert(item.output_name(), field_ref(path));
+            }
+        }
+        pipeline.push(json!({ "$project": project }));
+    }
+
+    Ok(SqlQuery {
+        collection,
+        pipeline,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::aggregate;
+
+    fn rows() -> Vec<Value> {
+        let Value::Array(rows) = json!([
+            {"_id": "1", "name": "it's", "n": 3, "g": "a", "x": {"y": 1}},
+            {"_id": "2", "name": "bob", "n": -2, "g": "b", "x": {"y": 2}},
+            {"_id": "3", "name": "cy", "n": 7, "g": "a", "x": {"y": 3}},
+            {"_id": "4", "name": "dee", "g": "b", "n": null},
+            {"_id": "5", "name": "ed", "n": 1.5},
+        ]) else {
+            unreachable!()
+        };
+        rows
+    }
+
+    fn select(sql: &str) -> Vec<Value> {
+        let query = parse(sql).unwrap();
+        assert_eq!(query.collection, "t");
+        let stages = aggregate::parse_pipeline(&query.pipeline).unwrap();
+        aggregate::run(&stages, rows())
+    }
+
+    fn ids(sql: &str) -> Vec<String> {
+        select(&sql.replacen("SELECT *", "SELECT _id", 1))
+            .iter()
+            .map(|r| r["_id"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn literals_and_quoting() {
+        assert_eq!(ids("SELECT * FROM t WHERE name = 'it''s';"), ["1"]);
+        assert_eq!(ids("SELECT * FROM t WHERE \"n\" = -2"), ["2"]);
+        assert_eq!(ids("SELECT * FROM t WHERE `data.x.y` >= 2.5"), ["3"]);
+        assert_eq!(ids("SELECT * FROM t WHERE n = 1.5"), ["5"]);
+        for bad in [
+            "SELECT * FROM t WHERE name = 'open",
+            "SELECT * FROM t WHERE \"n = 1",
+            "SELECT * FROM t WHERE n = 1.2.3",
+            "SELECT * FROM t; SELECT * FROM u",
+            "SELECT * FROM t WHERE n ! 1",
+            "SELECT * FROM t WHERE n ~ 1",
+        ] {
+            assert!(parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn predicates_and_precedence() {
+        // a literal on the left flips the comparison
+        assert_eq!(ids("SELECT * FROM t WHERE 2 < n"), ["1", "3"]);
+        assert_eq!(ids("SELECT * FROM t WHERE n IS NULL"), ["4"]);
+        assert_eq!(ids("SELECT * FROM t WHERE g IS NULL"), ["5"]);
+        assert_eq!(
+            ids("SELECT * FROM t WHERE n IS NOT NULL AND n <> 3"),
+            ["2", "3", "5"]
+        );
+        assert_eq!(
+            ids("SELECT * FROM t WHERE n NOT BETWEEN 0 AND 5"),
+            ["2", "3", "4"]
+        );
+        assert_eq!(ids("SELECT * FROM t WHERE g NOT IN ('a')"), ["2", "4", "5"]);
+        // AND binds tighter than OR, NOT tighter than AND
+        assert_eq!(
+            ids("SELECT * FROM t WHERE g = 'b' OR g = 'a' AND n > 5"),
+            ["2", "3", "4"]
+        );
+        assert_eq!(ids("SELECT * FROM t WHERE NOT g = 'a' AND n < 0"), ["2"]);
+        assert_eq!(
+            ids("SELECT * FROM t WHERE (g = 'b' OR g = 'a') AND n > 5"),
+            ["3"]
+        );
+        assert!(parse("SELECT * FROM t WHERE n NOT = 1").is_err());
+    }
+
+    #[test]
+    fn ordering_paging_and_aliases() {
+        // missing columns come out as null
+        let out =
+            select("SELECT name AS who, x.y FROM t WHERE n > 0 ORDER BY who DESC LIMIT 2 OFFSET 1");
+        assert_eq!(
+            out,
+            [
+                json!({"who": "ed", "x": {"y": null}}),
+                json!({"who": "cy", "x": {"y": 3}})
+            ]
+        );
+        // ORDER BY may use a column that is not selected; ties keep the earlier key's order
+        let out = select("SELECT name FROM t ORDER BY g, n DESC");
+        let names: Vec<&str> = out.iter().map(|r| r["name"].as_str().unwrap()).collect();
+        assert_eq!(names, ["ed", "cy", "it's", "bob", "dee"]);
+        for bad in [
+            "SELECT * FROM t LIMIT -1",
+            "SELECT * FROM t LIMIT 1 OFFSET",
+            "SELECT * FROM t extra",
+        ] {
+            assert!(parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn grouping() {
+        let out = select(
+            "SELECT g, count(*), sum(x.y), avg(n) AS mean FROM t GROUP BY g ORDER BY mean DESC",
+        );
+        assert_eq!(
+            out,
+            [
+                json!({"g": "a", "count(*)": 2, "sum(x_y)": 4, "mean": 5.0}),
+                json!({"g": null, "count(*)": 1, "sum(x_y)": 0, "mean": 1.5}),
+                json!({"g": "b", "count(*)": 2, "sum(x_y)": 2, "mean": -2.0}),
+            ]
+        );
+        // without GROUP BY the whole table is one group
+        assert_eq!(
+            select("SELECT count(n), max(n) FROM t"),
+            [json!({"count(n)": 4, "max(n)": 7})]
+        );
+        assert!(parse("SELECT name, count(*) FROM t GROUP BY g").is_err());
+        assert!(parse("SELECT * FROM t GROUP BY g").is_err());
+        assert!(parse("SELECT sum(*) FROM t").is_err());
+    }
+}
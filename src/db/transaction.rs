@@ -0,0 +1,578 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use super::{BulkOp, Collection, Database, DbError, Document, Store, update};
+
+type Key = (String, String);
+
+/// An optimistic multi-collection transaction. Reads record the version they
+/// saw and writes are buffered; [`Database::transaction`] validates the reads
+/// and applies every write atomically on commit.
+pub struct Transaction<'a> {
+    db: &'a Database,
+    collections: HashMap<String, Collection>,
+    reads: HashMap<Key, Option<u64>>,
+    writes: BTreeMap<Key, Option<Document>>,
+    author: Option<String>,
+}
+
+/// A write inside a committed transaction: the document's new state, or
+/// `None` when it was deleted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CommitWrite {
+    collection: String,
+    id: String,
+    document: Option<Document>,
+}
+
+/// The durable commit record, written before any collection is changed and
+/// removed once every collection has been persisted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CommitRecord {
+    id: String,
+    committed_at: DateTime<Utc>,
+    writes: Vec<CommitWrite>,
+}
+
+/// One operation of an HTTP transaction: a bulk operation plus the
+/// collection it applies to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxOp {
+    pub collection: String,
+    #[serde(flatten)]
+    pub op: BulkOp,
+}
+
+impl<'a> Transaction<'a> {
+    pub(super) fn new(db: &'a Database) -> Self {
+        Self {
+            db,
+            collections: HashMap::new(),
+            reads: HashMap::new(),
+            writes: BTreeMap::new(),
+            author: None,
+        }
+    }
+
+    /// Attributes the revisions this transaction writes to `author`.
+    pub fn set_author(&mut self, author: &str) {
+        self.author = Some(author.to_string());
+        for col in self.collections.values_mut() {
+            *col = col.with_author(author);
+        }
+    }
+
+    fn handle(&mut self, collection: &str) -> Result<Collection, DbError> {
+        if let Some(col) = self.collections.get(collection) {
+            return Ok(col.clone());
+        }
+        let mut col = self.db.collection(collection)?;
+        if let Some(author) = &self.author {
+            col = col.with_author(author);
+        }
+        self.collections.insert(collection.to_string(), col.clone());
+        Ok(col)
+    }
+
+    /// Reads a document as of this transaction, including its own writes.
+    pub fn get(&mut self, collection: &str, id: &str) -> Result<Option<Document>, DbError> {
+        let key = (collection.to_string(), id.to_string());
+        if let Some(pending) = self.writes.get(&key) {
+            return Ok(pending.clone());
+        }
+        let doc = self.handle(collection)?.find(id)?;
+        self.reads
+            .entry(key)
+            .or_insert_with(|| doc.as_ref().map(|d| d.version));
+        Ok(doc)
+    }
+
+    fn existing(&mut self, collection: &str, id: &str) -> Result<Document, DbError> {
+        self.get(collection, id)?.ok_or(DbError::NotFound)
+    }
+
+    fn stage(&mut self, collection: &str, id: &str, doc: Option<Document>) {
+        self.writes
+            .insert((collection.to_string(), id.to_string()), doc);
+    }
+
+    pub fn insert(
+        &mut self,
+        collection: &str,
+        data: Value,
+        ttl: Option<i64>,
+    ) -> Result<Document, DbError> {
+        self.create(collection, None, data, ttl)
+    }
+
+    pub fn insert_with_id(
+        &mut self,
+        collection: &str,
+        id: &str,
+        data: Value,
+        ttl: Option<i64>,
+    ) -> Result<Document, DbError> {
+        self.create(collection, Some(id), data, ttl)
+    }
+
+    fn create(
+        &mut self,
+        collection: &str,
+        id: Option<&str>,
+        data: Value,
+        ttl: Option<i64>,
+    ) -> Result<Document, DbError> {
+        let col = self.handle(collection)?;
+        let doc = col.build_document(&*col.read()?, id, data, ttl)?;
+        // reading the id makes the commit fail if someone else takes it first
+        if self.get(collection, &doc.id)?.is_some() {
+            return Err(DbError::DuplicateId(doc.id));
+        }
+        self.stage(collection, &doc.id, Some(doc.clone()));
+        Ok(doc)
+    }
+
+    pub fn update(&mut self, collection: &str, id: &str, data: Value) -> Result<Document, DbError> {
+        let mut doc = self.existing(collection, id)?;
+        self.handle(collection)?.validate(&data)?;
+        doc.data = data;
+        doc.touch();
+        self.stage(collection, id, Some(doc.clone()));
+        Ok(doc)
+    }
+
+    pub fn update_with(
+        &mut self,
+        collection: &str,
+        id: &str,
+        ops: &Value,
+    ) -> Result<Document, DbError> {
+        let ops = update::parse(ops)?;
+        let mut doc = self.existing(collection, id)?;
+        update::apply(&ops, &mut doc.data)?;
+        self.handle(collection)?.validate(&doc.data)?;
+        doc.touch();
+        self.stage(collection, id, Some(doc.clone()));
+        Ok(doc)
+    }
+
+    pub fn upsert(&mut self, collection: &str, id: &str, data: Value) -> Result<Document, DbError> {
+        let Some(mut doc) = self.get(collection, id)? else {
+            return self.create(collection, Some(id), data, None);
+        };
+        self.handle(collection)?.validate(&data)?;
+        doc.data = data;
+        doc.touch();
+        self.stage(collection, id, Some(doc.clone()));
+        Ok(doc)
+    }
+
+    pub fn delete(&mut self, collection: &str, id: &str) -> Result<(), DbError> {
+        self.existing(collection, id)?;
+        self.stage(collection, id, None);
+        Ok(())
+    }
+
+    /// Applies one bulk-style operation, returning the resulting document.
+    pub fn apply(&mut self, op: TxOp) -> Result<Option<Document>, DbError> {
+        let collection = op.collection.as_str();
+        match op.op {
+            BulkOp::Insert { id, data, ttl } => {
+                self.create(collection, id.as_deref(), data, ttl).map(Some)
+            }
+            BulkOp::Update { id, data } => self.update(collection, &id, data).map(Some),
+            BulkOp::Patch { id, ops } => self.update_with(collection, &id, &ops).map(Some),
+            BulkOp::Upsert { id, data } => self.upsert(collection, &id, data).map(Some),
+            BulkOp::Delete { id } => self.delete(collection, &id).map(|_| None),
+        }
+    }
+
+    pub(super) fn commit(mut self) -> Result<(), DbError> {
+        if self.writes.is_empty() {
+            return Ok(());
+        }
+        let mut names: Vec<&String> = self.collections.keys().collect();
+        names.sort();
+
+        // lock every involved collection in name order so concurrent commits
+        // can't deadlock, then validate what this transaction read
+        let mut guards = Vec::with_capacity(names.len());
+        for name in &names {
+            let col = &self.collections[*name];
+            guards.push((name.as_str(), col.write()?));
+        }
+        let now = Utc::now();
+        for ((collection, id), seen) in &self.reads {
+            let (_, store) = guards
+                .iter()
+                .find(|(name, _)| name == collection)
+                .ok_or(DbError::CollectionNotFound)?;
+            let current = store.get(id, now).map(|d| d.version);
+            if current != *seen {
+                return Err(DbError::TransactionConflict(format!(
+                    "{}/{}",
+                    collection, id
+                )));
+            }
+        }
+        for ((collection, id), document) in self.writes.iter_mut() {
+            let (_, store) = guards
+                .iter_mut()
+                .find(|(name, _)| name == collection)
+                .ok_or(DbError::CollectionNotFound)?;
+            let Some(doc) = document else {
+                continue;
+            };
+            match store.get(id, now) {
+                None => self.collections[collection].admit(store, doc)?,
+                // deleted and created again inside the transaction
+                Some(existing) => doc.version = doc.version.max(existing.version + 1),
+            }
+        }
+        for (name, store) in &guards {
+            let writes = self
+                .writes
+                .iter()
+                .filter(|((collection, _), _)| collection == name)
+                .map(|((_, id), document)| (id.as_str(), document.as_ref()));
+            self.collections[*name].charge(store, writes)?;
+        }
+
+        let record = CommitRecord {
+            id: Uuid::new_v4().to_string(),
+            committed_at: Utc::now(),
+            writes: self
+                .writes
+                .iter()
+                .map(|((collection, id), document)| CommitWrite {
+                    collection: collection.clone(),
+                    id: id.clone(),
+                    document: document.clone(),
+                })
+                .collect(),
+        };
+        let record_path = write_record(&self.db.path, &record)?;
+
+        for (name, store) in guards.iter_mut() {
+            let col = &self.collections[*name];
+            for write in record.writes.iter().filter(|w| w.collection == *name) {
+                let old = apply_write(store, write);
+                match (&write.document, old) {
+                    (Some(doc), _) => col.record_write(doc)?,
+                    (None, Some(old)) => {
+                        col.record_delete(&old)?;
+                        col.discard(old)?;
+                    }
+                    (None, None) => {}
+                }
+            }
+            col.evict(store)?;
+        }
+        drop(guards);
+
+        let written: Vec<&String> = names
+            .into_iter()
+            .filter(|name| record.writes.iter().any(|w| &w.collection == *name))
+            .collect();
+        for name in written {
+            self.collections[name].persist()?;
+        }
+        fs::remove_file(&record_path)?;
+        info!(
+            "Committed transaction {} ({} writes)",
+            record.id,
+            record.writes.len()
+        );
+        Ok(())
+    }
+}
+
+// Returns the document the write replaced, if any.
+fn apply_write(store: &mut Store, write: &CommitWrite) -> Option<Document> {
+    let old = store.take(&write.id);
+    if let Some(doc) = &write.document {
+        store.put(doc.clone());
+    }
+    old
+}
+
+fn txlog_dir(db_path: &Path) -> PathBuf {
+    db_path.join("_txlog")
+}
+
+fn write_record(db_path: &Path, record: &CommitRecord) -> Result<PathBuf, DbError> {
+    let dir = txlog_dir(db_path);
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("{}.json", record.id));
+    let mut file = fs::File::create(&path)?;
+    file.write_all(serde_json::to_string(record)?.as_bytes())?;
+    file.sync_all()?;
+    Ok(path)
+}
+
+/// Re-applies commit records left behind by a crash between writing the
+/// record and persisting the collections. Replaying is idempotent.
+pub(super) fn recover(db: &Database) -> Result<(), DbError> {
+    let dir = txlog_dir(&db.path);
+    if !dir.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        let record: CommitRecord = match fs::read_to_string(&path)
+            .map_err(DbError::from)
+            .and_then(|raw| serde_json::from_str(&raw).map_err(DbError::from))
+        {
+            Ok(record) => record,
+            Err(e) => {
+                // a torn record was never acknowledged, so nothing was applied
+                warn!(
+                    "Discarding unreadable commit record {}: {}",
+                    path.display(),
+                    e
+                );
+                fs::remove_file(&path)?;
+                continue;
+            }
+        };
+        let mut touched = Vec::new();
+        for write in &record.writes {
+            let col = db.collection(&write.collection)?;
+            apply_write(&mut *col.write()?, write);
+            if !touched.contains(&write.collection) {
+                touched.push(write.collection.clone());
+            }
+        }
+        for name in touched {
+            db.collection(&name)?.persist()?;
+        }
+        fs::remove_file(&path)?;
+        info!("Recovered transaction {}", record.id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{CollectionOptions, Limits, Selector, test_dir};
+    use serde_json::json;
+
+    #[test]
+    fn commits_atomically_and_rolls_back_on_error() {
+        let dir = test_dir("tx-commit");
+        let db = Database::new(&dir).unwrap();
+        let a = db.collection("a").unwrap();
+        let b = db.collection("b").unwrap();
+        let x = a.insert(json!({"bal": 100}), None).unwrap();
+        let y = b.insert(json!({"bal": 0}), None).unwrap();
+
+        db.transaction(|tx| {
+            tx.update_with("a", &x.id, &json!({"$inc": {"bal": -30}}))?;
+            tx.update_with("b", &y.id, &json!({"$inc": {"bal": 30}}))?;
+            tx.insert("log", json!({"amount": 30}), None)
+        })
+        .unwrap();
+        assert_eq!(a.find(&x.id).unwrap().unwrap().data["bal"], 70);
+        assert_eq!(b.find(&y.id).unwrap().unwrap().data["bal"], 30);
+        assert_eq!(db.collection("log").unwrap().find_all().unwrap().len(), 1);
+        assert!(fs::read_dir(txlog_dir(&dir)).unwrap().next().is_none());
+
+        let failed: Result<(), DbError> = db.transaction(|tx| {
+            tx.update("a", &x.id, json!({"bal": 0}))?;
+            Err(DbError::InvalidUpdate("abort".into()))
+        });
+        assert!(failed.is_err());
+        assert_eq!(a.find(&x.id).unwrap().unwrap().data["bal"], 70);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn reads_see_the_transactions_own_writes() {
+        let dir = test_dir("tx-own-writes");
+        let db = Database::new(&dir).unwrap();
+        let a = db.collection("a").unwrap();
+        a.upsert(&Selector::Id("x".into()), json!({"n": 1}))
+            .unwrap();
+        db.transaction(|tx| {
+            tx.delete("a", "x")?;
+            assert!(tx.get("a", "x")?.is_none());
+            assert!(matches!(
+                tx.update("a", "x", json!({})),
+                Err(DbError::NotFound)
+            ));
+            // the id is free again once deleted
+            tx.upsert("a", "x", json!({"n": 2}))?;
+            assert!(matches!(
+                tx.insert_with_id("a", "x", json!({}), None),
+                Err(DbError::DuplicateId(_))
+            ));
+            tx.update_with("a", "x", &json!({"$inc": {"n": 1}}))?;
+            assert_eq!(tx.get("a", "x")?.unwrap().data["n"], 3);
+            // nothing is visible outside until the commit
+            assert_eq!(a.find("x")?.unwrap().data["n"], 1);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(a.find("x").unwrap().unwrap().data["n"], 3);
+
+        // a document recreated in one transaction still gets a new version
+        let before = a.find("x").unwrap().unwrap();
+        db.transaction(|tx| {
+            tx.delete("a", "x")?;
+            tx.insert_with_id("a", "x", json!({}), None).map(|_| ())
+        })
+        .unwrap();
+        assert!(a.find("x").unwrap().unwrap().version > before.version);
+
+        // a transaction that only reads commits without a record
+        db.transaction(|tx| tx.get("a", "x").map(|_| ())).unwrap();
+        assert!(fs::read_dir(txlog_dir(&dir)).unwrap().next().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_transfers_keep_the_total() {
+        let dir = test_dir("tx-transfers");
+        let db = Database::new(&dir).unwrap();
+        let accounts = db.collection("accounts").unwrap();
+        for id in ["a", "b", "c"] {
+            accounts
+                .upsert(&Selector::Id(id.into()), json!({"bal": 100}))
+                .unwrap();
+        }
+        let transfer = |from: &str, to: &str| loop {
+            let result = db.transaction(|tx| {
+                tx.update_with("accounts", from, &json!({"$inc": {"bal": -1}}))?;
+                tx.update_with("accounts", to, &json!({"$inc": {"bal": 1}}))?;
+                tx.insert("ledger", json!({"from": from, "to": to}), None)?;
+                Ok(())
+            });
+            match result {
+                Err(DbError::TransactionConflict(_)) => continue,
+                other => return other.unwrap(),
+            }
+        };
+        std::thread::scope(|s| {
+            for (from, to) in [("a", "b"), ("b", "c"), ("c", "a"), ("a", "c")] {
+                s.spawn(move || (0..25).for_each(|_| transfer(from, to)));
+            }
+        });
+        let balances: Vec<i64> = ["a", "b", "c"]
+            .iter()
+            .map(|id| {
+                accounts.find(id).unwrap().unwrap().data["bal"]
+                    .as_i64()
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(balances, [75, 100, 125]);
+        let ledger = db.collection("ledger").unwrap();
+        assert_eq!(ledger.find_all().unwrap().len(), 100);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn an_id_taken_during_the_transaction_fails_the_commit() {
+        let dir = test_dir("tx-taken-id");
+        let db = Database::new(&dir).unwrap();
+        let a = db.collection("a").unwrap();
+        let result = db.transaction(|tx| {
+            tx.upsert("a", "x", json!({"by": "tx"}))?;
+            a.upsert(&Selector::Id("x".into()), json!({"by": "other"}))?;
+            Ok(())
+        });
+        assert!(matches!(result, Err(DbError::TransactionConflict(_))));
+        assert_eq!(a.find("x").unwrap().unwrap().data["by"], "other");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn conflicting_read_fails_the_commit() {
+        let dir = test_dir("tx-conflict");
+        let db = Database::new(&dir).unwrap();
+        let a = db.collection("a").unwrap();
+        let x = a.insert(json!({"bal": 1}), None).unwrap();
+        let result = db.transaction(|tx| {
+            tx.get("a", &x.id)?;
+            a.update(&x.id, json!({"bal": 2}))?;
+            tx.insert("a", json!({"bal": 3}), None)
+        });
+        assert!(matches!(result, Err(DbError::TransactionConflict(_))));
+        assert_eq!(a.find_all().unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recreating_a_read_document_fails_the_commit() {
+        let dir = test_dir("tx-recreate");
+        let db = Database::new(&dir).unwrap();
+        let a = db.collection("a").unwrap();
+        a.insert_with_id("x", json!({"bal": 1}), None).unwrap();
+        let result = db.transaction(|tx| {
+            tx.get("a", "x")?;
+            a.delete("x")?;
+            a.insert_with_id("x", json!({"bal": 2}), None)?;
+            tx.update("a", "x", json!({"bal": 3}))
+        });
+        assert!(matches!(result, Err(DbError::TransactionConflict(_))));
+        assert_eq!(a.find("x").unwrap().unwrap().data["bal"], 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn staged_inserts_count_against_the_document_limit() {
+        let dir = test_dir("tx-limit");
+        let db = Database::new(&dir).unwrap();
+        let options = CollectionOptions {
+            limits: Limits {
+                max_documents: Some(2),
+                max_document_bytes: None,
+            },
+            ..Default::default()
+        };
+        db.create_collection_with("a", options).unwrap();
+        let a = db.get_collection("a").unwrap();
+        a.insert(json!({}), None).unwrap();
+        let result = db.transaction(|tx| {
+            tx.insert("a", json!({}), None)?;
+            tx.insert("a", json!({}), None)
+        });
+        assert!(matches!(result, Err(DbError::LimitExceeded(_))));
+        assert_eq!(a.find_all().unwrap().len(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn recovery_replays_records_left_by_a_crash() {
+        let dir = test_dir("tx-recovery");
+        let db = Database::new(&dir).unwrap();
+        let x = db.collection("a").unwrap().insert(json!({}), None).unwrap();
+        let record = CommitRecord {
+            id: "r".into(),
+            committed_at: Utc::now(),
+            writes: vec![CommitWrite {
+                collection: "a".into(),
+                id: x.id.clone(),
+                document: None,
+            }],
+        };
+        write_record(&dir, &record).unwrap();
+        // a torn record was never acknowledged and is dropped
+        fs::write(txlog_dir(&dir).join("torn.json"), "{\"id\": ").unwrap();
+
+        let db = Database::load(&dir).unwrap();
+        assert!(db.collection("a").unwrap().find(&x.id).unwrap().is_none());
+        assert!(fs::read_dir(txlog_dir(&dir)).unwrap().next().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
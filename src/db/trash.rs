@@ -0,0 +1,343 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+use tracing::warn;
+
+use super::{CollectionName, DbError, Document, file, ttl};
+
+/// Soft-delete settings: deleted documents, and the collection itself when
+/// dropped, go to the trash instead of being removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SoftDelete {
+    /// Seconds before trashed items are purged for good; kept forever if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<i64>,
+}
+
+impl SoftDelete {
+    pub(super) fn check(&self) -> Result<(), DbError> {
+        self.retention.map(ttl::check_ttl).transpose()?;
+        Ok(())
+    }
+
+    pub(super) fn expired(&self, deleted_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        self.retention
+            .and_then(|secs| ttl::offset(deleted_at, secs))
+            .is_some_and(|purge_at| purge_at <= now)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedDocument {
+    pub document: Document,
+    pub deleted_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_by: Option<String>,
+}
+
+/// A dropped collection waiting in `_trash/collections/<id>/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrashedCollection {
+    pub id: String,
+    pub name: String,
+    pub dropped_at: DateTime<Utc>,
+    pub soft_delete: SoftDelete,
+}
+
+/// Deleted documents of one collection, stored in `_trash/<name>.json`.
+#[derive(Debug, Default)]
+pub(super) struct Trash {
+    documents: HashMap<String, TrashedDocument>,
+    dirty: bool,
+}
+
+pub(super) fn path(db_path: &Path, name: &CollectionName) -> PathBuf {
+    name.file_in(&db_path.join("_trash"))
+}
+
+fn collections_dir(db_path: &Path) -> PathBuf {
+    db_path.join("_trash").join("collections")
+}
+
+impl Trash {
+    pub fn load(path: &Path) -> Result<Self, DbError> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Ok(Self {
+            documents: serde_json::from_str(&fs::read_to_string(path)?)?,
+            dirty: false,
+        })
+    }
+
+    pub fn save_if_dirty(&mut self, path: &Path) -> Result<(), DbError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if self.documents.is_empty() {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        } else {
+            file::write_atomic(path, serde_json::to_string(&self.documents)?.as_bytes())?;
+        }
+        self.dirty = false;
+        Ok(())
+    }
+
+    pub fn put(&mut self, document: Document, deleted_by: Option<String>) {
+        let trashed = TrashedDocument {
+            document,
+            deleted_at: Utc::now(),
+            deleted_by,
+        };
+        self.documents.insert(trashed.document.id.clone(), trashed);
+        self.dirty = true;
+    }
+
+    pub fn get(&self, id: &str) -> Option<&TrashedDocument> {
+        self.documents.get(id)
+    }
+
+    pub fn take(&mut self, id: &str) -> Option<TrashedDocument> {
+        let trashed = self.documents.remove(id)?;
+        self.dirty = true;
+        Some(trashed)
+    }
+
+    pub fn list(&self) -> Vec<TrashedDocument> {
+        let mut trashed: Vec<TrashedDocument> = self.documents.values().cloned().collect();
+        trashed.sort_by(|a, b| (a.deleted_at, &a.document.id).cmp(&(b.deleted_at, &b.document.id)));
+        trashed
+    }
+
+    pub fn clear(&mut self) -> usize {
+        let purged = self.documents.len();
+        self.documents.clear();
+        self.dirty |= purged > 0;
+        purged
+    }
+
+    pub fn purge_expired(&mut self, soft_delete: &SoftDelete, now: DateTime<Utc>) -> usize {
+        let before = self.documents.len();
+        self.documents
+            .retain(|_, trashed| !soft_delete.expired(trashed.deleted_at, now));
+        let purged = before - self.documents.len();
+        self.dirty |= purged > 0;
+        purged
+    }
+}
+
+// Files of a collection and where they go inside its trash directory.
+pub(super) fn collection_files(
+    db_path: &Path,
+    name: &CollectionName,
+) -> [(PathBuf, &'static str); 4] {
+    [
+        (name.file_in(db_path), "data.json"),
+        (super::meta::path(db_path, name), "meta.json"),
+        (super::history::path(db_path, name), "history.json"),
+        (path(db_path, name), "trash.json"),
+    ]
+}
+
+/// Moves a collection's files into the trash. The record goes first, so a
+/// crash part way leaves an entry that can still be restored.
+pub(super) fn trash_collection(
+    db_path: &Path,
+    name: &CollectionName,
+    soft_delete: SoftDelete,
+) -> Result<TrashedCollection, DbError> {
+    let dropped_at = Utc::now();
+    let (id, dir) = claim_dir(db_path, name, dropped_at)?;
+    let trashed = TrashedCollection {
+        id,
+        name: name.to_string(),
+        dropped_at,
+        soft_delete,
+    };
+    file::write_atomic(
+        &dir.join("dropped.json"),
+        serde_json::to_string_pretty(&trashed)?.as_bytes(),
+    )?;
+    for (from, to) in collection_files(db_path, name) {
+        if from.exists() {
+            fs::rename(from, dir.join(to))?;
+        }
+    }
+    Ok(trashed)
+}
+
+// Creates a directory no earlier drop uses, even of the same collection in
+// the same millisecond.
+fn claim_dir(
+    db_path: &Path,
+    name: &CollectionName,
+    dropped_at: DateTime<Utc>,
+) -> Result<(String, PathBuf), DbError> {
+    let parent = collections_dir(db_path);
+    fs::create_dir_all(&parent)?;
+    let base = format!("{}-{}", name.file_stem(), dropped_at.timestamp_millis());
+    for n in 0.. {
+        let id = match n {
+            0 => base.clone(),
+            n => format!("{}-{}", base, n),
+        };
+        let dir = parent.join(&id);
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok((id, dir)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    unreachable!("ran out of trash ids")
+}
+
+/// Moves a trashed collection's files back, returning its name.
+pub(super) fn restore_collection(db_path: &Path, id: &str) -> Result<String, DbError> {
+    let trashed = trashed_collection(db_path, id)?;
+    let dir = collections_dir(db_path).join(id);
+    for (to, from) in collection_files(db_path, &CollectionName::new(&trashed.name)?) {
+        let from = dir.join(from);
+        if from.exists() {
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(from, to)?;
+        }
+    }
+    fs::remove_dir_all(dir)?;
+    Ok(trashed.name)
+}
+
+pub(super) fn purge_collection(db_path: &Path, id: &str) -> Result<(), DbError> {
+    trashed_collection(db_path, id)?;
+    fs::remove_dir_all(collections_dir(db_path).join(id))?;
+    Ok(())
+}
+
+fn trashed_collection(db_path: &Path, id: &str) -> Result<TrashedCollection, DbError> {
+    let file = collections_dir(db_path).join(id).join("dropped.json");
+    if id.contains(['/', '\\']) || id.starts_with('.') || !file.exists() {
+        return Err(DbError::CollectionNotFound);
+    }
+    Ok(serde_json::from_str(&fs::read_to_string(file)?)?)
+}
+
+pub(super) fn trashed_collections(db_path: &Path) -> Result<Vec<TrashedCollection>, DbError> {
+    let dir = collections_dir(db_path);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut trashed = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let id = entry?.file_name().to_string_lossy().to_string();
+        match trashed_collection(db_path, &id) {
+            Ok(collection) => trashed.push(collection),
+            Err(e) => warn!("Skipping trash entry {}: {}", id, e),
+        }
+    }
+    trashed.sort_by_key(|trashed| trashed.dropped_at);
+    Ok(trashed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, test_dir};
+    use serde_json::json;
+
+    #[test]
+    fn deleted_documents_wait_in_the_trash() {
+        let dir = test_dir("trash");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        col.set_soft_delete(Some(SoftDelete::default())).unwrap();
+        let col = col.with_author("ann");
+        for id in ["a", "b", "c"] {
+            col.insert_with_id(id, json!({"id": id}), None).unwrap();
+            col.delete(id).unwrap();
+        }
+        let trashed = col.trashed().unwrap();
+        assert_eq!(trashed.len(), 3);
+        assert_eq!(trashed[0].deleted_by.as_deref(), Some("ann"));
+
+        let doc = col.undelete("a").unwrap();
+        assert_eq!(doc.data, json!({"id": "a"}));
+        // above the tombstones of all three deleted documents
+        assert_eq!(doc.version, 7);
+        col.insert_with_id("b", json!({}), None).unwrap();
+        assert!(matches!(col.undelete("b"), Err(DbError::DuplicateId(_))));
+        col.purge("c").unwrap();
+        assert!(matches!(col.purge("c"), Err(DbError::NotFound)));
+        assert_eq!(col.empty_trash().unwrap(), 1);
+        let col = Database::new(&dir).unwrap().collection("c").unwrap();
+        assert!(col.trashed().unwrap().is_empty());
+        assert_eq!(col.find_all().unwrap().len(), 2);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dropped_collections_can_be_restored() {
+        let dir = test_dir("trash-drop");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        col.set_soft_delete(Some(SoftDelete {
+            retention: Some(3600),
+        }))
+        .unwrap();
+        col.insert_with_id("a", json!({}), None).unwrap();
+        db.drop_collection("c").unwrap();
+        let trashed = db.trashed_collections().unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].name, "c");
+        // nothing is old enough to be purged yet
+        assert_eq!(db.purge_expired_trash().unwrap(), 0);
+
+        db.collection("c").unwrap();
+        assert!(matches!(
+            db.restore_collection(&trashed[0].id),
+            Err(DbError::CollectionAlreadyExists)
+        ));
+        db.drop_collection("c").unwrap();
+        assert_eq!(db.trashed_collections().unwrap().len(), 1);
+        assert_eq!(db.restore_collection(&trashed[0].id).unwrap(), "c");
+        assert!(db.get_collection("c").unwrap().find("a").unwrap().is_some());
+        db.drop_collection("c").unwrap();
+        let id = db.trashed_collections().unwrap()[0].id.clone();
+        db.purge_collection(&id).unwrap();
+        assert!(db.trashed_collections().unwrap().is_empty());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dropping_the_same_name_twice_keeps_both_copies() {
+        let dir = test_dir("trash-twice");
+        let name = CollectionName::new("c").unwrap();
+        let mut ids = Vec::new();
+        for contents in ["first", "second"] {
+            file::write_atomic(&name.file_in(&dir), contents.as_bytes()).unwrap();
+            ids.push(
+                trash_collection(&dir, &name, SoftDelete::default())
+                    .unwrap()
+                    .id,
+            );
+        }
+        assert_ne!(ids[0], ids[1]);
+        // a directory left without its record is skipped, not an error
+        fs::create_dir_all(collections_dir(&dir).join("stray")).unwrap();
+        assert_eq!(trashed_collections(&dir).unwrap().len(), 2);
+
+        assert_eq!(restore_collection(&dir, &ids[0]).unwrap(), "c");
+        assert_eq!(fs::read_to_string(name.file_in(&dir)).unwrap(), "first");
+        assert!(matches!(
+            restore_collection(&dir, "stray"),
+            Err(DbError::CollectionNotFound)
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
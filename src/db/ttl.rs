@@ -0,0 +1,414 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+use tokio::{sync::Notify, task::JoinHandle};
+use tracing::{debug, error, info};
+
+use super::{DbError, Document, IndexDefinition, IndexKind, query::path_value};
+
+/// Longest TTL, retention or TTL index delay accepted, in seconds: a
+/// hundred years.
+pub const MAX_TTL_SECS: i64 = 100 * 365 * 24 * 60 * 60;
+
+/// Checks a TTL or retention of `secs` seconds: positive and no longer than
+/// [`MAX_TTL_SECS`].
+pub fn check_ttl(secs: i64) -> Result<i64, DbError> {
+    if (1..=MAX_TTL_SECS).contains(&secs) {
+        Ok(secs)
+    } else {
+        Err(DbError::InvalidTtl(format!(
+            "{} must be between 1 and {} seconds",
+            secs, MAX_TTL_SECS
+        )))
+    }
+}
+
+/// `secs` seconds after `at`, or an error for a TTL `check_ttl` rejects or a
+/// date past the end of time.
+pub(super) fn expires_in(at: DateTime<Utc>, secs: i64) -> Result<DateTime<Utc>, DbError> {
+    check_ttl(secs)?;
+    offset(at, secs)
+        .ok_or_else(|| DbError::InvalidTtl(format!("{} + {}s is out of range", at, secs)))
+}
+
+/// `at` moved by `secs` seconds, or `None` outside the range of dates.
+pub(super) fn offset(at: DateTime<Utc>, secs: i64) -> Option<DateTime<Utc>> {
+    at.checked_add_signed(TimeDelta::try_seconds(secs)?)
+}
+
+/// Expires documents `expire_after` seconds after the date in `field`.
+#[derive(Debug, Clone)]
+pub(super) struct TtlIndex {
+    pub field: String,
+    pub expire_after: i64,
+}
+
+impl TtlIndex {
+    pub fn from_definitions(definitions: &[IndexDefinition]) -> Vec<TtlIndex> {
+        definitions
+            .iter()
+            .filter(|definition| definition.kind == IndexKind::Ttl)
+            .map(|definition| TtlIndex {
+                field: definition.field.clone(),
+                expire_after: definition.expire_after.unwrap_or(0),
+            })
+            .collect()
+    }
+
+    pub fn deadline(&self, doc: &Document) -> Option<DateTime<Utc>> {
+        let date = date_value(path_value(&doc.data, &self.field)?)?;
+        offset(date, self.expire_after)
+    }
+}
+
+/// When `doc` expires: its own `expires_at` or the earliest deadline of a
+/// TTL index, whichever comes first.
+pub(super) fn expiry(doc: &Document, ttl_indexes: &[TtlIndex]) -> Option<DateTime<Utc>> {
+    ttl_indexes
+        .iter()
+        .filter_map(|index| index.deadline(doc))
+        .chain(doc.expires_at)
+        .min()
+}
+
+// Dates are RFC 3339 strings or Unix timestamps in seconds.
+pub(super) fn date_value(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(s) => DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|date| date.with_timezone(&Utc)),
+        Value::Number(n) => DateTime::from_timestamp(n.as_i64()?, 0),
+        _ => None,
+    }
+}
+
+/// Documents ordered by when they expire, so the cleaner only visits the
+/// ones that are due and reads can tell an expired document in O(1).
+#[derive(Debug, Clone, Default)]
+pub(super) struct ExpiryIndex {
+    ttl_indexes: Arc<[TtlIndex]>,
+    queue: BTreeSet<(DateTime<Utc>, String)>,
+    deadlines: HashMap<String, DateTime<Utc>>,
+}
+
+impl ExpiryIndex {
+    pub fn build<'a>(ttl_indexes: Vec<TtlIndex>, docs: impl Iterator<Item = &'a Document>) -> Self {
+        let mut index = Self {
+            ttl_indexes: ttl_indexes.into(),
+            ..Self::default()
+        };
+        for doc in docs {
+            index.insert(doc);
+        }
+        index
+    }
+
+    pub fn insert(&mut self, doc: &Document) {
+        self.remove(&doc.id);
+        if let Some(deadline) = expiry(doc, &self.ttl_indexes) {
+            self.queue.insert((deadline, doc.id.clone()));
+            self.deadlines.insert(doc.id.clone(), deadline);
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        if let Some(deadline) = self.deadlines.remove(id) {
+            self.queue.remove(&(deadline, id.to_string()));
+        }
+    }
+
+    pub fn is_expired(&self, id: &str, now: DateTime<Utc>) -> bool {
+        self.deadlines
+            .get(id)
+            .is_some_and(|deadline| *deadline <= now)
+    }
+
+    /// Ids whose deadline has passed, soonest first.
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<String> {
+        self.queue
+            .iter()
+            .take_while(|(deadline, _)| *deadline <= now)
+            .map(|(_, id)| id.clone())
+            .collect()
+    }
+
+    pub fn overdue(&self, now: DateTime<Utc>) -> usize {
+        self.queue
+            .iter()
+            .take_while(|(deadline, _)| *deadline <= now)
+            .count()
+    }
+
+    pub fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        self.queue.first().map(|(deadline, _)| *deadline)
+    }
+
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub fn ttl_indexes(&self) -> Arc<[TtlIndex]> {
+        Arc::clone(&self.ttl_indexes)
+    }
+}
+
+/// Counters kept by the cleaner, shared by every handle of a database.
+#[derive(Debug, Default)]
+pub(super) struct ExpiryMetrics {
+    sweeps: AtomicU64,
+    expired: AtomicU64,
+    last_sweep_micros: AtomicU64,
+    last_sweep_at: Mutex<Option<DateTime<Utc>>>,
+}
+
+impl ExpiryMetrics {
+    pub fn record_sweep(&self, expired: usize, at: DateTime<Utc>, micros: u64) {
+        self.sweeps.fetch_add(1, Ordering::Relaxed);
+        self.expired.fetch_add(expired as u64, Ordering::Relaxed);
+        self.last_sweep_micros.store(micros, Ordering::Relaxed);
+        if let Ok(mut last) = self.last_sweep_at.lock() {
+            *last = Some(at);
+        }
+    }
+
+    pub fn stats(&self, collections: Vec<CollectionExpiry>) -> ExpiryStats {
+        ExpiryStats {
+            sweeps: self.sweeps.load(Ordering::Relaxed),
+            expired: self.expired.load(Ordering::Relaxed),
+            last_sweep_at: self.last_sweep_at.lock().ok().and_then(|last| *last),
+            last_sweep_micros: self.last_sweep_micros.load(Ordering::Relaxed),
+            collections,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpiryStats {
+    /// Cleaner runs since the database was opened.
+    pub sweeps: u64,
+    /// Documents the cleaner has removed.
+    pub expired: u64,
+    pub last_sweep_at: Option<DateTime<Utc>>,
+    pub last_sweep_micros: u64,
+    pub collections: Vec<CollectionExpiry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionExpiry {
+    pub name: String,
+    /// Documents with an expiry, including overdue ones.
+    pub pending: usize,
+    /// Expired documents hidden from reads but not yet removed.
+    pub overdue: usize,
+    pub next_expiry: Option<DateTime<Utc>>,
+}
+
+/// Handle to a running TTL cleaner. Dropping it leaves the cleaner running.
+#[derive(Debug)]
+pub struct TtlCleaner {
+    stop: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+impl TtlCleaner {
+    /// Asks the cleaner to stop; a sweep in progress is finished first.
+    pub fn stop(&self) {
+        self.stop.notify_one();
+    }
+
+    /// Stops the cleaner and waits until it has.
+    pub async fn shutdown(self) {
+        self.stop();
+        if let Err(e) = self.task.await {
+            tracing::error!("TTL cleaner panicked: {}", e);
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+}
+
+/// Runs `sweep` every `interval_secs` on the current tokio runtime; it
+/// returns how many items it purged from the trash.
+pub(super) fn spawn_cleaner<F>(interval_secs: u64, sweep: F) -> TtlCleaner
+where
+    F: Fn() -> Result<usize, DbError> + Clone + Send + 'static,
+{
+    let stop = Arc::new(Notify::new());
+    let stopped = Arc::clone(&stop);
+    let task = tokio::spawn(async move {
+        let period = std::time::Duration::from_secs(interval_secs.max(1));
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = stopped.notified() => break,
+                _ = interval.tick() => {}
+            }
+            match tokio::task::spawn_blocking(sweep.clone()).await {
+                Ok(Ok(0)) => {}
+                Ok(Ok(purged)) => debug!("Purged {} expired items from the trash", purged),
+                Ok(Err(e)) => error!("TTL cleanup failed: {}", e),
+                Err(e) => error!("TTL cleanup panicked: {}", e),
+            }
+        }
+        info!("TTL cleaner stopped");
+    });
+    TtlCleaner { stop, task }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, DbError, HistoryRetention, Query, SoftDelete, test_dir};
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn ttl_indexes_expire_documents_after_their_date() {
+        let dir = test_dir("ttl-index");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let old = (Utc::now() - chrono::Duration::hours(2)).to_rfc3339();
+        col.insert_with_id("rfc", json!({"at": old}), None).unwrap();
+        let stamp = Utc::now().timestamp() - 7200;
+        col.insert_with_id("unix", json!({"at": stamp}), None)
+            .unwrap();
+        col.insert_with_id("fresh", json!({"at": Utc::now().to_rfc3339()}), None)
+            .unwrap();
+        col.insert_with_id("undated", json!({"at": "soon"}), None)
+            .unwrap();
+        col.create_ttl_index("at", 3600).unwrap();
+        let info = col.info().unwrap();
+        assert_eq!(
+            (info.indexes[0].kind, info.indexes[0].entries),
+            (IndexKind::Ttl, 3)
+        );
+
+        assert_eq!(col.remove_expired().unwrap(), 2);
+        assert!(col.find("fresh").unwrap().is_some());
+        col.drop_ttl_index("at").unwrap();
+        assert!(matches!(col.drop_ttl_index("at"), Err(DbError::NotFound)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expiry_can_be_extended_refreshed_and_cleared() {
+        let dir = test_dir("ttl-update");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let doc = col.insert(json!({}), Some(60)).unwrap();
+        let expires_at = doc.expires_at.unwrap();
+
+        let extended = col.extend_ttl(&doc.id, 60).unwrap();
+        assert_eq!(
+            extended.expires_at,
+            Some(expires_at + chrono::Duration::seconds(60))
+        );
+        assert_eq!(extended.version, doc.version);
+        assert!(matches!(
+            col.refresh_ttl(&doc.id, None),
+            Err(DbError::InvalidTtl(_))
+        ));
+        col.set_default_ttl(Some(10)).unwrap();
+        let refreshed = col.refresh_ttl(&doc.id, None).unwrap();
+        assert!(refreshed.expires_at.unwrap() < expires_at);
+        let cleared = col.clear_ttl(&doc.id).unwrap();
+        assert_eq!(cleared.expires_at, None);
+        assert!(matches!(
+            col.extend_ttl(&doc.id, 60),
+            Err(DbError::InvalidTtl(_))
+        ));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn expired_documents_are_hidden_until_they_are_removed() {
+        let dir = test_dir("ttl-lazy");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let old = (Utc::now() - chrono::Duration::hours(2)).to_rfc3339();
+        col.insert_with_id("old", json!({"at": old, "n": 1}), None)
+            .unwrap();
+        col.insert_with_id("new", json!({"at": Utc::now().to_rfc3339(), "n": 1}), None)
+            .unwrap();
+        col.create_ttl_index("at", 3600).unwrap();
+
+        assert!(col.find("old").unwrap().is_none());
+        let query = Query {
+            filter: json!({"n": 1}),
+            ..Default::default()
+        };
+        assert_eq!(col.query(&query).unwrap().len(), 1);
+        let expiry = col.expiry().unwrap();
+        assert_eq!((expiry.pending, expiry.overdue), (2, 1));
+
+        assert_eq!(db.remove_expired().unwrap(), 1);
+        let stats = db.expiry_stats().unwrap();
+        assert_eq!((stats.sweeps, stats.expired), (1, 1));
+        assert_eq!(stats.collections[0].overdue, 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ttls_must_be_positive_and_bounded() {
+        assert_eq!(check_ttl(1).unwrap(), 1);
+        assert_eq!(check_ttl(MAX_TTL_SECS).unwrap(), MAX_TTL_SECS);
+        for secs in [0, -1, MAX_TTL_SECS + 1, i64::MAX, i64::MIN] {
+            assert!(matches!(check_ttl(secs), Err(DbError::InvalidTtl(_))));
+        }
+        assert!(offset(DateTime::<Utc>::MAX_UTC, 1).is_none());
+        assert!(expires_in(DateTime::<Utc>::MAX_UTC, 1).is_err());
+    }
+
+    #[test]
+    fn out_of_range_ttls_fail_without_poisoning_the_collection() {
+        let dir = test_dir("ttl-range");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        for ttl in [10_000_000_000_000, i64::MAX, 0, -5] {
+            let err = col.insert(json!({}), Some(ttl)).unwrap_err();
+            assert!(matches!(err, DbError::InvalidTtl(_)));
+        }
+        let doc = col.insert(json!({}), Some(60)).unwrap();
+        assert!(col.extend_ttl(&doc.id, i64::MAX).is_err());
+        assert!(col.refresh_ttl(&doc.id, Some(i64::MAX)).is_err());
+        assert!(col.set_default_ttl(Some(i64::MAX)).is_err());
+        assert!(col.create_ttl_index("at", i64::MAX).is_err());
+        assert!(col.create_ttl_index("at", -1).is_err());
+        let retention = HistoryRetention {
+            max_age: Some(i64::MAX),
+            ..Default::default()
+        };
+        assert!(col.set_history(Some(retention)).is_err());
+        let soft_delete = SoftDelete {
+            retention: Some(-1),
+        };
+        assert!(col.set_soft_delete(Some(soft_delete)).is_err());
+
+        assert_eq!(col.find_all().unwrap().len(), 1);
+        col.insert(json!({}), None).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn index_deadlines_past_the_range_of_dates_never_expire() {
+        let index = TtlIndex {
+            field: "at".to_string(),
+            expire_after: MAX_TTL_SECS,
+        };
+        let mut doc =
+            Document::new("a".to_string(), json!({"at": "9999-12-31T00:00:00Z"}), None).unwrap();
+        assert!(index.deadline(&doc).is_some());
+        doc.data = json!({"at": 8_210_266_876_799_i64});
+        assert!(index.deadline(&doc).is_none());
+    }
+}
@@ -0,0 +1,241 @@
+use serde_json::{Map, Value, json};
+
+use super::{
+    DbError, Filter,
+    query::{path_value, path_value_mut, remove_path, set_path, values_equal},
+};
+
+/// A field-level update, e.g. `{"$set": {"name": "x"}, "$inc": {"visits": 1}}`.
+#[derive(Debug, Clone)]
+pub enum UpdateOp {
+    Set(String, Value),
+    Unset(String),
+    Inc(String, Value),
+    Push(String, Vec<Value>),
+    Pull(String, Pull),
+    Rename(String, String),
+}
+
+#[derive(Debug, Clone)]
+pub enum Pull {
+    Equal(Value),
+    Matching(Filter),
+}
+
+fn invalid(msg: impl Into<String>) -> DbError {
+    DbError::InvalidUpdate(msg.into())
+}
+
+fn fields<'a>(op: &str, arg: &'a Value) -> Result<&'a Map<String, Value>, DbError> {
+    arg.as_object()
+        .ok_or_else(|| invalid(format!("{} expects an object of fields", op)))
+}
+
+/// Parses an update document into operations, in the order they are applied.
+pub fn parse(spec: &Value) -> Result<Vec<UpdateOp>, DbError> {
+    let spec = spec
+        .as_object()
+        .ok_or_else(|| invalid("update must be an object"))?;
+    if spec.is_empty() {
+        return Err(invalid("update has no operators"));
+    }
+    let mut ops = Vec::new();
+    for (op, arg) in spec {
+        let args = fields(op, arg)?;
+        for (field, value) in args {
+            if field.is_empty() || field == "_id" {
+                return Err(invalid(format!("{} cannot modify '{}'", op, field)));
+            }
+            ops.push(match op.as_str() {
+                "$set" => UpdateOp::Set(field.clone(), value.clone()),
+                "$unset" => UpdateOp::Unset(field.clone()),
+                "$inc" if value.is_number() => UpdateOp::Inc(field.clone(), value.clone()),
+                "$inc" => return Err(invalid(format!("$inc on {} needs a number", field))),
+                "$push" => match value.get("$each") {
+                    Some(Value::Array(items)) => UpdateOp::Push(field.clone(), items.clone()),
+                    Some(_) => return Err(invalid("$each expects an array")),
+                    None => UpdateOp::Push(field.clone(), vec![value.clone()]),
+                },
+                "$pull" => {
+                    let is_condition = value
+                        .as_object()
+                        .is_some_and(|m| m.keys().next().is_some_and(|k| k.starts_with('$')));
+                    let pull = if is_condition {
+                        Pull::Matching(Filter::parse(&json!({ "v": value }))?)
+                    } else {
+                        Pull::Equal(value.clone())
+                    };
+                    UpdateOp::Pull(field.clone(), pull)
+                }
+                "$rename" => match value.as_str() {
+                    Some(to) if !to.is_empty() && to != "_id" && to != field => {
+                        UpdateOp::Rename(field.clone(), to.to_string())
+                    }
+                    _ => return Err(invalid(format!("invalid $rename target for {}", field))),
+                },
+                other => return Err(invalid(format!("unknown update operator {}", other))),
+            });
+        }
+    }
+    Ok(ops)
+}
+
+fn add(current: &Value, by: &Value) -> Option<Value> {
+    match (current.as_i64(), by.as_i64()) {
+        (Some(a), Some(b)) => a.checked_add(b).map(Value::from),
+        _ => serde_json::Number::from_f64(current.as_f64()? + by.as_f64()?).map(Value::Number),
+    }
+}
+
+/// Applies `ops` to `data`. On error `data` may be partially modified, so
+/// callers work on a copy.
+pub fn apply(ops: &[UpdateOp], data: &mut Value) -> Result<(), DbError> {
+    for op in ops {
+        match op {
+            UpdateOp::Set(field, value) => set_path(data, field, value.clone())
+                .map_err(|_| invalid(format!("cannot $set {}", field)))?,
+            UpdateOp::Unset(field) => {
+                remove_path(data, field);
+            }
+            UpdateOp::Inc(field, by) => {
+                let next = match path_value(data, field) {
+                    None | Some(Value::Null) => by.clone(),
+                    Some(current) if current.is_number() => add(current, by)
+                        .ok_or_else(|| invalid(format!("$inc on {} overflowed", field)))?,
+                    Some(_) => {
+                        return Err(invalid(format!("$inc target {} is not a number", field)));
+                    }
+                };
+                set_path(data, field, next)
+                    .map_err(|_| invalid(format!("cannot $inc {}", field)))?;
+            }
+            UpdateOp::Push(field, items) => match path_value_mut(data, field) {
+                Some(Value::Array(existing)) => existing.extend(items.iter().cloned()),
+                None | Some(Value::Null) => set_path(data, field, Value::Array(items.clone()))
+                    .map_err(|_| invalid(format!("cannot $push to {}", field)))?,
+                Some(_) => return Err(invalid(format!("$push target {} is not an array", field))),
+            },
+            UpdateOp::Pull(field, pull) => match path_value_mut(data, field) {
+                Some(Value::Array(existing)) => existing.retain(|item| match pull {
+                    Pull::Equal(value) => !values_equal(item, value),
+                    Pull::Matching(filter) => !filter.matches_value(&json!({ "v": item })),
+                }),
+                None => {}
+                Some(_) => return Err(invalid(format!("$pull target {} is not an array", field))),
+            },
+            UpdateOp::Rename(from, to) => {
+                if let Some(value) = remove_path(data, from) {
+                    set_path(data, to, value)
+                        .map_err(|_| invalid(format!("cannot $rename {} to {}", from, to)))?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Database, test_dir};
+    use std::fs;
+
+    fn updated(data: Value, spec: Value) -> Result<Value, DbError> {
+        let mut data = data;
+        apply(&parse(&spec)?, &mut data)?;
+        Ok(data)
+    }
+
+    #[test]
+    fn increments_keep_integers_exact_and_refuse_overflow() {
+        let data = json!({"i": 1, "f": 0.5, "s": "x", "n": null});
+        let out = updated(
+            data.clone(),
+            json!({"$inc": {"i": 2, "f": 1, "n": 3, "new.count": -1}}),
+        )
+        .unwrap();
+        assert_eq!(
+            out,
+            json!({"i": 3, "f": 1.5, "s": "x", "n": 3, "new": {"count": -1}})
+        );
+        assert!(updated(json!({"i": i64::MAX}), json!({"$inc": {"i": 1}})).is_err());
+        assert!(updated(data.clone(), json!({"$inc": {"s": 1}})).is_err());
+        assert!(updated(data, json!({"$inc": {"i": "1"}})).is_err());
+    }
+
+    #[test]
+    fn pushes_and_pulls() {
+        let data = json!({"a": [1, 2.0, {"x": 1}, 3], "s": "x"});
+        let out = updated(
+            data.clone(),
+            json!({"$push": {"a": {"$each": [4, 5]}, "b": [9]}, "$pull": {"c": 1}}),
+        )
+        .unwrap();
+        assert_eq!(out["a"], json!([1, 2.0, {"x": 1}, 3, 4, 5]));
+        // a plain array is pushed as one item
+        assert_eq!(out["b"], json!([[9]]));
+        assert!(out.get("c").is_none());
+
+        let out = updated(data.clone(), json!({"$pull": {"a": 2}})).unwrap();
+        assert_eq!(out["a"], json!([1, {"x": 1}, 3]));
+        let out = updated(data.clone(), json!({"$pull": {"a": {"$gte": 2}}})).unwrap();
+        assert_eq!(out["a"], json!([1, {"x": 1}]));
+        let out = updated(data.clone(), json!({"$pull": {"a": {"x": 1}}})).unwrap();
+        assert_eq!(out["a"], json!([1, 2.0, 3]));
+        for bad in [
+            json!({"$push": {"s": 1}}),
+            json!({"$pull": {"s": 1}}),
+            json!({"$push": {"a": {"$each": 1}}}),
+        ] {
+            assert!(updated(data.clone(), bad.clone()).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn sets_unsets_and_renames() {
+        let data = json!({"a": {"b": 1}, "s": "x"});
+        let out = updated(
+            data.clone(),
+            json!({"$rename": {"a.b": "c.d", "missing": "e"}, "$unset": {"s": "", "nope.x": ""}}),
+        )
+        .unwrap();
+        assert_eq!(out, json!({"a": {}, "c": {"d": 1}}));
+        // operators apply in order, so a later $set sees an earlier $rename
+        let out = updated(
+            data.clone(),
+            json!({"$rename": {"s": "t"}, "$set": {"t": "y"}}),
+        )
+        .unwrap();
+        assert_eq!(out, json!({"a": {"b": 1}, "t": "y"}));
+        assert!(updated(data.clone(), json!({"$set": {"s.x": 1}})).is_err());
+        for bad in [
+            json!({}),
+            json!([]),
+            json!({"$set": 1}),
+            json!({"$set": {"_id": 1}}),
+            json!({"$set": {"": 1}}),
+            json!({"$rename": {"a": "a"}}),
+            json!({"$rename": {"a": "_id"}}),
+            json!({"$rename": {"a": 1}}),
+            json!({"$max": {"a": 1}}),
+        ] {
+            assert!(parse(&bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn a_failed_update_leaves_the_document_alone() {
+        let dir = test_dir("update");
+        let db = Database::new(&dir).unwrap();
+        let col = db.collection("c").unwrap();
+        let doc = col.insert(json!({"n": 1, "s": "x"}), None).unwrap();
+        let err = col
+            .update_with(&doc.id, &json!({"$inc": {"n": 1}, "$push": {"s": 1}}))
+            .unwrap_err();
+        assert!(matches!(err, DbError::InvalidUpdate(_)));
+        let stored = col.find(&doc.id).unwrap().unwrap();
+        assert_eq!(stored.data, doc.data);
+        assert_eq!(stored.version, doc.version);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,622 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet, hash_map::DefaultHasher},
+    hash::{Hash, Hasher},
+};
+
+use super::{DbError, Document};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    #[default]
+    Cosine,
+    Dot,
+    L2,
+}
+
+impl Metric {
+    /// Lower is closer for every metric.
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self {
+            Metric::Cosine => {
+                let (mut dot, mut na, mut nb) = (0.0, 0.0, 0.0);
+                for (x, y) in a.iter().zip(b) {
+                    dot += x * y;
+                    na += x * x;
+                    nb += y * y;
+                }
+                if na == 0.0 || nb == 0.0 {
+                    1.0
+                } else {
+                    1.0 - dot / (na.sqrt() * nb.sqrt())
+                }
+            }
+            Metric::Dot => -a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>(),
+            Metric::L2 => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorIndexKind {
+    /// Exact brute-force search.
+    #[default]
+    Flat,
+    /// Approximate search over a hierarchical navigable small world graph.
+    Hnsw,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VectorIndexOptions {
+    pub dims: usize,
+    #[serde(default)]
+    pub metric: Metric,
+    #[serde(default)]
+    pub kind: VectorIndexKind,
+    #[serde(default = "default_m")]
+    pub m: usize,
+    #[serde(default = "default_ef_construction")]
+    pub ef_construction: usize,
+}
+
+fn default_m() -> usize {
+    16
+}
+
+fn default_ef_construction() -> usize {
+    100
+}
+
+impl VectorIndexOptions {
+    pub fn new(dims: usize, metric: Metric, kind: VectorIndexKind) -> Self {
+        Self {
+            dims,
+            metric,
+            kind,
+            m: default_m(),
+            ef_construction: default_ef_construction(),
+        }
+    }
+}
+
+/// A k-nearest-neighbour search over an array-of-floats field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnnQuery {
+    pub field: String,
+    pub vector: Vec<f32>,
+    pub k: usize,
+    /// Defaults to the index metric, or cosine without an index.
+    #[serde(default)]
+    pub metric: Option<Metric>,
+    /// Regular query filter applied to candidates.
+    #[serde(default)]
+    pub filter: Value,
+    /// Force brute-force search even when an HNSW index exists.
+    #[serde(default)]
+    pub exact: bool,
+    /// HNSW search breadth; larger is slower but more accurate.
+    #[serde(default)]
+    pub ef: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnnHit {
+    pub distance: f32,
+    pub document: Document,
+}
+
+pub fn vector_from_json(value: &Value) -> Option<Vec<f32>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|v| v.as_f64().map(|f| f as f32))
+        .collect()
+}
+
+pub fn check_dims(vector: &[f32], dims: usize) -> Result<(), DbError> {
+    if vector.len() == dims {
+        Ok(())
+    } else {
+        Err(DbError::InvalidQuery(format!(
+            "expected a vector of {} dimensions, got {}",
+            dims,
+            vector.len()
+        )))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Scored(f32, usize);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    id: String,
+    vector: Vec<f32>,
+    links: Vec<Vec<usize>>,
+    /// Per layer, the nodes whose links point at this one.
+    back: Vec<HashSet<usize>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VectorIndex {
+    options: VectorIndexOptions,
+    nodes: Vec<Option<Node>>,
+    slots: HashMap<String, usize>,
+    free: Vec<usize>,
+    entry: Option<usize>,
+}
+
+impl VectorIndex {
+    pub fn new(options: VectorIndexOptions) -> Self {
+        Self {
+            options,
+            nodes: Vec::new(),
+            slots: HashMap::new(),
+            free: Vec::new(),
+            entry: None,
+        }
+    }
+
+    pub fn options(&self) -> &VectorIndexOptions {
+        &self.options
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    pub fn insert(&mut self, id: &str, vector: Vec<f32>) {
+        // most updates leave the vector alone; keep the node where it is
+        if let Some(&slot) = self.slots.get(id)
+            && self.node(slot).vector == vector
+        {
+            return;
+        }
+        self.remove(id);
+        if vector.len() != self.options.dims {
+            return;
+        }
+        let level = if self.options.kind == VectorIndexKind::Hnsw {
+            self.random_level(id)
+        } else {
+            0
+        };
+        let node = Node {
+            id: id.to_string(),
+            vector,
+            links: vec![Vec::new(); level + 1],
+            back: vec![HashSet::new(); level + 1],
+        };
+        let slot = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = Some(node);
+                slot
+            }
+            None => {
+                self.nodes.push(Some(node));
+                self.nodes.len() - 1
+            }
+        };
+        self.slots.insert(id.to_string(), slot);
+        if self.options.kind == VectorIndexKind::Hnsw {
+            self.link(slot, level);
+        }
+    }
+
+    pub fn remove(&mut self, id: &str) {
+        let Some(slot) = self.slots.remove(id) else {
+            return;
+        };
+        let node = self.nodes[slot].take().expect("slot is occupied");
+        self.free.push(slot);
+        for (layer, links) in node.links.iter().enumerate() {
+            for &n in links {
+                self.node_mut(n).back[layer].remove(&slot);
+            }
+        }
+
+        // links are directed, so nodes other than its neighbours may point at
+        // the removed one; reconnect those through its neighbours so the graph
+        // stays navigable
+        for (layer, referrers) in node.back.iter().enumerate() {
+            for &n in referrers {
+                let mut candidates: Vec<usize> = self.node(n).links[layer]
+                    .iter()
+                    .chain(&node.links[layer])
+                    .copied()
+                    .filter(|&c| c != slot && c != n)
+                    .collect();
+                candidates.sort_unstable();
+                candidates.dedup();
+                let vector = self.node(n).vector.clone();
+                let links = self.closest(&vector, candidates, self.max_links(layer));
+                self.set_links(n, layer, links);
+            }
+        }
+
+        if self.entry == Some(slot) {
+            self.entry = self
+                .slots
+                .values()
+                .copied()
+                .max_by_key(|&s| self.node(s).links.len());
+        }
+    }
+
+    /// Nearest ids to `query` among those accepted by `accept`, closest first.
+    pub fn search(
+        &self,
+        query: &[f32],
+        k: usize,
+        ef: Option<usize>,
+        exact: bool,
+        accept: impl Fn(&str) -> bool,
+    ) -> Vec<(String, f32)> {
+        if exact || self.options.kind == VectorIndexKind::Flat {
+            return self.brute_force(query, k, accept);
+        }
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+
+        let mut ep = entry;
+        for layer in (1..self.node(entry).links.len()).rev() {
+            ep = self.greedy(query, ep, layer);
+        }
+
+        // widen the search until enough candidates survive the filter
+        let mut ef = ef.unwrap_or(self.options.ef_construction).max(k);
+        loop {
+            let found: Vec<(String, f32)> = self
+                .search_layer(query, &[ep], ef, 0)
+                .into_iter()
+                .map(|Scored(d, s)| (self.node(s).id.clone(), d))
+                .filter(|(id, _)| accept(id))
+                .take(k)
+                .collect();
+            if found.len() >= k {
+                return found;
+            }
+            if ef >= self.slots.len() {
+                // pruning can leave nodes no link reaches; scan for the rest
+                return self.brute_force(query, k, accept);
+            }
+            ef *= 2;
+        }
+    }
+
+    fn brute_force(
+        &self,
+        query: &[f32],
+        k: usize,
+        accept: impl Fn(&str) -> bool,
+    ) -> Vec<(String, f32)> {
+        let mut heap = BinaryHeap::new();
+        for (id, &slot) in &self.slots {
+            if !accept(id) {
+                continue;
+            }
+            heap.push(Scored(
+                self.options.metric.distance(query, &self.node(slot).vector),
+                slot,
+            ));
+            if heap.len() > k {
+                heap.pop();
+            }
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Scored(d, s)| (self.node(s).id.clone(), d))
+            .collect()
+    }
+
+    fn node(&self, slot: usize) -> &Node {
+        self.nodes[slot].as_ref().expect("slot is occupied")
+    }
+
+    fn node_mut(&mut self, slot: usize) -> &mut Node {
+        self.nodes[slot].as_mut().expect("slot is occupied")
+    }
+
+    // Points the links of `slot` on `layer` at `links`, keeping the back
+    // links of the nodes it stops and starts pointing at in step.
+    fn set_links(&mut self, slot: usize, layer: usize, links: Vec<usize>) {
+        let old = std::mem::replace(&mut self.node_mut(slot).links[layer], links);
+        for n in old {
+            // the node being removed is already gone
+            if let Some(node) = self.nodes[n].as_mut() {
+                node.back[layer].remove(&slot);
+            }
+        }
+        for n in self.node(slot).links[layer].clone() {
+            self.node_mut(n).back[layer].insert(slot);
+        }
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.options.m * 2
+        } else {
+            self.options.m
+        }
+    }
+
+    fn random_level(&self, id: &str) -> usize {
+        let mut hasher = DefaultHasher::new();
+        id.hash(&mut hasher);
+        let uniform = (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64;
+        let ml = 1.0 / (self.options.m.max(2) as f64).ln();
+        (-(1.0 - uniform).ln() * ml).floor() as usize
+    }
+
+    fn link(&mut self, slot: usize, level: usize) {
+        let Some(entry) = self.entry else {
+            self.entry = Some(slot);
+            return;
+        };
+        let query = self.node(slot).vector.clone();
+        let top = self.node(entry).links.len() - 1;
+
+        let mut ep = entry;
+        for layer in (level + 1..=top).rev() {
+            ep = self.greedy(&query, ep, layer);
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(&query, &[ep], self.options.ef_construction, layer);
+            ep = found.first().map_or(ep, |s| s.1);
+            let neighbours: Vec<usize> = found.iter().map(|s| s.1).take(self.options.m).collect();
+            self.set_links(slot, layer, neighbours.clone());
+
+            let max = self.max_links(layer);
+            for n in neighbours {
+                let mut links = self.node(n).links[layer].clone();
+                links.push(slot);
+                if links.len() > max {
+                    let vector = self.node(n).vector.clone();
+                    links = self.closest(&vector, links, max);
+                }
+                self.set_links(n, layer, links);
+            }
+        }
+        if level > top {
+            self.entry = Some(slot);
+        }
+    }
+
+    fn closest(&self, query: &[f32], candidates: Vec<usize>, max: usize) -> Vec<usize> {
+        let mut scored: Vec<Scored> = candidates
+            .into_iter()
+            .map(|c| Scored(self.options.metric.distance(query, &self.node(c).vector), c))
+            .collect();
+        scored.sort();
+        scored.into_iter().take(max).map(|s| s.1).collect()
+    }
+
+    fn greedy(&self, query: &[f32], mut current: usize, layer: usize) -> usize {
+        let metric = self.options.metric;
+        let mut best = metric.distance(query, &self.node(current).vector);
+        loop {
+            let mut improved = false;
+            for &n in self.node(current).links.get(layer).into_iter().flatten() {
+                let d = metric.distance(query, &self.node(n).vector);
+                if d < best {
+                    best = d;
+                    current = n;
+                    improved = true;
+                }
+            }
+            if !improved {
+                return current;
+            }
+        }
+    }
+
+    /// Beam search on one layer; returns up to `ef` nodes, closest first.
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entries: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Scored> {
+        let metric = self.options.metric;
+        let mut visited: HashSet<usize> = entries.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut results = BinaryHeap::new();
+        for &e in entries {
+            let s = Scored(metric.distance(query, &self.node(e).vector), e);
+            candidates.push(Reverse(s));
+            results.push(s);
+        }
+
+        while let Some(Reverse(current)) = candidates.pop() {
+            if results.len() >= ef && results.peek().is_some_and(|w: &Scored| current.0 > w.0) {
+                break;
+            }
+            for &n in self.node(current.1).links.get(layer).into_iter().flatten() {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let s = Scored(metric.distance(query, &self.node(n).vector), n);
+                if results.len() < ef || results.peek().is_some_and(|w| s.0 < w.0) {
+                    candidates.push(Reverse(s));
+                    results.push(s);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+        results.into_sorted_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hnsw(dims: usize) -> VectorIndex {
+        let mut options = VectorIndexOptions::new(dims, Metric::L2, VectorIndexKind::Hnsw);
+        options.m = 4;
+        options.ef_construction = 32;
+        VectorIndex::new(options)
+    }
+
+    // deterministic points so a failing recall can be reproduced
+    fn points(n: usize, dims: usize) -> Vec<Vec<f32>> {
+        let mut state = 0x2545_f491_4f6c_dd1d_u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 40) as f32 / (1u64 << 24) as f32
+        };
+        (0..n)
+            .map(|_| (0..dims).map(|_| next()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn distances_handle_zero_vectors() {
+        assert_eq!(Metric::Cosine.distance(&[0.0, 0.0], &[1.0, 0.0]), 1.0);
+        assert_eq!(Metric::Cosine.distance(&[2.0, 0.0], &[1.0, 0.0]), 0.0);
+        assert_eq!(Metric::Dot.distance(&[1.0, 2.0], &[3.0, 4.0]), -11.0);
+        assert_eq!(Metric::L2.distance(&[0.0, 0.0], &[3.0, 4.0]), 5.0);
+    }
+
+    #[test]
+    fn hnsw_finds_nearly_what_brute_force_finds() {
+        let mut index = hnsw(8);
+        for (i, v) in points(400, 8).into_iter().enumerate() {
+            index.insert(&i.to_string(), v);
+        }
+        let (mut hits, mut total) = (0, 0);
+        for query in points(20, 8) {
+            let exact: HashSet<String> = index
+                .search(&query, 10, None, true, |_| true)
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect();
+            let approx = index.search(&query, 10, None, false, |_| true);
+            assert_eq!(approx.len(), 10);
+            assert!(approx.windows(2).all(|w| w[0].1 <= w[1].1));
+            hits += approx.iter().filter(|(id, _)| exact.contains(id)).count();
+            total += 10;
+        }
+        assert!(hits * 10 >= total * 9, "recall {hits}/{total}");
+    }
+
+    #[test]
+    fn removals_keep_the_graph_navigable() {
+        let mut index = hnsw(4);
+        for (i, v) in points(200, 4).into_iter().enumerate() {
+            index.insert(&i.to_string(), v);
+        }
+        // removing the entry point forces a new one to be chosen
+        for _ in 0..10 {
+            let id = index.node(index.entry.unwrap()).id.clone();
+            index.remove(&id);
+        }
+        for i in 0..200 {
+            if index.slots.len() > 50 {
+                index.remove(&(i * 7 % 200).to_string());
+            }
+        }
+        // every link has its back link and no more, so a removal only visits
+        // the nodes that point at the removed one
+        for (slot, node) in index.nodes.iter().enumerate() {
+            let Some(node) = node else { continue };
+            for (layer, links) in node.links.iter().enumerate() {
+                assert!(
+                    links
+                        .iter()
+                        .all(|&n| index.node(n).back[layer].contains(&slot))
+                );
+            }
+            for (layer, back) in node.back.iter().enumerate() {
+                assert!(
+                    back.iter()
+                        .all(|&n| index.node(n).links[layer].contains(&slot))
+                );
+            }
+        }
+        let query = [0.5; 4];
+        let found = index.search(&query, 50, Some(8), false, |_| true);
+        assert_eq!(found.len(), 50);
+        assert!(found.iter().all(|(id, _)| index.slots.contains_key(id)));
+
+        // freed slots are reused
+        let slots = index.nodes.len();
+        index.insert("new", vec![0.5; 4]);
+        assert_eq!(index.nodes.len(), slots);
+        assert_eq!(index.search(&query, 1, None, false, |_| true)[0].0, "new");
+
+        // an unchanged vector keeps its node and links
+        let slot = index.slots["new"];
+        let links = index.node(slot).links.clone();
+        index.insert("new", vec![0.5; 4]);
+        assert_eq!(index.slots["new"], slot);
+        assert_eq!(index.node(slot).links, links);
+    }
+
+    #[test]
+    fn filtered_searches_widen_until_k_matches() {
+        let mut index = hnsw(2);
+        for (i, v) in points(300, 2).into_iter().enumerate() {
+            index.insert(&i.to_string(), v);
+        }
+        let accept = |id: &str| id.ends_with('7');
+        let found = index.search(&[0.0, 0.0], 5, Some(5), false, accept);
+        let exact = index.search(&[0.0, 0.0], 5, None, true, accept);
+        assert_eq!(found.len(), 5);
+        assert!(found.iter().all(|(id, _)| accept(id)));
+        assert_eq!(found, exact);
+    }
+
+    #[test]
+    fn vectors_of_the_wrong_size_are_not_indexed() {
+        let mut index = VectorIndex::new(VectorIndexOptions::new(
+            2,
+            Metric::Cosine,
+            VectorIndexKind::Flat,
+        ));
+        index.insert("a", vec![1.0, 0.0]);
+        // an update to a bad vector drops the old entry
+        index.insert("a", vec![1.0, 0.0, 0.0]);
+        assert!(index.slots.is_empty());
+        assert!(
+            index
+                .search(&[1.0, 0.0], 3, None, false, |_| true)
+                .is_empty()
+        );
+        assert!(check_dims(&[1.0], 2).is_err());
+        assert_eq!(vector_from_json(&serde_json::json!([1, "x"])), None);
+    }
+}